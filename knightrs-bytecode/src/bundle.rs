@@ -0,0 +1,200 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use knightrs_bytecode::program::Program;
+use knightrs_bytecode::strings::Encoding;
+use knightrs_bytecode::Options;
+
+// The bytes at the very end of every bundled executable.
+const MAGIC: [u8; 8] = *b"KNBUNDLE";
+
+// Bundles end with the length of their options and of their program (as little-endian `u64`s),
+// followed by `MAGIC`. The options and program come right before that.
+const TRAILER_LEN: usize = 8 + 8 + MAGIC.len();
+
+/// A program, and the options to run it with, which has been bundled into an executable.
+pub struct Bundle {
+	pub options: Options,
+	pub bytecode: Vec<u8>,
+}
+
+/// Writes a copy of the current executable to `path`, which runs `program` with `opts`.
+pub fn write(path: &Path, program: &Program<'_, '_, '_>, opts: &Options) -> io::Result<()> {
+	let options = encode_options(opts);
+	let mut bytecode = Vec::new();
+	program.serialize(&mut bytecode)?;
+
+	// `copy` also copies the permissions, so the bundle is executable too.
+	std::fs::copy(std::env::current_exe()?, path)?;
+	let mut file = OpenOptions::new().append(true).open(path)?;
+	file.write_all(&options)?;
+	file.write_all(&bytecode)?;
+	file.write_all(&(options.len() as u64).to_le_bytes())?;
+	file.write_all(&(bytecode.len() as u64).to_le_bytes())?;
+	file.write_all(&MAGIC)
+}
+
+/// Gets the bundle within the current executable, if it is one.
+///
+/// Returns `None` if the executable can't be read, as there's no way for it to have been bundled.
+pub fn embedded() -> Option<io::Result<Bundle>> {
+	let mut file = File::open(std::env::current_exe().ok()?).ok()?;
+	let length = file.seek(SeekFrom::End(0)).ok()?;
+	if length < TRAILER_LEN as u64 {
+		return None;
+	}
+
+	let mut trailer = [0; TRAILER_LEN];
+	file.seek(SeekFrom::End(-(TRAILER_LEN as i64))).ok()?;
+	file.read_exact(&mut trailer).ok()?;
+	if trailer[16..] != MAGIC {
+		return None;
+	}
+
+	let options_len = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+	let bytecode_len = u64::from_le_bytes(trailer[8..16].try_into().unwrap());
+	Some(read_bundle(&mut file, length - TRAILER_LEN as u64, options_len, bytecode_len))
+}
+
+fn corrupt() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "corrupt bundle")
+}
+
+// Reads the bundle which ends at `end` within `file`.
+fn read_bundle(
+	file: &mut File,
+	end: u64,
+	options_len: u64,
+	bytecode_len: u64,
+) -> io::Result<Bundle> {
+	let start = options_len
+		.checked_add(bytecode_len)
+		.and_then(|len| end.checked_sub(len))
+		.ok_or_else(corrupt)?;
+
+	let mut options = vec![0; options_len as usize];
+	let mut bytecode = vec![0; bytecode_len as usize];
+	file.seek(SeekFrom::Start(start))?;
+	file.read_exact(&mut options)?;
+	file.read_exact(&mut bytecode)?;
+
+	Ok(Bundle { options: decode_options(&options).ok_or_else(corrupt)?, bytecode })
+}
+
+// Every boolean option, in the order they're written in bundles. Bundles are only ever run by a
+// copy of the executable that wrote them, so the features (and thus the options) always match.
+fn flags(opts: &mut Options) -> Vec<&mut bool> {
+	let mut flags = vec![
+		&mut opts.optimizations.constant_folding,
+		&mut opts.optimizations.peephole,
+		&mut opts.optimizations.superinstructions,
+		&mut opts.register_vm,
+	];
+
+	#[cfg(feature = "jit")]
+	flags.push(&mut opts.jit);
+
+	#[cfg(feature = "compliance")]
+	flags.extend([
+		&mut opts.compliance.check_container_length,
+		&mut opts.compliance.i32_integer,
+		&mut opts.compliance.check_overflow,
+		&mut opts.compliance.check_integer_function_bounds,
+		&mut opts.compliance.variable_name_length,
+		&mut opts.compliance.variable_count,
+		&mut opts.compliance.forbid_trailing_tokens,
+		&mut opts.compliance.strict_blocks,
+		&mut opts.compliance.no_block_conversions,
+		&mut opts.compliance.limit_rand_range,
+		&mut opts.compliance.check_quit_status_codes,
+		&mut opts.compliance.strict_conversions,
+		&mut opts.compliance.disable_all_extensions,
+	]);
+
+	#[cfg(feature = "extensions")]
+	flags.extend([
+		&mut opts.extensions.builtin_fns.boolean,
+		&mut opts.extensions.builtin_fns.string,
+		&mut opts.extensions.builtin_fns.list,
+		&mut opts.extensions.builtin_fns.integer,
+		&mut opts.extensions.builtin_fns.null,
+		&mut opts.extensions.builtin_fns.assign_to_strings,
+		&mut opts.extensions.builtin_fns.assign_to_random,
+		&mut opts.extensions.syntax.list_literals,
+		&mut opts.extensions.syntax.string_interpolation,
+		&mut opts.extensions.syntax.control_flow,
+		&mut opts.extensions.types.floats,
+		&mut opts.extensions.types.hashmaps,
+		&mut opts.extensions.types.classes,
+		&mut opts.extensions.breaking.negate_reverses_collections,
+		&mut opts.extensions.breaking.random_can_be_negative,
+		&mut opts.extensions.functions.eval,
+		&mut opts.extensions.functions.value,
+		&mut opts.extensions.functions.system,
+		&mut opts.extensions.negative_indexing,
+		&mut opts.extensions.argv,
+	]);
+
+	#[cfg(feature = "debugger")]
+	flags.push(&mut opts.debugger.stacktrace);
+
+	#[cfg(feature = "embedded")]
+	flags.push(&mut opts.embedded.dont_exit_when_quitting);
+
+	#[cfg(feature = "check-variables")]
+	flags.push(&mut opts.check_variables);
+
+	#[cfg(feature = "check-parens")]
+	flags.push(&mut opts.check_parens);
+
+	flags
+}
+
+// Options are written as the encoding, then the max call depth (with `u64::MAX` for no limit), and
+// then a byte for each of the `flags`.
+fn encode_options(opts: &Options) -> Vec<u8> {
+	let encoding = match opts.encoding {
+		Encoding::Utf8 => 0,
+		#[cfg(feature = "compliance")]
+		Encoding::Knight => 1,
+		#[cfg(feature = "compliance")]
+		Encoding::Ascii => 2,
+	};
+	let max_call_depth = opts.max_call_depth.map_or(u64::MAX, |depth| depth as u64);
+
+	let mut bytes = vec![encoding];
+	bytes.extend_from_slice(&max_call_depth.to_le_bytes());
+	bytes.extend(flags(&mut opts.clone()).into_iter().map(|flag| *flag as u8));
+	bytes
+}
+
+fn decode_options(bytes: &[u8]) -> Option<Options> {
+	let (&encoding, rest) = bytes.split_first()?;
+	let (&max_call_depth, rest) = rest.split_first_chunk::<8>()?;
+
+	let encoding = match encoding {
+		0 => Encoding::Utf8,
+		#[cfg(feature = "compliance")]
+		1 => Encoding::Knight,
+		#[cfg(feature = "compliance")]
+		2 => Encoding::Ascii,
+		_ => return None,
+	};
+	let max_call_depth = match u64::from_le_bytes(max_call_depth) {
+		u64::MAX => None,
+		depth => Some(usize::try_from(depth).ok()?),
+	};
+
+	let mut opts = Options { encoding, max_call_depth, ..Options::default() };
+	let flags = flags(&mut opts);
+	if flags.len() != rest.len() {
+		return None;
+	}
+
+	for (flag, &byte) in flags.into_iter().zip(rest) {
+		*flag = byte != 0;
+	}
+
+	Some(opts)
+}
@@ -0,0 +1,133 @@
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use knightrs_bytecode::program::{Fnv1a, Program, FORMAT_VERSION};
+use knightrs_bytecode::strings::Encoding;
+use knightrs_bytecode::Options;
+
+/// What a program is cached under: its source, along with everything else which changes what it's
+/// compiled into.
+///
+/// The bytecode format and our own version are included, so entries written by other builds are
+/// never used. Entries are stored at the key's hash, but as that's only 64 bits, the entire key is
+/// stored in the entry as well, and compared when it's loaded.
+pub struct Key(Vec<u8>);
+
+impl Key {
+	/// Creates the key for `source` when it's compiled with `opts`.
+	pub fn new(source: &str, opts: &Options) -> Self {
+		let version = env!("CARGO_PKG_VERSION");
+		let options = compile_options(opts);
+
+		let mut key = Vec::new();
+		key.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+		for part in [version.as_bytes(), &options, source.as_bytes()] {
+			key.extend_from_slice(&(part.len() as u64).to_le_bytes());
+			key.extend_from_slice(part);
+		}
+		Self(key)
+	}
+
+	/// Gets the path within `dir` that the entry for `self` is at.
+	pub fn path(&self, dir: &Path) -> PathBuf {
+		// FNV-1a is used instead of `DefaultHasher`, as its output is the same across Rust versions, so
+		// cache entries stay valid when we're recompiled.
+		let mut hasher = Fnv1a::new();
+		Hasher::write(&mut hasher, &self.0);
+		dir.join(format!("{:016x}.knc", hasher.finish()))
+	}
+}
+
+// Gets the options which change how programs are compiled, as a byte each. Options which are only
+// used while running (such as `register_vm` or `max_call_depth`) are left out, so changing them
+// doesn't miss the cache. Anything the parser (or constant folding) reads must be in here.
+fn compile_options(opts: &Options) -> Vec<u8> {
+	let encoding = match opts.encoding {
+		Encoding::Utf8 => 0,
+		#[cfg(feature = "compliance")]
+		Encoding::Knight => 1,
+		#[cfg(feature = "compliance")]
+		Encoding::Ascii => 2,
+	};
+
+	let mut options = vec![
+		encoding,
+		opts.optimizations.constant_folding as u8,
+		opts.optimizations.peephole as u8,
+		opts.optimizations.superinstructions as u8,
+	];
+
+	#[cfg(feature = "compliance")]
+	options.extend([
+		opts.compliance.check_container_length as u8,
+		opts.compliance.i32_integer as u8,
+		opts.compliance.check_overflow as u8,
+		opts.compliance.check_integer_function_bounds as u8,
+		opts.compliance.variable_name_length as u8,
+		opts.compliance.variable_count as u8,
+		opts.compliance.forbid_trailing_tokens as u8,
+		opts.compliance.strict_blocks as u8,
+		opts.compliance.no_block_conversions as u8,
+		opts.compliance.strict_conversions as u8,
+		opts.compliance.disable_all_extensions as u8,
+	]);
+
+	#[cfg(feature = "extensions")]
+	options.extend([
+		opts.extensions.builtin_fns.boolean as u8,
+		opts.extensions.builtin_fns.string as u8,
+		opts.extensions.builtin_fns.list as u8,
+		opts.extensions.builtin_fns.integer as u8,
+		opts.extensions.builtin_fns.null as u8,
+		opts.extensions.builtin_fns.assign_to_strings as u8,
+		opts.extensions.builtin_fns.assign_to_random as u8,
+		opts.extensions.syntax.list_literals as u8,
+		opts.extensions.syntax.string_interpolation as u8,
+		opts.extensions.syntax.control_flow as u8,
+		opts.extensions.types.floats as u8,
+		opts.extensions.types.hashmaps as u8,
+		opts.extensions.types.classes as u8,
+		opts.extensions.breaking.negate_reverses_collections as u8,
+		opts.extensions.functions.eval as u8,
+		opts.extensions.functions.value as u8,
+		opts.extensions.functions.system as u8,
+		opts.extensions.negative_indexing as u8,
+	]);
+
+	#[cfg(feature = "check-variables")]
+	options.push(opts.check_variables as u8);
+
+	#[cfg(feature = "check-parens")]
+	options.push(opts.check_parens as u8);
+
+	options
+}
+
+/// Reads the serialized program cached at `path`, returning `None` if there isn't one, or if it was
+/// stored under a key other than `key`.
+pub fn load(path: &Path, key: &Key) -> Option<Vec<u8>> {
+	let entry = std::fs::read(path).ok()?;
+	let (len, rest) = entry.split_first_chunk::<8>()?;
+	let (stored_key, program) = rest.split_at_checked(u64::from_le_bytes(*len) as usize)?;
+
+	(stored_key == key.0).then(|| program.to_vec())
+}
+
+/// Writes `program` to `path`, under `key`.
+///
+/// The program is first written to a temporary file and then renamed, so other processes sharing
+/// the cache never see a partially-written program.
+pub fn store(path: &Path, key: &Key, program: &Program<'_, '_, '_>) -> io::Result<()> {
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+
+	let temp = path.with_extension(format!("knc.{}", std::process::id()));
+	let mut entry = Vec::new();
+	entry.write_all(&(key.0.len() as u64).to_le_bytes())?;
+	entry.write_all(&key.0)?;
+	program.serialize(&mut entry)?;
+	std::fs::write(&temp, entry)?;
+	std::fs::rename(&temp, path)
+}
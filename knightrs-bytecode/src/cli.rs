@@ -1,9 +1,12 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::{
 	arg, command, error, value_parser, Arg, ArgAction, Args, Command, CommandFactory, Parser,
+	Subcommand,
 };
 use knightrs_bytecode::{
+	gc::GcOptions,
 	parser::source_location::ProgramSource,
 	strings::{Encoding, KnStr},
 	value::KnString,
@@ -27,6 +30,147 @@ struct Cli {
 	#[arg(trailing_var_arg = true)]
 	argv: Vec<String>,
 	// .next_help_heading(heading)
+	#[command(subcommand)]
+	command: Option<Subcommands>,
+
+	/***************************************************************************
+	 *                                Bytecode                                 *
+	 ***************************************************************************/
+	/// Compile the program to bytecode, and write it to FILE instead of running it.
+	///
+	/// Files containing bytecode can be run by passing them to `-f`.
+	#[arg(long, value_name = "FILE")]
+	compile: Option<PathBuf>,
+
+	/// Translate the program to C, and write it to FILE instead of running it.
+	///
+	/// The output is standalone, and can be built with `cc -O2 -o PROGRAM FILE`.
+	#[arg(long, value_name = "FILE", conflicts_with = "compile")]
+	emit_c: Option<PathBuf>,
+
+	/// Translate the program to WebAssembly, and write it to FILE instead of running it.
+	///
+	/// The output is a WASI module, and can be run with `wasmtime FILE`.
+	#[cfg(feature = "wasm")]
+	#[arg(long, value_name = "FILE", conflicts_with_all = ["compile", "emit_c"])]
+	emit_wasm: Option<PathBuf>,
+
+	/// Make the module written by `--emit-wasm` import `OUTPUT`, `PROMPT`, and `RANDOM` (along with
+	/// errors and `QUIT`) from the host's `knight` module, instead of using WASI.
+	#[cfg(feature = "wasm")]
+	#[arg(long, requires = "emit_wasm")]
+	wasm_host_imports: bool,
+
+	/// Cache compiled programs in DIR, so unchanged programs don't need to be parsed again.
+	#[arg(long, value_name = "DIR")]
+	cache_dir: Option<PathBuf>,
+
+	/// Treat programs as bytecode assembly, instead of Knight source code.
+	#[arg(long)]
+	asm: bool,
+
+	/// Print the disassembled bytecode of programs instead of running them.
+	#[arg(long, conflicts_with = "cfg")]
+	disassemble: bool,
+
+	/// Print the control-flow graph of programs in the DOT format instead of running them.
+	#[arg(long)]
+	cfg: bool,
+
+	/***************************************************************************
+	 *                              Optimizations                              *
+	 ***************************************************************************/
+	/// Enable all optimizations
+	#[arg(short = 'O', long, overrides_with = "_no_optimize")]
+	optimize: bool,
+	/// Undoes a previous --optimize
+	#[arg(long, hide_short_help = true)]
+	_no_optimize: bool, // underscore because nothing checks for it
+
+	/// Evaluate constant expressions, and remove unused branches, when compiling
+	#[arg(long, hide_short_help = true, overrides_with = "no_fold_constants")]
+	fold_constants: bool,
+	/// Undoes fold_constants
+	#[arg(long, hide_short_help = true)]
+	no_fold_constants: bool,
+
+	/// Rewrite wasteful sequences of instructions after compiling
+	#[arg(long, hide_short_help = true, overrides_with = "no_peephole")]
+	peephole: bool,
+	/// Undoes peephole
+	#[arg(long, hide_short_help = true)]
+	no_peephole: bool,
+
+	/// Replace common sequences of instructions with fused ones after compiling
+	#[arg(long, hide_short_help = true, overrides_with = "no_superinstructions")]
+	superinstructions: bool,
+	/// Undoes superinstructions
+	#[arg(long, hide_short_help = true)]
+	no_superinstructions: bool,
+
+	/***************************************************************************
+	 *                                Execution                                *
+	 ***************************************************************************/
+	/// Run programs with the experimental register-based vm
+	#[arg(long, hide_short_help = true, overrides_with = "no_registers")]
+	registers: bool,
+	/// Undoes registers
+	#[arg(long, hide_short_help = true)]
+	no_registers: bool,
+
+	/// Compile frequently-called blocks to native code
+	#[arg(long, hide_short_help = true, overrides_with = "no_jit")]
+	jit: bool,
+	/// Undoes jit
+	#[arg(long, hide_short_help = true)]
+	no_jit: bool,
+
+	/// Stop programs with an error if more than DEPTH blocks are being called at once.
+	#[arg(long, value_name = "DEPTH")]
+	max_call_depth: Option<usize>,
+
+	/// Stop programs with an error once they've made AMOUNT backward jumps and calls.
+	#[arg(long, value_name = "AMOUNT")]
+	fuel: Option<u64>,
+
+	/// Stop programs with an error once they've run for MS milliseconds.
+	#[arg(long, value_name = "MS")]
+	timeout: Option<u64>,
+
+	/// If the program runs out of fuel or times out, save where it's up to in FILE, so that it can be
+	/// continued by --resume.
+	#[arg(long, value_name = "FILE")]
+	checkpoint: Option<PathBuf>,
+
+	/// Continue the program from where it was saved by --checkpoint to FILE, instead of starting it
+	/// from the beginning.
+	#[arg(long, value_name = "FILE")]
+	resume: Option<PathBuf>,
+
+	/// Write every line read by PROMPT, integer returned by RANDOM, and output of ` to FILE.
+	#[arg(long, value_name = "FILE", conflicts_with = "replay")]
+	record: Option<PathBuf>,
+
+	/// Use the inputs written by --record to FILE, instead of reading stdin, generating random
+	/// numbers, or running commands.
+	#[arg(long, value_name = "FILE")]
+	replay: Option<PathBuf>,
+
+	/// Print statistics about the garbage collector to stderr once programs finish.
+	#[arg(long)]
+	gc_stats: bool,
+
+	/// Write every value on the heap, and what's retaining it, to FILE once programs finish.
+	#[arg(long, value_name = "FILE")]
+	heap_snapshot: Option<PathBuf>,
+
+	/// Collect garbage on every allocation, and poison freed values. Very slow; only for testing.
+	#[arg(long)]
+	gc_stress: bool,
+
+	/// Stop programs with an error if their values would use more than BYTES bytes of memory.
+	#[arg(long, value_name = "BYTES")]
+	max_heap_bytes: Option<usize>,
 
 	/***************************************************************************
 	 *                                Debugger                                 *
@@ -364,6 +508,18 @@ struct Cli {
 	exit_when_quit: bool,
 }
 
+#[derive(Subcommand, Debug)]
+enum Subcommands {
+	/// Write a copy of this executable to FILE which runs the program, instead of running it.
+	///
+	/// The copy runs the program with the options given before `bundle`, and any arguments it's
+	/// given are passed to the program via `--ext-argv`.
+	Bundle {
+		#[arg(value_name = "FILE")]
+		output: PathBuf,
+	},
+}
+
 impl Cli {
 	pub fn options(&self) -> clap::error::Result<Options> {
 		let mut opts = Options::default();
@@ -398,6 +554,21 @@ impl Cli {
 			};
 		}
 
+		// Optimizations are always supported, so they don't need a feature.
+		let optimization = |yes: bool, no: bool| yes || (!no && self.optimize);
+		opts.optimizations.constant_folding =
+			optimization(self.fold_constants, self.no_fold_constants);
+		opts.optimizations.peephole = optimization(self.peephole, self.no_peephole);
+		opts.optimizations.superinstructions =
+			optimization(self.superinstructions, self.no_superinstructions);
+		opts.register_vm = self.registers && !self.no_registers;
+		opts.max_call_depth = self.max_call_depth;
+
+		check_option! {
+			feature = "jit", default = false;
+			opts.jit = jit, no_jit;
+		}
+
 		check_option! {
 			feature = "debugger", default = self.debugger;
 
@@ -477,17 +648,35 @@ impl CliOpts {
 	pub fn from_argv() -> Self {
 		let cli = Cli::parse();
 
-		let options = match cli.options() {
+		let mut options = match cli.options() {
 			Ok(opts) => opts,
 			Err(err) => err.format(&mut Cli::command()).exit(),
 		};
 
+		// `QUIT` normally exits immediately, so make it return instead so the gc can be reported on.
+		#[cfg(feature = "embedded")]
+		if cli.gc_stats || cli.heap_snapshot.is_some() {
+			options.embedded.dont_exit_when_quitting = true;
+		}
+
 		if cli.expression.is_empty() && cli.file.is_empty() {
 			Cli::command()
 				.error(error::ErrorKind::MissingRequiredArgument, "either -e or a file must be given")
 				.exit();
 		}
 
+		if cli.compile.is_some() && cli.expression.len() + cli.file.len() != 1 {
+			Cli::command()
+				.error(error::ErrorKind::TooManyValues, "--compile requires exactly one program")
+				.exit();
+		}
+
+		if cli.command.is_some() && cli.expression.len() + cli.file.len() != 1 {
+			Cli::command()
+				.error(error::ErrorKind::TooManyValues, "bundle requires exactly one program")
+				.exit();
+		}
+
 		debug_assert!(
 			cli.expression.is_empty() || cli.file.is_empty(),
 			"exaclty one of -e or a file mustve been given?"
@@ -522,17 +711,108 @@ impl CliOpts {
 		self.cli.argv.clone().into_iter()
 	}
 
+	pub fn compile(&self) -> Option<&Path> {
+		self.cli.compile.as_deref()
+	}
+
+	pub fn bundle(&self) -> Option<&Path> {
+		match &self.cli.command {
+			Some(Subcommands::Bundle { output }) => Some(output),
+			None => None,
+		}
+	}
+
+	pub fn emit_c(&self) -> Option<&Path> {
+		self.cli.emit_c.as_deref()
+	}
+
+	#[cfg(feature = "wasm")]
+	pub fn emit_wasm(&self) -> Option<&Path> {
+		self.cli.emit_wasm.as_deref()
+	}
+
+	#[cfg(feature = "wasm")]
+	pub fn wasm_imports(&self) -> knightrs_bytecode::program::WasmImports {
+		use knightrs_bytecode::program::WasmImports;
+
+		if self.cli.wasm_host_imports {
+			WasmImports::Host
+		} else {
+			WasmImports::Wasi
+		}
+	}
+
+	pub fn gc_options(&self) -> GcOptions {
+		let mut opts = GcOptions::default();
+		opts.stress = self.cli.gc_stress;
+		opts.max_heap_bytes = self.cli.max_heap_bytes;
+		opts
+	}
+
+	pub fn fuel(&self) -> Option<u64> {
+		self.cli.fuel
+	}
+
+	pub fn timeout(&self) -> Option<Duration> {
+		self.cli.timeout.map(Duration::from_millis)
+	}
+
+	pub fn checkpoint(&self) -> Option<&Path> {
+		self.cli.checkpoint.as_deref()
+	}
+
+	pub fn resume(&self) -> Option<&Path> {
+		self.cli.resume.as_deref()
+	}
+
+	pub fn record(&self) -> Option<&Path> {
+		self.cli.record.as_deref()
+	}
+
+	pub fn replay(&self) -> Option<&Path> {
+		self.cli.replay.as_deref()
+	}
+
+	pub fn gc_stats(&self) -> bool {
+		self.cli.gc_stats
+	}
+
+	pub fn heap_snapshot(&self) -> Option<&Path> {
+		self.cli.heap_snapshot.as_deref()
+	}
+
+	pub fn cache_dir(&self) -> Option<&Path> {
+		self.cli.cache_dir.as_deref()
+	}
+
+	pub fn asm(&self) -> bool {
+		self.cli.asm
+	}
+
+	pub fn disassemble(&self) -> bool {
+		self.cli.disassemble
+	}
+
+	pub fn cfg(&self) -> bool {
+		self.cli.cfg
+	}
+
+	// Files are read as bytes, as they might contain compiled bytecode.
 	pub fn source_iter<'s>(
 		&'s self,
-	) -> Box<dyn Iterator<Item = std::io::Result<(String, ProgramSource<'s>)>> + 's> {
+	) -> Box<dyn Iterator<Item = std::io::Result<(Vec<u8>, ProgramSource<'s>)>> + 's> {
 		if !self.cli.expression.is_empty() {
 			Box::new(
 				// TODO: remove this clone
-				self.cli.expression.iter().map(|source| Ok((source.clone(), ProgramSource::ExprFlag))),
+				self
+					.cli
+					.expression
+					.iter()
+					.map(|source| Ok((source.clone().into_bytes(), ProgramSource::ExprFlag))),
 			)
 		} else {
 			Box::new(self.cli.file.iter().map(|path| {
-				let source = std::fs::read_to_string(path)?;
+				let source = std::fs::read(path)?;
 				Ok((source, ProgramSource::File(path)))
 			}))
 		}
@@ -1,22 +1,104 @@
 use crate::gc::GcRoot;
 use crate::strings::KnStr;
-use std::io;
+use std::io::{self, BufRead, Write};
 
 use crate::gc::Gc;
 use crate::options::Options;
 use crate::value::{Integer, KnString};
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::{rngs::StdRng, SeedableRng};
+
+#[cfg(feature = "embedded")]
+mod capture;
+mod random;
+
+#[cfg(feature = "embedded")]
+pub use capture::Capture;
+use knightrs_common::transcript::Transcript;
+pub use knightrs_common::Input;
+use random::Tracked;
+pub use random::{Pcg32, Random};
+pub(crate) use random::{RngPosition, MAX_GENERATED};
 
 pub struct Environment<'gc> {
 	opts: Options,
-	rng: StdRng,
+	rng: Tracked<'gc>,
 	gc: &'gc Gc,
+	stdin: Stdin<'gc>,
+	stdout: Box<dyn Write + 'gc>,
+	transcript: Transcript<Box<dyn Write + 'gc>, Box<dyn BufRead + 'gc>>,
+}
+
+// Where `PROMPT` reads lines from.
+enum Stdin<'gc> {
+	// The process's stdin. Its lock isn't held between `PROMPT`s, so other things can read from it too.
+	Process,
+	Custom(Box<dyn BufRead + 'gc>),
 }
 
 impl<'gc> Environment<'gc> {
 	pub fn new(opts: Options, gc: &'gc Gc) -> Self {
-		// TODO: allow `rng` to be supplied by callers
-		Self { opts, rng: StdRng::from_entropy(), gc }
+		// It's seeded via `reseed`, so that the seed is known if the rng needs to be restored.
+		let mut rng = Tracked::new(Box::new(StdRng::from_entropy()));
+		rng.reseed(rand::random());
+
+		Self {
+			opts,
+			rng,
+			gc,
+			stdin: Stdin::Process,
+			stdout: Box::new(io::stdout()),
+			transcript: Transcript::None,
+		}
+	}
+
+	/// Records every nondeterministic [`Input`] given to programs (lines read by `PROMPT`, integers
+	/// returned by `RANDOM`, and the output of `` ` ``) to `transcript`, so they can be [replayed]
+	/// later.
+	///
+	/// [replayed]: Self::replay
+	pub fn record(&mut self, transcript: impl Write + 'gc) {
+		self.transcript = Transcript::Record(Box::new(transcript));
+	}
+
+	/// Gives programs the [`Input`]s from a transcript made by [`record`](Self::record), in order,
+	/// instead of reading stdin, generating random numbers, or running commands.
+	///
+	/// If a program asks for a different input than what was recorded next (such as `RANDOM` when
+	/// `PROMPT` was recorded, or a different command for `` ` ``), it stops with
+	/// [`Error::ReplayDiverged`](crate::Error::ReplayDiverged).
+	pub fn replay(&mut self, transcript: impl BufRead + 'gc) {
+		self.transcript = Transcript::Replay(Box::new(transcript));
+	}
+
+	/// Makes `PROMPT` read lines from `stdin`, instead of from the process's stdin.
+	#[cfg(feature = "embedded")]
+	pub fn set_stdin(&mut self, stdin: impl BufRead + 'gc) {
+		self.stdin = Stdin::Custom(Box::new(stdin));
+	}
+
+	/// Makes `RANDOM` use `rng`, instead of a [`StdRng`] seeded by the operating system.
+	///
+	/// Use [`Pcg32`] if the same seed needs to produce the same numbers on every platform and version.
+	/// When [restoring](crate::vm::Vm::restore) a checkpoint made before `RANDOM` was reseeded, the
+	/// environment must be given an rng identical to the one that was set when the checkpoint was made.
+	#[cfg(feature = "embedded")]
+	pub fn set_rng(&mut self, rng: impl Random + 'gc) {
+		self.rng = Tracked::new(Box::new(rng));
+	}
+
+	/// Makes `OUTPUT` and `DUMP` write to `stdout`, instead of to the process's stdout.
+	#[cfg(feature = "embedded")]
+	pub fn set_stdout(&mut self, stdout: impl Write + 'gc) {
+		self.stdout = Box::new(stdout);
+	}
+
+	/// Captures everything `OUTPUT` and `DUMP` write from now on, returning the [`Capture`] that it's
+	/// written to.
+	#[cfg(feature = "embedded")]
+	pub fn capture_output(&mut self) -> Capture {
+		let capture = Capture::new();
+		self.set_stdout(capture.clone());
+		capture
 	}
 
 	pub fn opts(&self) -> &Options {
@@ -28,10 +110,26 @@ impl<'gc> Environment<'gc> {
 	}
 
 	pub fn prompt(&mut self) -> crate::Result<Option<GcRoot<'gc, KnString<'gc>>>> {
+		let line = match self.transcript.replay(&Input::Prompt(None))? {
+			Some(Input::Prompt(line)) => line,
+			_ => self.read_line()?,
+		};
+
+		self.record_input(Input::Prompt(line.clone()))?;
+		match line {
+			Some(line) => Ok(Some(KnString::new(line, self.opts(), self.gc())?)),
+			None => Ok(None),
+		}
+	}
+
+	// Reads a line from stdin, stripping its line ending.
+	fn read_line(&mut self) -> crate::Result<Option<String>> {
 		let mut line = String::new();
-		let amnt = std::io::stdin()
-			.read_line(&mut line)
-			.map_err(|err| crate::Error::IoError { func: "PROMPT", err })?;
+		let amnt = match &mut self.stdin {
+			Stdin::Process => io::stdin().read_line(&mut line),
+			Stdin::Custom(stdin) => stdin.read_line(&mut line),
+		}
+		.map_err(|err| crate::Error::IoError { func: "PROMPT", err })?;
 
 		if amnt == 0 {
 			return Ok(None);
@@ -51,12 +149,11 @@ impl<'gc> Environment<'gc> {
 			}
 		}
 
-		Ok(Some(KnString::new(line, self.opts(), self.gc())?))
+		Ok(Some(line))
 	}
 
-	pub fn output(&mut self) -> impl io::Write {
-		// TODO: eventually allow for capturing output within Knight programs
-		std::io::stdout()
+	pub fn output(&mut self) -> &mut dyn Write {
+		&mut *self.stdout
 	}
 
 	#[cold] // Don't inline the big function, as it always exits the program.
@@ -81,7 +178,7 @@ impl<'gc> Environment<'gc> {
 
 	#[cfg(feature = "extensions")]
 	pub fn seed_random(&mut self, seed: Integer) {
-		self.rng = StdRng::seed_from_u64(seed.inner() as u64)
+		self.rng.reseed(seed.inner() as u64)
 	}
 
 	pub fn random(&mut self) -> crate::Result<Integer> {
@@ -97,27 +194,56 @@ impl<'gc> Environment<'gc> {
 			_ => Integer::max(&self.opts).inner(),
 		};
 
+		// Recorded integers are validated, as the transcript might've been made with other options.
+		if let Some(Input::Random(int)) = self.transcript.replay(&Input::Random(0))? {
+			if !(min..=max).contains(&int) {
+				return Err(crate::Error::DomainError("recorded RANDOM is out of bounds"));
+			}
+
+			return Ok(Integer::new_unvalidated_unchecked(int));
+		}
+
 		// We can do `new_unvalidated` as we clamp the min/max based on compliance.
-		Ok(Integer::new_unvalidated_unchecked(self.rng.gen_range(min..=max)))
+		let int = random::in_range(&mut self.rng, min, max);
+		self.record_input(Input::Random(int))?;
+		Ok(Integer::new_unvalidated_unchecked(int))
+	}
+
+	// Records `input` if a transcript is being recorded.
+	fn record_input(&mut self, input: Input) -> crate::Result<()> {
+		self.transcript.record(input).map_err(|err| crate::Error::IoError { func: "record", err })
+	}
+
+	// Where the rng is up to, for checkpoints.
+	pub(crate) fn rng_position(&self) -> RngPosition {
+		self.rng.position()
+	}
+
+	pub(crate) fn seek_rng(&mut self, position: RngPosition) {
+		self.rng.seek(position)
 	}
 
 	#[cfg(feature = "extensions")]
 	pub fn system(&mut self, cmd: &KnStr) -> crate::Result<GcRoot<'gc, KnString<'gc>>> {
 		use std::process::{Command, Stdio};
 
-		let output = Command::new("/bin/sh")
-			.arg("-c")
-			.arg(cmd.as_str())
-			.stdin(Stdio::inherit())
-			.output()
-			.expect("TODO: convert the error");
-
-		let output = KnString::new(
-			String::from_utf8(output.stdout).expect("TODO: handle the utf-8 error"),
-			&self.opts,
-			self.gc,
-		)?;
+		let command = cmd.as_str().to_string();
+		let requested = Input::System { command: command.clone(), output: String::new() };
+		let output = match self.transcript.replay(&requested)? {
+			Some(Input::System { output, .. }) => output,
+			_ => {
+				let output = Command::new("/bin/sh")
+					.arg("-c")
+					.arg(cmd.as_str())
+					.stdin(Stdio::inherit())
+					.output()
+					.expect("TODO: convert the error");
+
+				String::from_utf8(output.stdout).expect("TODO: handle the utf-8 error")
+			}
+		};
 
-		Ok(output)
+		self.record_input(Input::System { command, output: output.clone() })?;
+		Ok(KnString::new(output, &self.opts, self.gc)?)
 	}
 }
@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// An in-memory buffer that everything a program outputs can be written to.
+///
+/// Clones of a `Capture` share the same buffer, so one can be given to
+/// [`Environment::set_stdout`](super::Environment::set_stdout) while another is kept to read from.
+/// [`Environment::capture_output`](super::Environment::capture_output) does just that.
+#[derive(Debug, Default, Clone)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+	/// Creates a new, empty, `Capture`.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Gets everything that's been written so far.
+	pub fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.0.borrow()).into_owned()
+	}
+
+	/// Gets everything that's been written so far, and clears the buffer.
+	pub fn take(&self) -> String {
+		let bytes = std::mem::take(&mut *self.0.borrow_mut());
+		String::from_utf8(bytes)
+			.unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned())
+	}
+}
+
+impl Write for Capture {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.0.borrow_mut().extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
@@ -0,0 +1,90 @@
+pub(crate) use knightrs_common::random::in_range;
+pub use knightrs_common::random::{Pcg32, Random};
+
+// The environment's rng, which keeps track of where it's up to so that it can be put back there
+// later (see `Vm::checkpoint`). Rngs can't be inspected directly, so instead it tracks the seed
+// that was last used, and how many numbers have been generated since then.
+//
+// Putting it back means generating all of those numbers again, so once `MAX_GENERATED` numbers have
+// been generated, the rng reseeds itself with the next one.
+pub(super) struct Tracked<'gc> {
+	rng: Box<dyn Random + 'gc>,
+	position: RngPosition,
+}
+
+// The most numbers that are generated before the rng is reseeded.
+pub(crate) const MAX_GENERATED: u64 = 1 << 20;
+
+/// How far along its sequence of numbers an environment's rng is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RngPosition {
+	/// The seed the rng was last given, or `None` if it hasn't been reseeded since it was set.
+	pub seed: Option<u64>,
+
+	/// How many numbers have been generated since it was seeded (or set).
+	pub generated: u64,
+}
+
+impl<'gc> Tracked<'gc> {
+	pub(super) fn new(rng: Box<dyn Random + 'gc>) -> Self {
+		Self { rng, position: RngPosition { seed: None, generated: 0 } }
+	}
+
+	pub(super) fn position(&self) -> RngPosition {
+		self.position
+	}
+
+	// Moves the rng to `position`. If it doesn't have a seed, then the rng is assumed to be in the
+	// same state as when it was set.
+	pub(super) fn seek(&mut self, position: RngPosition) {
+		debug_assert!(position.generated <= MAX_GENERATED);
+
+		if let Some(seed) = position.seed {
+			self.reseed(seed);
+		}
+
+		for _ in 0..position.generated {
+			self.next_u64();
+		}
+	}
+}
+
+impl Random for Tracked<'_> {
+	#[inline]
+	fn next_u64(&mut self) -> u64 {
+		if self.position.generated == MAX_GENERATED {
+			let seed = self.rng.next_u64();
+			self.reseed(seed);
+		}
+
+		self.position.generated += 1;
+		self.rng.next_u64()
+	}
+
+	fn reseed(&mut self, seed: u64) {
+		self.position = RngPosition { seed: Some(seed), generated: 0 };
+		self.rng.reseed(seed);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn seeking_never_generates_too_many_numbers() {
+		let mut rng = Tracked::new(Box::new(Pcg32::new(1)));
+		for _ in 0..MAX_GENERATED + 5 {
+			rng.next_u64();
+		}
+
+		let position = rng.position();
+		assert!(position.seed.is_some());
+		assert_eq!(position.generated, 5);
+
+		let mut restored = Tracked::new(Box::new(Pcg32::new(2)));
+		restored.seek(position);
+		assert_eq!(restored.position(), position);
+		assert_eq!(restored.next_u64(), rng.next_u64());
+	}
+}
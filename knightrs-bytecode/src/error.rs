@@ -31,6 +31,34 @@ pub enum Error {
 	#[error("list is too large")]
 	ListIsTooLarge,
 
+	/// Indicates that more blocks were being called at once than [`max_call_depth`] allows.
+	///
+	/// [`max_call_depth`]: crate::Options::max_call_depth
+	#[error("stack depth exceeded (at most {0} nested calls are allowed)")]
+	StackDepthExceeded(usize),
+
+	/// Indicates that an operation would've used more memory than [`max_heap_bytes`] allows.
+	///
+	/// [`max_heap_bytes`]: crate::gc::GcOptions::max_heap_bytes
+	#[error("out of memory (at most {0} bytes may be used)")]
+	OutOfMemory(usize),
+
+	/// Indicates that the vm ran out of fuel. It can be resumed after being given more fuel.
+	///
+	/// See [`Vm::set_fuel`](crate::vm::Vm::set_fuel) for more details.
+	#[error("ran out of fuel")]
+	OutOfFuel,
+
+	/// Indicates that the vm's [`InterruptHandle`](crate::vm::InterruptHandle) was interrupted. It
+	/// can be resumed after the handle's reset.
+	#[error("interrupted")]
+	Interrupted,
+
+	/// Indicates that a program asked for a different input than what was recorded in the transcript
+	/// it's replaying. See [`Environment::replay`](crate::env::Environment::replay).
+	#[error("replay diverged: asked for {requested}, but the transcript has {recorded}")]
+	ReplayDiverged { requested: String, recorded: String },
+
 	#[error("(quit with exit status {0})")]
 	// #[cfg(any(doc, feature = "embedded"))]
 	#[cfg(feature = "embedded")]
@@ -49,6 +77,19 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl From<knightrs_common::transcript::ReplayError> for Error {
+	fn from(err: knightrs_common::transcript::ReplayError) -> Self {
+		use knightrs_common::transcript::ReplayError;
+
+		match err {
+			ReplayError::Io(err) => Self::IoError { func: "replay", err },
+			ReplayError::Diverged { requested, recorded } => {
+				Self::ReplayDiverged { requested, recorded }
+			}
+		}
+	}
+}
+
 impl From<crate::parser::ParseError<'_>> for Error {
 	fn from(err: crate::parser::ParseError<'_>) -> Self {
 		Self::ParseError(err.to_string())
@@ -1,9 +1,9 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::collections::HashSet;
-use std::fmt::{self, Debug, Formatter};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::mem::MaybeUninit;
 use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
 
 use crate::value::{Value, ValueAlign};
 
@@ -13,17 +13,64 @@ use crate::value::{Value, ValueAlign};
 /// [`ALLOC_VALUE_SIZE`] bytes, which means they can easily be mass-allocated.
 ///
 /// All allocated values are allocated via [`Gc::alloc_value_inner`].
+///
+/// # Generations
+/// Newly allocated values start out in the "nursery," which is collected whenever it has
+/// [`GcOptions::nursery_size`] values in it. Values which survive a collection are moved into the
+/// "old generation," which is only collected once it's doubled in size since its last collection.
+///
+/// Since values can't be modified once they're created, old values can never reference values in
+/// the nursery. This means nursery collections only need to trace from the roots, and can stop as
+/// soon as they reach an old value.
+///
+/// Old values keep [`FLAG_GC_MARKED`] set between collections, which is how they're told apart
+/// from values in the nursery when marking.
+///
+/// # Incremental Marking
+/// Collecting the old generation can take a while, so its marking is done in steps of (at most)
+/// [`GcOptions::pause_budget`] values, one step per allocation. Anything allocated while marking is
+/// marked immediately, as it can only reference values which were reachable when marking started.
+///
+/// # Roots
+/// Values are kept alive by [`GcRoot`]s, and by the functions registered with [`Gc::add_mark_fn`]
+/// (such as the one each [`Vm`](crate::vm::Vm) registers while it's running). Collections only
+/// happen during allocations when there's at least one mark fn, as there's no way to know which
+/// values are in use otherwise.
+///
+/// # Memory Limits
+/// The amount of memory used by values (including the buffers of strings and lists) can be limited
+/// via [`GcOptions::max_heap_bytes`]. Every allocation checks it, and operations which can create
+/// arbitrarily large values also check with [`Gc::reserve`] before building them. Both return
+/// [`Error::OutOfMemory`](crate::Error) if there isn't enough room, even after collecting garbage.
+///
+/// # Stress Testing
+/// When [`GcOptions::stress`] is set, a full collection is done on every allocation, so values which
+/// aren't rooted properly are freed as soon as possible. Freed values are also "poisoned:" They're
+/// never reused, and their contents are overwritten, so that using them afterwards panics (in debug
+/// builds) instead of quietly reading whatever was allocated in their place.
 #[must_use = "dropping `Gc` will leak all its memory"]
 pub struct Gc(RefCell<Inner>);
 
 struct Inner {
-	value_inners: Vec<*mut ValueInner>,
-	idx: usize,
-	roots: HashSet<*const ValueInner>,
+	nursery: Vec<*mut ValueInner>,
+	old: Vec<*mut ValueInner>,
+	free: Vec<*mut ValueInner>,
+	poisoned: Vec<*mut ValueInner>,
+	tracer: Tracer,
+	marking: bool,
+	major_threshold: usize,
+	heap_bytes: usize,
+	opts: GcOptions,
+	roots: HashMap<*const ValueInner, usize>,
 	paused: bool,
-	mark_fns: HashMap<usize, Box<dyn Fn()>>,
+	mark_fns: HashMap<usize, MarkFn>,
+	next_mark_fn: usize,
+	stats: GcStats,
 }
 
+// A function which marks values that're only reachable from outside the gc.
+type MarkFn = Box<dyn Fn(&mut Tracer)>;
+
 pub const ALLOC_VALUE_SIZE: usize = 32;
 
 #[repr(C)]
@@ -36,7 +83,12 @@ pub struct ValueInner {
 	data: [MaybeUninit<u8>; ALLOC_VALUE_SIZE - std::mem::size_of::<AtomicU8>()],
 }
 
+/// The byte that the contents of poisoned values are overwritten with.
+const POISON_BYTE: u8 = 0xDB;
+
 /// Indicates a value has been marked active during a mark-and-sweep.
+///
+/// Values which survive a collection stay marked, as that's how the old generation is tracked.
 const FLAG_GC_MARKED: u8 = 1 << 0;
 
 /// Indicates a value is static, and shouldn't be a part of the GC cycle.
@@ -64,22 +116,97 @@ pub const FLAG_CUSTOM_2: u8 = 1 << 6;
 /// An unused flag that types can use for their own purposes.
 pub const FLAG_CUSTOM_3: u8 = 1 << 7;
 
-const EMPTY_INNER: ValueInner = ValueInner {
-	_align: ValueAlign,
-	flags: AtomicU8::new(0),
-	data: [MaybeUninit::uninit(); ALLOC_VALUE_SIZE - std::mem::size_of::<AtomicU8>()],
-};
+// Allocates an unused `ValueInner`.
+fn alloc_empty_inner() -> *mut ValueInner {
+	Box::into_raw(Box::new(ValueInner {
+		_align: ValueAlign,
+		flags: AtomicU8::new(0),
+		data: [MaybeUninit::uninit(); ALLOC_VALUE_SIZE - std::mem::size_of::<AtomicU8>()],
+	}))
+}
 
 /// The options to give to [`Gc::new`]. More coming!
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct GcOptions {
 	pub starting_cap: usize, // TODO
+
+	/// How many values can be allocated before the nursery is collected.
+	pub nursery_size: usize,
+
+	/// The most values which are traced during each step of incremental marking. Smaller budgets
+	/// mean shorter pauses, but more of them.
+	pub pause_budget: usize,
+
+	/// Whether to collect everything on every allocation, and poison values once they're freed. This
+	/// is really slow, and is only meant for finding values which aren't rooted correctly.
+	pub stress: bool,
+
+	/// The most bytes that values, and the buffers of strings and lists, can use at once. `None`
+	/// means there's no limit.
+	pub max_heap_bytes: Option<usize>,
 }
 
 impl Default for GcOptions {
 	fn default() -> Self {
-		Self { starting_cap: 1000 }
+		Self {
+			starting_cap: 1000,
+			nursery_size: 1000,
+			pause_budget: 1000,
+			stress: false,
+			max_heap_bytes: None,
+		}
+	}
+}
+
+/// Statistics about what a [`Gc`] has done, as returned by [`Gc::stats`].
+#[derive(Debug, Default, Clone, Copy)]
+#[non_exhaustive]
+pub struct GcStats {
+	/// How many [`KnString`](crate::value::KnString)s have been allocated.
+	pub strings_allocated: usize,
+
+	/// How many [`List`](crate::value::List)s have been allocated.
+	pub lists_allocated: usize,
+
+	/// How many custom types have been allocated.
+	pub customs_allocated: usize,
+
+	/// How many values are currently allocated. This includes values which aren't in use anymore,
+	/// but haven't been collected yet.
+	pub live_values: usize,
+
+	/// How many bytes the currently allocated values use, including the buffers of strings and lists
+	/// which are too large to be embedded.
+	pub live_bytes: usize,
+
+	/// How many times just the nursery has been collected.
+	pub minor_collections: usize,
+
+	/// How many times both the nursery and the old generation have been collected.
+	pub major_collections: usize,
+
+	/// How long has been spent collecting garbage in total.
+	pub total_pause: Duration,
+
+	/// The longest that collecting garbage has paused the program for at once.
+	pub max_pause: Duration,
+}
+
+impl Display for GcStats {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		writeln!(
+			f,
+			"allocations: {} strings, {} lists, {} custom",
+			self.strings_allocated, self.lists_allocated, self.customs_allocated
+		)?;
+		writeln!(f, "live: {} values, {} bytes", self.live_values, self.live_bytes)?;
+		writeln!(
+			f,
+			"collections: {} minor, {} major",
+			self.minor_collections, self.major_collections
+		)?;
+		writeln!(f, "pauses: {:?} total, {:?} max", self.total_pause, self.max_pause)
 	}
 }
 
@@ -94,19 +221,29 @@ impl Gc {
 	pub fn new(opts: GcOptions) -> Self {
 		Self(
 			Inner {
-				value_inners: (0..opts.starting_cap)
-					.map(|_| Box::into_raw(Box::new(EMPTY_INNER)))
-					.collect(),
-				roots: HashSet::new(),
-				idx: 0,
+				nursery: Vec::new(),
+				old: Vec::new(),
+				free: (0..opts.starting_cap).map(|_| alloc_empty_inner()).collect(),
+				poisoned: Vec::new(),
+				tracer: Tracer(Vec::new()),
+				marking: false,
+				major_threshold: opts.starting_cap,
+				heap_bytes: 0,
+				opts,
+				roots: HashMap::new(),
 				paused: false,
 				mark_fns: HashMap::new(),
+				next_mark_fn: 0,
+				stats: GcStats::default(),
 			}
 			.into(),
 		)
 	}
 
-	// SAFETY: caller has to ensure that nothing allocated by `gc` escapes.
+	/// Runs `func` with the gc, and then shuts it down.
+	///
+	/// # Safety
+	/// Callers must ensure that nothing allocated by the gc escapes `func`.
 	pub unsafe fn run<T>(self, func: impl FnOnce(&Self) -> T) -> T {
 		let result = func(&self);
 		unsafe {
@@ -115,15 +252,75 @@ impl Gc {
 		result
 	}
 
+	/// Gets statistics about what `self` has done so far.
+	pub fn stats(&self) -> GcStats {
+		let inner = self.0.borrow();
+		let mut stats = inner.stats;
+		stats.live_values = inner.nursery.len() + inner.old.len();
+		stats.live_bytes = inner.heap_bytes;
+		stats
+	}
+
+	/// Ensures that `bytes` more bytes can be allocated without going over
+	/// [`GcOptions::max_heap_bytes`], collecting garbage first if they can't be.
+	///
+	/// This should be called before building the buffers of values which can be arbitrarily large.
+	/// Like allocating, it may collect garbage, so every value that's still in use must be rooted.
+	pub fn reserve(&self, bytes: usize) -> crate::Result<()> {
+		let inner = self.0.borrow();
+		let Some(max) = inner.opts.max_heap_bytes else {
+			return Ok(());
+		};
+
+		if inner.heap_bytes.saturating_add(bytes) <= max {
+			return Ok(());
+		}
+
+		let can_collect = !inner.paused && !inner.mark_fns.is_empty();
+		drop(inner);
+
+		if can_collect {
+			// SAFETY: Mark fns are registered, so everything in use is reachable (as with allocations).
+			unsafe {
+				self.mark_and_sweep();
+			}
+		}
+
+		if self.0.borrow().heap_bytes.saturating_add(bytes) <= max {
+			Ok(())
+		} else {
+			Err(crate::Error::OutOfMemory(max))
+		}
+	}
+
+	// Records that a value which was just allocated owns a buffer of `bytes` bytes.
+	pub(crate) fn add_buffer_bytes(&self, bytes: usize) {
+		self.0.borrow_mut().heap_bytes += bytes;
+	}
+
+	// Gets every value which is currently allocated.
+	pub(crate) fn allocated(&self) -> Vec<*const ValueInner> {
+		let inner = self.0.borrow();
+		inner.nursery.iter().chain(&inner.old).map(|&value_inner| value_inner.cast_const()).collect()
+	}
+
+	// Gets every value which is rooted by a `GcRoot`.
+	pub(crate) fn rooted(&self) -> Vec<*const ValueInner> {
+		self.0.borrow().roots.keys().copied().collect()
+	}
+
 	pub fn del_mark_fn(&self, index: usize) {
 		let _ = self.0.borrow_mut().mark_fns.remove(&index).expect("mark fn already removed");
 	}
 
-	pub fn add_mark_fn(&self, func: impl Fn() + 'static) -> usize {
+	/// Registers `func`, which marks values that are in use whenever there's a collection. The index
+	/// it returns is used to remove it via [`Gc::del_mark_fn`].
+	pub fn add_mark_fn(&self, func: impl Fn(&mut Tracer) + 'static) -> usize {
 		let mut inner = self.0.borrow_mut();
-		let len = inner.mark_fns.len();
-		inner.mark_fns.insert(len, Box::new(func));
-		len
+		let index = inner.next_mark_fn;
+		inner.next_mark_fn += 1;
+		inner.mark_fns.insert(index, Box::new(func));
+		index
 	}
 
 	/// Shuts down the [`Gc`] by cleaning up all memory associated with it.
@@ -133,7 +330,9 @@ impl Gc {
 	/// calling this function.
 	unsafe fn shutdown(self) {
 		// TODO: this borrow isnt sound
-		for &inner in &self.0.borrow().value_inners {
+		let inner = self.0.borrow();
+		let all = inner.nursery.iter().chain(&inner.old).chain(&inner.free).chain(&inner.poisoned);
+		for &inner in all {
 			unsafe {
 				ValueInner::deallocate(inner, false);
 				drop(Box::from_raw(inner));
@@ -141,40 +340,36 @@ impl Gc {
 		}
 	}
 
-	fn next_open_inner_(&self) -> Option<*mut ValueInner> {
-		let mut inner = self.0.borrow_mut();
-		while inner.idx < inner.value_inners.len() {
-			let value_inner = inner.value_inners[inner.idx];
-			inner.idx += 1;
-			if unsafe { &*ValueInner::flags(value_inner) }.load(Ordering::SeqCst) == 0 {
-				return Some(value_inner);
-			}
-		}
-		return None;
-	}
-
+	// Gets an unused `ValueInner` and adds it to the nursery, collecting garbage first if needed.
 	fn next_open_inner(&self) -> *mut ValueInner {
-		if let Some(inner) = self.next_open_inner_() {
-			return inner;
-		}
+		let (can_collect, stress) = {
+			let inner = self.0.borrow();
+			(!inner.paused && !inner.mark_fns.is_empty(), inner.opts.stress)
+		};
 
-		// TODO
-		if !self.0.borrow().paused && false {
+		if can_collect {
 			unsafe {
-				self.mark_and_sweep();
+				if stress {
+					self.mark_and_sweep();
+				} else {
+					self.collect_incrementally();
+				}
 			}
-			self.0.borrow_mut().idx = 0;
 		}
 
-		// extend the length
-		let len = self.0.borrow().value_inners.len();
-		self
-			.0
-			.borrow_mut()
-			.value_inners
-			.extend((0..=len).map(|_| Box::into_raw(Box::new(EMPTY_INNER))));
+		let mut inner = self.0.borrow_mut();
+		let value_inner = match inner.free.pop() {
+			Some(value_inner) => value_inner,
+			None => {
+				// extend the length
+				let len = inner.nursery.len() + inner.old.len();
+				inner.free.extend((0..=len).map(|_| alloc_empty_inner()));
+				inner.free.pop().expect("we just extended")
+			}
+		};
 
-		self.next_open_inner_().expect("we just extended")
+		inner.nursery.push(value_inner);
+		value_inner
 	}
 
 	pub fn pause(&self) {
@@ -189,66 +384,53 @@ impl Gc {
 		inner.paused = false;
 	}
 
-	// pub unsafe fn alloc_value_inner3(&mut self, flags: u8) -> *mut ValueInner2<[u8; 10000]> {
-	// 	unsafe { self.alloc_value_inner2::<[u8; 10000]>(flags) }
-	// }
-
-	// pub unsafe fn alloc_value_inner2<T>(&mut self, flags: u8) -> *mut ValueInner2<T> {
-	// 	const {
-	// 		let size = std::mem::size_of::<ValueInner2<T>>();
-	// 		assert!(size <= ALLOC_VALUE_SIZE);
-	// 		assert!(size <= ALLOC_VALUE_SIZE);
-	// 	}
-	// 	// };
-
-	// 	// fn size<const N: usize>(_: [(); N]) {}
-	// 	// size([(); std::mem::size_of::<ValueInner2<T>>()]);
-	// 	// // const SIZE: usize = {
-	// 	// // 	let size = ;
-	// 	// // 	assert!(size <= ALLOC_VALUE_SIZE);
-	// 	// // };
-
-	// 	std::ptr::null_mut()
-	// 	// debug_assert_eq!(flags & FLAG_GC_MARKED, 0, "cannot already be marked");
-	// }
-
 	/// Allocate another [`ValueInner`], possibly triggering a GC cycle if needed.
 	///
+	/// Returns [`Error::OutOfMemory`](crate::Error::OutOfMemory) if it'd go over
+	/// [`GcOptions::max_heap_bytes`] (see [`Gc::reserve`]).
+	///
 	/// `flags` should contain the flags for the [`ValueInner`], and must:
 	/// - Not contain [`FLAG_GC_MARKED`]
 	/// - Contain [`FLAG_IS_CUSTOM`] or contain exactly one of [`FLAG_IS_STRING`] or [`FLAG_IS_LIST`].
 	///
 	/// # Safety
 	/// Callers must ensure the above conditions are satisfied.
-	pub unsafe fn alloc_value_inner(&self, flags: u8) -> *mut ValueInner {
+	pub unsafe fn alloc_value_inner(&self, flags: u8) -> crate::Result<*mut ValueInner> {
 		debug_assert_eq!(flags & FLAG_GC_MARKED, 0, "cannot already be marked");
 
 		#[cfg(debug_assertions)]
 		{
 			let ty = flags & (FLAG_IS_STRING | FLAG_IS_LIST | FLAG_IS_CUSTOM);
-			// If we have all of them set, or
-			if ty == FLAG_IS_STRING || ty == (FLAG_IS_STRING | FLAG_IS_CUSTOM) {
-			} else if ty == FLAG_IS_LIST || ty == (FLAG_IS_LIST | FLAG_IS_CUSTOM) {
-			} else if ty == FLAG_IS_CUSTOM {
-			} else {
-				unreachable!("type passed in wasn't correct: {flags:08b}");
-			}
+			// (`FLAG_IS_CUSTOM` is shared with `FLAG_CUSTOM_0`, so strings and lists may set it too.)
+			let is_valid = ty & !FLAG_IS_CUSTOM == FLAG_IS_STRING
+				|| ty & !FLAG_IS_CUSTOM == FLAG_IS_LIST
+				|| ty == FLAG_IS_CUSTOM;
+			assert!(is_valid, "type passed in wasn't correct: {flags:08b}");
 		}
 
-		#[cfg(debug_assertions)] // always sweep after every allocation when testing
-		if !self.0.borrow().paused && false {
-			unsafe {
-				self.mark_and_sweep();
-			}
+		self.reserve(ALLOC_VALUE_SIZE)?;
+		let inner = self.next_open_inner();
+
+		let mut gc = self.0.borrow_mut();
+		gc.heap_bytes += ALLOC_VALUE_SIZE;
+		if flags & FLAG_IS_STRING != 0 {
+			gc.stats.strings_allocated += 1;
+		} else if flags & FLAG_IS_LIST != 0 {
+			gc.stats.lists_allocated += 1;
+		} else {
+			gc.stats.customs_allocated += 1;
 		}
 
-		let inner = self.next_open_inner();
+		// Values allocated while marking can only reference values that were reachable when marking
+		// started, so they're marked straight away instead of being traced.
+		let flags = if gc.marking { flags | FLAG_GC_MARKED } else { flags };
+		drop(gc);
 
 		unsafe {
 			(&raw mut (*inner).flags).write(AtomicU8::new(flags));
 		}
 
-		inner
+		Ok(inner)
 	}
 
 	// /// Indicates that `root` is a "root node."
@@ -261,33 +443,193 @@ impl Gc {
 	// 	}
 	// }
 
-	// pub only for testing
+	/// Does a full collection of both the nursery and old generation, finishing any incremental
+	/// collection which is in progress.
+	///
+	/// # Safety
+	/// Every value which is still in use must be reachable from a root or a mark fn.
 	pub unsafe fn mark_and_sweep(&self) {
-		for mark_fn in self.0.borrow().mark_fns.values() {
-			mark_fn()
+		self.record_pause(|| unsafe {
+			if !self.0.borrow().marking {
+				self.start_marking();
+			}
+
+			self.mark_step(usize::MAX);
+			self.finish_marking();
+		});
+	}
+
+	// Runs `collect`, and records how long it paused the program for.
+	fn record_pause(&self, collect: impl FnOnce()) {
+		let start = Instant::now();
+		collect();
+		let pause = start.elapsed();
+
+		let stats = &mut self.0.borrow_mut().stats;
+		stats.total_pause += pause;
+		stats.max_pause = stats.max_pause.max(pause);
+	}
+
+	// Does a bit of garbage collection, if it's needed: Either a step of an in-progress incremental
+	// collection, starting collecting the old generation, or collecting the nursery.
+	unsafe fn collect_incrementally(&self) {
+		let inner = self.0.borrow();
+		let (marking, pause_budget) = (inner.marking, inner.opts.pause_budget);
+		let major = inner.major_threshold <= inner.old.len();
+		let minor = inner.opts.nursery_size <= inner.nursery.len();
+		drop(inner);
+
+		if marking || major || minor {
+			self.record_pause(|| unsafe {
+				if marking {
+					if self.mark_step(pause_budget) {
+						self.finish_marking();
+					}
+				} else if major {
+					self.start_marking();
+				} else {
+					self.collect_nursery();
+				}
+			});
+		}
+	}
+
+	// Marks every root, which adds them to the tracer.
+	unsafe fn mark_roots(&self) {
+		let inner = &mut *self.0.borrow_mut();
+
+		for mark_fn in inner.mark_fns.values() {
+			mark_fn(&mut inner.tracer);
 		}
 
 		// Mark all elements accessible from the root
-		for &root in &self.0.borrow().roots {
+		for &root in inner.roots.keys() {
+			unsafe {
+				inner.tracer.mark(root);
+			}
+		}
+	}
+
+	// Traces through the marked values until `budget` values have been traced, returning whether
+	// everything reachable has been marked.
+	unsafe fn mark_step(&self, budget: usize) -> bool {
+		let tracer = &mut self.0.borrow_mut().tracer;
+		let mut traced = 0;
+
+		while traced < budget {
+			let Some(inner) = tracer.0.pop() else {
+				return true;
+			};
+
+			// Only lists are ever added to the tracer, as they're the only ones with children.
+			let list = unsafe { ValueInner::as_list(inner) }.expect("only lists are traced");
+			traced += 1 + list.len();
 			unsafe {
-				ValueInner::mark(root);
+				list.mark(tracer);
+			}
+		}
+
+		tracer.0.is_empty()
+	}
+
+	// Collects just the nursery. Since old values are already marked, marking stops at them.
+	unsafe fn collect_nursery(&self) {
+		unsafe {
+			self.mark_roots();
+			self.mark_step(usize::MAX);
+		}
+
+		let inner = &mut *self.0.borrow_mut();
+		let mut nursery = std::mem::take(&mut inner.nursery);
+		for value_inner in nursery.drain(..) {
+			if unsafe { ValueInner::sweep(value_inner) } {
+				inner.old.push(value_inner);
+			} else {
+				unsafe {
+					inner.release(value_inner);
+				}
 			}
 		}
 
-		// Sweep everything that's not needed
-		for &inner in &self.0.borrow().value_inners {
-			let old =
-				unsafe { &*ValueInner::flags(inner) }.fetch_and(!FLAG_GC_MARKED, Ordering::SeqCst);
+		inner.nursery = nursery; // reuse its allocation
+		inner.stats.minor_collections += 1;
+	}
+
+	// Starts an incremental collection of both generations.
+	unsafe fn start_marking(&self) {
+		let mut inner = self.0.borrow_mut();
+		debug_assert!(!inner.marking, "already marking");
+		debug_assert!(inner.tracer.0.is_empty(), "tracer wasn't emptied");
+		inner.marking = true;
+
+		for &value_inner in &inner.old {
+			unsafe { &*ValueInner::flags(value_inner) }.fetch_and(!FLAG_GC_MARKED, Ordering::SeqCst);
+		}
+		drop(inner);
 
-			debug_assert_eq!(old & FLAG_GC_STATIC, 0, "attempted to sweep a static flag?");
+		unsafe {
+			self.mark_roots();
+		}
+	}
 
-			// If it wasn't previously marked, then free it.
-			if old & FLAG_GC_MARKED == 0 {
+	// Sweeps both generations once everything's been marked. All the survivors are now old.
+	unsafe fn finish_marking(&self) {
+		let inner = &mut *self.0.borrow_mut();
+		debug_assert!(inner.tracer.0.is_empty(), "finished marking before tracing everything");
+		inner.marking = false;
+
+		let mut old = Vec::with_capacity(inner.old.len() + inner.nursery.len());
+		let mut nursery = std::mem::take(&mut inner.nursery);
+		for value_inner in std::mem::take(&mut inner.old).into_iter().chain(nursery.drain(..)) {
+			if unsafe { ValueInner::sweep(value_inner) } {
+				old.push(value_inner);
+			} else {
 				unsafe {
-					ValueInner::deallocate(inner, false);
+					inner.release(value_inner);
 				}
 			}
 		}
+
+		inner.old = old;
+		inner.nursery = nursery;
+		inner.major_threshold = (inner.old.len() * 2).max(inner.opts.starting_cap);
+		inner.stats.major_collections += 1;
+	}
+}
+
+impl Inner {
+	// Frees `value_inner`, and makes it available to be allocated again unless it should be poisoned.
+	unsafe fn release(&mut self, value_inner: *mut ValueInner) {
+		unsafe {
+			self.heap_bytes -= ValueInner::size(value_inner);
+			ValueInner::deallocate(value_inner, false);
+		}
+
+		if !self.opts.stress {
+			self.free.push(value_inner);
+			return;
+		}
+
+		// SAFETY: `value_inner` was just freed, so nothing else can be using its data.
+		unsafe {
+			(&raw mut (*value_inner).data)
+				.cast::<u8>()
+				.write_bytes(POISON_BYTE, size_of_val(&(*value_inner).data));
+		}
+		self.poisoned.push(value_inner);
+	}
+}
+
+/// Keeps track of values which have been marked during a collection, but whose children haven't
+/// been yet.
+pub struct Tracer(Vec<*const ValueInner>);
+
+impl Tracer {
+	// Marks `inner`, and adds it to the list of values to trace if it has children.
+	pub(crate) unsafe fn mark(&mut self, inner: *const ValueInner) {
+		if unsafe { ValueInner::mark(inner) } {
+			self.0.push(inner);
+		}
 	}
 }
 
@@ -303,13 +645,13 @@ pub unsafe trait GarbageCollected {
 	/// Marks all the values reachable from `self` as "active."
 	///
 	/// Note that this is called after `self` has been marked itself, so only children reachable from
-	/// `self` need to be marked.
+	/// `self` need to be marked. Their own children are then marked later on by `tracer`.
 	///
 	/// # Safety
 	/// This must only be called within a [`GarbageCollected::mark`] implementation. Calling it
 	/// randomly will cause random things to be marked, which possibily will leak memory. (Which
 	/// technically isn't undefined behaviour, but i've still marked it unsafe.)
-	unsafe fn mark(&self);
+	unsafe fn mark(&self, tracer: &mut Tracer);
 
 	/// Frees all the memory related to `self`, **but not** memory related to [`GarbageCollected`]
 	/// types `self` can access. (This is because they'll eventually have _thier_ `deallocate` called
@@ -327,8 +669,15 @@ impl ValueInner {
 		unsafe { &raw const (*this).flags }
 	}
 
+	// Gets the flags of `this`, which mustn't have been freed.
+	unsafe fn live_flags(this: *const Self) -> u8 {
+		let flags = unsafe { &*Self::flags(this) }.load(Ordering::SeqCst);
+		debug_assert_ne!(flags, 0, "used a value after it was freed");
+		flags
+	}
+
 	pub(crate) unsafe fn as_knstring<'gc>(this: *const Self) -> Option<crate::value::KnString<'gc>> {
-		if unsafe { &*Self::flags(this) }.load(Ordering::SeqCst) & FLAG_IS_STRING != 0 {
+		if unsafe { Self::live_flags(this) } & FLAG_IS_STRING != 0 {
 			Some(unsafe { crate::value::KnString::from_raw(this) })
 		} else {
 			None
@@ -336,36 +685,74 @@ impl ValueInner {
 	}
 
 	pub(crate) unsafe fn as_list<'gc>(this: *const Self) -> Option<crate::value::List<'gc>> {
-		if unsafe { &*Self::flags(this) }.load(Ordering::SeqCst) & FLAG_IS_LIST != 0 {
+		if unsafe { Self::live_flags(this) } & FLAG_IS_LIST != 0 {
 			Some(unsafe { crate::value::List::from_raw(this) })
 		} else {
 			None
 		}
 	}
 
-	pub(crate) unsafe fn mark(this: *const Self) {
+	// The name of the type `this` contains.
+	pub(crate) unsafe fn type_name(this: *const Self) -> &'static str {
+		let flags = unsafe { Self::live_flags(this) };
+
+		if flags & FLAG_IS_STRING != 0 {
+			"String"
+		} else if flags & FLAG_IS_LIST != 0 {
+			"List"
+		} else {
+			"Custom"
+		}
+	}
+
+	// How many bytes `this` uses, including the buffer it owns.
+	pub(crate) unsafe fn size(this: *const Self) -> usize {
+		let buffer_size = if let Some(string) = unsafe { Self::as_knstring(this) } {
+			string.buffer_size()
+		} else if let Some(list) = unsafe { Self::as_list(this) } {
+			list.buffer_size()
+		} else {
+			0
+		};
+
+		ALLOC_VALUE_SIZE + buffer_size
+	}
+
+	// Marks `this`, returning whether its children need to be traced.
+	pub(crate) unsafe fn mark(this: *const Self) -> bool {
 		let flags = unsafe { &*Self::flags(this) }.fetch_or(FLAG_GC_MARKED, Ordering::SeqCst);
+		debug_assert_ne!(flags, 0, "marked a value after it was freed");
 
 		// Don't mark static things
 		if flags & FLAG_GC_STATIC != 0 {
-			return;
+			return false;
 		}
 
-		// If it was already marked, it's a loop, don't go again
+		// If it was already marked, then either it's old or we've already traced it.
 		if flags & FLAG_GC_MARKED != 0 {
-			dbg!("can we even loop?");
-			return;
+			return false;
 		}
 
-		if let Some(list) = unsafe { Self::as_list(this) } {
-			unsafe {
-				list.mark();
-			}
-		}
+		flags & FLAG_IS_LIST != 0
+	}
+
+	// Returns whether `this` was marked during a collection, and so is still in use. (If it isn't,
+	// then it needs to be freed.)
+	unsafe fn sweep(this: *const Self) -> bool {
+		let old = unsafe { &*Self::flags(this) }.load(Ordering::SeqCst);
+		debug_assert_eq!(old & FLAG_GC_STATIC, 0, "attempted to sweep a static flag?");
+
+		old & FLAG_GC_MARKED != 0
 	}
 
 	pub(crate) unsafe fn deallocate(this: *const Self, check: bool) {
-		debug_assert_eq!(unsafe { &*Self::flags(this) }.load(Ordering::SeqCst) & FLAG_GC_STATIC, 0);
+		let flags = unsafe { &*Self::flags(this) }.load(Ordering::SeqCst);
+		debug_assert_eq!(flags & FLAG_GC_STATIC, 0);
+
+		// It's already been freed (or was never used to begin with).
+		if flags == 0 && !check {
+			return;
+		}
 
 		if let Some(string) = unsafe { Self::as_knstring(this) } {
 			unsafe {
@@ -384,8 +771,18 @@ impl ValueInner {
 	}
 }
 
+/// Types which are wrappers around a pointer to a [`ValueInner`].
+///
+/// # Safety
+/// `as_value_inner` must return a pointer to a [`ValueInner`] that's allocated by the gc, and
+/// `from_value_inner` must be its inverse.
 pub unsafe trait AsValueInner {
 	fn as_value_inner(&self) -> *const ValueInner;
+
+	/// Converts `inner` back into `Self`.
+	///
+	/// # Safety
+	/// `inner` must have come from [`AsValueInner::as_value_inner`] on the same type.
 	unsafe fn from_value_inner(inner: *const ValueInner) -> Self;
 }
 
@@ -401,7 +798,7 @@ impl<'gc, T: AsValueInner> GcRoot<'gc, T> {
 	// safety: that from_value_inner seems like it could be unsafe potentially lol
 	pub fn new(t: &T, gc: &'gc Gc) -> Self {
 		let inner = t.as_value_inner();
-		gc.0.borrow_mut().roots.insert(inner);
+		*gc.0.borrow_mut().roots.entry(inner).or_insert(0) += 1;
 
 		Self(unsafe { T::from_value_inner(inner) }, Some(gc))
 	}
@@ -411,12 +808,18 @@ impl<'gc, T: AsValueInner> GcRoot<'gc, T> {
 		Self(t, None)
 	}
 
-	// SAFETY: caller must ensure that it wno't be GC'd until it's a part of the root node list
+	/// Gets the rooted value, without keeping it rooted.
+	///
+	/// # Safety
+	/// Callers must ensure that it won't be collected until it's reachable from some other root.
 	pub unsafe fn assume_used(&self) -> T {
 		unsafe { T::from_value_inner(self.0.as_value_inner()) }
 	}
 
-	// SAFETY: The return value needs to now reference `self`
+	/// Calls `func` with the rooted value, unrooting it afterwards.
+	///
+	/// # Safety
+	/// The return value of `func` mustn't reference the value, as it's no longer rooted.
 	pub unsafe fn with_inner<R>(mut self, func: impl FnOnce(T) -> R) -> R {
 		let inner = unsafe { std::ptr::read(&self.0) };
 		let result = func(inner);
@@ -438,8 +841,13 @@ impl<'gc, T: AsValueInner> GcRoot<'gc, T> {
 			let mut gc_inner = gc.0.borrow_mut();
 			let inner = self.0.as_value_inner();
 
-			if !gc_inner.roots.remove(&inner) {
+			let Some(count) = gc_inner.roots.get_mut(&inner) else {
 				unreachable!("unroot of a non-rooted inner? inner={inner:?}, gc={:?}", &gc_inner.roots);
+			};
+
+			*count -= 1;
+			if *count == 0 {
+				gc_inner.roots.remove(&inner);
 			}
 		}
 	}
@@ -1,4 +1,6 @@
 #![allow(unused)]
+mod bundle;
+mod cache;
 mod cli;
 
 use std::default;
@@ -16,25 +18,228 @@ use knightrs_bytecode::vm::*;
 use knightrs_bytecode::Options;
 use source_location::ProgramSource;
 
-fn run(
-	env: &mut Environment<'_>,
-	source: ProgramSource<'_>,
-	program: &str,
-	argv: impl Iterator<Item = String>,
-) -> Result<(), String> {
+fn compile<'src, 'path, 'gc>(
+	env: &mut Environment<'gc>,
+	source: ProgramSource<'path>,
+	program: &'src str,
+) -> Result<Program<'src, 'path, 'gc>, String> {
 	let gc = env.gc();
-	let mut parser = Parser::new(env, source, &program).map_err(|s| s.to_string())?;
+	let mut parser = Parser::new(env, source, program).map_err(|s| s.to_string())?;
 
 	gc.pause();
-	let program = parser.parse_program().map_err(|err| err.to_string())?;
+	let program = parser.parse_program().map_err(|err| err.to_string());
+	gc.unpause();
 
 	// dbg!(&program);
+	program
+}
 
-	let mut vm = Vm::new(&program, env);
-	// gc.add_mark_fn(|| vm.mark());
+fn load<'path, 'gc>(
+	env: &Environment<'gc>,
+	source: ProgramSource<'path>,
+	bytecode: &[u8],
+) -> Result<Program<'static, 'path, 'gc>, String> {
+	let gc = env.gc();
+
+	gc.pause();
+	// SAFETY: The gc is paused.
+	let program = unsafe { Program::deserialize(bytecode, source, env) }.map_err(|e| e.to_string());
 	gc.unpause();
 
-	vm.run_entire_program(argv).map_err(|e| e.to_string()).and(Ok(()))
+	program
+}
+
+fn assemble<'src, 'path, 'gc>(
+	env: &Environment<'gc>,
+	source: ProgramSource<'path>,
+	assembly: &'src str,
+) -> Result<Program<'src, 'path, 'gc>, String> {
+	let gc = env.gc();
+
+	gc.pause();
+	// SAFETY: The gc is paused.
+	let program = unsafe { Program::assemble(assembly, source, env) }.map_err(|e| e.to_string());
+	gc.unpause();
+
+	program
+}
+
+fn run<'gc>(
+	env: &mut Environment<'gc>,
+	program: &Program<'_, '_, 'gc>,
+	argv: impl Iterator<Item = String>,
+	cliopts: Option<&CliOpts>,
+) -> Result<(), String> {
+	let mut vm = Vm::new(program, env);
+	vm.set_fuel(cliopts.and_then(CliOpts::fuel));
+	if let Some(timeout) = cliopts.and_then(CliOpts::timeout) {
+		vm.interrupt_handle().interrupt_after(timeout);
+	}
+	let result = match cliopts.and_then(CliOpts::resume) {
+		Some(path) => {
+			let checkpoint =
+				std::fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+
+			// SAFETY: It's up to the user to only resume checkpoints made by `--checkpoint`.
+			unsafe { vm.restore(&checkpoint) }.map_err(|err| format!("{}: {err}", path.display()))?;
+			if !vm.is_suspended() {
+				return Err(format!("{}: the program wasn't stopped", path.display()));
+			}
+			vm.resume()
+		}
+		None => vm.run_entire_program(argv),
+	};
+
+	if let Some(path) = cliopts.and_then(CliOpts::checkpoint) {
+		let stopped = matches!(
+			result,
+			Err(knightrs_bytecode::Error::OutOfFuel | knightrs_bytecode::Error::Interrupted)
+		);
+		if stopped && vm.is_suspended() {
+			let mut checkpoint = Vec::new();
+			vm.checkpoint(&mut checkpoint).map_err(|err| err.to_string())?;
+			std::fs::write(path, checkpoint).map_err(|err| format!("{}: {err}", path.display()))?;
+		}
+	}
+
+	if let Some(path) = cliopts.and_then(CliOpts::heap_snapshot) {
+		std::fs::write(path, vm.heap_snapshot().to_string())
+			.map_err(|err| format!("{}: {err}", path.display()))?;
+	}
+	drop(vm);
+
+	if cliopts.is_some_and(CliOpts::gc_stats) {
+		eprint!("{}", env.gc().stats());
+	}
+
+	match result {
+		// `QUIT` only returns when the gc's being reported on, so exit now that that's done.
+		#[cfg(feature = "embedded")]
+		Err(knightrs_bytecode::Error::Exit(status)) => std::process::exit(status),
+		result => result.map_err(|e| e.to_string()).and(Ok(())),
+	}
+}
+
+fn compile_and_run(
+	env: &mut Environment<'_>,
+	cliopts: &CliOpts,
+	source: ProgramSource<'_>,
+	contents: Vec<u8>,
+) -> Result<(), String> {
+	let text;
+	let program = if contents.starts_with(&MAGIC) {
+		load(env, source, &contents)?
+	} else {
+		text = String::from_utf8(contents).map_err(|err| err.to_string())?;
+		if cliopts.asm() {
+			return finish(env, cliopts, assemble(env, source, &text)?);
+		}
+
+		let cache = cliopts.cache_dir().map(|dir| {
+			let key = cache::Key::new(&text, env.opts());
+			(key.path(dir), key)
+		});
+
+		// If the cached version is missing or out of date, just parse the program again.
+		let cached = cache.as_ref().and_then(|(path, key)| cache::load(path, key));
+		match cached.and_then(|bytecode| load(env, source, &bytecode).ok()) {
+			Some(program) => program,
+			None => {
+				let program = compile(env, source, &text)?;
+				if let Some((path, key)) = cache {
+					if let Err(err) = cache::store(&path, &key, &program) {
+						eprintln!("warning: unable to cache program at {}: {err}", path.display());
+					}
+				}
+				program
+			}
+		}
+	};
+
+	finish(env, cliopts, program)
+}
+
+// Does whatever the command-line options asked for with the loaded `program`.
+fn finish<'gc>(
+	env: &mut Environment<'gc>,
+	cliopts: &CliOpts,
+	program: Program<'_, '_, 'gc>,
+) -> Result<(), String> {
+	if cliopts.disassemble() {
+		print!("{}", program.disassemble());
+		return Ok(());
+	}
+
+	if cliopts.cfg() {
+		print!("{}", program.cfg_dot());
+		return Ok(());
+	}
+
+	if let Some(path) = cliopts.compile() {
+		let mut bytecode = Vec::new();
+		program.serialize(&mut bytecode).map_err(|err| err.to_string())?;
+		return std::fs::write(path, bytecode).map_err(|err| format!("{}: {err}", path.display()));
+	}
+
+	if let Some(path) = cliopts.bundle() {
+		return bundle::write(path, &program, env.opts())
+			.map_err(|err| format!("{}: {err}", path.display()));
+	}
+
+	if let Some(path) = cliopts.emit_c() {
+		let source = program.to_c(env.opts()).map_err(|err| err.to_string())?;
+		return std::fs::write(path, source.to_string())
+			.map_err(|err| format!("{}: {err}", path.display()));
+	}
+
+	#[cfg(feature = "wasm")]
+	if let Some(path) = cliopts.emit_wasm() {
+		let source = program.to_wat(env.opts()).map_err(|err| err.to_string())?;
+		let module = source.with_imports(cliopts.wasm_imports()).to_wasm();
+		return std::fs::write(path, module).map_err(|err| format!("{}: {err}", path.display()));
+	}
+
+	run(env, &program, cliopts.argv(), Some(cliopts))
+}
+
+// Runs the program that's been bundled into this executable, passing it our arguments.
+fn run_bundle(bundle: bundle::Bundle) -> Result<(), String> {
+	let argv = std::env::args().skip(1);
+
+	#[cfg(feature = "extensions")]
+	let takes_argv = bundle.options.extensions.argv;
+	#[cfg(not(feature = "extensions"))]
+	let takes_argv = false;
+
+	if argv.len() != 0 && !takes_argv {
+		return Err(
+			"arguments may not be supplied unless the program was bundled with --ext-argv".to_string(),
+		);
+	}
+
+	unsafe {
+		let gc = Gc::default();
+		gc.run(|gc| {
+			let mut env = Environment::new(bundle.options, &gc);
+			let program = load(&env, ProgramSource::Other("<bundle>"), &bundle.bytecode)?;
+			run(&mut env, &program, argv, None)
+		})
+	}
+}
+
+// Records or replays the environment's inputs, if the command-line options asked for it.
+fn setup_transcript(env: &mut Environment<'_>, cliopts: &CliOpts) -> Result<(), String> {
+	if let Some(path) = cliopts.record() {
+		let file = std::fs::File::create(path).map_err(|err| format!("{}: {err}", path.display()))?;
+		env.record(std::io::BufWriter::new(file));
+	}
+
+	if let Some(path) = cliopts.replay() {
+		let file = std::fs::File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+		env.replay(std::io::BufReader::new(file));
+	}
+
+	Ok(())
 }
 
 fn main1() {
@@ -48,7 +253,8 @@ fn main1() {
 			let greeting = v2::KnString::new_unvalidated(
 				"hello worldhello worldhello worldhello worldhello worldhello world".into(),
 				&gc,
-			);
+			)
+			.unwrap();
 
 			dbg!(greeting.to_list(&mut env));
 
@@ -77,10 +283,18 @@ fn main1() {
 }
 
 fn main() {
+	if let Some(bundle) = bundle::embedded() {
+		if let Err(err) = bundle.map_err(|err| err.to_string()).and_then(run_bundle) {
+			eprintln!("error: {err}");
+			std::process::exit(1);
+		}
+		return;
+	}
+
 	let cliopts = CliOpts::from_argv();
 
 	unsafe {
-		let gc = Gc::default();
+		let gc = Gc::new(cliopts.gc_options());
 		gc.run(|gc| {
 			let mut env = Environment::new(
 				cliopts.options().clone(), // TODO: remove this clone
@@ -127,11 +341,17 @@ fn main() {
 				&gc,
 			);
 
+			if let Err(err) = setup_transcript(&mut env, &cliopts) {
+				eprintln!("error: {err}");
+				std::process::exit(1);
+			}
+
 			// TODO: args
 			for maybe_oops in cliopts.source_iter() {
-				if let Err(err) = maybe_oops.map_err(|x| x.to_string()).and_then(|(program, source)| {
-					run(&mut env, source, &program, cliopts.argv()).map_err(|s| s.to_string())
-				}) {
+				if let Err(err) = maybe_oops
+					.map_err(|x| x.to_string())
+					.and_then(|(contents, source)| compile_and_run(&mut env, &cliopts, source, contents))
+				{
 					eprintln!("error: {err}");
 					std::process::exit(1);
 				}
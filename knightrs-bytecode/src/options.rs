@@ -4,6 +4,30 @@ use crate::strings::Encoding;
 pub struct Options {
 	pub encoding: Encoding,
 
+	pub optimizations: Optimizations,
+
+	/// Run programs with the experimental register-based vm instead of the stack-based one.
+	///
+	/// Programs which can't be [verified](crate::program::Program::verify) are always run with the
+	/// stack-based vm.
+	pub register_vm: bool,
+
+	/// The maximum amount of blocks which can be called at once, or `None` for no limit.
+	///
+	/// Calls don't use the Rust stack, so without a limit deeply recursive programs will only stop
+	/// once they run out of memory. Tail calls don't count towards the limit, but `EVAL` does count as
+	/// a call. Unlike other calls, nested `EVAL`s do use the Rust stack, so programs which nest them
+	/// deeply need a limit.
+	pub max_call_depth: Option<usize>,
+
+	/// Compile blocks which are called frequently to native code.
+	///
+	/// Only blocks which use integers and variables are compiled, and the compiled code falls back
+	/// to the stack-based vm whenever it runs into anything else. This is ignored when the
+	/// [register-based vm](Self::register_vm) is being used.
+	#[cfg(feature = "jit")]
+	pub jit: bool,
+
 	#[cfg(feature = "compliance")]
 	pub compliance: Compliance,
 
@@ -23,6 +47,23 @@ pub struct Options {
 	pub check_parens: bool, // TODO: also make this strict compliance
 }
 
+/// Optimizations which are done when compiling programs.
+///
+/// None of these change how programs behave, including which errors they raise.
+#[derive(Default, Clone)]
+pub struct Optimizations {
+	/// Evaluate functions whose arguments are all literals at compile-time, and remove branches and
+	/// statements which are never run or have no effect.
+	pub constant_folding: bool,
+
+	/// Rewrite wasteful sequences of instructions (such as pushing a constant and then immediately
+	/// popping it) after compiling.
+	pub peephole: bool,
+
+	/// Replace common sequences of instructions with single, fused instructions after compiling.
+	pub superinstructions: bool,
+}
+
 #[derive(Default, Clone)]
 #[cfg(feature = "debugger")]
 pub struct Debugger {
@@ -47,6 +47,12 @@ pub enum ParseErrorKind {
 	#[error("{0}")]
 	StringError(#[from] StringError),
 
+	/// A literal in the source would've used more memory than [`max_heap_bytes`] allows.
+	///
+	/// [`max_heap_bytes`]: crate::gc::GcOptions::max_heap_bytes
+	#[error("out of memory (at most {0} bytes may be used)")]
+	OutOfMemory(usize),
+
 	#[error("missing argument {1} for function {0:?}")]
 	MissingArgument(char, usize),
 
@@ -95,6 +101,17 @@ pub enum ParseErrorKind {
 	NotAHexChar(char),
 }
 
+// Creating literals can only fail if they're invalid strings, or there's no memory left for them.
+impl From<crate::Error> for ParseErrorKind {
+	fn from(err: crate::Error) -> Self {
+		match err {
+			crate::Error::StringError(err) => Self::StringError(err),
+			crate::Error::OutOfMemory(max) => Self::OutOfMemory(max),
+			other => bug!("unexpected error while creating a literal: {}", other),
+		}
+	}
+}
+
 impl ParseErrorKind {
 	// this tuple is a huge hack. maybe when i remove it i can also remove `'filename`
 	pub fn error<'path>(self, whence: SourceLocation<'path>) -> ParseError<'path> {
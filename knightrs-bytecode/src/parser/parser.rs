@@ -1,5 +1,6 @@
 mod ast;
 mod function;
+mod optimize;
 
 #[cfg(feature = "check-parens")]
 mod parens;
@@ -8,9 +9,10 @@ use super::VariableName;
 use crate::parser::{
 	source_location::ProgramSource, ParseError, ParseErrorKind, Parseable, SourceLocation,
 };
-use crate::program::{Compilable, Compiler, DeferredJump, JumpIndex, Program};
+use crate::program::{Compilable, Compiler, Program};
 use crate::Gc;
 use crate::{Environment, Options};
+use ast::{Ast, AstInner};
 use std::path::Path;
 
 pub struct Parser<'env, 'src, 'path, 'gc> {
@@ -19,9 +21,6 @@ pub struct Parser<'env, 'src, 'path, 'gc> {
 	source: &'src str, // can't use `KnStr` b/c it has a length limit.
 	compiler: Compiler<'src, 'path, 'gc>,
 	lineno: usize,
-
-	// Start is loop begin, vec is those to jump to loop end
-	loops: Vec<(JumpIndex, Vec<DeferredJump>)>,
 }
 
 #[cfg(feature = "compliance")]
@@ -57,7 +56,6 @@ impl<'env, 'src, 'path, 'gc> Parser<'env, 'src, 'path, 'gc> {
 			filename,
 			source,
 			lineno: 1,
-			loops: Vec::new(),
 		})
 	}
 
@@ -95,8 +93,6 @@ impl<'env, 'src, 'path, 'gc> Parser<'env, 'src, 'path, 'gc> {
 
 		if head == '\n' {
 			self.lineno += 1;
-			#[cfg(feature = "debugger")]
-			self.compiler.record_source_location(self.location());
 		}
 
 		self.source = chars.as_str();
@@ -187,7 +183,7 @@ impl<'env, 'src, 'path, 'gc> Parser<'env, 'src, 'path, 'gc> {
 	/// This will return an [`ErrorKind::TrailingTokens`] if [`forbid_trailing_tokens`](
 	/// crate::env::flags::Compliance::forbid_trailing_tokens) is set.
 	pub fn parse_program(mut self) -> Result<Program<'src, 'path, 'gc>, ParseError<'path>> {
-		self.parse_expression()?;
+		let mut ast = self.parse_expression()?;
 
 		// If we forbid any trailing tokens, then see if we could have parsed anything else.
 		#[cfg(feature = "compliance")]
@@ -197,40 +193,66 @@ impl<'env, 'src, 'path, 'gc> Parser<'env, 'src, 'path, 'gc> {
 			return Err(self.error(ParseErrorKind::TrailingTokens));
 		}
 
+		if self.env.opts().optimizations.constant_folding {
+			ast = ast.fold_constants(self.env);
+		}
+
+		ast.compile(&mut self.compiler, self.env.opts())?;
+
+		// Building only fails if there's no memory left for the program's constants.
+		let location = self.location();
+		let out_of_memory = |err: crate::Error| ParseErrorKind::from(err).error(location);
+
+		if self.env.opts().optimizations.peephole {
+			// SAFETY: all jumps have been resolved, as compiling is finished.
+			unsafe { self.compiler.peephole() }.map_err(out_of_memory)?;
+		}
+
+		if self.env.opts().optimizations.superinstructions {
+			// SAFETY: all jumps have been resolved, as compiling is finished.
+			unsafe { self.compiler.fuse_superinstructions() }.map_err(out_of_memory)?;
+		}
+
 		// SAFETY: this program ensures that things are built properly
-		Ok(unsafe { self.compiler.build() })
+		unsafe { self.compiler.build() }.map_err(out_of_memory)
 	}
 
 	/// Parses a single expression and returns it.
-	pub fn parse_expression(&mut self) -> Result<(), ParseError<'path>> {
+	pub fn parse_expression(&mut self) -> Result<Ast<'src, 'path, 'gc>, ParseError<'path>> {
 		self.strip_whitespace_and_comments();
+		let location = self.location();
+		let literal = |value| Ok(Ast::new(AstInner::Literal(value), location));
 
 		if let Some(x) = crate::value::Integer::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+			return literal(x.into());
 		}
 		if let Some(x) = crate::value::Boolean::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+			return literal(x.into());
 		}
 		if let Some(x) = crate::value::Null::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+			return literal(x.into());
 		}
+		// SAFETY (for both): the gc isn't collecting while we're parsing, and the value will be
+		// referenced by the program once it's compiled.
 		if let Some(x) = crate::value::List::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+			return literal(unsafe { x.with_inner(Into::into) });
 		}
 		if let Some(x) = crate::value::KnString::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+			return literal(unsafe { x.with_inner(Into::into) });
 		}
-		if let Some(x) = VariableName::parse(self)? {
-			return x.compile(&mut self.compiler, &self.env.opts());
+		if let Some((name, location)) = VariableName::parse(self)? {
+			return Ok(Ast::new(AstInner::Variable(name), location));
 		}
 
 		#[cfg(feature = "check-parens")]
-		if self.env.opts().check_parens && parens::parse_parens(self)? {
-			return Ok(());
+		if self.env.opts().check_parens {
+			if let Some(ast) = parens::parse_parens(self)? {
+				return Ok(ast);
+			}
 		}
 
-		if function::Function::parse(self)? {
-			return Ok(());
+		if let Some(ast) = function::Function::parse(self)? {
+			return Ok(ast);
 		}
 
 		let chr = self.peek().ok_or_else(|| self.error(ParseErrorKind::EmptySource))?;
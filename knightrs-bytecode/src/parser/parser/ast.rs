@@ -1,20 +1,24 @@
-#![allow(unused)]
 use crate::parser::{ParseError, SourceLocation, VariableName};
 use crate::program::{Compilable, Compiler, JumpWhen};
+use crate::value::Block;
+#[cfg(feature = "extensions")]
+use crate::vm::opcode::DynamicAssignment;
 use crate::vm::Opcode;
 use crate::Options;
 use crate::Value;
 
+/// A parsed expression, which is optimized and then compiled into a [`Program`](
+/// crate::program::Program).
 pub struct Ast<'s, 'p, 'gc> {
-	inner: AstInner<'s, 'p, 'gc>,
-	location: SourceLocation<'p>,
+	pub inner: AstInner<'s, 'p, 'gc>,
+	pub location: SourceLocation<'p>,
 }
 
 pub enum AstInner<'s, 'p, 'gc> {
 	Literal(Value<'gc>),
 	Variable(VariableName<'s>),
-	Then(Vec<Ast<'s, 'p, 'gc>>),
-	Block(Box<Ast<'s, 'p, 'gc>>),
+	Then(Vec<Ast<'s, 'p, 'gc>>), // All but the last are popped.
+	Block(Box<Ast<'s, 'p, 'gc>>, Option<VariableName<'s>>),
 
 	SimpleAssign(VariableName<'s>, Box<Ast<'s, 'p, 'gc>>),
 	And(Box<Ast<'s, 'p, 'gc>>, Box<Ast<'s, 'p, 'gc>>),
@@ -22,8 +26,21 @@ pub enum AstInner<'s, 'p, 'gc> {
 	If(Box<Ast<'s, 'p, 'gc>>, Box<Ast<'s, 'p, 'gc>>, Box<Ast<'s, 'p, 'gc>>),
 	While(Box<Ast<'s, 'p, 'gc>>, Box<Ast<'s, 'p, 'gc>>),
 
-	SimpleOpcode(Opcode, Vec<Box<Ast<'s, 'p, 'gc>>>),
-	Custom(Box<dyn Compilable<'s, 'p, 'gc>>),
+	// The arguments are pushed in order, and then the opcode is run.
+	SimpleOpcode(Opcode, Vec<Ast<'s, 'p, 'gc>>),
+
+	#[cfg(feature = "extensions")]
+	AssignDynamic(DynamicAssignment, Box<Ast<'s, 'p, 'gc>>),
+	#[cfg(feature = "extensions")]
+	Break,
+	#[cfg(feature = "extensions")]
+	Continue,
+}
+
+impl<'s, 'p, 'gc> Ast<'s, 'p, 'gc> {
+	pub fn new(inner: AstInner<'s, 'p, 'gc>, location: SourceLocation<'p>) -> Self {
+		Self { inner, location }
+	}
 }
 
 unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
@@ -32,6 +49,9 @@ unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
 		compiler: &mut Compiler<'s, 'p, 'gc>,
 		opts: &Options,
 	) -> Result<(), ParseError<'p>> {
+		#[cfg(feature = "stacktrace")]
+		compiler.record_source_location(self.location);
+
 		match self.inner {
 			AstInner::Literal(value) => {
 				compiler.push_constant(value);
@@ -40,7 +60,6 @@ unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
 			AstInner::Variable(var) => (var, self.location).compile(compiler, opts),
 			AstInner::Then(stmts) => {
 				let mut stmts = stmts.into_iter();
-				// TODO: check for simple assign
 				stmts.next().unwrap().compile(compiler, opts)?;
 				for stmt in stmts {
 					unsafe { compiler.opcode_without_offset(Opcode::Pop) }
@@ -52,6 +71,12 @@ unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
 				for arg in args {
 					arg.compile(compiler, opts)?;
 				}
+
+				// Arguments can span multiple lines, so make sure the opcode itself is attributed to
+				// the line the function was on.
+				#[cfg(feature = "stacktrace")]
+				compiler.record_source_location(self.location);
+
 				// safety: todo
 				unsafe {
 					compiler.opcode_without_offset(op);
@@ -59,17 +84,30 @@ unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
 				Ok(())
 			}
 
-			AstInner::Block(value) => {
-				todo!();
-			}
+			AstInner::Block(body, name) => {
+				// TODO: improve blocks later on by not having to jump over their definitions always.
+				let jump_after = compiler.defer_jump(JumpWhen::Always);
 
-			AstInner::SimpleAssign(name, value) => {
-				value.compile(compiler, opts)?;
+				let jump_index = compiler.jump_index();
+				body.compile(compiler, opts)?;
 				unsafe {
-					compiler.set_variable(name, &opts);
+					compiler.opcode_without_offset(Opcode::Return);
+					jump_after.jump_to_current(compiler);
 				}
+
+				compiler.push_constant(Block::new(jump_index).into());
+
+				#[cfg(feature = "stacktrace")]
+				compiler.record_block(self.location, jump_index, name);
+				#[cfg(not(feature = "stacktrace"))]
+				let _ = name;
 				Ok(())
 			}
+
+			AstInner::SimpleAssign(name, value) => {
+				value.compile(compiler, opts)?;
+				unsafe { compiler.set_variable(name, opts) }.map_err(|err| err.error(self.location))
+			}
 			AstInner::And(left, right) => {
 				left.compile(compiler, opts)?;
 				unsafe {
@@ -140,7 +178,40 @@ unsafe impl<'s, 'p, 'gc> Compilable<'s, 'p, 'gc> for Ast<'s, 'p, 'gc> {
 				compiler.push_constant(crate::Value::NULL);
 				Ok(())
 			}
-			AstInner::Custom(custom) => todo!(), //custom.compile(compiler, opts),
+
+			#[cfg(feature = "extensions")]
+			AstInner::AssignDynamic(kind, value) => {
+				value.compile(compiler, opts)?;
+				unsafe {
+					compiler.opcode_with_offset(Opcode::AssignDynamic, kind as _);
+				}
+				Ok(())
+			}
+
+			#[cfg(feature = "extensions")]
+			AstInner::Break => {
+				let deferred = compiler.defer_jump(JumpWhen::Always);
+				compiler
+					.loops
+					.last_mut()
+					.expect("<todo: exception when `break` when nothing to break, or in a funciton?>")
+					.1
+					.push(deferred);
+				Ok(())
+			}
+
+			#[cfg(feature = "extensions")]
+			AstInner::Continue => {
+				let starting = compiler
+					.loops
+					.last()
+					.expect("<todo: exception when `break` when nothing to break, or in a funciton?>")
+					.0;
+				unsafe {
+					compiler.jump_to(JumpWhen::Always, starting);
+				}
+				Ok(())
+			}
 		}
 	}
 }
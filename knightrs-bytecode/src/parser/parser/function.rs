@@ -1,5 +1,5 @@
+use super::{Ast, AstInner};
 use crate::parser::{ParseError, ParseErrorKind, Parseable, Parser, VariableName};
-use crate::program::JumpWhen;
 use crate::value::KnString;
 #[cfg(feature = "extensions")]
 use crate::vm::opcode::DynamicAssignment;
@@ -57,12 +57,12 @@ fn simple_opcode_for(func: char, opts: &Options) -> Option<Opcode> {
 	}
 }
 
-fn parse_argument<'path>(
-	parser: &mut Parser<'_, '_, 'path, '_>,
+fn parse_argument<'src, 'path, 'gc>(
+	parser: &mut Parser<'_, 'src, 'path, 'gc>,
 	start: &SourceLocation<'path>,
 	fn_name: char,
 	arg: usize,
-) -> Result<(), ParseError<'path>> {
+) -> Result<Ast<'src, 'path, 'gc>, ParseError<'path>> {
 	match parser.parse_expression() {
 		Err(err) if matches!(err.kind, ParseErrorKind::EmptySource) => {
 			return Err(ParseErrorKind::MissingArgument(fn_name, arg).error(*start));
@@ -71,10 +71,10 @@ fn parse_argument<'path>(
 	}
 }
 
-fn parse_assignment<'path>(
+fn parse_assignment<'src, 'path, 'gc>(
 	start: SourceLocation<'path>,
-	parser: &mut Parser<'_, '_, 'path, '_>,
-) -> Result<(), ParseError<'path>> {
+	parser: &mut Parser<'_, 'src, 'path, 'gc>,
+) -> Result<AstInner<'src, 'path, 'gc>, ParseError<'path>> {
 	parser.strip_whitespace_and_comments();
 
 	// TODO: handle `()` around variable name.
@@ -83,19 +83,18 @@ fn parse_assignment<'path>(
 			return Err(ParseErrorKind::MissingArgument('=', 1).error(start));
 		}
 		Err(err) => return Err(err),
-		Ok(Some((name, location))) => {
+		Ok(Some((name, _location))) => {
 			// try for a block, if so give it a name.
 			parser.strip_whitespace_and_comments();
-			if parser.peek().map_or(false, |c| c == 'B') {
+			let value = if parser.peek().map_or(false, |c| c == 'B') {
 				parser.strip_keyword_function();
-				parse_block(start, parser, Some(name.clone()))?;
+				let block = parse_block(start, parser, Some(name.clone()))?;
+				Ast::new(block, start)
 			} else {
-				parse_argument(parser, &start, '=', 2)?;
-			}
-			// ew, cloning is not a good answer.
-			let opts = (*parser.opts()).clone();
-			unsafe { parser.compiler().set_variable(name, &opts) }
-				.map_err(|err| err.error(location))?;
+				parse_argument(parser, &start, '=', 2)?
+			};
+
+			Ok(AstInner::SimpleAssign(name, Box::new(value)))
 		}
 		Ok(None) => {
 			#[cfg(feature = "extensions")]
@@ -105,25 +104,19 @@ fn parse_assignment<'path>(
 					Some('R') => {
 						if parser.opts().extensions.builtin_fns.assign_to_random {
 							parser.strip_keyword_function();
-							parse_argument(parser, &start, '=', 2)?;
-							unsafe {
-								parser.compiler.opcode_with_offset(
-									Opcode::AssignDynamic,
-									DynamicAssignment::Random as _,
-								);
-							}
-							return Ok(());
+							let value = parse_argument(parser, &start, '=', 2)?;
+							return Ok(AstInner::AssignDynamic(
+								DynamicAssignment::Random,
+								Box::new(value),
+							));
 						}
 						// no else so we fallthru to the end
 					}
 					Some('O') | Some('P') | Some('$') => todo!("assign to builtins"),
 					_ if parser.opts().extensions.builtin_fns.assign_to_strings => {
-						parse_argument(parser, &start, '=', 1)?;
-						parse_argument(parser, &start, '=', 2)?;
-						unsafe {
-							parser.compiler().opcode_without_offset(Opcode::SetDynamicVar);
-						}
-						return Ok(());
+						let name = parse_argument(parser, &start, '=', 1)?;
+						let value = parse_argument(parser, &start, '=', 2)?;
+						return Ok(AstInner::SimpleOpcode(Opcode::SetDynamicVar, vec![name, value]));
 					}
 					_ => {}
 				}
@@ -132,34 +125,21 @@ fn parse_assignment<'path>(
 			return Err(ParseErrorKind::CanOnlyAssignToVariables.error(start));
 		}
 	}
-
-	Ok(())
 }
 
-fn parse_block<'src, 'path>(
+fn parse_block<'src, 'path, 'gc>(
 	start: SourceLocation<'path>,
-	parser: &mut Parser<'_, 'src, 'path, '_>,
+	parser: &mut Parser<'_, 'src, 'path, 'gc>,
 	name: Option<VariableName<'src>>,
-) -> Result<(), ParseError<'path>> {
-	// TODO: improve blocks later on by not having to jump over their definitions always.
-	let jump_after = parser.compiler().defer_jump(JumpWhen::Always);
-
-	let jump_index = parser.compiler().jump_index();
-	parse_argument(parser, &start, 'B', 1)?;
-	unsafe {
-		parser.compiler().opcode_without_offset(Opcode::Return);
-		jump_after.jump_to_current(parser.compiler());
-	}
-
-	parser.compiler().push_constant(crate::value::Block::new(jump_index).into());
-
-	#[cfg(feature = "debugger")]
-	parser.compiler().record_block(start, jump_index, name);
-	Ok(())
+) -> Result<AstInner<'src, 'path, 'gc>, ParseError<'path>> {
+	let body = parse_argument(parser, &start, 'B', 1)?;
+	Ok(AstInner::Block(Box::new(body), name))
 }
 
 impl Function {
-	pub fn parse<'path>(parser: &mut Parser<'_, '_, 'path, '_>) -> Result<bool, ParseError<'path>> {
+	pub fn parse<'src, 'path, 'gc>(
+		parser: &mut Parser<'_, 'src, 'path, 'gc>,
+	) -> Result<Option<Ast<'src, 'path, 'gc>>, ParseError<'path>> {
 		// this should be reowrked ot allow for registering arbitrary functions, as it doesn't
 		// support `X`s
 
@@ -168,120 +148,101 @@ impl Function {
 		} else if let Some(chr) = parser.advance_if(|c| "!%&*+,-/:;<=>?[]^|~`".contains(c)) {
 			(chr, "")
 		} else {
-			return Ok(false);
+			return Ok(None);
 		};
 
 		let start = parser.location();
+		let boxed = |ast| Box::new(ast);
 
 		// Handle opcodes without anything special
 		if let Some(simple_opcode) = simple_opcode_for(fn_name, parser.opts()) {
 			debug_assert!(!simple_opcode.takes_offset()); // no simple opcodes take offsets
 
+			let mut args = Vec::with_capacity(simple_opcode.arity());
 			for arg in 0..simple_opcode.arity() {
-				parse_argument(parser, &start, fn_name, arg + 1)?;
-			}
-
-			unsafe {
-				// todo: rename to simple opcode?
-				parser.compiler.opcode_without_offset(simple_opcode);
+				args.push(parse_argument(parser, &start, fn_name, arg + 1)?);
 			}
 
-			return Ok(true);
+			return Ok(Some(Ast::new(AstInner::SimpleOpcode(simple_opcode, args), start)));
 		}
 
 		// This is a simple op, except its arity is 0 so it never pops.
 		if fn_name == 'D' {
-			parse_argument(parser, &start, fn_name, 1)?;
-			unsafe {
-				// todo: rename to simple opcode?
-				parser.compiler.opcode_without_offset(Opcode::Dump);
-			}
-			return Ok(true);
+			let arg = parse_argument(parser, &start, fn_name, 1)?;
+			return Ok(Some(Ast::new(AstInner::SimpleOpcode(Opcode::Dump, vec![arg]), start)));
 		}
 
 		// Non-simple ones
-		match fn_name {
+		let inner = match fn_name {
 			';' => {
-				parse_argument(parser, &start, fn_name, 1)?;
-				unsafe {
-					parser.compiler.opcode_without_offset(Opcode::Pop);
+				let first = parse_argument(parser, &start, fn_name, 1)?;
+				let second = parse_argument(parser, &start, fn_name, 2)?;
+
+				// Flatten chains of `;`s, as they're usually nested to the right.
+				let mut stmts = vec![first];
+				match second.inner {
+					AstInner::Then(rest) => stmts.extend(rest),
+					_ => stmts.push(second),
 				}
-				parse_argument(parser, &start, fn_name, 1)?;
-				Ok(true)
+				AstInner::Then(stmts)
 			}
 
 			// technically not needed, as it wont ever get here. same with the if
 			#[cfg(feature = "check-parens")]
 			':' if parser.opts().check_parens => {
-				parse_argument(parser, &start, fn_name, 1)?;
-				return Ok(true);
+				return parse_argument(parser, &start, fn_name, 1).map(Some)
 			}
-			'=' => parse_assignment(start, parser).and(Ok(true)),
-			'B' => parse_block(start, parser, None).and(Ok(true)),
-			'&' | '|' => {
-				parse_argument(parser, &start, fn_name, 1)?;
-				unsafe {
-					parser.compiler().opcode_without_offset(Opcode::Dup);
-				}
-				let end = parser.compiler().defer_jump(if fn_name == '&' {
-					JumpWhen::False
-				} else {
-					JumpWhen::True
-				});
-				unsafe {
-					// delete the value we dont want
-					parser.compiler().opcode_without_offset(Opcode::Pop);
-				}
-				parse_argument(parser, &start, fn_name, 2)?;
-				unsafe {
-					end.jump_to_current(parser.compiler());
-				}
-				Ok(true)
+			'=' => parse_assignment(start, parser)?,
+			'B' => parse_block(start, parser, None)?,
+			'&' => {
+				let lhs = parse_argument(parser, &start, fn_name, 1)?;
+				let rhs = parse_argument(parser, &start, fn_name, 2)?;
+				AstInner::And(boxed(lhs), boxed(rhs))
+			}
+			'|' => {
+				let lhs = parse_argument(parser, &start, fn_name, 1)?;
+				let rhs = parse_argument(parser, &start, fn_name, 2)?;
+				AstInner::Or(boxed(lhs), boxed(rhs))
 			}
 			'I' => {
-				parse_argument(parser, &start, fn_name, 1)?;
-				let to_false = parser.compiler().defer_jump(JumpWhen::False);
-				parse_argument(parser, &start, fn_name, 2)?;
-				let to_end = parser.compiler().defer_jump(JumpWhen::Always);
-				unsafe {
-					to_false.jump_to_current(&mut parser.compiler());
-				}
-				parse_argument(parser, &start, fn_name, 3)?;
-				unsafe {
-					to_end.jump_to_current(parser.compiler());
-				}
-				Ok(true)
+				let cond = parse_argument(parser, &start, fn_name, 1)?;
+				let iftrue = parse_argument(parser, &start, fn_name, 2)?;
+				let iffalse = parse_argument(parser, &start, fn_name, 3)?;
+				AstInner::If(boxed(cond), boxed(iftrue), boxed(iffalse))
 			}
 			'W' => {
-				let while_start = parser.compiler().jump_index();
-
-				parse_argument(parser, &start, fn_name, 1)?;
-				let deferred = parser.compiler().defer_jump(JumpWhen::False);
-				parser.loops.push((while_start, vec![deferred]));
-
-				parse_argument(parser, &start, fn_name, 3)?;
-				unsafe {
-					parser.compiler().opcode_without_offset(Opcode::Pop);
-					parser.compiler().jump_to(JumpWhen::Always, while_start);
-				}
-
-				// jump all `break`s to the end
-				for deferred in parser.loops.pop().unwrap().1 {
-					unsafe {
-						deferred.jump_to_current(parser.compiler());
-					}
-				}
-				parser.compiler().push_constant(crate::Value::NULL);
-
-				Ok(true)
+				let cond = parse_argument(parser, &start, fn_name, 1)?;
+				let body = parse_argument(parser, &start, fn_name, 2)?;
+				AstInner::While(boxed(cond), boxed(body))
 			}
 
 			#[cfg(feature = "extensions")]
 			'X' if parser.opts().extensions.syntax.string_interpolation
 				&& parser.advance_if('"').is_some() =>
 			{
+				let gc = parser.gc();
 				let mut acc = String::new();
-				let mut should_add = false;
+				let mut interpolated = None::<Ast<'src, 'path, 'gc>>;
+
+				// SAFETY: the gc isn't collecting while we're parsing, and the string will be
+				// referenced by the program once it's compiled.
+				let literal = |string| {
+					let string = KnString::new_unvalidated(string, gc)
+						.map_err(|err| ParseErrorKind::from(err).error(start))?;
+					Ok::<_, ParseError<'path>>(Ast::new(
+						AstInner::Literal(unsafe { string.with_inner(Into::into) }),
+						start,
+					))
+				};
+
+				// Each piece is added onto what's been interpolated so far.
+				let append = |so_far: Option<Ast<'src, 'path, 'gc>>, piece| match so_far {
+					Some(so_far) => {
+						Ast::new(AstInner::SimpleOpcode(Opcode::Add, vec![so_far, piece]), start)
+					}
+					None => piece,
+				};
+
 				loop {
 					match parser
 						.advance()
@@ -289,12 +250,11 @@ impl Function {
 					{
 						'"' => break,
 						'{' => {
-							let gc = parser.gc();
-							let (compiler, opts) = parser._compiler_opts();
-							KnString::new_unvalidated(std::mem::take(&mut acc), gc)
-								.compile(compiler, opts)?;
+							// Always start with a string, so that the `+`s concatenate.
+							let prefix = literal(std::mem::take(&mut acc))?;
+							interpolated = Some(append(interpolated, prefix));
 
-							parser.parse_expression()?;
+							let expr = parser.parse_expression()?;
 
 							match parser.parse_expression() {
 								Err(err) if matches!(err.kind, ParseErrorKind::UnknownTokenStart('}')) => {
@@ -303,12 +263,7 @@ impl Function {
 								other => return Err(ParseErrorKind::UnmatchedClosingBrace.error(start)),
 							}
 
-							// SAFETY: We compiled the previous string and this one
-							unsafe {
-								parser.compiler.opcode_without_offset(Opcode::Add);
-							}
-
-							should_add = true;
+							interpolated = Some(append(interpolated, expr));
 						}
 						'\\' => match parser
 							.advance()
@@ -347,48 +302,25 @@ impl Function {
 						other => acc.push(other),
 					}
 				}
-				use crate::program::Compilable;
 
-				let gc = parser.gc();
-				let (compiler, opts) = parser._compiler_opts();
-				KnString::new_unvalidated(acc, gc).compile(compiler, opts)?;
-
-				if should_add {
-					unsafe {
-						parser.compiler.opcode_without_offset(Opcode::Add);
-					}
-				}
-
-				Ok(true)
+				let suffix = literal(acc)?;
+				return Ok(Some(append(interpolated, suffix)));
 			}
 
 			// TODO: extensions lol
 			#[cfg(feature = "extensions")]
 			'X' => match full_name {
-				"BREAK" if parser.opts().extensions.syntax.control_flow => {
-					let deferred = parser.compiler().defer_jump(JumpWhen::Always);
-					parser
-						.loops
-						.last_mut()
-						.expect("<todo: exception when `break` when nothing to break, or in a funciton?>")
-						.1
-						.push(deferred);
-					Ok(true)
+				"BREAK" if parser.opts().extensions.syntax.control_flow => AstInner::Break,
+				"CONTINUE" if parser.opts().extensions.syntax.control_flow => AstInner::Continue,
+				_ => {
+					return Err(
+						ParseErrorKind::UnknownExtensionFunction(full_name.to_string()).error(start),
+					)
 				}
-				"CONTINUE" if parser.opts().extensions.syntax.control_flow => {
-					let starting = parser
-						.loops
-						.last()
-						.expect("<todo: exception when `break` when nothing to break, or in a funciton?>")
-						.0;
-					unsafe {
-						parser.compiler().jump_to(JumpWhen::Always, starting);
-					}
-					Ok(true)
-				}
-				_ => Err(ParseErrorKind::UnknownExtensionFunction(full_name.to_string()).error(start)),
 			},
-			_ => Err(ParseErrorKind::UnknownTokenStart(fn_name).error(start)),
-		}
+			_ => return Err(ParseErrorKind::UnknownTokenStart(fn_name).error(start)),
+		};
+
+		Ok(Some(Ast::new(inner, start)))
 	}
 }
@@ -0,0 +1,253 @@
+use super::{Ast, AstInner};
+use crate::value::{List, ToBoolean};
+use crate::vm::Opcode;
+use crate::{Environment, Options, Value};
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+
+/// The longest string or list that constant folding will create.
+///
+/// Anything longer is left to be created at runtime, so folding doesn't bloat programs (or take
+/// forever to compile things like `* "a" 1000000000`).
+const MAX_FOLDED_LENGTH: usize = 1024;
+
+fn container_len(value: Value<'_>) -> Option<usize> {
+	value.as_knstring().map(|string| string.len()).or_else(|| value.as_list().map(|list| list.len()))
+}
+
+// Whether `opcode` can be evaluated at compile-time with `args`.
+//
+// Only functions without side effects are folded. Additionally, combinations of arguments which
+// would convert strings to integers are never folded, as that conversion (along with some extension
+// functions) isn't fully implemented yet and panics in some cases---if the program never actually
+// ran that code, folding it shouldn't cause a panic. Strings are never converted to lists either,
+// as that pauses the gc, which is already paused while parsing.
+fn can_fold(opcode: Opcode, args: &[Value<'_>], opts: &Options) -> bool {
+	let is_string = |value: &Value<'_>| value.as_knstring().is_some();
+	let is_container = |value: &Value<'_>| container_len(*value).is_some();
+
+	match (opcode, args) {
+		(Opcode::Not | Opcode::Box | Opcode::Length, [_]) => true,
+		(Opcode::Negate, [arg]) => {
+			#[cfg(feature = "extensions")]
+			if opts.extensions.breaking.negate_reverses_collections {
+				return false;
+			}
+
+			!is_string(arg)
+		}
+		(Opcode::Ascii, [arg]) => arg.as_integer().is_some() || is_string(arg),
+		(Opcode::Head | Opcode::Tail, [arg]) => is_container(arg),
+
+		(Opcode::Eql, [_, _]) => true,
+		(Opcode::Add | Opcode::Lth | Opcode::Gth, [lhs, rhs]) if lhs.as_integer().is_some() => {
+			!is_string(rhs)
+		}
+		(Opcode::Add, [lhs, rhs]) if lhs.as_list().is_some() => !is_string(rhs),
+		(Opcode::Add, [lhs, _]) => is_container(lhs),
+		(Opcode::Lth | Opcode::Gth, [lhs, _]) => is_string(lhs) || lhs.as_boolean().is_some(),
+		(Opcode::Sub | Opcode::Div | Opcode::Mod | Opcode::Pow | Opcode::Mul, [lhs, rhs])
+			if lhs.as_integer().is_some() =>
+		{
+			!is_string(rhs)
+		}
+		(Opcode::Pow, [lhs, _]) => lhs.as_list().is_some(),
+		(Opcode::Mul, [lhs, rhs]) => container_len(*lhs)
+			.zip(rhs.as_integer())
+			.and_then(|(len, amount)| len.checked_mul(usize::try_from(amount.inner()).ok()?))
+			.is_some_and(|total| total <= MAX_FOLDED_LENGTH),
+
+		(Opcode::Get, [container, start, len]) => {
+			is_container(container) && !is_string(start) && !is_string(len)
+		}
+		(Opcode::Set, [container, start, len, replacement]) => {
+			is_container(container)
+				&& !is_string(start)
+				&& !is_string(len)
+				&& (container.as_list().is_none() || !is_string(replacement))
+		}
+
+		_ => false,
+	}
+}
+
+// Evaluates `opcode` the same way the `Vm` would.
+//
+// SAFETY: The gc mustn't collect while folding, as the result isn't rooted.
+unsafe fn evaluate<'gc>(
+	opcode: Opcode,
+	args: &[Value<'gc>],
+	env: &mut Environment<'gc>,
+) -> crate::Result<Value<'gc>> {
+	let mut result = MaybeUninit::uninit();
+
+	unsafe {
+		match (opcode, args) {
+			(Opcode::Not, [arg]) => arg.kn_not(&mut result, env)?,
+			(Opcode::Negate, [arg]) => arg.kn_negate(&mut result, env)?,
+			(Opcode::Ascii, [arg]) => arg.kn_ascii(&mut result, env)?,
+			(Opcode::Head, [arg]) => arg.kn_head(&mut result, env)?,
+			(Opcode::Tail, [arg]) => arg.kn_tail(&mut result, env)?,
+			(Opcode::Length, [arg]) => {
+				result.write(arg.kn_length(env)?.into());
+			}
+			(Opcode::Box, [arg]) => {
+				List::boxed(*arg, env.gc())?.with_inner(|inner| result.write(inner.into()));
+			}
+
+			(Opcode::Add, [lhs, rhs]) => lhs.kn_plus(rhs, &mut result, env)?,
+			(Opcode::Sub, [lhs, rhs]) => lhs.kn_minus(rhs, &mut result, env)?,
+			(Opcode::Mul, [lhs, rhs]) => lhs.kn_asterisk(rhs, &mut result, env)?,
+			(Opcode::Div, [lhs, rhs]) => lhs.kn_slash(rhs, &mut result, env)?,
+			(Opcode::Mod, [lhs, rhs]) => lhs.kn_percent(rhs, &mut result, env)?,
+			(Opcode::Pow, [lhs, rhs]) => lhs.kn_caret(rhs, &mut result, env)?,
+			(Opcode::Lth, [lhs, rhs]) => {
+				result.write((lhs.kn_compare(rhs, "<", env)? == Ordering::Less).into());
+			}
+			(Opcode::Gth, [lhs, rhs]) => {
+				result.write((lhs.kn_compare(rhs, ">", env)? == Ordering::Greater).into());
+			}
+			(Opcode::Eql, [lhs, rhs]) => {
+				result.write(lhs.kn_equals(rhs, env)?.into());
+			}
+
+			(Opcode::Get, [container, start, len]) => {
+				container.kn_get(start, len, &mut result, env)?
+			}
+			(Opcode::Set, [container, start, len, repl]) => {
+				container.kn_set(start, len, repl, &mut result, env)?
+			}
+
+			_ => bug!("cannot evaluate {:?}", opcode),
+		}
+
+		Ok(result.assume_init())
+	}
+}
+
+impl<'s, 'p, 'gc> Ast<'s, 'p, 'gc> {
+	fn as_literal(&self) -> Option<Value<'gc>> {
+		match self.inner {
+			AstInner::Literal(value) => Some(value),
+			_ => None,
+		}
+	}
+
+	// Gets the truthiness of `self` if it's a literal.
+	fn literal_truthiness(&self, env: &mut Environment<'gc>) -> Option<bool> {
+		self.as_literal()?.to_boolean(env).ok()
+	}
+
+	// Whether `self` can be removed when its value isn't used.
+	fn is_side_effect_free(&self, opts: &Options) -> bool {
+		match self.inner {
+			AstInner::Literal(_) | AstInner::Block(..) => true,
+
+			// Accessing undefined variables is an error when variables are checked.
+			AstInner::Variable(_) => {
+				cfg_expr!(feature = "check-variables", !opts.check_variables, true)
+			}
+			_ => false,
+		}
+	}
+
+	/// Folds pure functions whose arguments are all literals, and removes branches and statements
+	/// which are never run or have no effect.
+	///
+	/// Functions which would fail at runtime are left alone, so that the errors still occur when
+	/// (and if) they're run. Since evaluating functions respects `env`'s options, anything which
+	/// is checked by them (such as overflow) is also left alone.
+	pub fn fold_constants(self, env: &mut Environment<'gc>) -> Self {
+		let location = self.location;
+		let inner = match self.inner {
+			inner @ (AstInner::Literal(_) | AstInner::Variable(_)) => inner,
+
+			AstInner::Then(stmts) => {
+				let count = stmts.len();
+				let mut folded = Vec::with_capacity(count);
+				for (idx, stmt) in stmts.into_iter().enumerate() {
+					let stmt = stmt.fold_constants(env);
+
+					// The last statement is the result, so it has to be kept.
+					if idx + 1 == count || !stmt.is_side_effect_free(env.opts()) {
+						folded.push(stmt);
+					}
+				}
+
+				if folded.len() == 1 {
+					return folded.pop().unwrap();
+				}
+				AstInner::Then(folded)
+			}
+
+			AstInner::Block(body, name) => AstInner::Block(Box::new(body.fold_constants(env)), name),
+			AstInner::SimpleAssign(name, value) => {
+				AstInner::SimpleAssign(name, Box::new(value.fold_constants(env)))
+			}
+
+			AstInner::And(lhs, rhs) => {
+				let lhs = Box::new(lhs.fold_constants(env));
+				match lhs.literal_truthiness(env) {
+					Some(true) => return rhs.fold_constants(env),
+					Some(false) => return *lhs,
+					None => AstInner::And(lhs, Box::new(rhs.fold_constants(env))),
+				}
+			}
+
+			AstInner::Or(lhs, rhs) => {
+				let lhs = Box::new(lhs.fold_constants(env));
+				match lhs.literal_truthiness(env) {
+					Some(true) => return *lhs,
+					Some(false) => return rhs.fold_constants(env),
+					None => AstInner::Or(lhs, Box::new(rhs.fold_constants(env))),
+				}
+			}
+
+			AstInner::If(cond, iftrue, iffalse) => {
+				let cond = Box::new(cond.fold_constants(env));
+				match cond.literal_truthiness(env) {
+					Some(true) => return iftrue.fold_constants(env),
+					Some(false) => return iffalse.fold_constants(env),
+					None => AstInner::If(
+						cond,
+						Box::new(iftrue.fold_constants(env)),
+						Box::new(iffalse.fold_constants(env)),
+					),
+				}
+			}
+
+			AstInner::While(cond, body) => {
+				let cond = Box::new(cond.fold_constants(env));
+				match cond.literal_truthiness(env) {
+					Some(false) => AstInner::Literal(Value::NULL),
+					_ => AstInner::While(cond, Box::new(body.fold_constants(env))),
+				}
+			}
+
+			AstInner::SimpleOpcode(opcode, args) => {
+				let args = args.into_iter().map(|arg| arg.fold_constants(env)).collect::<Vec<_>>();
+				let literals = args.iter().map(Ast::as_literal).collect::<Option<Vec<_>>>();
+
+				let folded = literals
+					.filter(|literals| can_fold(opcode, literals, env.opts()))
+					// SAFETY: the gc isn't collecting while we're parsing.
+					.and_then(|literals| unsafe { evaluate(opcode, &literals, env) }.ok())
+					.filter(|&value| container_len(value).is_none_or(|len| len <= MAX_FOLDED_LENGTH));
+
+				match folded {
+					Some(value) => AstInner::Literal(value),
+					None => AstInner::SimpleOpcode(opcode, args),
+				}
+			}
+
+			#[cfg(feature = "extensions")]
+			AstInner::AssignDynamic(kind, value) => {
+				AstInner::AssignDynamic(kind, Box::new(value.fold_constants(env)))
+			}
+			#[cfg(feature = "extensions")]
+			inner @ (AstInner::Break | AstInner::Continue) => inner,
+		};
+
+		Ast::new(inner, location)
+	}
+}
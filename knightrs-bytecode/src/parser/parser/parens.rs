@@ -1,8 +1,9 @@
+use super::Ast;
 use crate::parser::{ParseError, ParseErrorKind, Parser};
 
-pub fn parse_parens<'path>(
-	parser: &mut Parser<'_, '_, 'path, '_>,
-) -> Result<bool, ParseError<'path>> {
+pub fn parse_parens<'src, 'path, 'gc>(
+	parser: &mut Parser<'_, 'src, 'path, 'gc>,
+) -> Result<Option<Ast<'src, 'path, 'gc>>, ParseError<'path>> {
 	// If we have a `)`, that means it's a random `)` in the source.
 	if parser.advance_if(')').is_some() {
 		return Err(parser.error(ParseErrorKind::UnmatchedClosingParen));
@@ -10,11 +11,11 @@ pub fn parse_parens<'path>(
 
 	// If we don't have a `(`, then we aren't parsing parens/
 	if parser.advance_if('(').is_none() {
-		return Ok(false);
+		return Ok(None);
 	}
 
 	let start = parser.location();
-	parser.parse_expression()?;
+	let ast = parser.parse_expression()?;
 
 	//
 	parser.strip_whitespace_and_comments();
@@ -22,5 +23,5 @@ pub fn parse_parens<'path>(
 		return Err(ParseErrorKind::MissingClosingParen.error(start));
 	}
 
-	return Ok(true);
+	return Ok(Some(ast));
 }
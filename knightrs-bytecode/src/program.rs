@@ -1,23 +1,42 @@
+mod assemble;
+mod c;
 mod compiler;
+mod disassemble;
+mod registers;
+mod serialize;
+mod translate;
+mod verify;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 use crate::parser::{SourceLocation, VariableName};
 use crate::value::Value;
-use crate::vm::Opcode;
+use crate::vm::{FusedOperands, Opcode};
+pub use assemble::AssembleError;
+pub use c::CSource;
 pub use compiler::{Compilable, Compiler};
+pub use disassemble::{CfgDot, Disassembly};
 use indexmap::IndexSet;
+pub use registers::RegisterCode;
+pub(crate) use registers::{Instruction, Operand, Register};
+pub use serialize::{DeserializeError, Fnv1a, FORMAT_VERSION, MAGIC};
+pub(crate) use serialize::{Reader, Writer};
 use std::fmt::{self, Debug, Formatter};
-
-// todo: u32 vs u64? i did u64 bx `0x00ff_ffff` isn't a lot of offsets.
-type InstructionAndOffset = i64;
+pub use translate::TranslateError;
+pub use verify::VerifyError;
+#[cfg(feature = "wasm")]
+pub use wasm::{WasmImports, WatSource};
 
 /// A Program represents an executable Knight program.
 ///
 /// After being parsed, Knight programs become [`Program`]s, which can then be run by
 /// [`Vm`](crate::VM)s later on.
 pub struct Program<'src, 'path, 'gc> {
-	// The code for the program. The bottom-most byte is the opcode, and when that's shifted away,
-	// the remainder is the offset.
-	code: Box<[InstructionAndOffset]>,
+	// The code for the program. Each instruction is an opcode byte, followed by its offset (if it
+	// takes one) in little-endian; see [`Opcode::offset_len`]. All offsets into the code (such as
+	// jump targets) are in bytes. The code is always followed by `CODE_PADDING` zero bytes, which
+	// aren't part of the program, so that `opcode_at` can read offsets without checking their size.
+	code: Box<[u8]>,
 
 	// All the constants that've been seen in the program. Used by [`Opcode::PushConstant`].
 	constants: Box<[Value<'gc>]>,
@@ -45,6 +64,15 @@ pub struct Program<'src, 'path, 'gc> {
 	_ignored: (&'src (), &'path ()),
 }
 
+// The amount of zero bytes at the end of every program's code, enough for the largest offset.
+const CODE_PADDING: usize = 8;
+
+// Adds the padding `Program` requires to the end of `code`.
+fn pad_code(mut code: Vec<u8>) -> Box<[u8]> {
+	code.resize(code.len() + CODE_PADDING, 0);
+	code.into_boxed_slice()
+}
+
 /// A type that represents a place programs can jump to.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct JumpIndex(pub(super) usize);
@@ -68,23 +96,43 @@ pub enum JumpWhen {
 	Always,
 }
 
+// Gets the offset that the instruction `opcode` (with the operand `operand`) can jump to, if any.
+pub(crate) fn jump_target(opcode: Opcode, operand: usize) -> Option<usize> {
+	match opcode {
+		Opcode::Jump | Opcode::JumpIfTrue | Opcode::JumpIfFalse => Some(operand),
+		_ if opcode.is_fused_branch() => Some(FusedOperands::unpack(operand).target),
+		_ => None,
+	}
+}
+
+// Changes the target of the jump `opcode` to `target`, returning its new operand. Returns `None`
+// if the new operand wouldn't fit.
+fn retarget(opcode: Opcode, operand: usize, target: usize) -> Option<usize> {
+	debug_assert!(jump_target(opcode, operand).is_some());
+
+	if opcode.is_fused_branch() {
+		FusedOperands { target, ..FusedOperands::unpack(operand) }.pack()
+	} else {
+		Some(target)
+	}
+}
+
 impl Debug for Program<'_, '_, '_> {
 	/// Write the debug output for `Program`.
 	///
 	/// This also decodes the bytecode contained within the [`Program`], to make it easy understand
 	/// what's happening.
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-		struct Bytecode<'a>(&'a [InstructionAndOffset]);
-		impl Debug for Bytecode<'_> {
+		struct Bytecode<'a, 'src, 'path, 'gc>(&'a Program<'src, 'path, 'gc>);
+		impl Debug for Bytecode<'_, '_, '_, '_> {
 			fn fmt(&self, f: &mut Formatter) -> fmt::Result {
 				if !f.alternate() {
 					return f.write_str("[...]");
 				}
 
 				let mut bytecode = f.debug_list();
-				for (idx, &number) in self.0.into_iter().enumerate() {
-					let opcode = unsafe { Opcode::from_byte_unchecked(number as u8) };
-					let offset = (number >> 0o10) as usize;
+				for idx in self.0.instruction_offsets() {
+					let (opcode, offset) = self.0.decode_at(idx).unwrap();
 					if opcode.takes_offset() {
 						bytecode.entry(&format!("{}: {:?} (offset={})", idx, opcode, offset));
 					} else {
@@ -97,7 +145,7 @@ impl Debug for Program<'_, '_, '_> {
 
 		let mut prog = f.debug_struct("Program");
 		prog.field("constants", &self.constants);
-		prog.field("bytecode", &Bytecode(&self.code));
+		prog.field("bytecode", &Bytecode(self));
 		prog.field("variables", &self.variables);
 
 		prog.finish()
@@ -108,30 +156,91 @@ impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
 	/// Gets the opcode, and its offset, at `offset`.
 	///
 	/// # Safety
-	/// `location` must be `<` the source code's length.
+	/// `location` must be the offset of an instruction within the code.
 	#[inline]
 	pub unsafe fn opcode_at(&self, location: usize) -> (Opcode, usize) {
-		debug_assert!(location < self.code.len());
+		debug_assert!(location < self.code().len());
 
 		// SAFETY: caller ensures the locationis correct.
-		let number = *unsafe { self.code.get_unchecked(location) };
+		let byte = *unsafe { self.code.get_unchecked(location) };
 
 		// SAFETY: we know as this type was constructed that all programs result
 		// in valid opcodes
-		let opcode = unsafe { Opcode::from_byte_unchecked(number as u8) };
-		let location = (number >> 0o10) as usize;
+		let opcode = unsafe { Opcode::from_byte_unchecked(byte) };
+
+		// Always reading eight bytes and masking off the unused ones is a lot faster than branching
+		// on the offset's size.
+		// SAFETY: the code's padding ensures there's always eight bytes after an opcode.
+		let raw = unsafe {
+			u64::from_le_bytes(self.code.as_ptr().add(location + 1).cast::<[u8; 8]>().read())
+		};
+		const MASKS: [u64; 3] = [0, u32::MAX as u64, u64::MAX];
+		let offset = (raw & MASKS[opcode.offset_len() / 4]) as usize;
+
+		(opcode, offset)
+	}
+
+	// Gets the program's code, without its padding.
+	pub(crate) fn code(&self) -> &[u8] {
+		&self.code[..self.code.len() - CODE_PADDING]
+	}
 
-		(opcode, location)
+	// Decodes the instruction at `offset`, returning `None` if its opcode is invalid, or if its
+	// offset runs past the end of the code.
+	fn decode_at(&self, offset: usize) -> Option<(Opcode, usize)> {
+		let opcode = Opcode::from_byte(*self.code().get(offset)?)?;
+		let bytes = self.code().get(offset + 1..offset + opcode.encoded_len())?;
+
+		let mut le_bytes = [0; 8];
+		le_bytes[..bytes.len()].copy_from_slice(bytes);
+		Some((opcode, u64::from_le_bytes(le_bytes) as usize))
+	}
+
+	// Gets the offsets of every instruction within the code, stopping early at the first invalid
+	// instruction (as the offsets of instructions after it aren't known).
+	fn instruction_offsets(&self) -> impl Iterator<Item = usize> + '_ {
+		let mut offset = 0;
+		std::iter::from_fn(move || {
+			let (opcode, _) = self.decode_at(offset)?;
+			let current = offset;
+			offset += opcode.encoded_len();
+			Some(current)
+		})
 	}
 
-	pub unsafe fn mark(&self) {
+	/// Marks the program's constants, so they aren't freed while it's still in use.
+	///
+	/// # Safety
+	/// This must only be called by the gc the program's constants were allocated in, while it's
+	/// collecting garbage.
+	pub unsafe fn mark(&self, tracer: &mut crate::gc::Tracer) {
 		use crate::gc::GarbageCollected;
 
 		for constant in self.constants.iter() {
 			unsafe {
-				constant.mark();
+				constant.mark(tracer);
+			}
+		}
+	}
+
+	// Gets every block within the constants, along with the index of the constant it's from. Blocks
+	// nested within list constants are included too, as they can be called after being `GET`.
+	fn block_constants(&self) -> Vec<(usize, JumpIndex)> {
+		fn visit(value: Value<'_>, constant: usize, blocks: &mut Vec<(usize, JumpIndex)>) {
+			if let Some(block) = value.as_block() {
+				blocks.push((constant, block.inner()));
+			} else if let Some(list) = value.as_list() {
+				for element in list.iter() {
+					visit(element, constant, blocks);
+				}
 			}
 		}
+
+		let mut blocks = Vec::new();
+		for (constant, &value) in self.constants.iter().enumerate() {
+			visit(value, constant, &mut blocks);
+		}
+		blocks
 	}
 
 	/// Gets constant constant at `offset`.
@@ -143,6 +252,11 @@ impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
 		unsafe { *self.constants.get_unchecked(offset) }
 	}
 
+	// Gets all the constants in the program.
+	pub(crate) fn constants(&self) -> &[Value<'gc>] {
+		&self.constants
+	}
+
 	/// The number of variables that're defined in this program.
 	#[inline]
 	pub fn num_variables(&self) -> usize {
@@ -175,3 +289,155 @@ impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gc::{Gc, GcOptions};
+	use crate::parser::source_location::ProgramSource;
+
+	fn program(code: Vec<u8>) -> Program<'static, 'static, 'static> {
+		Program {
+			code: pad_code(code),
+			constants: Box::new([]),
+			variables: IndexSet::new(),
+
+			#[cfg(feature = "stacktrace")]
+			source_lines: Default::default(),
+
+			#[cfg(feature = "stacktrace")]
+			block_locations: Default::default(),
+
+			_ignored: (&(), &()),
+		}
+	}
+
+	// Every way an instruction can be read back out of `program`, which should all agree.
+	fn decode(program: &Program<'_, '_, '_>) -> Vec<(usize, Opcode, usize)> {
+		let decoded = program
+			.instruction_offsets()
+			.map(|at| {
+				let (opcode, operand) = program.decode_at(at).unwrap();
+				(at, opcode, operand)
+			})
+			.collect::<Vec<_>>();
+
+		for &(at, opcode, operand) in &decoded {
+			// SAFETY: `at` came from `instruction_offsets`.
+			assert_eq!(unsafe { program.opcode_at(at) }, (opcode, operand), "at {at}");
+		}
+
+		decoded
+	}
+
+	#[test]
+	fn offset_widths() {
+		assert_eq!(Opcode::Dup.offset_len(), 0);
+		assert_eq!(Opcode::GetVar.offset_len(), 4);
+		assert_eq!(Opcode::AddVarConst.offset_len(), 8);
+
+		for byte in 0..=u8::MAX {
+			let Some(opcode) = Opcode::from_byte(byte) else { continue };
+			let expected = match () {
+				_ if opcode.is_fused() => 8,
+				_ if opcode.takes_offset() => 4,
+				_ => 0,
+			};
+			assert_eq!(opcode.offset_len(), expected, "{opcode:?}");
+			assert_eq!(opcode.encoded_len(), 1 + expected, "{opcode:?}");
+		}
+	}
+
+	#[test]
+	fn round_trip_at_every_width() {
+		let fused = FusedOperands { variable: 0xfffe, constant: 0xabcd, target: 0, jump_if: true };
+		let packed = fused.pack().unwrap();
+
+		// SAFETY: Nothing is allocated, and the program's never run.
+		let (code, decoded) = unsafe {
+			Gc::new(GcOptions::default()).run(|gc| {
+				let start = SourceLocation::new(ProgramSource::Other("<test>"), 1);
+				let mut compiler = Compiler::new(start, gc);
+
+				compiler.opcode_with_offset(Opcode::GetVar, 0x1234_5678);
+				compiler.opcode_without_offset(Opcode::Dup);
+				compiler.opcode_with_offset(Opcode::AddVarConst, packed);
+				compiler.opcode_with_offset(
+					Opcode::BranchVarLth,
+					FusedOperands { target: 4, ..fused }.pack().unwrap(),
+				);
+				compiler.opcode_with_offset(Opcode::PushConstant, u32::MAX as usize);
+				let program = compiler.build().unwrap();
+
+				(program.code().to_vec(), decode(&program))
+			})
+		};
+
+		// The branch's target is the index of an instruction, which is changed into its offset.
+		let branch = FusedOperands { target: 24, ..fused }.pack().unwrap();
+
+		let mut expected = vec![Opcode::GetVar as u8, 0x78, 0x56, 0x34, 0x12, Opcode::Dup as u8];
+		expected.push(Opcode::AddVarConst as u8);
+		expected.extend_from_slice(&(packed as u64).to_le_bytes());
+		expected.push(Opcode::BranchVarLth as u8);
+		expected.extend_from_slice(&(branch as u64).to_le_bytes());
+		expected.extend_from_slice(&[Opcode::PushConstant as u8, 0xff, 0xff, 0xff, 0xff]);
+		expected.push(Opcode::Return as u8);
+		assert_eq!(code, expected);
+
+		assert_eq!(
+			decoded,
+			[
+				(0, Opcode::GetVar, 0x1234_5678),
+				(5, Opcode::Dup, 0),
+				(6, Opcode::AddVarConst, packed),
+				(15, Opcode::BranchVarLth, branch),
+				(24, Opcode::PushConstant, u32::MAX as usize),
+				(29, Opcode::Return, 0),
+			]
+		);
+	}
+
+	// `opcode_at` always reads eight bytes after the opcode, so the bytes of whatever comes next
+	// mustn't end up in shorter operands.
+	#[test]
+	fn opcode_at_ignores_the_next_instruction() {
+		let mut code = vec![Opcode::Dup as u8, Opcode::GetVar as u8, 1, 0, 0, 0];
+		code.extend_from_slice(&[Opcode::AddVarConst as u8, 0xff, 0xff, 0xff, 0xff]);
+		code.extend_from_slice(&[0xff, 0xff, 0xff, 0x7f, Opcode::Return as u8]);
+		let program = program(code);
+
+		assert_eq!(
+			decode(&program),
+			[
+				(0, Opcode::Dup, 0),
+				(1, Opcode::GetVar, 1),
+				(6, Opcode::AddVarConst, 0x7fff_ffff_ffff_ffff),
+				(15, Opcode::Return, 0),
+			]
+		);
+	}
+
+	// Programs don't have to end in a `Return` (they just can't be run unless they do), so an
+	// operand can end exactly at the end of the code, where only the padding is after it.
+	#[test]
+	fn last_instruction_at_the_end_of_the_code() {
+		for opcode in [Opcode::Dup, Opcode::GetVar, Opcode::AddVarConst] {
+			let mut code = vec![opcode as u8];
+			code.resize(opcode.encoded_len(), 0xff);
+			let program = program(code);
+
+			assert_eq!(program.code().len(), opcode.encoded_len());
+			assert_eq!(program.code.len(), opcode.encoded_len() + CODE_PADDING);
+			assert!(program.code[opcode.encoded_len()..].iter().all(|&byte| byte == 0));
+
+			let operand = [0, u32::MAX as usize, u64::MAX as usize][opcode.offset_len() / 4];
+			assert_eq!(decode(&program), [(0, opcode, operand)], "{opcode:?}");
+		}
+
+		// The same goes for one that's cut off, which mustn't be read into the padding.
+		let program = program(vec![Opcode::Dup as u8, Opcode::GetVar as u8, 1, 2]);
+		assert_eq!(program.decode_at(1), None);
+		assert_eq!(program.instruction_offsets().collect::<Vec<_>>(), [0]);
+	}
+}
@@ -0,0 +1,459 @@
+use super::{Compiler, JumpIndex, JumpWhen, Program, VerifyError};
+use crate::parser::{source_location::ProgramSource, ParseErrorKind, SourceLocation, VariableName};
+use crate::strings::KnStr;
+use crate::value::{Block, Integer, KnString, List, Value};
+use crate::vm::{FusedOperands, Opcode};
+use crate::Environment;
+use std::collections::HashMap;
+
+/// Problems that can occur when [assembling](Program::assemble) a program.
+///
+/// Line numbers are those of the assembly source, starting at one.
+#[derive(Error, Debug)]
+pub enum AssembleError {
+	/// An instruction's name wasn't a valid [`Opcode`].
+	#[error("line {lineno}: unknown opcode {name:?}")]
+	UnknownOpcode { lineno: usize, name: String },
+
+	/// An opcode which takes an operand wasn't given one.
+	#[error("line {lineno}: {opcode:?} requires an operand")]
+	MissingOperand { lineno: usize, opcode: Opcode },
+
+	/// There was something left over at the end of a line.
+	#[error("line {lineno}: unexpected {found:?}")]
+	UnexpectedInput { lineno: usize, found: String },
+
+	/// A constant wasn't a valid literal.
+	#[error("line {lineno}: invalid literal")]
+	InvalidLiteral { lineno: usize },
+
+	/// A label was declared more than once.
+	#[error("line {lineno}: label {label:?} was already declared")]
+	DuplicateLabel { lineno: usize, label: String },
+
+	/// A label was referenced, but never declared.
+	#[error("line {lineno}: unknown label {label:?}")]
+	UnknownLabel { lineno: usize, label: String },
+
+	/// A variable name wasn't valid.
+	#[error("line {lineno}: invalid variable {name:?}")]
+	InvalidVariableName { lineno: usize, name: String },
+
+	/// A variable couldn't be used.
+	#[error("line {lineno}: {source}")]
+	Variable { lineno: usize, source: ParseErrorKind },
+
+	/// The kind given to [`Opcode::AssignDynamic`] was unknown.
+	#[cfg(feature = "extensions")]
+	#[error("line {lineno}: unknown dynamic assignment {name:?}")]
+	UnknownDynamicAssignment { lineno: usize, name: String },
+
+	/// A superinstruction's operands were too large to be packed together.
+	#[error("line {lineno}: operands are too large for {opcode:?}")]
+	OperandTooLarge { lineno: usize, opcode: Opcode },
+
+	/// A constant couldn't be created.
+	#[error("line {lineno}: {source}")]
+	Value { lineno: usize, source: crate::Error },
+
+	/// There wasn't enough memory to build the program.
+	#[error("{0}")]
+	Build(crate::Error),
+
+	/// The assembled program wasn't valid.
+	#[error("{0}")]
+	Verify(#[from] VerifyError),
+}
+
+// A single line of assembly that's being parsed.
+struct Line<'src> {
+	rest: &'src str,
+	lineno: usize,
+}
+
+impl<'src> Line<'src> {
+	fn skip_whitespace(&mut self) {
+		self.rest = self.rest.trim_start();
+	}
+
+	// Returns whether the line is finished, which is the case when it's either empty or a comment.
+	fn is_at_end(&mut self) -> bool {
+		self.skip_whitespace();
+		self.rest.is_empty() || self.rest.starts_with(';')
+	}
+
+	fn expect_end(&mut self) -> Result<(), AssembleError> {
+		if self.is_at_end() {
+			Ok(())
+		} else {
+			Err(AssembleError::UnexpectedInput { lineno: self.lineno, found: self.rest.to_string() })
+		}
+	}
+
+	// Words are used for opcodes, labels, variables, and keywords, so the `:` of a label declaration
+	// isn't included.
+	fn word(&mut self) -> Option<&'src str> {
+		self.skip_whitespace();
+		let len = self
+			.rest
+			.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+			.unwrap_or(self.rest.len());
+
+		if len == 0 {
+			return None;
+		}
+
+		let (word, rest) = self.rest.split_at(len);
+		self.rest = rest;
+		Some(word)
+	}
+
+	fn expect(&mut self, chr: char) -> Result<(), AssembleError> {
+		if self.eat(chr) {
+			Ok(())
+		} else {
+			Err(AssembleError::UnexpectedInput { lineno: self.lineno, found: self.rest.to_string() })
+		}
+	}
+
+	fn eat(&mut self, chr: char) -> bool {
+		self.skip_whitespace();
+		match self.rest.strip_prefix(chr) {
+			Some(rest) => {
+				self.rest = rest;
+				true
+			}
+			None => false,
+		}
+	}
+
+	fn invalid_literal(&self) -> AssembleError {
+		AssembleError::InvalidLiteral { lineno: self.lineno }
+	}
+
+	// Parses a string, which uses the same escapes as Rust's `Debug` output for strings.
+	fn string(&mut self) -> Result<String, AssembleError> {
+		let mut string = String::new();
+		let mut chars = self.rest.char_indices();
+
+		loop {
+			let Some((_, chr)) = chars.next() else { return Err(self.invalid_literal()) };
+
+			match chr {
+				'"' => break,
+				'\\' => string.push(match chars.next().ok_or_else(|| self.invalid_literal())?.1 {
+					'n' => '\n',
+					't' => '\t',
+					'r' => '\r',
+					'0' => '\0',
+					c @ ('\\' | '"' | '\'') => c,
+					'u' => {
+						let hex = chars.as_str();
+						let end = hex.find('}').ok_or_else(|| self.invalid_literal())?;
+						let codepoint = hex
+							.strip_prefix('{')
+							.and_then(|hex| u32::from_str_radix(&hex[..end - 1], 16).ok())
+							.and_then(char::from_u32)
+							.ok_or_else(|| self.invalid_literal())?;
+						chars.nth(end);
+						codepoint
+					}
+					_ => return Err(self.invalid_literal()),
+				}),
+				_ => string.push(chr),
+			}
+		}
+
+		self.rest = chars.as_str();
+		Ok(string)
+	}
+}
+
+struct Assembler<'src, 'path, 'env, 'gc> {
+	compiler: Compiler<'src, 'path, 'gc>,
+	env: &'env Environment<'gc>,
+	whence: ProgramSource<'path>,
+	labels: HashMap<&'src str, usize>,
+}
+
+impl<'src, 'gc> Assembler<'src, '_, '_, 'gc> {
+	fn label(&self, line: &mut Line<'src>) -> Result<usize, AssembleError> {
+		let label = line.word().ok_or_else(|| line.invalid_literal())?;
+		self.labels.get(label).copied().ok_or_else(|| AssembleError::UnknownLabel {
+			lineno: line.lineno,
+			label: label.to_string(),
+		})
+	}
+
+	fn variable(&self, line: &mut Line<'src>) -> Result<VariableName<'src>, AssembleError> {
+		let lineno = line.lineno;
+		let name = line.word().ok_or(AssembleError::InvalidLiteral { lineno })?;
+
+		let mut chars = name.chars();
+		let is_valid = chars.next().is_some_and(|c| c.is_lowercase() || c == '_')
+			&& chars.all(|c| c.is_lowercase() || c.is_ascii_digit() || c == '_');
+		if !is_valid {
+			return Err(AssembleError::InvalidVariableName { lineno, name: name.to_string() });
+		}
+
+		VariableName::new(KnStr::new_unvalidated(name), self.env.opts())
+			.map_err(|source| AssembleError::Variable { lineno, source })
+	}
+
+	// Like the parser, this relies on the caller making sure the `Gc` doesn't collect constants
+	// before they're placed into the program.
+	fn value(&mut self, line: &mut Line<'src>) -> Result<Value<'gc>, AssembleError> {
+		let lineno = line.lineno;
+		let opts = self.env.opts();
+		let error = |source: crate::Error| AssembleError::Value { lineno, source };
+
+		if line.eat('"') {
+			let string = KnString::new(line.string()?, opts, self.env.gc()).map_err(error)?;
+			return Ok(unsafe { string.with_inner(Value::from) });
+		}
+
+		if line.eat('[') {
+			let mut elements = Vec::new();
+			if !line.eat(']') {
+				loop {
+					elements.push(self.value(line)?);
+					if line.eat(']') {
+						break;
+					}
+					if !line.eat(',') {
+						return Err(line.invalid_literal());
+					}
+				}
+			}
+
+			let list = List::from_slice(&elements, opts, self.env.gc()).map_err(error)?;
+			return Ok(unsafe { list.with_inner(Value::from) });
+		}
+
+		Ok(match line.word().ok_or_else(|| line.invalid_literal())? {
+			"null" => Value::NULL,
+			"true" => Value::TRUE,
+			"false" => Value::FALSE,
+			"block" => {
+				#[cfg(feature = "stacktrace")]
+				let mut label = Line { rest: line.rest, lineno };
+				let target = JumpIndex(self.label(line)?);
+
+				// Blocks are named after their labels (like the disassembler does the reverse), as long
+				// as they're valid variable names.
+				#[cfg(feature = "stacktrace")]
+				{
+					let name = self.variable(&mut label).ok();
+					self.compiler.record_block(SourceLocation::new(self.whence, lineno), target, name);
+				}
+
+				Block::new(target).into()
+			}
+			word => {
+				let int = word.parse().map_err(|_| line.invalid_literal())?;
+				Integer::new_error(int, opts).map_err(|err| error(err.into()))?.into()
+			}
+		})
+	}
+
+	fn instruction(&mut self, opcode: Opcode, line: &mut Line<'src>) -> Result<(), AssembleError> {
+		let lineno = line.lineno;
+
+		if !opcode.takes_offset() {
+			// SAFETY: `opcode` doesn't take an offset, and the program is verified after assembling.
+			unsafe { self.compiler.opcode_without_offset(opcode) };
+			return Ok(());
+		}
+
+		if line.is_at_end() {
+			return Err(AssembleError::MissingOperand { lineno, opcode });
+		}
+
+		let variable_error = |source| AssembleError::Variable { lineno, source };
+
+		// SAFETY (for all of these): The operands are all looked up, and the program is verified
+		// after assembling, so invalid programs are never returned.
+		match opcode {
+			Opcode::PushConstant => {
+				let value = self.value(line)?;
+				self.compiler.push_constant(value);
+			}
+
+			Opcode::Jump | Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+				let when = match opcode {
+					Opcode::Jump => JumpWhen::Always,
+					Opcode::JumpIfTrue => JumpWhen::True,
+					_ => JumpWhen::False,
+				};
+				let target = self.label(line)?;
+				unsafe { self.compiler.jump_to(when, JumpIndex(target)) };
+			}
+
+			Opcode::GetVar => {
+				let name = self.variable(line)?;
+				self.compiler.get_variable(name, self.env.opts()).map_err(variable_error)?;
+			}
+
+			Opcode::SetVar => {
+				let name = self.variable(line)?;
+				unsafe { self.compiler.set_variable(name, self.env.opts()) }.map_err(variable_error)?;
+			}
+
+			Opcode::SetVarPop => {
+				let name = self.variable(line)?;
+				#[allow(deprecated)]
+				unsafe { self.compiler.set_variable_pop(name, self.env.opts()) }
+					.map_err(variable_error)?;
+			}
+
+			_ if opcode.is_fused() => {
+				let name = self.variable(line)?;
+				let variable =
+					self.compiler.variable_index(name, self.env.opts()).map_err(variable_error)?;
+				line.expect(',')?;
+				let value = self.value(line)?;
+				let constant = self.compiler.constant_index(value);
+
+				let mut operands = FusedOperands { variable, constant, target: 0, jump_if: false };
+				if opcode.is_fused_branch() {
+					line.expect(',')?;
+					operands.target = self.label(line)?;
+					operands.jump_if = match (line.word(), line.word()) {
+						(Some("if"), Some("true")) => true,
+						(Some("if"), Some("false")) => false,
+						_ => return Err(line.invalid_literal()),
+					};
+				}
+
+				let operands =
+					operands.pack().ok_or(AssembleError::OperandTooLarge { lineno, opcode })?;
+				unsafe { self.compiler.opcode_with_offset(opcode, operands) };
+			}
+
+			#[cfg(feature = "extensions")]
+			Opcode::AssignDynamic => {
+				let name = line.word().unwrap_or_default();
+				let kind = (0..)
+					.map_while(super::disassemble::dynamic_assignment_name)
+					.position(|kind| kind == name)
+					.ok_or_else(|| AssembleError::UnknownDynamicAssignment {
+						lineno,
+						name: name.to_string(),
+					})?;
+				unsafe { self.compiler.opcode_with_offset(opcode, kind) };
+			}
+
+			_ => unreachable!("unhandled opcode which takes an offset: {opcode:?}"),
+		}
+
+		Ok(())
+	}
+}
+
+// Strips off a leading offset (as emitted by the disassembler) and splits a line into its labels and
+// the instruction name.
+fn split_line<'src>(
+	line: &mut Line<'src>,
+	labels: &mut Vec<&'src str>,
+) -> Result<Option<&'src str>, AssembleError> {
+	loop {
+		if line.is_at_end() {
+			return Ok(None);
+		}
+
+		let word = line.word().ok_or_else(|| AssembleError::UnexpectedInput {
+			lineno: line.lineno,
+			found: line.rest.to_string(),
+		})?;
+
+		if line.eat(':') {
+			labels.push(word);
+		} else if !word.bytes().all(|b| b.is_ascii_digit()) {
+			return Ok(Some(word));
+		}
+	}
+}
+
+fn opcode_named(name: &str) -> Option<Opcode> {
+	(0..=u8::MAX)
+		.filter_map(Opcode::from_byte)
+		.find(|opcode| format!("{opcode:?}").eq_ignore_ascii_case(name))
+}
+
+impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
+	/// Assembles a program from its textual representation.
+	///
+	/// Each line consists of optional `label:`s, followed by an optional [`Opcode`] (whose name is
+	/// case-insensitive) and its operand. Anything after a `;` is a comment, and a leading offset is
+	/// ignored, so the output of [`Program::disassemble`] can be assembled again. Operands are:
+	///
+	/// - For [`Opcode::PushConstant`], a literal: an integer, a string (using Rust's escapes),
+	///   `true`, `false`, `null`, a list of literals (eg `[1, "a"]`), or `block LABEL`.
+	/// - For jumps, a label.
+	/// - For variable opcodes, the name of the variable.
+	/// - For superinstructions, a variable and a literal separated by a comma (eg `i, 1`). Branches
+	///   are additionally followed by `, LABEL if true` or `, LABEL if false`.
+	/// - For [`Opcode::AssignDynamic`], one of `output`, `prompt`, `random`, or `system`. (Only
+	///   `random` will pass verification, as it's the only one the vm supports.)
+	///
+	/// A [`Opcode::Return`] is always added at the end of the program, and the program is then
+	/// [verified](Program::verify). Source locations (if `stacktrace` is enabled) are the lines of
+	/// the assembly, and use `whence`.
+	///
+	/// # Safety
+	/// The [`Gc`](crate::Gc) mustn't collect garbage while the program's being assembled (eg by
+	/// [pausing](crate::Gc::pause) it), as with parsing.
+	pub unsafe fn assemble(
+		source: &'src str,
+		whence: ProgramSource<'path>,
+		env: &Environment<'gc>,
+	) -> Result<Self, AssembleError> {
+		// First, find where every label points so that jumps can go forwards.
+		let mut labels = HashMap::new();
+		let mut instruction_count = 0;
+		let mut line_labels = Vec::new();
+		for (idx, text) in source.lines().enumerate() {
+			let mut line = Line { rest: text, lineno: idx + 1 };
+			let name = split_line(&mut line, &mut line_labels)?;
+
+			for label in line_labels.drain(..) {
+				if labels.insert(label, instruction_count).is_some() {
+					return Err(AssembleError::DuplicateLabel {
+						lineno: line.lineno,
+						label: label.to_string(),
+					});
+				}
+			}
+
+			if name.is_some() {
+				instruction_count += 1;
+			}
+		}
+
+		let start = SourceLocation::new(whence, 1);
+		let mut assembler =
+			Assembler { compiler: Compiler::new(start, env.gc()), env, whence, labels };
+
+		for (idx, text) in source.lines().enumerate() {
+			let mut line = Line { rest: text, lineno: idx + 1 };
+			let Some(name) = split_line(&mut line, &mut line_labels)? else { continue };
+			line_labels.clear();
+
+			let opcode = opcode_named(name).ok_or_else(|| AssembleError::UnknownOpcode {
+				lineno: line.lineno,
+				name: name.to_string(),
+			})?;
+
+			#[cfg(feature = "stacktrace")]
+			assembler.compiler.record_source_location(SourceLocation::new(whence, line.lineno));
+
+			assembler.instruction(opcode, &mut line)?;
+			line.expect_end()?;
+		}
+
+		// SAFETY: The program is verified before it's returned, so if it's invalid it's never run.
+		let program = unsafe { assembler.compiler.build() }.map_err(AssembleError::Build)?;
+		program.verify()?;
+		Ok(program)
+	}
+}
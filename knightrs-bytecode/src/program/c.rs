@@ -0,0 +1,267 @@
+use super::{jump_target, Program, TranslateError};
+use crate::value::Value;
+use crate::vm::{FusedOperands, Opcode};
+use crate::Options;
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+// The runtime that translated programs use, which implements Knight's values and functions.
+const RUNTIME: &str = include_str!("c/runtime.c");
+
+/// A [`Program`] translated to C, returned by [`Program::to_c`].
+///
+/// The output is a single, standalone C file, which can be built with `cc -O2 -o program FILE`.
+pub struct CSource<'a, 'src, 'path, 'gc> {
+	program: &'a Program<'src, 'path, 'gc>,
+	depths: Vec<Option<usize>>,
+	// Configuration macros for the runtime. Any which aren't defined default to `0`.
+	config: Vec<(&'static str, bool)>,
+}
+
+impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
+	/// Translates `self` to C.
+	///
+	/// Each block becomes a series of labels within a single C function, and the stack is lowered to
+	/// a local array. The integer width and overflow checking from `opts` are compiled in, as are
+	/// the other compliance checks which happen at runtime. Extensions which need the interpreter,
+	/// such as `EVAL` and dynamic variables, aren't supported.
+	///
+	/// # Errors
+	/// Returns an error if `self` can't be [verified](Program::verify), or if it uses an opcode
+	/// which can't be translated.
+	pub fn to_c(&self, opts: &Options) -> Result<CSource<'_, 'src, 'path, 'gc>, TranslateError> {
+		let depths = self.translatable_stack_depths()?;
+
+		#[cfg_attr(not(any(feature = "compliance", feature = "extensions")), allow(unused_mut))]
+		let mut config = Vec::new();
+
+		#[cfg(feature = "compliance")]
+		config.extend([
+			("KN_I32_INTEGER", opts.compliance.i32_integer),
+			("KN_CHECK_OVERFLOW", opts.compliance.check_overflow),
+			("KN_CHECK_INTEGER_FUNCTION_BOUNDS", opts.compliance.check_integer_function_bounds),
+			("KN_LIMIT_RAND_RANGE", opts.compliance.limit_rand_range),
+			("KN_CHECK_QUIT_STATUS_CODES", opts.compliance.check_quit_status_codes),
+		]);
+
+		#[cfg(feature = "check-variables")]
+		config.push(("KN_CHECK_VARIABLES", opts.check_variables));
+
+		#[cfg(feature = "extensions")]
+		config.push(("KN_ARGV", opts.extensions.argv));
+
+		let _ = opts;
+		Ok(CSource { program: self, depths, config })
+	}
+}
+
+// Writes `bytes` as a C string literal. Anything other than printable ASCII is written in octal,
+// which (unlike hex) never runs into the characters after it. `?` is escaped to avoid trigraphs.
+fn write_c_string(f: &mut Formatter, bytes: &[u8]) -> fmt::Result {
+	f.write_str("\"")?;
+	for &byte in bytes {
+		match byte {
+			b'"' | b'\\' | b'?' => write!(f, "\\{}", byte as char)?,
+			b' '..=b'~' => write!(f, "{}", byte as char)?,
+			_ => write!(f, "\\{byte:03o}")?,
+		}
+	}
+	f.write_str("\"")
+}
+
+// Writes a C expression which creates `value`.
+fn write_c_value(f: &mut Formatter, value: Value<'_>) -> fmt::Result {
+	if value.is_null() {
+		write!(f, "kn_null()")
+	} else if let Some(boolean) = value.as_boolean() {
+		write!(f, "kn_boolean({boolean})")
+	} else if let Some(integer) = value.as_integer() {
+		write!(f, "kn_integer(INT64_C({integer}))")
+	} else if let Some(string) = value.as_knstring() {
+		write!(f, "kn_string_new(")?;
+		write_c_string(f, string.as_str().as_bytes())?;
+		write!(f, ", {})", string.as_str().len())
+	} else if let Some(list) = value.as_list() {
+		if list.is_empty() {
+			return write!(f, "kn_list_value(&kn_empty_list)");
+		}
+
+		write!(f, "kn_list_from({}, (kn_value[]) {{ ", list.len())?;
+		for (idx, element) in list.iter().enumerate() {
+			if idx != 0 {
+				write!(f, ", ")?;
+			}
+			write_c_value(f, element)?;
+		}
+		write!(f, " }})")
+	} else if let Some(block) = value.as_block() {
+		write!(f, "kn_block({})", block.inner().0)
+	} else {
+		bug!("unknown value type: {:?}", value)
+	}
+}
+
+impl CSource<'_, '_, '_, '_> {
+	// Writes the statement(s) for the instruction at `offset`, where the stack is `depth` deep.
+	fn write_instruction(
+		&self,
+		f: &mut Formatter,
+		opcode: Opcode,
+		operand: usize,
+		depth: usize,
+	) -> fmt::Result {
+		// The top few values of the stack, where `top!(0)` is the topmost.
+		macro_rules! top {
+			($idx:expr) => {
+				format_args!("stack[{}]", depth - 1 - $idx)
+			};
+		}
+
+		let unary = |name: &str| format!("{} = {name}({});", top!(0), top!(0));
+		let binary = |name: &str| format!("{} = {name}({}, {});", top!(1), top!(1), top!(0));
+
+		let statement = match opcode {
+			Opcode::PushConstant => format!("stack[{depth}] = kn_retain(kn_constants[{operand}]);"),
+			Opcode::Jump => format!("goto L{operand};"),
+			Opcode::JumpIfTrue => format!("if (kn_truthy({})) goto L{operand};", top!(0)),
+			Opcode::JumpIfFalse => format!("if (!kn_truthy({})) goto L{operand};", top!(0)),
+			Opcode::GetVar => format!("stack[{depth}] = kn_get_variable({operand});"),
+			Opcode::SetVar => format!("kn_set_variable({operand}, kn_retain({}));", top!(0)),
+			Opcode::SetVarPop => format!("kn_set_variable({operand}, {});", top!(0)),
+
+			Opcode::AddVarConst | Opcode::SubVarConst => {
+				let FusedOperands { variable, constant, .. } = FusedOperands::unpack(operand);
+				let function = if opcode == Opcode::AddVarConst { "kn_add" } else { "kn_subtract" };
+				format!(
+					"kn_set_variable({variable}, {function}(kn_get_variable({variable}), \
+					 kn_retain(kn_constants[{constant}])));"
+				)
+			}
+			Opcode::BranchVarLth | Opcode::BranchVarGth | Opcode::BranchVarEql => {
+				let FusedOperands { variable, constant, target, jump_if } =
+					FusedOperands::unpack(operand);
+				let function = match opcode {
+					Opcode::BranchVarLth => "kn_less_than",
+					Opcode::BranchVarGth => "kn_greater_than",
+					_ => "kn_equals",
+				};
+				format!(
+					"if ({}kn_truthy({function}(kn_get_variable({variable}), \
+					 kn_retain(kn_constants[{constant}])))) goto L{target};",
+					if jump_if { "" } else { "!" }
+				)
+			}
+
+			Opcode::Prompt => format!("stack[{depth}] = kn_prompt();"),
+			Opcode::Random => format!("stack[{depth}] = kn_random();"),
+			Opcode::Dup => format!("stack[{depth}] = kn_retain({});", top!(0)),
+			Opcode::Dump => format!("kn_dump({});", top!(0)),
+
+			Opcode::Return => format!("return {};", top!(0)),
+			Opcode::Call => unary("kn_call"),
+			Opcode::Quit => format!("kn_quit({});", top!(0)),
+			Opcode::Output => unary("kn_output"),
+			Opcode::Length => unary("kn_length"),
+			Opcode::Not => unary("kn_not"),
+			Opcode::Negate => unary("kn_negate"),
+			Opcode::Ascii => unary("kn_ascii"),
+			Opcode::Box => unary("kn_box"),
+			Opcode::Head => unary("kn_head"),
+			Opcode::Tail => unary("kn_tail"),
+			Opcode::Pop => format!("kn_release({});", top!(0)),
+
+			Opcode::Add => binary("kn_add"),
+			Opcode::Sub => binary("kn_subtract"),
+			Opcode::Mul => binary("kn_multiply"),
+			Opcode::Div => binary("kn_divide"),
+			Opcode::Mod => binary("kn_remainder"),
+			Opcode::Pow => binary("kn_power"),
+			Opcode::Lth => binary("kn_less_than"),
+			Opcode::Gth => binary("kn_greater_than"),
+			Opcode::Eql => binary("kn_equals"),
+
+			Opcode::Get => {
+				format!("{} = kn_get({}, {}, {});", top!(2), top!(2), top!(1), top!(0))
+			}
+			Opcode::Set => {
+				format!("{} = kn_set({}, {}, {}, {});", top!(3), top!(3), top!(2), top!(1), top!(0))
+			}
+
+			_ => bug!("{:?} isn't supported by the C backend", opcode),
+		};
+
+		writeln!(f, "\t{statement}")
+	}
+}
+
+impl Display for CSource<'_, '_, '_, '_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let program = self.program;
+		let reachable =
+			|| program.instruction_offsets().filter(|&offset| self.depths[offset].is_some());
+
+		writeln!(f, "/* Translated from Knight by knightrs-bytecode. */")?;
+		for &(name, enabled) in &self.config {
+			writeln!(f, "#define {name} {}", enabled as u8)?;
+		}
+		writeln!(f, "#define KN_VARIABLE_COUNT {}", program.num_variables())?;
+		writeln!(f)?;
+		f.write_str(RUNTIME)?;
+		writeln!(f)?;
+
+		writeln!(f, "static const char *const kn_variable_names[KN_VARIABLE_COUNT + 1] = {{")?;
+		for variable in program.variables.iter() {
+			write!(f, "\t")?;
+			write_c_string(f, variable.to_string().as_bytes())?;
+			writeln!(f, ",")?;
+		}
+		writeln!(f, "\tNULL\n}};")?;
+		writeln!(f)?;
+
+		writeln!(f, "static kn_value kn_constants[{}];", program.constants.len() + 1)?;
+		writeln!(f)?;
+		writeln!(f, "static void kn_initialize_constants(void) {{")?;
+		for (idx, &constant) in program.constants.iter().enumerate() {
+			write!(f, "\tkn_constants[{idx}] = kn_immortal(")?;
+			write_c_value(f, constant)?;
+			writeln!(f, ");")?;
+		}
+		writeln!(f, "}}")?;
+		writeln!(f)?;
+
+		// The program itself starts at offset `0`, and is run just like any other block.
+		let mut blocks = BTreeSet::from([0]);
+		blocks.extend(program.block_constants().into_iter().map(|(_, block)| block.0));
+
+		// Only jump targets and the starts of blocks need labels.
+		let mut labels = blocks.clone();
+		for offset in reachable() {
+			// SAFETY: `offset` came from `instruction_offsets`.
+			let (opcode, operand) = unsafe { program.opcode_at(offset) };
+			labels.extend(jump_target(opcode, operand));
+		}
+
+		let stack_size = reachable().filter_map(|offset| self.depths[offset]).max().unwrap_or(0) + 1;
+		writeln!(f, "static kn_value kn_run(size_t block) {{")?;
+		writeln!(f, "\tkn_value stack[{stack_size}];")?;
+		writeln!(f)?;
+		writeln!(f, "\tswitch (block) {{")?;
+		for block in blocks {
+			writeln!(f, "\tcase {block}: goto L{block};")?;
+		}
+		writeln!(f, "\tdefault: kn_error(\"unknown block %zu\", block);")?;
+		writeln!(f, "\t}}")?;
+
+		for offset in reachable() {
+			if labels.contains(&offset) {
+				writeln!(f, "L{offset}:")?;
+			}
+
+			// SAFETY: `offset` came from `instruction_offsets`.
+			let (opcode, operand) = unsafe { program.opcode_at(offset) };
+			self.write_instruction(f, opcode, operand, self.depths[offset].unwrap())?;
+		}
+
+		writeln!(f, "}}")
+	}
+}
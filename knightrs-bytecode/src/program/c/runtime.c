@@ -0,0 +1,1213 @@
+/*
+ * The runtime for Knight programs translated to C by `Program::to_c`.
+ *
+ * Translated programs define the `KN_*` configuration macros, include this file, and then define
+ * `kn_run` (which runs a block), along with the program's variables and constants.
+ *
+ * Strings and lists are reference counted. Every function which takes a `kn_value` takes ownership
+ * of it, and every function which returns one returns an owned value, unless it's documented
+ * otherwise. Constants are never freed.
+ */
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#if defined(__GNUC__) || defined(__clang__)
+#define KN_NORETURN __attribute__((noreturn))
+#define KN_FORMAT(fmt, args) __attribute__((format(printf, fmt, args)))
+/* Programs rarely use every function in the runtime. */
+#pragma GCC diagnostic ignored "-Wunused-function"
+#pragma GCC diagnostic ignored "-Wunused-const-variable"
+#else
+#define KN_NORETURN
+#define KN_FORMAT(fmt, args)
+#endif
+
+/* Configuration, which translated programs set based on their `Options`. */
+#ifndef KN_I32_INTEGER
+#define KN_I32_INTEGER 0
+#endif
+#ifndef KN_CHECK_OVERFLOW
+#define KN_CHECK_OVERFLOW 0
+#endif
+#ifndef KN_CHECK_INTEGER_FUNCTION_BOUNDS
+#define KN_CHECK_INTEGER_FUNCTION_BOUNDS 0
+#endif
+#ifndef KN_LIMIT_RAND_RANGE
+#define KN_LIMIT_RAND_RANGE 0
+#endif
+#ifndef KN_CHECK_QUIT_STATUS_CODES
+#define KN_CHECK_QUIT_STATUS_CODES 0
+#endif
+#ifndef KN_CHECK_VARIABLES
+#define KN_CHECK_VARIABLES 0
+#endif
+#ifndef KN_ARGV
+#define KN_ARGV 0
+#endif
+
+/* The refcount of values which are never freed. */
+#define KN_IMMORTAL SIZE_MAX
+
+/* `KN_UNDEFINED` is only ever used for variables which haven't been assigned yet. */
+typedef enum {
+	KN_UNDEFINED,
+	KN_NULL,
+	KN_BOOLEAN,
+	KN_INTEGER,
+	KN_STRING,
+	KN_LIST,
+	KN_BLOCK
+} kn_kind;
+
+typedef struct kn_string kn_string;
+typedef struct kn_list kn_list;
+
+typedef struct {
+	kn_kind kind;
+	union {
+		bool boolean;
+		int64_t integer;
+		kn_string *string;
+		kn_list *list;
+		size_t block;
+	} as;
+} kn_value;
+
+struct kn_string {
+	size_t refcount;
+	size_t length;
+	char bytes[];
+};
+
+struct kn_list {
+	size_t refcount;
+	size_t length;
+	kn_value elements[];
+};
+
+/* Runs the block starting at the bytecode offset `block`; defined by the translated program. */
+static kn_value kn_run(size_t block);
+
+/*****************************************************************************************
+ *                                        Errors                                         *
+ *****************************************************************************************/
+
+KN_NORETURN KN_FORMAT(1, 2) static void kn_error(const char *format, ...) {
+	va_list args;
+
+	fflush(stdout);
+	fputs("error: runtime error: ", stderr);
+	va_start(args, format);
+	vfprintf(stderr, format, args);
+	va_end(args);
+	fputc('\n', stderr);
+	exit(1);
+}
+
+static const char *kn_type_name(kn_value value) {
+	switch (value.kind) {
+	case KN_NULL: return "Null";
+	case KN_BOOLEAN: return "Boolean";
+	case KN_INTEGER: return "Integer";
+	case KN_STRING: return "String";
+	case KN_LIST: return "List";
+	case KN_BLOCK: return "Block";
+	default: return "<undefined>";
+	}
+}
+
+KN_NORETURN static void kn_type_error(kn_value value, const char *function) {
+	kn_error("bad type %s to function \"%s\"", kn_type_name(value), function);
+}
+
+KN_NORETURN static void kn_overflow(char function) {
+	kn_error("method '%c' overflowed the bounds", function);
+}
+
+KN_NORETURN static void kn_out_of_memory(void) {
+	fflush(stdout);
+	fputs("error: out of memory\n", stderr);
+	abort();
+}
+
+static void *kn_allocate(size_t size) {
+	void *pointer = malloc(size);
+
+	if (pointer == NULL)
+		kn_out_of_memory();
+
+	return pointer;
+}
+
+/*****************************************************************************************
+ *                                     Construction                                      *
+ *****************************************************************************************/
+
+static kn_string kn_empty_string = { KN_IMMORTAL, 0 };
+static kn_list kn_empty_list = { KN_IMMORTAL, 0 };
+
+static kn_value kn_null(void) {
+	kn_value value;
+	value.kind = KN_NULL;
+	value.as.integer = 0;
+	return value;
+}
+
+static kn_value kn_boolean(bool boolean) {
+	kn_value value;
+	value.kind = KN_BOOLEAN;
+	value.as.boolean = boolean;
+	return value;
+}
+
+static kn_value kn_integer(int64_t integer) {
+	kn_value value;
+	value.kind = KN_INTEGER;
+	value.as.integer = integer;
+	return value;
+}
+
+static kn_value kn_block(size_t block) {
+	kn_value value;
+	value.kind = KN_BLOCK;
+	value.as.block = block;
+	return value;
+}
+
+static kn_value kn_string_value(kn_string *string) {
+	kn_value value;
+	value.kind = KN_STRING;
+	value.as.string = string;
+	return value;
+}
+
+static kn_value kn_list_value(kn_list *list) {
+	kn_value value;
+	value.kind = KN_LIST;
+	value.as.list = list;
+	return value;
+}
+
+/* Allocates a string of `length` bytes, which the caller must fill in. */
+static kn_string *kn_string_alloc(size_t length) {
+	kn_string *string;
+
+	if (length == 0)
+		return &kn_empty_string;
+
+	string = kn_allocate(sizeof(kn_string) + length + 1);
+	string->refcount = 1;
+	string->length = length;
+	string->bytes[length] = '\0';
+	return string;
+}
+
+static kn_value kn_string_new(const char *bytes, size_t length) {
+	kn_string *string = kn_string_alloc(length);
+	memcpy(string->bytes, bytes, length);
+	return kn_string_value(string);
+}
+
+/* Allocates a list of `length` elements, which the caller must fill in. */
+static kn_list *kn_list_alloc(size_t length) {
+	kn_list *list;
+
+	if (length == 0)
+		return &kn_empty_list;
+
+	list = kn_allocate(sizeof(kn_list) + length * sizeof(kn_value));
+	list->refcount = 1;
+	list->length = length;
+	return list;
+}
+
+/* Creates a list out of `length` `elements`, taking ownership of them. */
+static kn_value kn_list_from(size_t length, const kn_value *elements) {
+	kn_list *list = kn_list_alloc(length);
+	memcpy(list->elements, elements, length * sizeof(kn_value));
+	return kn_list_value(list);
+}
+
+/* Makes `value` (and everything in it, if it's a list) never be freed; used for constants. */
+static kn_value kn_immortal(kn_value value) {
+	if (value.kind == KN_STRING)
+		value.as.string->refcount = KN_IMMORTAL;
+	else if (value.kind == KN_LIST)
+		value.as.list->refcount = KN_IMMORTAL;
+
+	return value;
+}
+
+/*****************************************************************************************
+ *                                   Reference Counts                                    *
+ *****************************************************************************************/
+
+/* Borrows `value`, returning a new reference to it. */
+static kn_value kn_retain(kn_value value) {
+	if (value.kind == KN_STRING && value.as.string->refcount != KN_IMMORTAL)
+		value.as.string->refcount++;
+	else if (value.kind == KN_LIST && value.as.list->refcount != KN_IMMORTAL)
+		value.as.list->refcount++;
+
+	return value;
+}
+
+static void kn_release(kn_value value) {
+	size_t i;
+
+	if (value.kind == KN_STRING) {
+		if (value.as.string->refcount != KN_IMMORTAL && --value.as.string->refcount == 0)
+			free(value.as.string);
+	} else if (value.kind == KN_LIST) {
+		if (value.as.list->refcount != KN_IMMORTAL && --value.as.list->refcount == 0) {
+			for (i = 0; i < value.as.list->length; i++)
+				kn_release(value.as.list->elements[i]);
+			free(value.as.list);
+		}
+	}
+}
+
+/*****************************************************************************************
+ *                                       Integers                                        *
+ *****************************************************************************************/
+
+#if KN_I32_INTEGER
+#define KN_INTEGER_MAX ((int64_t) INT32_MAX)
+#define KN_INTEGER_MIN ((int64_t) INT32_MIN)
+#else
+#define KN_INTEGER_MAX (INT64_MAX >> 1)
+#define KN_INTEGER_MIN (INT64_MIN >> 1)
+#endif
+
+/* Truncates `integer` to 63 bits, which is all the interpreter has room for. */
+static int64_t kn_truncate(int64_t integer) {
+	return (int64_t) ((uint64_t) integer << 1) >> 1;
+}
+
+/* Creates the result of the function `function`, making sure it's in bounds. */
+static kn_value kn_integer_result(int64_t integer, char function) {
+#if KN_I32_INTEGER
+	if (integer < KN_INTEGER_MIN || KN_INTEGER_MAX < integer)
+		kn_overflow(function);
+#else
+	(void) function;
+#endif
+
+	return kn_integer(kn_truncate(integer));
+}
+
+static int64_t kn_add_integers(int64_t lhs, int64_t rhs) {
+#if KN_CHECK_OVERFLOW
+	int64_t result;
+	if (__builtin_add_overflow(lhs, rhs, &result))
+		kn_overflow('+');
+	return result;
+#else
+	return (int64_t) ((uint64_t) lhs + (uint64_t) rhs);
+#endif
+}
+
+static int64_t kn_subtract_integers(int64_t lhs, int64_t rhs) {
+#if KN_CHECK_OVERFLOW
+	int64_t result;
+	if (__builtin_sub_overflow(lhs, rhs, &result))
+		kn_overflow('-');
+	return result;
+#else
+	return (int64_t) ((uint64_t) lhs - (uint64_t) rhs);
+#endif
+}
+
+static int64_t kn_multiply_integers(int64_t lhs, int64_t rhs, char function) {
+#if KN_CHECK_OVERFLOW
+	int64_t result;
+	if (__builtin_mul_overflow(lhs, rhs, &result))
+		kn_overflow(function);
+	return result;
+#else
+	(void) function;
+	return (int64_t) ((uint64_t) lhs * (uint64_t) rhs);
+#endif
+}
+
+static int64_t kn_power_integers(int64_t base, int64_t exponent) {
+	int64_t result = 1;
+	uint32_t remaining;
+
+	if (exponent < 0) {
+#if KN_CHECK_INTEGER_FUNCTION_BOUNDS
+		kn_error("domain error: negative exponent");
+#else
+		switch (base) {
+		case -1: return exponent % 2 == 0 ? base : 1;
+		case 0: kn_error("0 exponentiated by a negative power");
+		case 1: return 1;
+		default: return 0;
+		}
+#endif
+	}
+
+	if (UINT32_MAX < (uint64_t) exponent) {
+		if (base == 0 || base == 1)
+			return base;
+		kn_error("domain error: exponent too large");
+	}
+
+	for (remaining = (uint32_t) exponent; remaining != 0; remaining >>= 1) {
+		if (remaining & 1)
+			result = kn_multiply_integers(result, base, '^');
+		if (remaining != 1)
+			base = kn_multiply_integers(base, base, '^');
+	}
+
+	return result;
+}
+
+/* Parses an integer out of `string` (borrowed), like the Knight spec says to. */
+static int64_t kn_parse_integer(const kn_string *string) {
+	const char *bytes = string->bytes, *end = bytes + string->length;
+	bool negative = false;
+	uint64_t integer = 0;
+
+	while (bytes < end && (*bytes == ' ' || ('\t' <= *bytes && *bytes <= '\r')))
+		bytes++;
+
+	if (bytes < end && (*bytes == '-' || *bytes == '+'))
+		negative = *bytes++ == '-';
+
+	for (; bytes < end && '0' <= *bytes && *bytes <= '9'; bytes++) {
+		if ((uint64_t) INT64_MAX / 10 < integer)
+			kn_error("integer overflow");
+
+		integer = integer * 10 + (uint64_t) (*bytes - '0');
+
+		if ((uint64_t) INT64_MAX + negative < integer)
+			kn_error("integer overflow");
+	}
+
+	integer = negative ? (uint64_t) 0 - integer : integer;
+
+#if KN_I32_INTEGER
+	if ((int64_t) integer < KN_INTEGER_MIN || KN_INTEGER_MAX < (int64_t) integer)
+		kn_error("integer %" PRId64 " is out of bounds", (int64_t) integer);
+#endif
+
+	return kn_truncate((int64_t) integer);
+}
+
+/*****************************************************************************************
+ *                                      Conversions                                      *
+ *****************************************************************************************/
+
+/* Converts `value` (borrowed) to a boolean. */
+static bool kn_to_boolean(kn_value value) {
+	switch (value.kind) {
+	case KN_NULL: return false;
+	case KN_BOOLEAN: return value.as.boolean;
+	case KN_INTEGER: return value.as.integer != 0;
+	case KN_STRING: return value.as.string->length != 0;
+	case KN_LIST: return value.as.list->length != 0;
+	default: return true;
+	}
+}
+
+/* Converts `value` (borrowed) to an integer. */
+static int64_t kn_to_integer(kn_value value) {
+	switch (value.kind) {
+	case KN_NULL: return 0;
+	case KN_BOOLEAN: return value.as.boolean;
+	case KN_INTEGER: return value.as.integer;
+	case KN_STRING: return kn_parse_integer(value.as.string);
+	case KN_LIST: return (int64_t) value.as.list->length;
+	default: kn_error("cannot convert Blocks to integers");
+	}
+}
+
+static kn_string *kn_list_join(const kn_list *list, const kn_string *separator);
+
+/* Converts `value` to a string. */
+static kn_string *kn_to_string(kn_value value) {
+	static kn_string *newline = NULL;
+	char buffer[32];
+	kn_string *string;
+
+	switch (value.kind) {
+	case KN_NULL:
+		return &kn_empty_string;
+
+	case KN_BOOLEAN:
+		return kn_string_new(value.as.boolean ? "true" : "false", value.as.boolean ? 4 : 5)
+		    .as.string;
+
+	case KN_INTEGER:
+		snprintf(buffer, sizeof buffer, "%" PRId64, value.as.integer);
+		return kn_string_new(buffer, strlen(buffer)).as.string;
+
+	case KN_STRING:
+		return value.as.string;
+
+	case KN_LIST:
+		if (newline == NULL)
+			newline = kn_immortal(kn_string_new("\n", 1)).as.string;
+
+		string = kn_list_join(value.as.list, newline);
+		kn_release(value);
+		return string;
+
+	default:
+		kn_error("cannot convert Blocks to strings");
+	}
+}
+
+/* The length of the UTF-8 character starting with `byte`. */
+static size_t kn_char_length(unsigned char byte) {
+	if (byte < 0x80)
+		return 1;
+	if (byte < 0xE0)
+		return 2;
+	if (byte < 0xF0)
+		return 3;
+	return 4;
+}
+
+/* Converts `value` to a list. */
+static kn_list *kn_to_list(kn_value value) {
+	kn_list *list;
+	size_t i, length;
+	int64_t integer;
+
+	switch (value.kind) {
+	case KN_NULL:
+		return &kn_empty_list;
+
+	case KN_BOOLEAN:
+		if (!value.as.boolean)
+			return &kn_empty_list;
+		list = kn_list_alloc(1);
+		list->elements[0] = value;
+		return list;
+
+	case KN_INTEGER:
+		integer = value.as.integer;
+		for (length = 1; integer / 10 != 0; integer /= 10)
+			length++;
+
+		list = kn_list_alloc(length);
+		integer = value.as.integer;
+		for (i = length; i != 0; integer /= 10)
+			list->elements[--i] = kn_integer(integer % 10);
+		return list;
+
+	case KN_STRING:
+		length = 0;
+		for (i = 0; i < value.as.string->length; i += kn_char_length(value.as.string->bytes[i]))
+			length++;
+
+		list = kn_list_alloc(length);
+		length = 0;
+		for (i = 0; i < value.as.string->length; i += kn_char_length(value.as.string->bytes[i])) {
+			list->elements[length++] = kn_string_new(
+			    value.as.string->bytes + i,
+			    kn_char_length(value.as.string->bytes[i]));
+		}
+
+		kn_release(value);
+		return list;
+
+	case KN_LIST:
+		return value.as.list;
+
+	default:
+		kn_error("cannot convert Blocks to lists");
+	}
+}
+
+/*****************************************************************************************
+ *                                     Strings and Lists                                  *
+ *****************************************************************************************/
+
+/* Joins `list` (borrowed) with `separator` (borrowed). */
+static kn_string *kn_list_join(const kn_list *list, const kn_string *separator) {
+	kn_string **strings, *result;
+	size_t i, length = 0, position = 0;
+
+	if (list->length == 0)
+		return &kn_empty_string;
+
+	strings = kn_allocate(list->length * sizeof(kn_string *));
+	for (i = 0; i < list->length; i++) {
+		strings[i] = kn_to_string(kn_retain(list->elements[i]));
+		length += strings[i]->length + (i == 0 ? 0 : separator->length);
+	}
+
+	result = kn_string_alloc(length);
+	for (i = 0; i < list->length; i++) {
+		if (i != 0) {
+			memcpy(result->bytes + position, separator->bytes, separator->length);
+			position += separator->length;
+		}
+
+		memcpy(result->bytes + position, strings[i]->bytes, strings[i]->length);
+		position += strings[i]->length;
+		kn_release(kn_string_value(strings[i]));
+	}
+
+	free(strings);
+	return result;
+}
+
+/* Makes a new list out of `length` elements of `list` (borrowed) starting at `start`, followed by
+ * `count` elements from `rest` (borrowed), followed by `list`'s elements from `resume` onwards. */
+static kn_list *kn_list_splice(
+    const kn_list *list,
+    size_t start,
+    size_t length,
+    const kn_list *rest,
+    size_t count,
+    size_t resume) {
+	size_t i, tail = resume < list->length ? list->length - resume : 0;
+	kn_list *result = kn_list_alloc(length + count + tail);
+
+	for (i = 0; i < length; i++)
+		result->elements[i] = kn_retain(list->elements[start + i]);
+	for (i = 0; i < count; i++)
+		result->elements[length + i] = kn_retain(rest->elements[i]);
+	for (i = 0; i < tail; i++)
+		result->elements[length + count + i] = kn_retain(list->elements[resume + i]);
+
+	return result;
+}
+
+/* Converts `start` and `length` into the start and end of a range, for `GET` and `SET`. */
+static void kn_range(kn_value start, kn_value length, size_t *range_start, size_t *range_end) {
+	int64_t begin = kn_to_integer(start), amount;
+
+	kn_release(start);
+	if (begin < 0)
+		kn_error("domain error: negative start position");
+
+	amount = kn_to_integer(length);
+	kn_release(length);
+	if (amount < 0)
+		kn_error("domain error: negative length");
+
+	*range_start = (size_t) begin;
+	*range_end = (size_t) begin + (size_t) amount;
+}
+
+/* Compares `lhs` and `rhs` (both borrowed) for `<` and `>`, which is `function`. */
+static int kn_compare(kn_value lhs, kn_value rhs, const char *function) {
+	kn_string *string;
+	kn_list *list;
+	size_t i, length;
+	int64_t integer;
+	bool boolean;
+	int cmp;
+
+	switch (lhs.kind) {
+	case KN_INTEGER:
+		integer = kn_to_integer(rhs);
+		return (lhs.as.integer > integer) - (lhs.as.integer < integer);
+
+	case KN_BOOLEAN:
+		boolean = kn_to_boolean(rhs);
+		return (int) lhs.as.boolean - (int) boolean;
+
+	case KN_STRING:
+		string = kn_to_string(kn_retain(rhs));
+		length = lhs.as.string->length < string->length ? lhs.as.string->length : string->length;
+		cmp = memcmp(lhs.as.string->bytes, string->bytes, length);
+		if (cmp == 0)
+			cmp = (lhs.as.string->length > string->length) - (lhs.as.string->length < string->length);
+		kn_release(kn_string_value(string));
+		return (cmp > 0) - (cmp < 0);
+
+	case KN_LIST:
+		list = kn_to_list(kn_retain(rhs));
+		length = lhs.as.list->length < list->length ? lhs.as.list->length : list->length;
+		cmp = 0;
+		for (i = 0; cmp == 0 && i < length; i++)
+			cmp = kn_compare(lhs.as.list->elements[i], list->elements[i], function);
+		if (cmp == 0)
+			cmp = (lhs.as.list->length > list->length) - (lhs.as.list->length < list->length);
+		kn_release(kn_list_value(list));
+		return cmp;
+
+	default:
+		kn_type_error(lhs, function);
+	}
+}
+
+/* Checks whether `lhs` and `rhs` (both borrowed) are equal. */
+static bool kn_equal(kn_value lhs, kn_value rhs) {
+	size_t i;
+
+	if (lhs.kind != rhs.kind)
+		return false;
+
+	switch (lhs.kind) {
+	case KN_NULL: return true;
+	case KN_BOOLEAN: return lhs.as.boolean == rhs.as.boolean;
+	case KN_INTEGER: return lhs.as.integer == rhs.as.integer;
+	case KN_BLOCK: return lhs.as.block == rhs.as.block;
+
+	case KN_STRING:
+		return lhs.as.string->length == rhs.as.string->length
+		    && memcmp(lhs.as.string->bytes, rhs.as.string->bytes, lhs.as.string->length) == 0;
+
+	case KN_LIST:
+		if (lhs.as.list->length != rhs.as.list->length)
+			return false;
+		for (i = 0; i < lhs.as.list->length; i++)
+			if (!kn_equal(lhs.as.list->elements[i], rhs.as.list->elements[i]))
+				return false;
+		return true;
+
+	default:
+		return false;
+	}
+}
+
+/*****************************************************************************************
+ *                                       Functions                                       *
+ *****************************************************************************************/
+
+static bool kn_truthy(kn_value value) {
+	bool truthy = kn_to_boolean(value);
+	kn_release(value);
+	return truthy;
+}
+
+static kn_value kn_prompt(void) {
+	size_t length = 0, capacity = 64;
+	char *line = kn_allocate(capacity);
+	kn_value result;
+	int chr;
+
+	while ((chr = getchar()) != EOF && chr != '\n') {
+		if (length == capacity && (line = realloc(line, capacity *= 2)) == NULL)
+			kn_out_of_memory();
+		line[length++] = (char) chr;
+	}
+
+	if (length == 0 && chr == EOF) {
+		free(line);
+		return kn_null();
+	}
+
+	if (length != 0 && line[length - 1] == '\r')
+		length--;
+
+	result = kn_string_new(line, length);
+	free(line);
+	return result;
+}
+
+static kn_value kn_random(void) {
+	static uint64_t state = 0;
+
+	if (state == 0)
+		state = (uint64_t) time(NULL) * 6364136223846793005u + 1442695040888963407u;
+
+	/* xorshift64 */
+	state ^= state << 13;
+	state ^= state >> 7;
+	state ^= state << 17;
+
+#if KN_LIMIT_RAND_RANGE
+	return kn_integer((int64_t) (state % 0x8000));
+#else
+	return kn_integer((int64_t) (state % ((uint64_t) KN_INTEGER_MAX + 1)));
+#endif
+}
+
+static kn_value kn_call(kn_value value) {
+	if (value.kind != KN_BLOCK)
+		kn_type_error(value, "CALL");
+
+	return kn_run(value.as.block);
+}
+
+KN_NORETURN static void kn_quit(kn_value value) {
+	int64_t status = kn_to_integer(value);
+
+	if (status < INT32_MIN || INT32_MAX < status)
+		kn_error("domain error: QUIT: not in bounds");
+
+#if KN_CHECK_QUIT_STATUS_CODES
+	if (status < 0 || 127 < status)
+		kn_error("domain error: QUIT: not in bounds");
+#endif
+
+	exit((int) status);
+}
+
+static void kn_dump_string(const kn_string *string) {
+	size_t i;
+	unsigned char chr;
+
+	putchar('"');
+	for (i = 0; i < string->length; i++) {
+		switch (chr = (unsigned char) string->bytes[i]) {
+		case '\t': fputs("\\t", stdout); break;
+		case '\n': fputs("\\n", stdout); break;
+		case '\r': fputs("\\r", stdout); break;
+		case '\0': fputs("\\0", stdout); break;
+		case '"': fputs("\\\"", stdout); break;
+		case '\\': fputs("\\\\", stdout); break;
+		default:
+			if (chr < 0x20 || chr == 0x7F)
+				printf("\\u{%x}", chr);
+			else
+				putchar(chr);
+		}
+	}
+	putchar('"');
+}
+
+/* Dumps `value` (borrowed). */
+static void kn_dump(kn_value value) {
+	size_t i;
+
+	switch (value.kind) {
+	case KN_NULL: fputs("null", stdout); break;
+	case KN_BOOLEAN: fputs(value.as.boolean ? "true" : "false", stdout); break;
+	case KN_INTEGER: printf("%" PRId64, value.as.integer); break;
+	case KN_STRING: kn_dump_string(value.as.string); break;
+	case KN_LIST:
+		putchar('[');
+		for (i = 0; i < value.as.list->length; i++) {
+			if (i != 0)
+				fputs(", ", stdout);
+			kn_dump(value.as.list->elements[i]);
+		}
+		putchar(']');
+		break;
+	default: kn_type_error(value, "DUMP");
+	}
+}
+
+static kn_value kn_output(kn_value value) {
+	kn_string *string = kn_to_string(value);
+
+	if (string->length != 0 && string->bytes[string->length - 1] == '\\') {
+		fwrite(string->bytes, 1, string->length - 1, stdout);
+	} else {
+		fwrite(string->bytes, 1, string->length, stdout);
+		putchar('\n');
+	}
+
+	kn_release(kn_string_value(string));
+	return kn_null();
+}
+
+static kn_value kn_length(kn_value value) {
+	size_t length;
+
+	if (value.kind == KN_STRING)
+		length = value.as.string->length;
+	else {
+		value = kn_list_value(kn_to_list(value));
+		length = value.as.list->length;
+	}
+
+	kn_release(value);
+	return kn_integer((int64_t) length);
+}
+
+static kn_value kn_not(kn_value value) {
+	return kn_boolean(!kn_truthy(value));
+}
+
+static kn_value kn_negate(kn_value value) {
+	int64_t integer = kn_to_integer(value);
+	kn_release(value);
+	return kn_integer_result(kn_subtract_integers(0, integer), '~');
+}
+
+static kn_value kn_ascii(kn_value value) {
+	const unsigned char *bytes;
+	int64_t codepoint;
+	char buffer[4];
+
+	if (value.kind == KN_INTEGER) {
+		codepoint = value.as.integer;
+
+		if (codepoint < 0 || 0x10FFFF < codepoint || (0xD800 <= codepoint && codepoint <= 0xDFFF))
+			kn_error("integer Integer(%" PRId64 ") isn't a valid char for Utf8", codepoint);
+
+		if (codepoint < 0x80) {
+			buffer[0] = (char) codepoint;
+			return kn_string_new(buffer, 1);
+		} else if (codepoint < 0x800) {
+			buffer[0] = (char) (0xC0 | (codepoint >> 6));
+			buffer[1] = (char) (0x80 | (codepoint & 0x3F));
+			return kn_string_new(buffer, 2);
+		} else if (codepoint < 0x10000) {
+			buffer[0] = (char) (0xE0 | (codepoint >> 12));
+			buffer[1] = (char) (0x80 | ((codepoint >> 6) & 0x3F));
+			buffer[2] = (char) (0x80 | (codepoint & 0x3F));
+			return kn_string_new(buffer, 3);
+		} else {
+			buffer[0] = (char) (0xF0 | (codepoint >> 18));
+			buffer[1] = (char) (0x80 | ((codepoint >> 12) & 0x3F));
+			buffer[2] = (char) (0x80 | ((codepoint >> 6) & 0x3F));
+			buffer[3] = (char) (0x80 | (codepoint & 0x3F));
+			return kn_string_new(buffer, 4);
+		}
+	}
+
+	if (value.kind != KN_STRING)
+		kn_type_error(value, "ASCII");
+
+	if (value.as.string->length == 0)
+		kn_error("domain error: empty string for head");
+
+	bytes = (const unsigned char *) value.as.string->bytes;
+	switch (kn_char_length(bytes[0])) {
+	case 1: codepoint = bytes[0]; break;
+	case 2: codepoint = (bytes[0] & 0x1F) << 6 | (bytes[1] & 0x3F); break;
+	case 3: codepoint = (bytes[0] & 0x0F) << 12 | (bytes[1] & 0x3F) << 6 | (bytes[2] & 0x3F); break;
+	default:
+		codepoint = (int64_t) (bytes[0] & 0x07) << 18 | (bytes[1] & 0x3F) << 12
+		    | (bytes[2] & 0x3F) << 6 | (bytes[3] & 0x3F);
+	}
+
+	kn_release(value);
+	return kn_integer(codepoint);
+}
+
+static kn_value kn_box(kn_value value) {
+	kn_list *list = kn_list_alloc(1);
+	list->elements[0] = value;
+	return kn_list_value(list);
+}
+
+static kn_value kn_head(kn_value value) {
+	kn_value head;
+
+	if (value.kind == KN_STRING) {
+		if (value.as.string->length == 0)
+			kn_error("domain error: empty string for head");
+		head = kn_string_new(value.as.string->bytes, kn_char_length(value.as.string->bytes[0]));
+	} else if (value.kind == KN_LIST) {
+		if (value.as.list->length == 0)
+			kn_error("domain error: empty list for head");
+		head = kn_retain(value.as.list->elements[0]);
+	} else {
+		kn_type_error(value, "[");
+	}
+
+	kn_release(value);
+	return head;
+}
+
+static kn_value kn_tail(kn_value value) {
+	kn_value tail;
+	size_t first;
+
+	if (value.kind == KN_STRING) {
+		if (value.as.string->length == 0)
+			kn_error("domain error: empty string for tail");
+		first = kn_char_length(value.as.string->bytes[0]);
+		tail = kn_string_new(value.as.string->bytes + first, value.as.string->length - first);
+	} else if (value.kind == KN_LIST) {
+		if (value.as.list->length == 0)
+			kn_error("domain error: empty list for head");
+		tail = kn_list_value(
+		    kn_list_splice(value.as.list, 1, value.as.list->length - 1, &kn_empty_list, 0, SIZE_MAX));
+	} else {
+		kn_type_error(value, "]");
+	}
+
+	kn_release(value);
+	return tail;
+}
+
+static kn_value kn_add(kn_value lhs, kn_value rhs) {
+	kn_string *string, *rhs_string;
+	kn_list *list;
+	int64_t integer;
+
+	switch (lhs.kind) {
+	case KN_INTEGER:
+		integer = kn_to_integer(rhs);
+		kn_release(rhs);
+		return kn_integer_result(kn_add_integers(lhs.as.integer, integer), '+');
+
+	case KN_STRING:
+		rhs_string = kn_to_string(rhs);
+		if (rhs_string->length == 0) {
+			kn_release(kn_string_value(rhs_string));
+			return lhs;
+		}
+
+		string = kn_string_alloc(lhs.as.string->length + rhs_string->length);
+		memcpy(string->bytes, lhs.as.string->bytes, lhs.as.string->length);
+		memcpy(string->bytes + lhs.as.string->length, rhs_string->bytes, rhs_string->length);
+		kn_release(lhs);
+		kn_release(kn_string_value(rhs_string));
+		return kn_string_value(string);
+
+	case KN_LIST:
+		list = kn_to_list(rhs);
+		rhs = kn_list_value(kn_list_splice(lhs.as.list, 0, lhs.as.list->length, list, list->length, SIZE_MAX));
+		kn_release(lhs);
+		kn_release(kn_list_value(list));
+		return rhs;
+
+	default:
+		kn_type_error(lhs, "+");
+	}
+}
+
+static kn_value kn_subtract(kn_value lhs, kn_value rhs) {
+	int64_t integer;
+
+	if (lhs.kind != KN_INTEGER)
+		kn_type_error(lhs, "-");
+
+	integer = kn_to_integer(rhs);
+	kn_release(rhs);
+	return kn_integer_result(kn_subtract_integers(lhs.as.integer, integer), '-');
+}
+
+static kn_value kn_multiply(kn_value lhs, kn_value rhs) {
+	kn_string *string;
+	kn_list *list;
+	int64_t amount;
+	size_t i, length;
+
+	if (lhs.kind != KN_INTEGER && lhs.kind != KN_STRING && lhs.kind != KN_LIST)
+		kn_type_error(lhs, "*");
+
+	amount = kn_to_integer(rhs);
+	kn_release(rhs);
+
+	switch (lhs.kind) {
+	case KN_INTEGER:
+		return kn_integer_result(kn_multiply_integers(lhs.as.integer, amount, '*'), '*');
+
+	case KN_STRING:
+		if (amount < 0)
+			kn_error("domain error: repetition count is negative");
+
+		length = lhs.as.string->length;
+		if (length != 0 && (size_t) amount > (SIZE_MAX / 2) / length)
+			kn_error("domain error: repetition is too large");
+
+		string = kn_string_alloc(length * (size_t) amount);
+		for (i = 0; i < (size_t) amount; i++)
+			memcpy(string->bytes + i * length, lhs.as.string->bytes, length);
+		kn_release(lhs);
+		return kn_string_value(string);
+
+	case KN_LIST:
+		if (amount < 0)
+			kn_error("domain error: repetition count is negative");
+
+		length = lhs.as.list->length;
+		if (length != 0 && (size_t) amount > (SIZE_MAX / 2) / sizeof(kn_value) / length)
+			kn_error("bounds too large!");
+
+		list = kn_list_alloc(length * (size_t) amount);
+		for (i = 0; i < length * (size_t) amount; i++)
+			list->elements[i] = kn_retain(lhs.as.list->elements[i % length]);
+		kn_release(lhs);
+		return kn_list_value(list);
+
+	default:
+		kn_type_error(lhs, "*");
+	}
+}
+
+static kn_value kn_divide(kn_value lhs, kn_value rhs) {
+	int64_t integer;
+
+	if (lhs.kind != KN_INTEGER)
+		kn_type_error(lhs, "/");
+
+	integer = kn_to_integer(rhs);
+	kn_release(rhs);
+	if (integer == 0)
+		kn_error("division by zero");
+
+	return kn_integer_result(lhs.as.integer / integer, '/');
+}
+
+static kn_value kn_remainder(kn_value lhs, kn_value rhs) {
+	int64_t integer;
+
+	if (lhs.kind != KN_INTEGER)
+		kn_type_error(lhs, "%");
+
+	integer = kn_to_integer(rhs);
+	kn_release(rhs);
+	if (integer == 0)
+		kn_error("remainder by zero");
+
+#if KN_CHECK_INTEGER_FUNCTION_BOUNDS
+	if (lhs.as.integer < 0)
+		kn_error("domain error: remainder with a negative number");
+	if (integer < 0)
+		kn_error("domain error: remainder by a negative base");
+#endif
+
+	return kn_integer_result(lhs.as.integer % integer, '%');
+}
+
+static kn_value kn_power(kn_value lhs, kn_value rhs) {
+	kn_string *separator, *joined;
+	int64_t exponent;
+
+	if (lhs.kind == KN_LIST) {
+		separator = kn_to_string(rhs);
+		joined = kn_list_join(lhs.as.list, separator);
+		kn_release(kn_string_value(separator));
+		kn_release(lhs);
+		return kn_string_value(joined);
+	}
+
+	if (lhs.kind != KN_INTEGER)
+		kn_type_error(lhs, "^");
+
+	exponent = kn_to_integer(rhs);
+	kn_release(rhs);
+	return kn_integer_result(kn_power_integers(lhs.as.integer, exponent), '^');
+}
+
+static kn_value kn_less_than(kn_value lhs, kn_value rhs) {
+	bool less = kn_compare(lhs, rhs, "<") < 0;
+	kn_release(lhs);
+	kn_release(rhs);
+	return kn_boolean(less);
+}
+
+static kn_value kn_greater_than(kn_value lhs, kn_value rhs) {
+	bool greater = kn_compare(lhs, rhs, ">") > 0;
+	kn_release(lhs);
+	kn_release(rhs);
+	return kn_boolean(greater);
+}
+
+static kn_value kn_equals(kn_value lhs, kn_value rhs) {
+	bool equal = kn_equal(lhs, rhs);
+	kn_release(lhs);
+	kn_release(rhs);
+	return kn_boolean(equal);
+}
+
+static kn_value kn_get(kn_value value, kn_value start, kn_value length) {
+	size_t begin, end;
+	kn_value result;
+
+	kn_range(start, length, &begin, &end);
+
+	if (value.kind == KN_LIST) {
+		if (value.as.list->length < end)
+			kn_error("domain error: invalid args for get for list");
+		result = kn_list_value(kn_list_splice(value.as.list, begin, end - begin, &kn_empty_list, 0, SIZE_MAX));
+	} else if (value.kind == KN_STRING) {
+		if (value.as.string->length < end)
+			kn_error("domain error: invalid args for get for str");
+		result = kn_string_new(value.as.string->bytes + begin, end - begin);
+	} else {
+		kn_type_error(value, "GET");
+	}
+
+	kn_release(value);
+	return result;
+}
+
+static kn_value kn_set(kn_value value, kn_value start, kn_value length, kn_value replacement) {
+	size_t begin, end, prefix, suffix;
+	kn_string *string, *rest;
+	kn_list *list;
+	kn_value result;
+
+	kn_range(start, length, &begin, &end);
+
+	if (value.kind == KN_LIST) {
+		list = kn_to_list(replacement);
+		prefix = begin < value.as.list->length ? begin : value.as.list->length;
+		result = kn_list_value(kn_list_splice(value.as.list, 0, prefix, list, list->length, end));
+		kn_release(kn_list_value(list));
+	} else if (value.kind == KN_STRING) {
+		rest = kn_to_string(replacement);
+		prefix = begin < value.as.string->length ? begin : value.as.string->length;
+		suffix = end < value.as.string->length ? value.as.string->length - end : 0;
+
+		string = kn_string_alloc(prefix + rest->length + suffix);
+		memcpy(string->bytes, value.as.string->bytes, prefix);
+		memcpy(string->bytes + prefix, rest->bytes, rest->length);
+		if (suffix != 0)
+			memcpy(string->bytes + prefix + rest->length, value.as.string->bytes + end, suffix);
+		kn_release(kn_string_value(rest));
+		result = kn_string_value(string);
+	} else {
+		kn_type_error(value, "SET");
+	}
+
+	kn_release(value);
+	return result;
+}
+
+/*****************************************************************************************
+ *                                       Variables                                       *
+ *****************************************************************************************/
+
+/* Both of these are defined by the translated program. */
+static kn_value kn_variables[KN_VARIABLE_COUNT + 1];
+static const char *const kn_variable_names[KN_VARIABLE_COUNT + 1];
+
+static kn_value kn_get_variable(size_t index) {
+	kn_value value = kn_variables[index];
+
+	if (value.kind == KN_UNDEFINED) {
+#if KN_CHECK_VARIABLES
+		kn_error("undefined variable %s accessed", kn_variable_names[index]);
+#else
+		return kn_null();
+#endif
+	}
+
+	return kn_retain(value);
+}
+
+static void kn_set_variable(size_t index, kn_value value) {
+	kn_release(kn_variables[index]);
+	kn_variables[index] = value;
+}
+
+/*****************************************************************************************
+ *                                         Main                                          *
+ *****************************************************************************************/
+
+/* Creates all the constants; defined by the translated program. */
+static void kn_initialize_constants(void);
+
+int main(int argc, char **argv) {
+#if KN_ARGV
+	kn_list *arguments;
+	int i, skip = 1 < argc && strcmp(argv[1], "--") == 0;
+
+	arguments = kn_list_alloc((size_t) (argc - 1 - skip));
+	for (i = 1 + skip; i < argc; i++)
+		arguments->elements[i - 1 - skip] = kn_string_new(argv[i], strlen(argv[i]));
+	kn_set_variable(0, kn_list_value(arguments));
+#else
+	(void) argc;
+	(void) argv;
+#endif
+
+	kn_initialize_constants();
+	kn_release(kn_run(0));
+	return 0;
+}
@@ -1,13 +1,16 @@
-use super::{DeferredJump, InstructionAndOffset, JumpIndex, JumpWhen, Program};
+mod peephole;
+mod superinstructions;
+
+use super::{jump_target, pad_code, retarget, DeferredJump, JumpIndex, JumpWhen, Program};
 use crate::gc::Gc;
 use crate::options::Options;
 use crate::parser::{ParseError, ParseErrorKind, SourceLocation, VariableName};
 use crate::strings::KnStr;
-use crate::value::Value;
+use crate::value::{Block, List, Value};
 use crate::vm::Opcode;
 
 use indexmap::IndexSet;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 // safety: cannot do invalid things with the builder.
 pub unsafe trait Compilable<'src, 'path, 'gc> {
@@ -21,11 +24,10 @@ pub unsafe trait Compilable<'src, 'path, 'gc> {
 
 /// A Compiler is used to construct [`Program`]s, which are then run via the [`Vm`](crate::Vm).
 pub struct Compiler<'src, 'path, 'gc> {
-	// The current code so far; The bottom-most byte is the opcode, and when that's shifted away, the
-	// remainder is the offset.
-	code: Vec<InstructionAndOffset>,
+	// The current code so far, one instruction per element. It's only encoded into bytes when the
+	// program is built, so until then all jump indices refer to instructions, not bytes.
+	code: Vec<(Opcode, usize)>,
 
-	#[allow(unused)] // it'll be actually used later
 	gc: &'gc Gc,
 
 	// All the constants that've been declared so far. Used with [`Opcode::PushConstant`].
@@ -42,6 +44,10 @@ pub struct Compiler<'src, 'path, 'gc> {
 	#[cfg(feature = "stacktrace")]
 	source_lines: HashMap<usize, SourceLocation<'path>>,
 
+	// The location most recently recorded in `source_lines`, so the same one isn't added repeatedly.
+	#[cfg(feature = "stacktrace")]
+	current_location: SourceLocation<'path>,
+
 	// Only enabled when stacktrace printing is enabled, this is a mapping of jump indices (which
 	// correspond to the first instruction of a [`Block`]) to the (optional) name of the block, and
 	// the location where the block was declared.
@@ -55,8 +61,18 @@ pub struct Compiler<'src, 'path, 'gc> {
 	_ignored: &'path (),
 }
 
-fn code_from_opcode_and_offset(opcode: Opcode, offset: usize) -> InstructionAndOffset {
-	opcode as InstructionAndOffset | (offset as InstructionAndOffset) << 0o10
+// The offset used by jumps which have been deferred, but not yet `jump_to`'d.
+const DEFERRED_JUMP_OFFSET: usize = usize::MAX;
+
+// Calls `func` with every block within `value`, including those nested within lists.
+fn for_each_block(value: Value<'_>, func: &mut impl FnMut(Block)) {
+	if let Some(block) = value.as_block() {
+		func(block);
+	} else if let Some(list) = value.as_list() {
+		for element in list.iter() {
+			for_each_block(element, func);
+		}
+	}
 }
 
 // TODO: Make a "build-a-block" function
@@ -87,6 +103,9 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 				sl
 			},
 
+			#[cfg(feature = "stacktrace")]
+			current_location: start.clone(),
+
 			#[cfg(feature = "stacktrace")]
 			block_locations: {
 				let mut bl = HashMap::new();
@@ -104,7 +123,8 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 	/// value on top of its stack whenever it returns, which is the return value of the program.
 	///
 	/// Additionally, the caller must enure that all deferred jumps have been `jump_to`'d
-	pub unsafe fn build(mut self) -> Program<'src, 'path, 'gc> {
+	/// This returns an error only if there's no memory left for the constants which refer to blocks.
+	pub unsafe fn build(mut self) -> crate::Result<Program<'src, 'path, 'gc>> {
 		// SAFETY: The caller guarantees that we'll always have exactly one opcode on the top when
 		// the program is finished executing, so we know
 		unsafe {
@@ -112,12 +132,52 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 		}
 
 		#[cfg(debug_assertions)]
-		for &opcode in self.code.iter() {
-			debug_assert_ne!(opcode, 0, "deferred jump which was never un-deferred encountered.")
+		for &(_, offset) in self.code.iter() {
+			debug_assert_ne!(
+				offset, DEFERRED_JUMP_OFFSET,
+				"deferred jump which was never un-deferred encountered."
+			)
+		}
+
+		// Convert everything which refers to instruction indices into byte offsets.
+		let mut offsets = Vec::with_capacity(self.code.len() + 1);
+		let mut len = 0;
+		for &(opcode, _) in self.code.iter() {
+			offsets.push(len);
+			len += opcode.encoded_len();
+		}
+		offsets.push(len);
+
+		let mut code = Vec::with_capacity(len);
+		for &(opcode, mut offset) in self.code.iter() {
+			if let Some(target) = jump_target(opcode, offset) {
+				offset = retarget(opcode, offset, offsets[target])
+					.expect("programs larger than 2GiB aren't supported");
+			}
+
+			let bytes = offset.to_le_bytes();
+			let (encoded, rest) = bytes.split_at(opcode.offset_len());
+			assert!(rest.iter().all(|&byte| byte == 0), "offset {offset} is too large for {opcode:?}");
+
+			code.push(opcode as u8);
+			code.extend_from_slice(encoded);
+		}
+
+		self.remap_blocks(|index| offsets[index])?;
+
+		#[cfg(feature = "stacktrace")]
+		{
+			self.source_lines =
+				self.source_lines.into_iter().map(|(index, loc)| (offsets[index], loc)).collect();
+			self.block_locations = self
+				.block_locations
+				.into_iter()
+				.map(|(index, location)| (JumpIndex(offsets[index.0]), location))
+				.collect();
 		}
 
-		Program {
-			code: self.code.into_boxed_slice(),
+		Ok(Program {
+			code: pad_code(code),
 			constants: self.constants.into_boxed_slice(),
 			variables: self.variables,
 
@@ -128,7 +188,7 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 			block_locations: self.block_locations,
 
 			_ignored: (&(), &()),
-		}
+		})
 	}
 
 	/// Gets the current index for the program, for use later on with jumps.
@@ -139,7 +199,10 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 	/// Indicates that a new line of code, located at `loc`, is about to begin. Used for stacktraces.
 	#[cfg(feature = "stacktrace")]
 	pub fn record_source_location(&mut self, loc: SourceLocation<'path>) {
-		self.source_lines.insert(self.code.len(), loc);
+		if loc != self.current_location {
+			self.source_lines.insert(self.code.len(), loc);
+			self.current_location = loc;
+		}
 	}
 
 	/// Indicates that at the offset `whence`, a block named `name` with the source location `loc`
@@ -173,7 +236,7 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 	/// the deferred jump is.
 	pub fn defer_jump(&mut self, when: JumpWhen) -> DeferredJump {
 		let deferred = self.code.len();
-		self.code.push(0);
+		self.code.push((Opcode::Jump, DEFERRED_JUMP_OFFSET));
 		DeferredJump(deferred, when)
 	}
 
@@ -181,26 +244,30 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 	pub unsafe fn opcode_with_offset(&mut self, opcode: Opcode, offset: usize) {
 		debug_assert!(opcode.takes_offset());
 
-		// No need to check if `offset as InstructionAndOffset`'s topbit is nonzero, as that's so massive it'll never happen
-		self.code.push(code_from_opcode_and_offset(opcode, offset))
+		self.code.push((opcode, offset))
 	}
 
 	// SAFETY: `opcode` mustn't take an offset
 	pub unsafe fn opcode_without_offset(&mut self, opcode: Opcode) {
 		debug_assert!(!opcode.takes_offset());
 
-		self.code.push(code_from_opcode_and_offset(opcode, 0)) // any offset'll do, it's ignored
+		self.code.push((opcode, 0)) // any offset'll do, it's ignored
 	}
 
-	pub fn push_constant(&mut self, value: Value<'gc>) {
-		let index = match self.constants.iter().enumerate().find(|(_, v)| value == **v) {
+	/// Gets the index of `value` in the list of constants, adding it if it's not already there.
+	pub fn constant_index(&mut self, value: Value<'gc>) -> usize {
+		match self.constants.iter().enumerate().find(|(_, v)| value == **v) {
 			Some((index, _)) => index,
 			None => {
 				let i = self.constants.len();
 				self.constants.push(value);
 				i
 			}
-		};
+		}
+	}
+
+	pub fn push_constant(&mut self, value: Value<'gc>) {
+		let index = self.constant_index(value);
 
 		// SAFETY: we know that `index` is a valid constant cause we just checked
 		unsafe {
@@ -208,7 +275,8 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 		}
 	}
 
-	fn variable_index(
+	/// Gets the index of the variable `name`, adding it if it's not already been used.
+	pub fn variable_index(
 		&mut self,
 		name: VariableName<'src>,
 		opts: &Options,
@@ -274,6 +342,115 @@ impl<'src, 'path, 'gc> Compiler<'src, 'path, 'gc> {
 
 		Ok(())
 	}
+
+	// Every index which is jumped to, either by jump instructions or by calling blocks.
+	fn jump_targets(&self) -> HashSet<usize> {
+		let mut targets = self
+			.code
+			.iter()
+			.filter_map(|&(opcode, offset)| jump_target(opcode, offset))
+			.collect::<HashSet<_>>();
+
+		for &constant in self.constants.iter() {
+			for_each_block(constant, &mut |block| {
+				targets.insert(block.inner().0);
+			});
+		}
+
+		targets
+	}
+
+	// Changes the start of every block within the constants (including those within lists) to
+	// `remap(start)`. Lists which contain blocks are replaced, so this fails if there's no memory
+	// left for the new ones.
+	fn remap_blocks(&mut self, remap: impl Fn(usize) -> usize) -> crate::Result<()> {
+		fn remap_value<'gc>(
+			value: Value<'gc>,
+			remap: &impl Fn(usize) -> usize,
+			gc: &'gc Gc,
+		) -> crate::Result<Value<'gc>> {
+			if let Some(block) = value.as_block() {
+				return Ok(Block::new(JumpIndex(remap(block.inner().0))).into());
+			}
+
+			let Some(list) = value.as_list() else {
+				return Ok(value);
+			};
+
+			let elements = list
+				.iter()
+				.map(|element| remap_value(element, remap, gc))
+				.collect::<crate::Result<Vec<_>>>()?;
+			if list.iter().eq(elements.iter().copied()) {
+				return Ok(value);
+			}
+
+			// SAFETY: the gc isn't collecting while compiling, so the new list won't be freed.
+			Ok(unsafe { List::from_slice_unvalidated(&elements, gc)?.with_inner(Value::from) })
+		}
+
+		for constant in self.constants.iter_mut() {
+			*constant = remap_value(*constant, &remap, self.gc)?;
+		}
+		Ok(())
+	}
+
+	// Removes every instruction whose index in `removed` is `true`, and then fixes up everything
+	// that refers to offsets in the code. This fails only if `remap_blocks` does.
+	fn remove_instructions(&mut self, removed: &[bool]) -> crate::Result<()> {
+		// `remap[old]` is the offset of the first instruction at or after `old` that wasn't removed.
+		// It includes one past the end, as the `Return` added by `build` is jumped to.
+		let mut remap = Vec::with_capacity(removed.len() + 1);
+		let mut new_len = 0;
+		for &is_removed in removed {
+			remap.push(new_len);
+			new_len += usize::from(!is_removed);
+		}
+		remap.push(new_len);
+
+		let mut idx = 0;
+		self.code.retain(|_| {
+			idx += 1;
+			!removed[idx - 1]
+		});
+
+		for (opcode, offset) in self.code.iter_mut() {
+			if let Some(target) = jump_target(*opcode, *offset) {
+				// Targets only ever decrease, so they always fit.
+				*offset = retarget(*opcode, *offset, remap[target]).unwrap();
+			}
+		}
+
+		self.remap_blocks(|index| remap[index])?;
+
+		#[cfg(feature = "stacktrace")]
+		{
+			// Every remaining instruction keeps the location it had before, and a location is only
+			// recorded when it differs from the previous instruction's.
+			let mut source_lines = std::collections::HashMap::with_capacity(self.source_lines.len());
+			let mut current = None;
+			let mut last_recorded = None;
+			for (old, &new) in remap.iter().enumerate() {
+				if let Some(&location) = self.source_lines.get(&old) {
+					current = Some(location);
+				}
+
+				let is_kept = removed.get(old).is_none_or(|&is_removed| !is_removed);
+				if is_kept && current != last_recorded {
+					source_lines.insert(new, current.unwrap());
+					last_recorded = current;
+				}
+			}
+			self.source_lines = source_lines;
+
+			self.block_locations = std::mem::take(&mut self.block_locations)
+				.into_iter()
+				.map(|(index, location)| (JumpIndex(remap[index.0]), location))
+				.collect();
+		}
+
+		Ok(())
+	}
 }
 
 impl DeferredJump {
@@ -287,9 +464,8 @@ impl DeferredJump {
 	}
 
 	/// Reify `self` by jumping to the position `index` in `compiler`.
-
 	pub unsafe fn jump_to(self, compiler: &mut Compiler<'_, '_, '_>, index: JumpIndex) {
-		assert_eq!(0, compiler.code[self.0]);
+		assert_eq!(DEFERRED_JUMP_OFFSET, compiler.code[self.0].1);
 
 		let opcode = match self.1 {
 			JumpWhen::True => Opcode::JumpIfTrue,
@@ -297,6 +473,6 @@ impl DeferredJump {
 			JumpWhen::Always => Opcode::Jump,
 		};
 
-		compiler.code[self.0] = code_from_opcode_and_offset(opcode, index.0);
+		compiler.code[self.0] = (opcode, index.0);
 	}
 }
@@ -0,0 +1,160 @@
+use super::Compiler;
+use crate::program::{jump_target, retarget};
+use crate::vm::Opcode;
+
+impl Compiler<'_, '_, '_> {
+	/// Rewrites wasteful sequences of instructions in the code emitted so far.
+	///
+	/// This removes `PushConstant`s and `Dup`s whose values are immediately popped, merges `SetVar`
+	/// and `Pop` into `SetVarPop`, merges `Not` into the conditional jump following it, retargets
+	/// jumps which land on unconditional jumps, replaces unconditional jumps to `Return` with `Return`
+	/// (so `CALL`s in the branches of `IF`s become tail calls), and removes jumps to the next
+	/// instruction. Jumps, blocks, and source locations are all updated to account for removed
+	/// instructions.
+	///
+	/// Instructions which are the destinations of jumps are never merged into the instructions
+	/// before them, so this never changes how programs behave.
+	///
+	/// This returns an error only if there's no memory left for the constants which refer to blocks.
+	///
+	/// # Safety
+	/// All deferred jumps must have been `jump_to`'d.
+	pub unsafe fn peephole(&mut self) -> crate::Result<()> {
+		// Removing instructions can expose new patterns, so keep going until nothing changes.
+		while self.peephole_pass()? {}
+		Ok(())
+	}
+
+	// Follows `target` through unconditional jumps, returning where it eventually ends up.
+	fn thread_jump(&self, mut target: usize) -> usize {
+		// Limit the amount of jumps followed, in case there's an infinite loop of them.
+		for _ in 0..self.code.len() {
+			match self.code.get(target).copied() {
+				Some((Opcode::Jump, next)) if next != target => target = next,
+				_ => break,
+			}
+		}
+
+		target
+	}
+
+	// Runs a single pass of the peephole optimizer, returning whether anything changed.
+	fn peephole_pass(&mut self) -> crate::Result<bool> {
+		let targets = self.jump_targets();
+		let len = self.code.len();
+		let mut removed = vec![false; len];
+		let mut changed = false;
+
+		let mut idx = 0;
+		while idx < len {
+			let (opcode, offset) = self.code[idx];
+
+			if let Some(target) = jump_target(opcode, offset) {
+				let threaded = self.thread_jump(target);
+				if let Some(operand) = retarget(opcode, offset, threaded).filter(|_| threaded != target)
+				{
+					self.code[idx] = (opcode, operand);
+					changed = true;
+				}
+
+				if opcode == Opcode::Jump && threaded == idx + 1 {
+					removed[idx] = true;
+					changed = true;
+				} else if opcode == Opcode::Jump
+					&& self.code.get(threaded).is_some_and(|&(next, _)| next == Opcode::Return)
+				{
+					self.code[idx] = (Opcode::Return, 0);
+					changed = true;
+				}
+
+				idx += 1;
+				continue;
+			}
+
+			// Nothing can be merged with the next instruction if something jumps directly to it.
+			let next = self.code.get(idx + 1).filter(|_| !targets.contains(&(idx + 1)));
+			let Some((next_opcode, next_offset)) = next.copied() else {
+				idx += 1;
+				continue;
+			};
+
+			let replacement = match (opcode, next_opcode) {
+				(Opcode::PushConstant | Opcode::Dup, Opcode::Pop) => None,
+				(Opcode::SetVar, Opcode::Pop) => Some((Opcode::SetVarPop, offset)),
+				(Opcode::Not, Opcode::JumpIfTrue) => Some((Opcode::JumpIfFalse, next_offset)),
+				(Opcode::Not, Opcode::JumpIfFalse) => Some((Opcode::JumpIfTrue, next_offset)),
+				_ => {
+					idx += 1;
+					continue;
+				}
+			};
+
+			match replacement {
+				Some(instruction) => self.code[idx] = instruction,
+				None => removed[idx] = true,
+			}
+			removed[idx + 1] = true;
+			changed = true;
+			idx += 2;
+		}
+
+		if removed.contains(&true) {
+			self.remove_instructions(&removed)?;
+		}
+
+		Ok(changed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::gc::{Gc, GcOptions};
+	use crate::parser::{source_location::ProgramSource, SourceLocation};
+	use crate::vm::FusedOperands;
+
+	// Runs the peephole optimizer on `code`, returning what it's rewritten into.
+	fn peephole(code: Vec<(Opcode, usize)>) -> Vec<(Opcode, usize)> {
+		// SAFETY: Nothing is allocated, and there's no deferred jumps.
+		unsafe {
+			Gc::new(GcOptions::default()).run(|gc| {
+				let start = SourceLocation::new(ProgramSource::Other("<test>"), 1);
+				let mut compiler = Compiler::new(start, gc);
+				compiler.code = code;
+				compiler.peephole().unwrap();
+				compiler.code
+			})
+		}
+	}
+
+	fn branch(target: usize) -> (Opcode, usize) {
+		let operands = FusedOperands { variable: 0, constant: 0, target, jump_if: false };
+		(Opcode::BranchVarLth, operands.pack().unwrap())
+	}
+
+	#[test]
+	fn rewrites_and_updates_jumps() {
+		let code = vec![
+			(Opcode::Not, 0),
+			(Opcode::JumpIfTrue, 4),
+			(Opcode::PushConstant, 0),
+			(Opcode::Pop, 0),
+			(Opcode::Dup, 0),
+		];
+
+		assert_eq!(peephole(code), [(Opcode::JumpIfFalse, 1), (Opcode::Dup, 0)]);
+	}
+
+	#[test]
+	fn threads_jumps_only_when_their_targets_fit() {
+		// The branch goes straight to the `Dup`, and then the jump (which is now the next instruction)
+		// is removed.
+		let threaded = peephole(vec![branch(1), (Opcode::Jump, 2), (Opcode::Dup, 0)]);
+		assert_eq!(threaded, [branch(1), (Opcode::Dup, 0)]);
+
+		// Superinstructions' targets have to be less than 2^31, so this branch has to keep going
+		// through the jump.
+		let too_far = vec![branch(1), (Opcode::Jump, 1 << 31)];
+		assert_eq!(peephole(too_far.clone()), too_far);
+	}
+}
@@ -0,0 +1,88 @@
+use super::Compiler;
+use crate::vm::{FusedOperands, Opcode};
+
+impl Compiler<'_, '_, '_> {
+	/// Replaces common sequences of instructions with superinstructions.
+	///
+	/// Currently, `GetVar; PushConstant; Add; SetVarPop` (and the same with `Sub`) which assign to the
+	/// same variable are replaced by [`Opcode::AddVarConst`] (or [`Opcode::SubVarConst`]), and
+	/// `GetVar; PushConstant; Lth; JumpIfFalse` (along with `Gth`, `Eql`, and `JumpIfTrue`) are
+	/// replaced by [`Opcode::BranchVarLth`] (or `Gth`/`Eql`). Since `SetVarPop` is only emitted by
+	/// [`Compiler::peephole`], this should be run after it.
+	///
+	/// Sequences are only replaced if nothing jumps into the middle of them, and (when stacktraces
+	/// are enabled) if they're all on the same line, so this never changes how programs behave.
+	///
+	/// This returns an error only if there's no memory left for the constants which refer to blocks.
+	///
+	/// # Safety
+	/// All deferred jumps must have been `jump_to`'d.
+	pub unsafe fn fuse_superinstructions(&mut self) -> crate::Result<()> {
+		let targets = self.jump_targets();
+		let mut removed = vec![false; self.code.len()];
+
+		let mut idx = 0;
+		while idx + 4 <= self.code.len() {
+			let is_fusable = (idx + 1..idx + 4).all(|inner| {
+				#[cfg(feature = "stacktrace")]
+				if self.source_lines.contains_key(&inner) {
+					return false;
+				}
+
+				!targets.contains(&inner)
+			});
+
+			match is_fusable.then(|| self.fuse_at(idx)).flatten() {
+				Some((opcode, operands)) => {
+					self.code[idx] = (opcode, operands);
+					removed[idx + 1..idx + 4].fill(true);
+					idx += 4;
+				}
+				None => idx += 1,
+			}
+		}
+
+		if removed.contains(&true) {
+			self.remove_instructions(&removed)?;
+		}
+
+		Ok(())
+	}
+
+	// Gets the superinstruction (and its operands) that the four instructions starting at `idx` can
+	// be replaced with, if any.
+	fn fuse_at(&self, idx: usize) -> Option<(Opcode, usize)> {
+		let [first, second, third, fourth] = [0, 1, 2, 3].map(|inner| self.code[idx + inner]);
+
+		let (Opcode::GetVar, variable) = first else {
+			return None;
+		};
+		let (Opcode::PushConstant, constant) = second else {
+			return None;
+		};
+
+		let (opcode, target, jump_if) = match (third.0, fourth) {
+			(Opcode::Add, (Opcode::SetVarPop, assigned)) if assigned == variable => {
+				(Opcode::AddVarConst, 0, false)
+			}
+			(Opcode::Sub, (Opcode::SetVarPop, assigned)) if assigned == variable => {
+				(Opcode::SubVarConst, 0, false)
+			}
+			(
+				Opcode::Lth | Opcode::Gth | Opcode::Eql,
+				(jump @ (Opcode::JumpIfTrue | Opcode::JumpIfFalse), target),
+			) => {
+				let opcode = match third.0 {
+					Opcode::Lth => Opcode::BranchVarLth,
+					Opcode::Gth => Opcode::BranchVarGth,
+					_ => Opcode::BranchVarEql,
+				};
+				(opcode, target, jump == Opcode::JumpIfTrue)
+			}
+			_ => return None,
+		};
+
+		let operands = FusedOperands { variable, constant, target, jump_if }.pack()?;
+		Some((opcode, operands))
+	}
+}
@@ -0,0 +1,301 @@
+use super::{jump_target, Program};
+use crate::value::Value;
+use crate::vm::{FusedOperands, Opcode};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter, Write};
+
+/// A human-readable listing of a [`Program`]'s bytecode, returned by [`Program::disassemble`].
+///
+/// The output is valid input for [`Program::assemble`].
+pub struct Disassembly<'a, 'src, 'path, 'gc> {
+	program: &'a Program<'src, 'path, 'gc>,
+	labels: BTreeMap<usize, Vec<String>>,
+}
+
+/// The control-flow graph of a [`Program`] in the DOT format, returned by [`Program::cfg_dot`].
+pub struct CfgDot<'a, 'src, 'path, 'gc>(Disassembly<'a, 'src, 'path, 'gc>);
+
+/// Gets the name of a [`DynamicAssignment`](crate::vm::opcode::DynamicAssignment).
+#[cfg(feature = "extensions")]
+pub(super) fn dynamic_assignment_name(kind: usize) -> Option<&'static str> {
+	use crate::vm::opcode::DynamicAssignment;
+
+	match kind {
+		_ if kind == DynamicAssignment::Output as usize => Some("output"),
+		_ if kind == DynamicAssignment::Prompt as usize => Some("prompt"),
+		_ if kind == DynamicAssignment::Random as usize => Some("random"),
+		_ if kind == DynamicAssignment::System as usize => Some("system"),
+		_ => None,
+	}
+}
+
+impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
+	/// Disassembles `self` into a human-readable listing.
+	///
+	/// Each instruction is printed with its offset, [`Opcode`], and operand. Constants and variables
+	/// are resolved, and jump targets and blocks are given labels. When `stacktrace` is enabled,
+	/// source locations are included as comments.
+	pub fn disassemble(&self) -> Disassembly<'_, 'src, 'path, 'gc> {
+		Disassembly { program: self, labels: self.labels() }
+	}
+
+	/// Gets the control-flow graph of `self`, in the DOT format.
+	///
+	/// Each node is a basic block, and edges are jumps (labeled `true`/`false` for conditional
+	/// ones) or fallthroughs.
+	pub fn cfg_dot(&self) -> CfgDot<'_, 'src, 'path, 'gc> {
+		CfgDot(self.disassemble())
+	}
+
+	fn labels(&self) -> BTreeMap<usize, Vec<String>> {
+		let mut labels = BTreeMap::<usize, Vec<String>>::new();
+
+		for offset in self.instruction_offsets() {
+			let target =
+				self.decode_at(offset).and_then(|(opcode, operand)| jump_target(opcode, operand));
+			if let Some(target) = target {
+				let label = format!("L{target:04}");
+				let existing = labels.entry(target).or_default();
+				if !existing.contains(&label) {
+					existing.push(label);
+				}
+			}
+		}
+
+		let mut seen_names = HashMap::<String, usize>::new();
+		for (_, block) in self.block_constants() {
+			let target = block.0;
+
+			#[cfg(feature = "stacktrace")]
+			let name = self.block_locations.get(&block).and_then(|(name, _)| name.as_ref());
+			#[cfg(not(feature = "stacktrace"))]
+			let name = None::<&crate::parser::VariableName<'_>>;
+
+			let label = match name.map(ToString::to_string) {
+				// Multiple blocks can be assigned to the same name, so make sure labels are unique.
+				Some(name) if *seen_names.entry(name.clone()).or_insert(target) == target => name,
+				_ => format!("block_{target:04}"),
+			};
+
+			let existing = labels.entry(target).or_default();
+			if !existing.contains(&label) {
+				existing.insert(0, label);
+			}
+		}
+
+		labels
+	}
+}
+
+impl Disassembly<'_, '_, '_, '_> {
+	fn label_for(&self, target: usize) -> &str {
+		self.labels.get(&target).and_then(|labels| labels.first()).map_or("<unknown>", |l| l)
+	}
+
+	fn write_value(&self, value: Value<'_>, out: &mut impl Write) -> fmt::Result {
+		if value.is_null() {
+			out.write_str("null")
+		} else if let Some(boolean) = value.as_boolean() {
+			write!(out, "{boolean}")
+		} else if let Some(integer) = value.as_integer() {
+			write!(out, "{integer}")
+		} else if let Some(string) = value.as_knstring() {
+			write!(out, "{:?}", string.as_str())
+		} else if let Some(list) = value.as_list() {
+			out.write_str("[")?;
+			for (idx, element) in list.iter().enumerate() {
+				if idx != 0 {
+					out.write_str(", ")?;
+				}
+				self.write_value(element, out)?;
+			}
+			out.write_str("]")
+		} else if let Some(block) = value.as_block() {
+			write!(out, "block {}", self.label_for(block.inner().0))
+		} else {
+			bug!("unknown value type: {:?}", value)
+		}
+	}
+
+	// Writes the instruction at `offset` (without a trailing newline)
+	fn write_instruction(&self, offset: usize, out: &mut impl Write) -> fmt::Result {
+		let program = self.program;
+		write!(out, "{offset:>6}  ")?;
+
+		let Some((opcode, operand)) = program.decode_at(offset) else {
+			let byte = program.code()[offset];
+			return match Opcode::from_byte(byte) {
+				Some(opcode) => write!(out, "<truncated {opcode:?}>"),
+				None => write!(out, "<invalid opcode {byte:#04x}>"),
+			};
+		};
+
+		if !opcode.takes_offset() {
+			return write!(out, "{opcode:?}");
+		}
+
+		write!(out, "{:<14}", format!("{opcode:?}"))?;
+		match opcode {
+			Opcode::PushConstant => match program.constants.get(operand) {
+				Some(&constant) => {
+					self.write_value(constant, out)?;
+					write!(out, "  ; #{operand}")
+				}
+				None => write!(out, "<invalid constant #{operand}>"),
+			},
+
+			Opcode::Jump | Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+				write!(out, "{}", self.label_for(operand))
+			}
+
+			Opcode::GetVar | Opcode::SetVar | Opcode::SetVarPop => {
+				match program.variables.get_index(operand) {
+					Some(name) => write!(out, "{name}  ; ${operand}"),
+					None => write!(out, "<invalid variable ${operand}>"),
+				}
+			}
+
+			_ if opcode.is_fused() => {
+				let FusedOperands { variable, constant, target, jump_if } =
+					FusedOperands::unpack(operand);
+
+				let Some(name) = program.variables.get_index(variable) else {
+					return write!(out, "<invalid variable ${variable}>");
+				};
+				let Some(&value) = program.constants.get(constant) else {
+					return write!(out, "<invalid constant #{constant}>");
+				};
+
+				write!(out, "{name}, ")?;
+				self.write_value(value, out)?;
+				if opcode.is_fused_branch() {
+					write!(out, ", {} if {jump_if}", self.label_for(target))?;
+				}
+				write!(out, "  ; ${variable} #{constant}")
+			}
+
+			#[cfg(feature = "extensions")]
+			Opcode::AssignDynamic => match dynamic_assignment_name(operand) {
+				Some(name) => out.write_str(name),
+				None => write!(out, "<invalid dynamic assignment {operand}>"),
+			},
+
+			_ => write!(out, "{operand}"),
+		}
+	}
+
+	#[cfg(feature = "stacktrace")]
+	fn source_location_comment(&self, offset: usize) -> Option<String> {
+		self.program.source_lines.get(&offset).map(|location| format!("; {location}"))
+	}
+
+	#[cfg(not(feature = "stacktrace"))]
+	fn source_location_comment(&self, _offset: usize) -> Option<String> {
+		None
+	}
+}
+
+impl Display for Disassembly<'_, '_, '_, '_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let program = self.program;
+		writeln!(
+			f,
+			"; {} instructions ({} bytes), {} constants, {} variables",
+			program.instruction_offsets().count(),
+			program.code().len(),
+			program.constants.len(),
+			program.variables.len()
+		)?;
+
+		let mut offset = 0;
+		while offset < program.code().len() {
+			for label in self.labels.get(&offset).into_iter().flatten() {
+				writeln!(f, "{label}:")?;
+			}
+
+			if let Some(comment) = self.source_location_comment(offset) {
+				writeln!(f, "        {comment}")?;
+			}
+
+			self.write_instruction(offset, f)?;
+			writeln!(f)?;
+
+			// The rest of the code can't be decoded if an instruction is invalid.
+			let Some((opcode, _)) = program.decode_at(offset) else {
+				break;
+			};
+			offset += opcode.encoded_len();
+		}
+
+		Ok(())
+	}
+}
+
+impl Display for CfgDot<'_, '_, '_, '_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let disassembly = &self.0;
+		let program = disassembly.program;
+		let len = program.code().len();
+		let offsets = program.instruction_offsets().collect::<Vec<_>>();
+
+		// Every label, along with every instruction following a branch, starts a basic block.
+		let mut leaders = BTreeSet::from([0]);
+		leaders.extend(disassembly.labels.keys().copied().filter(|&target| target < len));
+		for &offset in offsets.iter() {
+			let (opcode, operand) = program.decode_at(offset).unwrap();
+			if matches!(opcode, Opcode::Return | Opcode::Quit)
+				|| jump_target(opcode, operand).is_some()
+			{
+				leaders.insert(offset + opcode.encoded_len());
+			}
+		}
+		leaders.retain(|&leader| leader < len);
+
+		writeln!(f, "digraph program {{")?;
+		writeln!(f, "\tnode [shape=box, fontname=\"monospace\"];")?;
+
+		let leaders = leaders.into_iter().collect::<Vec<_>>();
+		for (idx, &start) in leaders.iter().enumerate() {
+			let end = leaders.get(idx + 1).copied().unwrap_or(len);
+
+			let mut label = String::new();
+			for name in disassembly.labels.get(&start).into_iter().flatten() {
+				writeln!(label, "{name}:")?;
+			}
+			let instructions = &offsets[offsets.partition_point(|&offset| offset < start)
+				..offsets.partition_point(|&offset| offset < end)];
+			for &offset in instructions {
+				disassembly.write_instruction(offset, &mut label)?;
+				label.push('\n');
+			}
+
+			let escaped = label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\l");
+			writeln!(f, "\tn{start} [label=\"{escaped}\"];")?;
+
+			match instructions.last().and_then(|&last| program.decode_at(last)) {
+				Some((Opcode::Return | Opcode::Quit, _)) | None => {}
+				Some((Opcode::Jump, target)) => writeln!(f, "\tn{start} -> n{target};")?,
+				Some((opcode, operand)) if jump_target(opcode, operand).is_some() => {
+					let (target, jump_if) = match opcode {
+						Opcode::JumpIfTrue => (operand, true),
+						Opcode::JumpIfFalse => (operand, false),
+						_ => {
+							let operands = FusedOperands::unpack(operand);
+							(operands.target, operands.jump_if)
+						}
+					};
+
+					let (taken, fallthru) = if jump_if { ("true", "false") } else { ("false", "true") };
+					writeln!(f, "\tn{start} -> n{target} [label=\"{taken}\"];")?;
+					if end < len {
+						writeln!(f, "\tn{start} -> n{end} [label=\"{fallthru}\"];")?;
+					}
+				}
+				Some(_) if end < len => writeln!(f, "\tn{start} -> n{end};")?,
+				Some(_) => {}
+			}
+		}
+
+		writeln!(f, "}}")
+	}
+}
@@ -0,0 +1,465 @@
+use super::{jump_target, JumpIndex, Program, VerifyError};
+use crate::vm::{FusedOperands, Opcode};
+use crate::Options;
+
+/// Where an [`Instruction`] reads a value from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operand {
+	/// A variable in the program. Every variable is its own register.
+	Variable(u32),
+
+	/// A temporary register, relative to the start of the current frame.
+	Temporary(u32),
+
+	/// A constant in the program.
+	Constant(u32),
+}
+
+/// Where an [`Instruction`] writes its result to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Register {
+	/// A variable in the program.
+	Variable(u32),
+
+	/// A temporary register, relative to the start of the current frame.
+	Temporary(u32),
+}
+
+/// A single instruction for the register-based [`Vm`](crate::vm::Vm).
+///
+/// Most instructions run an [`Opcode`] on their operands and write the result to a register, so
+/// they behave exactly like the opcode does in the stack-based bytecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instruction {
+	/// Copies `src` into `dst`.
+	Move { dst: Register, src: Operand },
+
+	/// Jumps to the instruction at `target`.
+	Jump { target: u32 },
+
+	/// Jumps to the instruction at `target` if `cond`'s truthiness is `jump_if`.
+	JumpIf { cond: Operand, jump_if: bool, target: u32 },
+
+	/// Runs the zero-argument `opcode`.
+	Nullary { opcode: Opcode, dst: Register },
+
+	/// Runs the one-argument `opcode`.
+	Unary { opcode: Opcode, dst: Register, arg: Operand },
+
+	/// Runs the two-argument `opcode`.
+	Binary { opcode: Opcode, dst: Register, lhs: Operand, rhs: Operand },
+
+	/// Runs [`Opcode::Get`].
+	Get { dst: Register, args: [Operand; 3] },
+
+	/// Runs [`Opcode::Set`].
+	Set { dst: Register, args: [Operand; 4] },
+
+	/// Runs [`Opcode::Dump`].
+	Dump { value: Operand },
+
+	/// Runs [`Opcode::AssignDynamic`], with `kind` as its offset.
+	#[cfg(feature = "extensions")]
+	AssignDynamic { kind: u32, value: Operand },
+
+	/// Runs [`Opcode::Quit`].
+	Quit { status: Operand },
+
+	/// Returns `value` from the current block.
+	Return { value: Operand },
+}
+
+impl Instruction {
+	// Gets a mutable reference to where `self` writes its result, if it has one.
+	fn destination_mut(&mut self) -> Option<&mut Register> {
+		match self {
+			Self::Move { dst, .. }
+			| Self::Nullary { dst, .. }
+			| Self::Unary { dst, .. }
+			| Self::Binary { dst, .. }
+			| Self::Get { dst, .. }
+			| Self::Set { dst, .. } => Some(dst),
+			_ => None,
+		}
+	}
+}
+
+/// A [`Program`] which has been translated for the register-based [`Vm`](crate::vm::Vm).
+///
+/// Every variable is its own register, and the values which the bytecode would keep on the stack
+/// are instead stored in temporary registers, one for each depth of the stack. Temporaries are
+/// local to each call, so every call to a block gets a fresh frame of them.
+#[derive(Debug)]
+pub struct RegisterCode {
+	instructions: Box<[Instruction]>,
+
+	// The amount of temporary registers each frame needs.
+	frame_size: usize,
+
+	// The index of the instruction that each block starts at, indexed by the block's offset within
+	// the bytecode. (Offsets which blocks can't start at are `u32::MAX`.)
+	entries: Box<[u32]>,
+
+	// Only enabled when stacktrace printing is enabled, this is the `current_index` which the stack-
+	// based vm would have when running each instruction, so stacktraces are identical.
+	#[cfg(feature = "stacktrace")]
+	origins: Box<[usize]>,
+}
+
+// Converts a bytecode offset (such as a variable or constant index) into an operand index.
+fn index(offset: usize) -> u32 {
+	offset.try_into().expect("programs with more than u32::MAX operands aren't supported")
+}
+
+impl RegisterCode {
+	/// Translates `program`'s bytecode into register code.
+	///
+	/// Programs are [verified](Program::verify) first, as the stack depth at each instruction needs
+	/// to be known. The register code behaves identically to the bytecode, including the order in
+	/// which errors (such as undefined variables when `opts.check_variables` is set) are raised.
+	pub fn new(program: &Program<'_, '_, '_>, opts: &Options) -> Result<Self, VerifyError> {
+		let depths = program.stack_depths()?;
+
+		// Jump targets and blocks can be reached from multiple places, so the stack needs to be in
+		// a known state when they're reached.
+		let mut leaders = vec![false; program.code().len()];
+		leaders[0] = true;
+		for offset in program.instruction_offsets() {
+			let (opcode, operand) = program.decode_at(offset).unwrap();
+			if let Some(target) = jump_target(opcode, operand) {
+				leaders[target] = true;
+			}
+		}
+		for (_, JumpIndex(target)) in program.block_constants() {
+			leaders[target] = true;
+		}
+
+		#[cfg(feature = "check-variables")]
+		let reads_can_fail = opts.check_variables;
+		#[cfg(not(feature = "check-variables"))]
+		let (reads_can_fail, _) = (false, opts);
+
+		let mut codegen = Codegen {
+			instructions: Vec::new(),
+			#[cfg(feature = "stacktrace")]
+			origins: Vec::new(),
+			stack: Vec::new(),
+			reachable: false,
+			origin: 0,
+			reads_can_fail,
+			retargetable: None,
+		};
+
+		let mut entries = vec![u32::MAX; program.code().len()].into_boxed_slice();
+		for offset in program.instruction_offsets() {
+			// Unreachable code is never run, so there's no need to translate it.
+			let Some(depth) = depths[offset] else {
+				continue;
+			};
+
+			if leaders[offset] {
+				codegen.start_leader(depth);
+				entries[offset] = index(codegen.instructions.len());
+			}
+
+			let (opcode, operand) = program.decode_at(offset).unwrap();
+			codegen.origin = offset + opcode.encoded_len();
+			codegen.translate(opcode, operand);
+		}
+
+		// Jumps were emitted with bytecode offsets, as the instructions they pointed to may not have
+		// been translated yet.
+		for instruction in codegen.instructions.iter_mut() {
+			if let Instruction::Jump { target } | Instruction::JumpIf { target, .. } = instruction {
+				*target = entries[*target as usize];
+			}
+		}
+
+		Ok(Self {
+			instructions: codegen.instructions.into_boxed_slice(),
+			// `+ 1` for the temporary that fused branches and discarded variable reads use.
+			frame_size: depths.iter().flatten().max().map_or(0, |&max| max + 1),
+			entries,
+			#[cfg(feature = "stacktrace")]
+			origins: codegen.origins.into_boxed_slice(),
+		})
+	}
+
+	/// Gets the instruction at `index`.
+	///
+	/// # Safety
+	/// `index` must be in bounds. (Indices from [`entry`](Self::entry) and jump targets always are,
+	/// as is the index after any instruction other than a [`Jump`](Instruction::Jump),
+	/// [`Quit`](Instruction::Quit), or [`Return`](Instruction::Return).)
+	#[inline]
+	pub(crate) unsafe fn instruction_at(&self, index: usize) -> Instruction {
+		debug_assert!(index < self.instructions.len());
+		unsafe { *self.instructions.get_unchecked(index) }
+	}
+
+	/// The amount of temporary registers each call needs.
+	#[inline]
+	pub(crate) fn frame_size(&self) -> usize {
+		self.frame_size
+	}
+
+	/// Gets the index of the instruction that the block starting at `block` in the bytecode starts at.
+	#[inline]
+	pub(crate) fn entry(&self, block: JumpIndex) -> usize {
+		let entry = self.entries[block.0];
+		debug_assert_ne!(entry, u32::MAX, "block {block:?} isn't the start of a block");
+		entry as usize
+	}
+
+	/// Whether the instruction at `index` returns `result` (possibly after jumping, like the end of an
+	/// `IF`'s branch does), i.e. whether a call which writes to `result` just before it is in tail
+	/// position.
+	#[inline]
+	pub(crate) fn is_tail_call(&self, index: usize, result: Register) -> bool {
+		let Register::Temporary(result) = result else {
+			return false;
+		};
+
+		let returns_result = |instruction: Option<&Instruction>| matches!(instruction, Some(&Instruction::Return { value: Operand::Temporary(value) }) if value == result);
+
+		match self.instructions.get(index) {
+			Some(&Instruction::Jump { target }) => {
+				returns_result(self.instructions.get(target as usize))
+			}
+			instruction => returns_result(instruction),
+		}
+	}
+
+	/// Gets the bytecode offset after the instruction that the instruction at `index` came from.
+	#[cfg(feature = "stacktrace")]
+	#[inline]
+	pub(crate) fn origin(&self, index: usize) -> usize {
+		self.origins[index]
+	}
+}
+
+struct Codegen {
+	instructions: Vec<Instruction>,
+	#[cfg(feature = "stacktrace")]
+	origins: Vec<usize>,
+
+	// The operands that'd be on the stack at this point in the bytecode. Reading variables and
+	// pushing constants are deferred until they're actually needed, so that they can be used as
+	// operands directly instead of being copied into temporaries first.
+	stack: Vec<Operand>,
+
+	// Whether the bytecode being translated can be reached by falling through from the previous one.
+	reachable: bool,
+
+	// The offset right after the bytecode instruction that's being translated.
+	origin: usize,
+
+	// Whether reading a variable can fail, in which case reads have to happen in the same order as
+	// they would in the bytecode.
+	reads_can_fail: bool,
+
+	// The index of the last instruction emitted, if it wrote to the temporary at the top of the
+	// stack, and nothing's read from that temporary since.
+	retargetable: Option<usize>,
+}
+
+impl Codegen {
+	fn emit(&mut self, instruction: Instruction) {
+		self.instructions.push(instruction);
+		#[cfg(feature = "stacktrace")]
+		self.origins.push(self.origin);
+		self.retargetable = None;
+	}
+
+	// Emits `instruction`, whose result is pushed onto the stack.
+	fn emit_result(&mut self, instruction: impl FnOnce(Register) -> Instruction) {
+		let depth = index(self.stack.len());
+		self.emit(instruction(Register::Temporary(depth)));
+		self.stack.push(Operand::Temporary(depth));
+		self.retargetable = Some(self.instructions.len() - 1);
+	}
+
+	// Copies the operand at `depth` into its temporary, if it's not already there.
+	fn materialize(&mut self, depth: usize) {
+		let temporary = Operand::Temporary(index(depth));
+		let src = std::mem::replace(&mut self.stack[depth], temporary);
+
+		if src != temporary {
+			self.emit(Instruction::Move { dst: Register::Temporary(index(depth)), src });
+		}
+	}
+
+	// Materializes every operand on the stack that matches `condition`.
+	fn materialize_if(&mut self, condition: impl Fn(Operand) -> bool) {
+		for depth in 0..self.stack.len() {
+			if condition(self.stack[depth]) {
+				self.materialize(depth);
+			}
+		}
+	}
+
+	// Materializes all deferred variable reads, if reading variables can fail. This is done before
+	// emitting anything else, so that undefined variables are reported before anything else runs.
+	fn flush_reads(&mut self, keep: usize) {
+		if self.reads_can_fail {
+			for depth in 0..self.stack.len() - keep {
+				if matches!(self.stack[depth], Operand::Variable(_)) {
+					self.materialize(depth);
+				}
+			}
+		}
+	}
+
+	// Starts translating a jump target or block, which always has its stack in temporaries.
+	fn start_leader(&mut self, depth: usize) {
+		if self.reachable {
+			debug_assert_eq!(self.stack.len(), depth);
+			self.materialize_if(|_| true);
+		}
+
+		self.stack = (0..depth).map(|depth| Operand::Temporary(index(depth))).collect();
+		self.reachable = true;
+		self.retargetable = None;
+	}
+
+	// Assigns the top of the stack to `variable`, popping it if `pop` is set.
+	fn assign(&mut self, variable: u32, pop: bool) {
+		// Any deferred reads of `variable` need to see its old value.
+		self.materialize_if(|operand| operand == Operand::Variable(variable));
+
+		if !pop {
+			let value = *self.stack.last().unwrap();
+			self.emit(Instruction::Move { dst: Register::Variable(variable), src: value });
+			return;
+		}
+
+		let value = self.stack.pop().unwrap();
+
+		// If the value was just computed, have it written to `variable` directly.
+		if value == Operand::Temporary(index(self.stack.len())) {
+			if let Some(last) = self.retargetable {
+				*self.instructions[last].destination_mut().unwrap() = Register::Variable(variable);
+				self.retargetable = None;
+				return;
+			}
+		}
+
+		self.emit(Instruction::Move { dst: Register::Variable(variable), src: value });
+	}
+
+	fn translate(&mut self, opcode: Opcode, operand: usize) {
+		debug_assert!(self.reachable);
+
+		match opcode {
+			Opcode::PushConstant => self.stack.push(Operand::Constant(index(operand))),
+			Opcode::GetVar => self.stack.push(Operand::Variable(index(operand))),
+			Opcode::SetVar => self.assign(index(operand), false),
+			Opcode::SetVarPop => self.assign(index(operand), true),
+
+			Opcode::Dup => self.stack.push(*self.stack.last().unwrap()),
+			Opcode::Pop => {
+				// Discarded reads still need to happen if they can fail.
+				if self.reads_can_fail && matches!(self.stack.last(), Some(Operand::Variable(_))) {
+					self.materialize(self.stack.len() - 1);
+				}
+				self.stack.pop();
+			}
+
+			Opcode::Jump => {
+				self.materialize_if(|_| true);
+				self.emit(Instruction::Jump { target: index(operand) });
+				self.reachable = false;
+			}
+			Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+				let cond = self.stack.pop().unwrap();
+				self.materialize_if(|_| true);
+				let jump_if = opcode == Opcode::JumpIfTrue;
+				self.emit(Instruction::JumpIf { cond, jump_if, target: index(operand) });
+			}
+
+			Opcode::AddVarConst | Opcode::SubVarConst => {
+				let FusedOperands { variable, constant, .. } = FusedOperands::unpack(operand);
+				let (variable, constant) = (index(variable), index(constant));
+
+				self.materialize_if(|operand| operand == Operand::Variable(variable));
+				self.flush_reads(0);
+				self.emit(Instruction::Binary {
+					opcode: if opcode == Opcode::AddVarConst { Opcode::Add } else { Opcode::Sub },
+					dst: Register::Variable(variable),
+					lhs: Operand::Variable(variable),
+					rhs: Operand::Constant(constant),
+				});
+			}
+			Opcode::BranchVarLth | Opcode::BranchVarGth | Opcode::BranchVarEql => {
+				let FusedOperands { variable, constant, target, jump_if } =
+					FusedOperands::unpack(operand);
+
+				// The comparison goes in the temporary just past the top of the stack.
+				self.materialize_if(|_| true);
+				let cond = index(self.stack.len());
+				self.emit(Instruction::Binary {
+					opcode: match opcode {
+						Opcode::BranchVarLth => Opcode::Lth,
+						Opcode::BranchVarGth => Opcode::Gth,
+						_ => Opcode::Eql,
+					},
+					dst: Register::Temporary(cond),
+					lhs: Operand::Variable(index(variable)),
+					rhs: Operand::Constant(index(constant)),
+				});
+				let cond = Operand::Temporary(cond);
+				self.emit(Instruction::JumpIf { cond, jump_if, target: index(target) });
+			}
+
+			Opcode::Dump => {
+				self.flush_reads(1);
+				self.emit(Instruction::Dump { value: *self.stack.last().unwrap() });
+			}
+
+			#[cfg(feature = "extensions")]
+			Opcode::AssignDynamic => {
+				self.flush_reads(1);
+				let value = *self.stack.last().unwrap();
+				self.emit(Instruction::AssignDynamic { kind: index(operand), value });
+			}
+
+			Opcode::Return => {
+				let value = self.stack.pop().unwrap();
+				self.emit(Instruction::Return { value });
+				self.reachable = false;
+			}
+
+			Opcode::Quit => {
+				let status = self.stack.pop().unwrap();
+				self.flush_reads(0);
+				self.emit(Instruction::Quit { status });
+				self.reachable = false;
+			}
+
+			_ => {
+				let args = self.stack.split_off(self.stack.len() - opcode.arity());
+
+				// Blocks can assign to any variable, so deferred reads must happen before calling them.
+				#[cfg(feature = "extensions")]
+				let assigns_variables = matches!(opcode, Opcode::Call | Opcode::SetDynamicVar);
+				#[cfg(not(feature = "extensions"))]
+				let assigns_variables = opcode == Opcode::Call;
+
+				if assigns_variables {
+					self.materialize_if(|operand| matches!(operand, Operand::Variable(_)));
+				} else {
+					self.flush_reads(0);
+				}
+
+				self.emit_result(|dst| match args[..] {
+					[] => Instruction::Nullary { opcode, dst },
+					[arg] => Instruction::Unary { opcode, dst, arg },
+					[lhs, rhs] => Instruction::Binary { opcode, dst, lhs, rhs },
+					[a, b, c] => Instruction::Get { dst, args: [a, b, c] },
+					[a, b, c, d] => Instruction::Set { dst, args: [a, b, c, d] },
+					_ => bug!("opcode {:?} has too many arguments", opcode),
+				});
+			}
+		}
+	}
+}
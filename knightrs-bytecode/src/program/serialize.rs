@@ -0,0 +1,472 @@
+use super::{pad_code, JumpIndex, Program};
+use crate::parser::{source_location::ProgramSource, SourceLocation, VariableName};
+use crate::strings::KnStr;
+use crate::value::{Block, Integer, KnString, List, Value};
+use crate::Environment;
+use indexmap::IndexSet;
+use std::hash::Hasher;
+use std::io::{self, Write};
+
+/// The bytes every serialized [`Program`] starts with.
+pub const MAGIC: [u8; 4] = *b"KNC\0";
+
+/// The current version of the serialized format.
+///
+/// This is bumped whenever the layout of serialized programs (or the opcodes within them) changes,
+/// and programs serialized with a different version will be rejected by [`Program::deserialize`].
+pub const FORMAT_VERSION: u16 = 3;
+
+// Features which change the encoding of programs, and so must match between the program that was
+// serialized and the program that's loading it.
+const FEATURE_STACKTRACE: u8 = 1 << 0;
+const FEATURE_EXTENSIONS: u8 = 1 << 1;
+
+const ENCODING_FEATURES: u8 = {
+	let mut features = 0;
+	if cfg!(feature = "stacktrace") {
+		features |= FEATURE_STACKTRACE;
+	}
+	if cfg!(feature = "extensions") {
+		features |= FEATURE_EXTENSIONS;
+	}
+	features
+};
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_BLOCK: u8 = 6;
+
+// How deeply lists can be nested within constants. Constants are read recursively, so without a
+// limit a small, corrupt input could overflow the stack.
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// Problems that can occur when [deserializing](Program::deserialize) a [`Program`].
+#[derive(Error, Debug)]
+pub enum DeserializeError {
+	/// The input didn't start with [`MAGIC`], so it's not a serialized program.
+	#[error("not a compiled knight program")]
+	BadMagic,
+
+	/// The input was serialized with a different [`FORMAT_VERSION`].
+	#[error("unsupported bytecode version {0} (expected {FORMAT_VERSION})")]
+	UnsupportedVersion(u16),
+
+	/// The input was serialized with a different set of encoding-relevant features enabled.
+	#[error("bytecode was compiled with incompatible features (stacktrace/extensions)")]
+	FeatureMismatch,
+
+	/// The input ended before the entire program was read.
+	#[error("unexpected end of bytecode")]
+	UnexpectedEof,
+
+	/// There was leftover data after the program was read.
+	#[error("trailing data after bytecode")]
+	TrailingData,
+
+	/// A constant had an unknown tag.
+	#[error("unknown constant tag {0}")]
+	UnknownConstantTag(u8),
+
+	/// A string (either a variable name or a string constant) wasn't valid utf-8.
+	#[error("invalid utf-8 in bytecode")]
+	InvalidUtf8,
+
+	/// A variable name was invalid.
+	#[error("invalid variable name {0:?}")]
+	InvalidVariableName(String),
+
+	/// The same variable was in the list of variables more than once.
+	#[error("duplicate variable {0:?}")]
+	DuplicateVariable(String),
+
+	/// A constant had lists nested more deeply than is supported.
+	#[error("constants are nested too deeply")]
+	TooDeeplyNested,
+
+	/// A block constant started past the end of the program's code.
+	#[error("block constant starts at {0}, past the end of the bytecode")]
+	BlockOutOfBounds(usize),
+
+	/// A line number of zero was encountered.
+	#[error("invalid line number 0")]
+	InvalidLineNumber,
+
+	/// A value within the program wasn't valid under the current options.
+	#[error("{0}")]
+	Value(#[from] crate::Error),
+
+	/// The program's bytecode wasn't [valid](Program::verify).
+	#[error("{0}")]
+	Verify(#[from] super::VerifyError),
+}
+
+pub(crate) struct Writer<W>(pub(crate) W);
+
+impl<W: Write> Writer<W> {
+	pub(crate) fn u8(&mut self, byte: u8) -> io::Result<()> {
+		self.0.write_all(&[byte])
+	}
+
+	pub(crate) fn u64(&mut self, num: u64) -> io::Result<()> {
+		self.0.write_all(&num.to_le_bytes())
+	}
+
+	pub(crate) fn usize(&mut self, num: usize) -> io::Result<()> {
+		self.u64(num as u64)
+	}
+
+	pub(crate) fn str(&mut self, string: &str) -> io::Result<()> {
+		self.usize(string.len())?;
+		self.0.write_all(string.as_bytes())
+	}
+
+	fn value(&mut self, value: Value<'_>) -> io::Result<()> {
+		if value.is_null() {
+			self.u8(TAG_NULL)
+		} else if let Some(boolean) = value.as_boolean() {
+			self.u8(if boolean { TAG_TRUE } else { TAG_FALSE })
+		} else if let Some(integer) = value.as_integer() {
+			self.u8(TAG_INTEGER)?;
+			self.u64(integer.inner() as u64)
+		} else if let Some(string) = value.as_knstring() {
+			self.u8(TAG_STRING)?;
+			self.str(string.as_str())
+		} else if let Some(list) = value.as_list() {
+			self.u8(TAG_LIST)?;
+			self.usize(list.len())?;
+			list.iter().try_for_each(|element| self.value(element))
+		} else if let Some(block) = value.as_block() {
+			self.u8(TAG_BLOCK)?;
+			self.usize(block.inner().0)
+		} else {
+			bug!("unknown value type: {:?}", value)
+		}
+	}
+}
+
+/// The FNV-1a hash, which is used instead of `DefaultHasher` as its output is the same on every
+/// platform and Rust version.
+///
+/// Bytes can be hashed either with [`Hasher`] or [`Write`], which never fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+	/// Creates a new hasher, which hasn't hashed anything yet.
+	pub const fn new() -> Self {
+		Self(0xcbf2_9ce4_8422_2325)
+	}
+}
+
+impl Default for Fnv1a {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Hasher for Fnv1a {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.0 ^= byte as u64;
+			self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+		}
+	}
+}
+
+impl Write for Fnv1a {
+	fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+		Hasher::write(self, bytes);
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+	pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], DeserializeError> {
+		if self.0.len() < len {
+			return Err(DeserializeError::UnexpectedEof);
+		}
+
+		let (bytes, rest) = self.0.split_at(len);
+		self.0 = rest;
+		Ok(bytes)
+	}
+
+	pub(crate) fn u8(&mut self) -> Result<u8, DeserializeError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	pub(crate) fn u16(&mut self) -> Result<u16, DeserializeError> {
+		Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+	}
+
+	pub(crate) fn u64(&mut self) -> Result<u64, DeserializeError> {
+		Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+	}
+
+	// Lengths are checked against the remaining input, so a corrupt length can't cause a massive
+	// allocation before we notice it's bogus.
+	pub(crate) fn len(&mut self, min_element_size: usize) -> Result<usize, DeserializeError> {
+		let len = self.u64()?;
+		match usize::try_from(len) {
+			Ok(len) if len.saturating_mul(min_element_size) <= self.0.len() => Ok(len),
+			_ => Err(DeserializeError::UnexpectedEof),
+		}
+	}
+
+	pub(crate) fn str(&mut self) -> Result<&'a str, DeserializeError> {
+		let len = self.len(1)?;
+		std::str::from_utf8(self.bytes(len)?).map_err(|_| DeserializeError::InvalidUtf8)
+	}
+
+	fn lineno(&mut self) -> Result<usize, DeserializeError> {
+		match self.len(0)? {
+			0 => Err(DeserializeError::InvalidLineNumber),
+			lineno => Ok(lineno),
+		}
+	}
+
+	pub(crate) fn variable_name(&mut self) -> Result<VariableName<'static>, DeserializeError> {
+		let name = self.str()?;
+
+		let mut chars = name.chars();
+		let is_valid = chars.next().is_some_and(|c| c.is_lowercase() || c == '_')
+			&& chars.all(|c| c.is_lowercase() || c.is_ascii_digit() || c == '_')
+			&& name.len() <= VariableName::MAX_NAME_LEN;
+
+		if !is_valid {
+			return Err(DeserializeError::InvalidVariableName(name.to_string()));
+		}
+
+		Ok(VariableName::new_unvalidated(KnStr::new_unvalidated(name)).become_owned())
+	}
+
+	// Like the parser, this relies on the caller making sure the `Gc` doesn't collect constants
+	// before they're placed into the program. Blocks are checked against `code_len` here, as they
+	// can't even be created if they're too large; the verifier checks they're actually in bounds.
+	// `depth` is how many lists the value is within.
+	fn value<'gc>(
+		&mut self,
+		env: &Environment<'gc>,
+		code_len: usize,
+		depth: usize,
+	) -> Result<Value<'gc>, DeserializeError> {
+		Ok(match self.u8()? {
+			TAG_NULL => Value::NULL,
+			TAG_FALSE => Value::FALSE,
+			TAG_TRUE => Value::TRUE,
+			TAG_INTEGER => {
+				Integer::new_error(self.u64()? as i64, env.opts()).map_err(crate::Error::from)?.into()
+			}
+			TAG_STRING => {
+				let string = KnString::new(self.str()?.to_string(), env.opts(), env.gc())?;
+				unsafe { string.with_inner(Value::from) }
+			}
+			TAG_LIST if depth == MAX_NESTING_DEPTH => return Err(DeserializeError::TooDeeplyNested),
+			TAG_LIST => {
+				let len = self.len(1)?;
+				let mut elements = Vec::with_capacity(len);
+				for _ in 0..len {
+					elements.push(self.value(env, code_len, depth + 1)?);
+				}
+
+				let list = List::from_slice(&elements, env.opts(), env.gc())?;
+				unsafe { list.with_inner(Value::from) }
+			}
+			TAG_BLOCK => match self.len(0)? {
+				index if index < code_len => Block::new(JumpIndex(index)).into(),
+				index => return Err(DeserializeError::BlockOutOfBounds(index)),
+			},
+			tag => return Err(DeserializeError::UnknownConstantTag(tag)),
+		})
+	}
+}
+
+impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
+	/// Serializes `self` into the versioned `.knc` format, writing it to `out`.
+	///
+	/// The resulting bytes can be loaded again via [`Program::deserialize`]. Note that the
+	/// [`ProgramSource`] of the program isn't serialized, and is instead supplied when deserializing.
+	pub fn serialize(&self, out: impl Write) -> io::Result<()> {
+		let mut out = Writer(out);
+
+		out.0.write_all(&MAGIC)?;
+		out.0.write_all(&FORMAT_VERSION.to_le_bytes())?;
+		out.u8(ENCODING_FEATURES)?;
+
+		out.usize(self.code().len())?;
+		out.0.write_all(self.code())?;
+
+		out.usize(self.constants.len())?;
+		for &constant in self.constants.iter() {
+			out.value(constant)?;
+		}
+
+		out.usize(self.variables.len())?;
+		for variable in self.variables.iter() {
+			out.str(&variable.to_string())?;
+		}
+
+		#[cfg(feature = "stacktrace")]
+		{
+			// Sort them so the output is deterministic.
+			let mut source_lines = self.source_lines.iter().collect::<Vec<_>>();
+			source_lines.sort_by_key(|(&offset, _)| offset);
+			out.usize(source_lines.len())?;
+			for (&offset, location) in source_lines {
+				out.usize(offset)?;
+				out.usize(location.lineno())?;
+			}
+
+			let mut block_locations = self.block_locations.iter().collect::<Vec<_>>();
+			block_locations.sort_by_key(|(index, _)| index.0);
+			out.usize(block_locations.len())?;
+			for (index, (name, location)) in block_locations {
+				out.usize(index.0)?;
+				match name {
+					Some(name) => {
+						out.u8(1)?;
+						out.str(&name.to_string())?;
+					}
+					None => out.u8(0)?,
+				}
+				out.usize(location.lineno())?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// Hashes the parts of `self` which affect how it runs, i.e. everything but the source locations.
+	pub(crate) fn fingerprint(&self) -> u64 {
+		let mut out = Writer(Fnv1a::new());
+
+		// Writing to the hasher never fails.
+		let _ = out.0.write_all(self.code());
+		for &constant in self.constants.iter() {
+			let _ = out.value(constant);
+		}
+		for variable in self.variables.iter() {
+			let _ = out.str(&variable.to_string());
+		}
+
+		out.0.finish()
+	}
+}
+
+impl<'path, 'gc> Program<'static, 'path, 'gc> {
+	/// Loads a program that was [serialized](Program::serialize) into `input`.
+	///
+	/// All constants are allocated within `env`'s [`Gc`](crate::Gc), and are validated against
+	/// `env`'s options. All source locations (if `stacktrace` is enabled) will use `source`. The
+	/// loaded program is [verified](Program::verify), so `input` doesn't need to be trusted.
+	///
+	/// # Safety
+	/// The [`Gc`](crate::Gc) mustn't collect garbage while the program's being loaded (eg by
+	/// [pausing](crate::Gc::pause) it), as with parsing.
+	pub unsafe fn deserialize(
+		input: &[u8],
+		source: ProgramSource<'path>,
+		env: &Environment<'gc>,
+	) -> Result<Self, DeserializeError> {
+		let mut input = Reader(input);
+
+		if input.bytes(MAGIC.len()).map_err(|_| DeserializeError::BadMagic)? != MAGIC {
+			return Err(DeserializeError::BadMagic);
+		}
+
+		match input.u16()? {
+			FORMAT_VERSION => {}
+			version => return Err(DeserializeError::UnsupportedVersion(version)),
+		}
+
+		if input.u8()? != ENCODING_FEATURES {
+			return Err(DeserializeError::FeatureMismatch);
+		}
+
+		let code_len = input.len(1)?;
+		let code = input.bytes(code_len)?.to_vec();
+
+		let constants_len = input.len(1)?;
+		let mut constants = Vec::with_capacity(constants_len);
+		for _ in 0..constants_len {
+			constants.push(input.value(env, code.len(), 0)?);
+		}
+
+		let variables_len = input.len(size_of::<u64>())?;
+		let mut variables = IndexSet::with_capacity(variables_len);
+		for _ in 0..variables_len {
+			let variable = input.variable_name()?;
+			if variables.contains(&variable) {
+				return Err(DeserializeError::DuplicateVariable(variable.to_string()));
+			}
+			variables.insert(variable);
+		}
+
+		#[cfg(feature = "stacktrace")]
+		let source_lines = {
+			let len = input.len(2 * size_of::<u64>())?;
+			let mut source_lines = std::collections::HashMap::with_capacity(len);
+			for _ in 0..len {
+				let offset = input.len(0)?;
+				source_lines.insert(offset, SourceLocation::new(source, input.lineno()?));
+			}
+
+			// `source_location_at` relies on the first instruction always having a location.
+			source_lines.entry(0).or_insert(SourceLocation::new(source, 1));
+			source_lines
+		};
+
+		#[cfg(feature = "stacktrace")]
+		let block_locations = {
+			let len = input.len(2 * size_of::<u64>() + 1)?;
+			let mut block_locations = std::collections::HashMap::with_capacity(len);
+			for _ in 0..len {
+				let index = JumpIndex(input.len(0)?);
+				let name = match input.u8()? {
+					0 => None,
+					_ => Some(input.variable_name()?),
+				};
+				block_locations.insert(index, (name, SourceLocation::new(source, input.lineno()?)));
+			}
+			block_locations
+		};
+
+		if !input.0.is_empty() {
+			return Err(DeserializeError::TrailingData);
+		}
+
+		#[cfg(not(feature = "stacktrace"))]
+		let _ = source;
+
+		let program = Program {
+			code: pad_code(code),
+			constants: constants.into_boxed_slice(),
+			variables,
+
+			#[cfg(feature = "stacktrace")]
+			source_lines,
+
+			#[cfg(feature = "stacktrace")]
+			block_locations,
+
+			_ignored: (&(), &()),
+		};
+
+		program.verify()?;
+		Ok(program)
+	}
+}
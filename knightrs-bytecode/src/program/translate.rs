@@ -0,0 +1,48 @@
+use super::{Program, VerifyError};
+use crate::vm::Opcode;
+
+/// Problems that can occur when translating a [`Program`] to another language, such as with
+/// [`Program::to_c`].
+#[derive(Error, Debug)]
+pub enum TranslateError {
+	/// The program wasn't well-formed.
+	#[error("{0}")]
+	Verify(#[from] VerifyError),
+
+	/// The program used an opcode which can't be translated.
+	#[error("{opcode:?} (at offset {offset}) can't be translated")]
+	UnsupportedOpcode { offset: usize, opcode: Opcode },
+}
+
+impl Program<'_, '_, '_> {
+	// Gets the [stack depths](Program::stack_depths) of `self`, making sure every reachable
+	// instruction can be translated.
+	pub(super) fn translatable_stack_depths(&self) -> Result<Vec<Option<usize>>, TranslateError> {
+		let depths = self.stack_depths()?;
+
+		for offset in self.instruction_offsets().filter(|&offset| depths[offset].is_some()) {
+			// SAFETY: `offset` came from `instruction_offsets`.
+			let (opcode, _) = unsafe { self.opcode_at(offset) };
+			if !is_supported(opcode) {
+				return Err(TranslateError::UnsupportedOpcode { offset, opcode });
+			}
+		}
+
+		Ok(depths)
+	}
+}
+
+// Whether `opcode` can be translated. Extensions which need the interpreter, such as `EVAL` and
+// dynamic variables, can't be.
+fn is_supported(opcode: Opcode) -> bool {
+	#[cfg(feature = "extensions")]
+	if matches!(
+		opcode,
+		Opcode::AssignDynamic | Opcode::Eval | Opcode::Value | Opcode::System | Opcode::SetDynamicVar
+	) {
+		return false;
+	}
+
+	let _ = opcode;
+	true
+}
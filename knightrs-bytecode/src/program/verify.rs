@@ -0,0 +1,437 @@
+use super::{JumpIndex, Program};
+use crate::vm::{FusedOperands, Opcode};
+
+/// Problems that [`Program::verify`] can find within a [`Program`].
+///
+/// Offsets are the indices of instructions within the program.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+	/// The program had no instructions at all.
+	#[error("program has no instructions")]
+	EmptyProgram,
+
+	/// An instruction's opcode wasn't a valid [`Opcode`].
+	#[error("invalid opcode {byte:#04x} at offset {offset}")]
+	InvalidOpcode { offset: usize, byte: u8 },
+
+	/// An instruction's offset ran past the end of the program.
+	#[error("{opcode:?} at offset {offset} is truncated")]
+	TruncatedInstruction { offset: usize, opcode: Opcode },
+
+	/// A jump's target wasn't the start of an instruction within the program.
+	#[error("jump at offset {offset} to {target} isn't to the start of an instruction")]
+	JumpOutOfBounds { offset: usize, target: usize },
+
+	/// An instruction referenced a constant that doesn't exist.
+	#[error("constant {constant} at offset {offset} is out of bounds")]
+	ConstantOutOfBounds { offset: usize, constant: usize },
+
+	/// An instruction referenced a variable that doesn't exist.
+	#[error("variable {variable} at offset {offset} is out of bounds")]
+	VariableOutOfBounds { offset: usize, variable: usize },
+
+	/// An [`Opcode::AssignDynamic`] had an assignment kind that the [`Vm`](crate::vm::Vm) can't run.
+	/// Currently, only assigning to `RANDOM` is supported.
+	#[cfg(feature = "extensions")]
+	#[error("unsupported dynamic assignment {kind} at offset {offset}")]
+	InvalidDynamicAssignment { offset: usize, kind: usize },
+
+	/// A block constant didn't point to the start of an instruction within the program.
+	#[error(
+		"block constant {constant} starts at {target}, which isn't the start of an instruction"
+	)]
+	BlockOutOfBounds { constant: usize, target: usize },
+
+	/// An instruction needed more values than were on the stack.
+	#[error(
+		"stack underflow at offset {offset}: {opcode:?} needs {needed}, but only {depth} exist"
+	)]
+	StackUnderflow { offset: usize, opcode: Opcode, needed: usize, depth: usize },
+
+	/// Two different paths reached the same instruction with different stack depths.
+	#[error("inconsistent stack depth at offset {offset}: {expected} vs {found}")]
+	InconsistentStackDepth { offset: usize, expected: usize, found: usize },
+
+	/// An [`Opcode::Return`] was reached without exactly one value on the stack.
+	#[error("return at offset {offset} has {depth} values on the stack, not 1")]
+	InvalidReturn { offset: usize, depth: usize },
+
+	/// Execution could continue past the last instruction.
+	#[error("execution can run past the end of the program after offset {offset}")]
+	FallsOffEnd { offset: usize },
+
+	/// The program doesn't define `_argv`, which the [`Vm`](crate::vm::Vm) always assigns.
+	#[cfg(feature = "extensions")]
+	#[error("program doesn't have an `_argv` variable")]
+	MissingArgv,
+}
+
+/// Decodes the instruction at `offset`, ensuring that its opcode and offset are valid.
+///
+/// Jump targets aren't checked, as that requires knowing where every instruction starts.
+fn decode(program: &Program<'_, '_, '_>, offset: usize) -> Result<(Opcode, usize), VerifyError> {
+	let byte = program.code()[offset];
+	let opcode = Opcode::from_byte(byte).ok_or(VerifyError::InvalidOpcode { offset, byte })?;
+	let (_, operand) =
+		program.decode_at(offset).ok_or(VerifyError::TruncatedInstruction { offset, opcode })?;
+
+	match opcode {
+		Opcode::PushConstant if program.constants.len() <= operand => {
+			Err(VerifyError::ConstantOutOfBounds { offset, constant: operand })
+		}
+
+		Opcode::GetVar | Opcode::SetVar | Opcode::SetVarPop if program.num_variables() <= operand => {
+			Err(VerifyError::VariableOutOfBounds { offset, variable: operand })
+		}
+
+		_ if opcode.is_fused() => {
+			let FusedOperands { variable, constant, .. } = FusedOperands::unpack(operand);
+
+			if program.num_variables() <= variable {
+				Err(VerifyError::VariableOutOfBounds { offset, variable })
+			} else if program.constants.len() <= constant {
+				Err(VerifyError::ConstantOutOfBounds { offset, constant })
+			} else {
+				Ok((opcode, operand))
+			}
+		}
+
+		#[cfg(feature = "extensions")]
+		Opcode::AssignDynamic if operand != crate::vm::opcode::DynamicAssignment::Random as usize => {
+			Err(VerifyError::InvalidDynamicAssignment { offset, kind: operand })
+		}
+
+		_ => Ok((opcode, operand)),
+	}
+}
+
+impl Program<'_, '_, '_> {
+	/// Verifies that `self` is well-formed, so it can be safely run by a [`Vm`](crate::vm::Vm).
+	///
+	/// This checks that every instruction is a valid [`Opcode`], that all jumps, constants, and
+	/// variables referenced are in bounds, and that the stack never underflows. The stack depth is
+	/// tracked separately for the program itself and every block within it, as each starts with an
+	/// empty stack, and must have exactly one value on it when it returns.
+	pub fn verify(&self) -> Result<(), VerifyError> {
+		self.stack_depths().map(drop)
+	}
+
+	/// Verifies `self`, returning the depth of the stack before each instruction.
+	///
+	/// Depths are indexed by offset, and are `None` for offsets which either aren't the start of an
+	/// instruction, or which can never be reached.
+	pub(crate) fn stack_depths(&self) -> Result<Vec<Option<usize>>, VerifyError> {
+		if self.code().is_empty() {
+			return Err(VerifyError::EmptyProgram);
+		}
+
+		#[cfg(feature = "extensions")]
+		if self.variables.first().is_none_or(|name| name.to_string() != "_argv") {
+			return Err(VerifyError::MissingArgv);
+		}
+
+		// Instructions are variable-length, so `instructions[offset]` is only `Some` if an
+		// instruction starts at `offset`.
+		let mut instructions = vec![None; self.code().len()];
+		let mut offset = 0;
+		while offset < self.code().len() {
+			let (opcode, operand) = decode(self, offset)?;
+			instructions[offset] = Some((opcode, operand));
+			offset += opcode.encoded_len();
+		}
+
+		let is_instruction = |offset: usize| instructions.get(offset).is_some_and(Option::is_some);
+		for (offset, &instruction) in instructions.iter().enumerate() {
+			let target = instruction.and_then(|(opcode, operand)| super::jump_target(opcode, operand));
+			if let Some(target) = target.filter(|&target| !is_instruction(target)) {
+				return Err(VerifyError::JumpOutOfBounds { offset, target });
+			}
+		}
+
+		// Every block, along with the program itself, starts with an empty stack.
+		let mut depths = vec![None; self.code().len()];
+		let mut worklist = vec![(0, 0)];
+		for (constant, JumpIndex(target)) in self.block_constants() {
+			if !is_instruction(target) {
+				return Err(VerifyError::BlockOutOfBounds { constant, target });
+			}
+			worklist.push((target, 0));
+		}
+
+		while let Some((offset, depth)) = worklist.pop() {
+			match depths[offset] {
+				Some(expected) if expected == depth => continue,
+				Some(expected) => {
+					return Err(VerifyError::InconsistentStackDepth { offset, expected, found: depth })
+				}
+				None => depths[offset] = Some(depth),
+			}
+
+			// `offset` is always the start of an instruction, as jumps and blocks were checked above.
+			let (opcode, operand) = instructions[offset].unwrap();
+
+			// Most opcodes pop their arguments and push their result. The exceptions are opcodes
+			// which only peek at the top of the stack, and those that don't push anything.
+			let needed = match opcode {
+				Opcode::SetVar | Opcode::Dup | Opcode::Dump => 1,
+				#[cfg(feature = "extensions")]
+				Opcode::AssignDynamic => 1,
+				_ => opcode.arity(),
+			};
+
+			if depth < needed {
+				return Err(VerifyError::StackUnderflow { offset, opcode, needed, depth });
+			}
+
+			let next_depth = match opcode {
+				Opcode::Return if depth != 1 => {
+					return Err(VerifyError::InvalidReturn { offset, depth })
+				}
+				Opcode::Return | Opcode::Quit => continue,
+
+				Opcode::Jump => {
+					worklist.push((operand, depth));
+					continue;
+				}
+				Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+					worklist.push((operand, depth - 1));
+					depth - 1
+				}
+
+				_ if opcode.is_fused_branch() => {
+					worklist.push((FusedOperands::unpack(operand).target, depth));
+					depth
+				}
+				Opcode::AddVarConst | Opcode::SubVarConst => depth,
+
+				Opcode::PushConstant | Opcode::GetVar | Opcode::Dup => depth + 1,
+				Opcode::SetVar | Opcode::Dump => depth,
+				#[cfg(feature = "extensions")]
+				Opcode::AssignDynamic => depth,
+				Opcode::SetVarPop | Opcode::Pop => depth - 1,
+				_ => depth - opcode.arity() + 1,
+			};
+
+			let next = offset + opcode.encoded_len();
+			if next == self.code().len() {
+				return Err(VerifyError::FallsOffEnd { offset });
+			}
+
+			worklist.push((next, next_depth));
+		}
+
+		Ok(depths)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::parser::VariableName;
+	use crate::strings::KnStr;
+	use crate::value::{Block, Value};
+
+	// Encodes `instructions` into code, the same way the compiler does.
+	fn encode(instructions: &[(Opcode, usize)]) -> Vec<u8> {
+		let mut code = Vec::new();
+		for &(opcode, operand) in instructions {
+			code.push(opcode as u8);
+			code.extend_from_slice(&(operand as u64).to_le_bytes()[..opcode.offset_len()]);
+		}
+		code
+	}
+
+	fn program(
+		code: Vec<u8>,
+		constants: Vec<Value<'static>>,
+		variables: &[&'static str],
+	) -> Program<'static, 'static, 'static> {
+		Program {
+			code: super::super::pad_code(code),
+			constants: constants.into_boxed_slice(),
+			variables: variables
+				.iter()
+				.map(|&name| VariableName::new_unvalidated(KnStr::new_unvalidated(name)))
+				.collect(),
+
+			#[cfg(feature = "stacktrace")]
+			source_lines: Default::default(),
+
+			#[cfg(feature = "stacktrace")]
+			block_locations: Default::default(),
+
+			_ignored: (&(), &()),
+		}
+	}
+
+	// The variables every program needs.
+	const VARIABLES: &[&str] = &["_argv", "a"];
+
+	fn verify(instructions: &[(Opcode, usize)]) -> Result<(), VerifyError> {
+		program(encode(instructions), vec![Value::NULL], VARIABLES).verify()
+	}
+
+	#[test]
+	fn accepts_valid_programs() {
+		assert_eq!(verify(&[(Opcode::PushConstant, 0), (Opcode::Return, 0)]), Ok(()));
+
+		// An `IF` whose branches both push a value.
+		let branches = [
+			(Opcode::GetVar, 1),       // 0
+			(Opcode::JumpIfFalse, 20), // 5
+			(Opcode::PushConstant, 0), // 10
+			(Opcode::Jump, 25),        // 15
+			(Opcode::PushConstant, 0), // 20
+			(Opcode::Return, 0),       // 25
+		];
+		assert_eq!(verify(&branches), Ok(()));
+	}
+
+	#[test]
+	fn empty_program() {
+		assert_eq!(program(vec![], vec![], VARIABLES).verify(), Err(VerifyError::EmptyProgram));
+	}
+
+	#[test]
+	fn invalid_opcode() {
+		assert_eq!(
+			program(vec![0xff], vec![], VARIABLES).verify(),
+			Err(VerifyError::InvalidOpcode { offset: 0, byte: 0xff })
+		);
+	}
+
+	#[test]
+	fn truncated_instruction() {
+		let mut code = encode(&[(Opcode::PushConstant, 0)]);
+		code.pop();
+		assert_eq!(
+			program(code, vec![Value::NULL], VARIABLES).verify(),
+			Err(VerifyError::TruncatedInstruction { offset: 0, opcode: Opcode::PushConstant })
+		);
+	}
+
+	#[test]
+	fn jump_out_of_bounds() {
+		// Both past the end of the code, and into the middle of an instruction.
+		for target in [100, 1] {
+			assert_eq!(
+				verify(&[(Opcode::Jump, target), (Opcode::PushConstant, 0), (Opcode::Return, 0)]),
+				Err(VerifyError::JumpOutOfBounds { offset: 0, target })
+			);
+		}
+	}
+
+	#[test]
+	fn constant_out_of_bounds() {
+		assert_eq!(
+			verify(&[(Opcode::PushConstant, 1), (Opcode::Return, 0)]),
+			Err(VerifyError::ConstantOutOfBounds { offset: 0, constant: 1 })
+		);
+
+		let fused = FusedOperands { variable: 1, constant: 1, target: 0, jump_if: false };
+		assert_eq!(
+			verify(&[
+				(Opcode::AddVarConst, fused.pack().unwrap()),
+				(Opcode::PushConstant, 0),
+				(Opcode::Return, 0)
+			]),
+			Err(VerifyError::ConstantOutOfBounds { offset: 0, constant: 1 })
+		);
+	}
+
+	#[test]
+	fn variable_out_of_bounds() {
+		assert_eq!(
+			verify(&[(Opcode::GetVar, 2), (Opcode::Return, 0)]),
+			Err(VerifyError::VariableOutOfBounds { offset: 0, variable: 2 })
+		);
+
+		let fused = FusedOperands { variable: 2, constant: 0, target: 0, jump_if: false };
+		assert_eq!(
+			verify(&[
+				(Opcode::SubVarConst, fused.pack().unwrap()),
+				(Opcode::PushConstant, 0),
+				(Opcode::Return, 0)
+			]),
+			Err(VerifyError::VariableOutOfBounds { offset: 0, variable: 2 })
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "extensions")]
+	fn invalid_dynamic_assignment() {
+		use crate::vm::opcode::DynamicAssignment;
+
+		let assign = |kind| {
+			verify(&[(Opcode::PushConstant, 0), (Opcode::AssignDynamic, kind), (Opcode::Return, 0)])
+		};
+
+		assert_eq!(assign(DynamicAssignment::Random as usize), Ok(()));
+		for kind in [DynamicAssignment::Output, DynamicAssignment::Prompt, DynamicAssignment::System]
+		{
+			let kind = kind as usize;
+			assert_eq!(assign(kind), Err(VerifyError::InvalidDynamicAssignment { offset: 5, kind }));
+		}
+		assert_eq!(assign(100), Err(VerifyError::InvalidDynamicAssignment { offset: 5, kind: 100 }));
+	}
+
+	#[test]
+	fn block_out_of_bounds() {
+		let code = encode(&[(Opcode::PushConstant, 0), (Opcode::Return, 0)]);
+
+		// Both past the end of the code, and into the middle of an instruction.
+		for target in [code.len(), 1] {
+			let block = Block::new(JumpIndex(target)).into();
+			assert_eq!(
+				program(code.clone(), vec![block], VARIABLES).verify(),
+				Err(VerifyError::BlockOutOfBounds { constant: 0, target })
+			);
+		}
+	}
+
+	#[test]
+	fn stack_underflow() {
+		assert_eq!(
+			verify(&[(Opcode::PushConstant, 0), (Opcode::Add, 0), (Opcode::Return, 0)]),
+			Err(VerifyError::StackUnderflow { offset: 5, opcode: Opcode::Add, needed: 2, depth: 1 })
+		);
+	}
+
+	#[test]
+	fn inconsistent_stack_depth() {
+		// Only one path pushes a value before they join at the `Return`.
+		let result = verify(&[
+			(Opcode::PushConstant, 0), // 0
+			(Opcode::JumpIfTrue, 15),  // 5
+			(Opcode::PushConstant, 0), // 10
+			(Opcode::Return, 0),       // 15
+		]);
+		assert!(
+			matches!(result, Err(VerifyError::InconsistentStackDepth { offset: 15, .. })),
+			"{result:?}"
+		);
+	}
+
+	#[test]
+	fn invalid_return() {
+		assert_eq!(
+			verify(&[(Opcode::PushConstant, 0), (Opcode::PushConstant, 0), (Opcode::Return, 0)]),
+			Err(VerifyError::InvalidReturn { offset: 10, depth: 2 })
+		);
+	}
+
+	#[test]
+	fn falls_off_end() {
+		assert_eq!(
+			verify(&[(Opcode::PushConstant, 0), (Opcode::Pop, 0)]),
+			Err(VerifyError::FallsOffEnd { offset: 5 })
+		);
+	}
+
+	#[test]
+	#[cfg(feature = "extensions")]
+	fn missing_argv() {
+		let code = encode(&[(Opcode::PushConstant, 0), (Opcode::Return, 0)]);
+		assert_eq!(program(code, vec![Value::NULL], &["a"]).verify(), Err(VerifyError::MissingArgv));
+	}
+}
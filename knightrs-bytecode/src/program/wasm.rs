@@ -0,0 +1,521 @@
+use super::{jump_target, Program, TranslateError};
+use crate::value::Value;
+use crate::vm::{FusedOperands, Opcode};
+use crate::Options;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+
+// The runtime that translated programs use, which implements Knight's values and functions.
+const RUNTIME: &str = include_str!("wasm/runtime.wat");
+
+// The runtime's I/O, for each kind of imports.
+const WASI_IMPORTS: &str = include_str!("wasm/wasi.wat");
+const HOST_IMPORTS: &str = include_str!("wasm/host.wat");
+
+// The runtime's configuration globals. Any which aren't enabled are `0`.
+const CONFIG: [&str; 7] = [
+	"i32_integer",
+	"check_overflow",
+	"check_integer_function_bounds",
+	"limit_rand_range",
+	"check_quit_status_codes",
+	"check_variables",
+	"argv",
+];
+
+// Where the program's data starts; everything before it is reserved for the runtime.
+const STATIC_DATA: u32 = 256;
+
+// The runtime's empty string and list, which are also used for empty constants.
+const EMPTY_STRING: u32 = 192;
+const EMPTY_LIST: u32 = 200;
+
+/// A [`Program`] translated to WebAssembly text, returned by [`Program::to_wat`].
+///
+/// The output is a single module. By default it's a WASI command, which can be run with
+/// `wasmtime FILE`, but it can [import its I/O from the host](WasmImports::Host) instead.
+pub struct WatSource<'a, 'src, 'path, 'gc> {
+	program: &'a Program<'src, 'path, 'gc>,
+	depths: Vec<Option<usize>>,
+	// The configuration globals which are enabled.
+	enabled: Vec<&'static str>,
+	imports: WasmImports,
+}
+
+/// Where translated WebAssembly modules get their I/O from.
+///
+/// Either way, modules export their memory as `memory`, and run the program when `_start` is
+/// called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WasmImports {
+	/// WASI, so that modules can be run by anything which supports it.
+	#[default]
+	Wasi,
+
+	/// Functions which the host provides, in the `knight` module:
+	///
+	/// - `output(bytes: i32, length: i32)` writes to stdout, for `OUTPUT` and `DUMP`.
+	/// - `prompt(buffer: i32, capacity: i32) -> i32` reads up to `capacity` bytes of stdin into
+	///   `buffer`, returning how many it read, or `0` at the end of input. `PROMPT` splits them into
+	///   lines, so they don't need to be a whole line.
+	/// - `random() -> i64` returns 64 random bits, for `RANDOM`.
+	/// - `error(bytes: i32, length: i32)` writes part of an error message. The last part ends with a
+	///   newline, after which `quit(1)` is called.
+	/// - `quit(status: i32)` exits with `status`, for `QUIT` and errors. It shouldn't return, and the
+	///   module traps if it does.
+	///
+	/// Addresses are within the exported memory. `argv` is always empty.
+	Host,
+}
+
+impl<'a, 'src, 'path, 'gc> WatSource<'a, 'src, 'path, 'gc> {
+	/// Makes the module get its I/O from `imports`, instead of from WASI.
+	pub fn with_imports(mut self, imports: WasmImports) -> Self {
+		self.imports = imports;
+		self
+	}
+
+	/// Assembles the module into the binary format.
+	pub fn to_wasm(&self) -> Vec<u8> {
+		match wat::parse_str(self.to_string()) {
+			Ok(module) => module,
+			Err(err) => bug!("invalid WebAssembly was generated: {}", err),
+		}
+	}
+}
+
+impl<'src, 'path, 'gc> Program<'src, 'path, 'gc> {
+	/// Translates `self` to WebAssembly text.
+	///
+	/// Like [`Program::to_c`], each block becomes a label within a single function, and the stack
+	/// is lowered to locals. Constants are placed in the module's memory, along with a small runtime
+	/// which uses WASI for I/O (or [the host's imports](WatSource::with_imports)). The same options
+	/// as [`Program::to_c`] are compiled in, and the same extensions aren't supported.
+	///
+	/// # Errors
+	/// Returns an error if `self` can't be [verified](Program::verify), or if it uses an opcode
+	/// which can't be translated.
+	pub fn to_wat(&self, opts: &Options) -> Result<WatSource<'_, 'src, 'path, 'gc>, TranslateError> {
+		let depths = self.translatable_stack_depths()?;
+
+		#[cfg_attr(not(any(feature = "compliance", feature = "extensions")), allow(unused_mut))]
+		let mut config = Vec::new();
+
+		#[cfg(feature = "compliance")]
+		config.extend([
+			("i32_integer", opts.compliance.i32_integer),
+			("check_overflow", opts.compliance.check_overflow),
+			("check_integer_function_bounds", opts.compliance.check_integer_function_bounds),
+			("limit_rand_range", opts.compliance.limit_rand_range),
+			("check_quit_status_codes", opts.compliance.check_quit_status_codes),
+		]);
+
+		#[cfg(feature = "check-variables")]
+		config.push(("check_variables", opts.check_variables));
+
+		#[cfg(feature = "extensions")]
+		config.push(("argv", opts.extensions.argv));
+
+		let _ = opts;
+		let enabled = config.into_iter().filter(|&(_, enabled)| enabled).map(|(name, _)| name);
+		Ok(WatSource {
+			program: self,
+			depths,
+			enabled: enabled.collect(),
+			imports: WasmImports::default(),
+		})
+	}
+
+	/// Translates `self` to a WebAssembly module which uses WASI, in the binary format.
+	///
+	/// See [`Program::to_wat`] for details.
+	///
+	/// # Errors
+	/// Returns an error if `self` can't be [verified](Program::verify), or if it uses an opcode
+	/// which can't be translated.
+	pub fn to_wasm(&self, opts: &Options) -> Result<Vec<u8>, TranslateError> {
+		Ok(self.to_wat(opts)?.to_wasm())
+	}
+}
+
+// The contents of memory after `STATIC_DATA`, which is built up while translating.
+struct StaticData {
+	bytes: Vec<u8>,
+	// The addresses of everything that's been added with `add_bytes`, so they can be reused.
+	existing: HashMap<Vec<u8>, u32>,
+}
+
+impl StaticData {
+	fn new() -> Self {
+		Self { bytes: Vec::new(), existing: HashMap::new() }
+	}
+
+	// The address of the next byte to be added.
+	fn address(&self) -> u32 {
+		STATIC_DATA + self.bytes.len() as u32
+	}
+
+	fn align(&mut self) {
+		self.bytes.resize(self.bytes.len().next_multiple_of(8), 0);
+	}
+
+	// Adds `bytes`, returning their address. The same bytes are only ever added once.
+	fn add_bytes(&mut self, bytes: &[u8], aligned: bool) -> u32 {
+		if let Some(&address) = self.existing.get(bytes) {
+			return address;
+		}
+
+		if aligned {
+			self.align();
+		}
+		let address = self.address();
+		self.bytes.extend_from_slice(bytes);
+		self.existing.insert(bytes.to_vec(), address);
+		address
+	}
+
+	// Adds a string or list which is never freed, returning its address.
+	fn add_object(&mut self, length: usize, contents: &[u8]) -> u32 {
+		let mut object = Vec::with_capacity(8 + contents.len());
+		object.extend_from_slice(&u32::MAX.to_le_bytes());
+		object.extend_from_slice(&(length as u32).to_le_bytes());
+		object.extend_from_slice(contents);
+		self.add_bytes(&object, true)
+	}
+
+	// Adds `value`, returning its encoding. `labels` are the labels of `$run`, which blocks refer to.
+	fn add_value(&mut self, value: Value<'_>, labels: &[usize]) -> i64 {
+		if value.is_null() {
+			8
+		} else if let Some(boolean) = value.as_boolean() {
+			if boolean {
+				24
+			} else {
+				16
+			}
+		} else if let Some(integer) = value.as_integer() {
+			(integer.inner() << 1) | 1
+		} else if let Some(string) = value.as_knstring() {
+			let bytes = string.as_str().as_bytes();
+			let address =
+				if bytes.is_empty() { EMPTY_STRING } else { self.add_object(bytes.len(), bytes) };
+			(i64::from(address) << 3) | 2
+		} else if let Some(list) = value.as_list() {
+			let mut elements = Vec::with_capacity(list.len() * 8);
+			for element in list.iter() {
+				elements.extend_from_slice(&self.add_value(element, labels).to_le_bytes());
+			}
+
+			let address =
+				if list.is_empty() { EMPTY_LIST } else { self.add_object(list.len(), &elements) };
+			(i64::from(address) << 3) | 4
+		} else if let Some(block) = value.as_block() {
+			let Ok(label) = labels.binary_search(&block.inner().0) else {
+				bug!("block {:?} doesn't have a label", block.inner());
+			};
+			((label as i64) << 3) | 6
+		} else {
+			bug!("unknown value type: {:?}", value)
+		}
+	}
+
+	// Writes the data segments for `self`.
+	fn write(&self, f: &mut Formatter) -> fmt::Result {
+		writeln!(f, "(data (i32.const {STATIC_DATA})")?;
+		for chunk in self.bytes.chunks(64) {
+			f.write_str("\t\"")?;
+			for &byte in chunk {
+				// Anything other than printable ASCII (and quotes and backslashes) is written in hex.
+				if matches!(byte, b' '..=b'~') && !matches!(byte, b'"' | b'\\') {
+					write!(f, "{}", byte as char)?;
+				} else {
+					write!(f, "\\{byte:02x}")?;
+				}
+			}
+			writeln!(f, "\"")?;
+		}
+		writeln!(f, ")")
+	}
+}
+
+// Replaces each `(str "...")` in `runtime` with the address and length of the string, which is
+// added to `data`. Only the escapes which the runtime uses are supported.
+fn expand_strings(runtime: &str, data: &mut StaticData) -> String {
+	const START: &str = "(str \"";
+
+	let mut expanded = String::with_capacity(runtime.len());
+	let mut rest = runtime;
+	while let Some(start) = rest.find(START) {
+		expanded.push_str(&rest[..start]);
+		rest = &rest[start + START.len()..];
+
+		let mut string = Vec::new();
+		let mut chars = rest.char_indices();
+		let end = loop {
+			match chars.next() {
+				Some((end, '"')) => break end,
+				Some((_, '\\')) => string.push(match chars.next() {
+					Some((_, 'n')) => b'\n',
+					Some((_, 't')) => b'\t',
+					Some((_, chr @ ('"' | '\'' | '\\'))) => chr as u8,
+					other => bug!("unknown escape in the runtime: {:?}", other),
+				}),
+				Some((_, chr)) => string.push(chr as u8),
+				None => bug!("unterminated string in the runtime"),
+			}
+		};
+
+		let Some(after) = rest[end + 1..].strip_prefix(')') else {
+			bug!("`str` in the runtime doesn't end with `)`");
+		};
+
+		let address = data.add_bytes(&string, false);
+		expanded.push_str(&format!("(i32.const {address}) (i32.const {})", string.len()));
+		rest = after;
+	}
+
+	expanded.push_str(rest);
+	expanded
+}
+
+impl WatSource<'_, '_, '_, '_> {
+	// Writes the instructions for the one at `offset`, where the stack is `depth` deep. `labels` are
+	// the labels of `$run`, and `constants` are the encoded constants.
+	fn write_instruction(
+		&self,
+		f: &mut Formatter,
+		offset: usize,
+		depth: usize,
+		labels: &[usize],
+		constants: &[i64],
+	) -> fmt::Result {
+		// SAFETY: `offset` came from `instruction_offsets`.
+		let (opcode, operand) = unsafe { self.program.opcode_at(offset) };
+
+		// The top few values of the stack, where `top(0)` is the topmost.
+		let top = |idx: usize| format!("$s{}", depth - 1 - idx);
+
+		// Jumps forward can branch straight to the end of their label's block, which they're always
+		// within. Jumps backward have to go through `$dispatch`.
+		let jump = |target: usize| {
+			if offset < target {
+				format!("br $L{target}")
+			} else {
+				let Ok(label) = labels.binary_search(&target) else {
+					bug!("jump target {} doesn't have a label", target);
+				};
+				format!("i32.const {label}\n\t\tlocal.set $label\n\t\tbr $dispatch")
+			}
+		};
+
+		let unary =
+			|name: &str| format!("local.get {}\n\t\tcall ${name}\n\t\tlocal.set {}", top(0), top(0));
+		let binary = |name: &str| {
+			format!(
+				"local.get {}\n\t\tlocal.get {}\n\t\tcall ${name}\n\t\tlocal.set {}",
+				top(1),
+				top(0),
+				top(1)
+			)
+		};
+
+		let instructions = match opcode {
+			Opcode::PushConstant => {
+				format!("i64.const {}\n\t\tlocal.set $s{depth}", constants[operand])
+			}
+			Opcode::Jump => jump(operand),
+			Opcode::JumpIfTrue | Opcode::JumpIfFalse => format!(
+				"local.get {}\n\t\tcall $truthy\n\t\t{}if\n\t\t{}\n\t\tend",
+				top(0),
+				if opcode == Opcode::JumpIfTrue { "" } else { "i32.eqz\n\t\t" },
+				jump(operand)
+			),
+			Opcode::GetVar => {
+				format!("i32.const {operand}\n\t\tcall $get_variable\n\t\tlocal.set $s{depth}")
+			}
+			Opcode::SetVar => format!(
+				"i32.const {operand}\n\t\tlocal.get {}\n\t\tcall $retain\n\t\tcall $set_variable",
+				top(0)
+			),
+			Opcode::SetVarPop => {
+				format!("i32.const {operand}\n\t\tlocal.get {}\n\t\tcall $set_variable", top(0))
+			}
+
+			Opcode::AddVarConst | Opcode::SubVarConst => {
+				let FusedOperands { variable, constant, .. } = FusedOperands::unpack(operand);
+				let function = if opcode == Opcode::AddVarConst { "$add" } else { "$subtract" };
+				format!(
+					"i32.const {variable}\n\t\ti32.const {variable}\n\t\tcall $get_variable\n\t\t\
+					 i64.const {}\n\t\tcall {function}\n\t\tcall $set_variable",
+					constants[constant]
+				)
+			}
+			Opcode::BranchVarLth | Opcode::BranchVarGth | Opcode::BranchVarEql => {
+				let FusedOperands { variable, constant, target, jump_if } =
+					FusedOperands::unpack(operand);
+				let function = match opcode {
+					Opcode::BranchVarLth => "$less_than",
+					Opcode::BranchVarGth => "$greater_than",
+					_ => "$equals",
+				};
+				format!(
+					"i32.const {variable}\n\t\tcall $get_variable\n\t\ti64.const {}\n\t\t\
+					 call {function}\n\t\tcall $truthy\n\t\t{}if\n\t\t{}\n\t\tend",
+					constants[constant],
+					if jump_if { "" } else { "i32.eqz\n\t\t" },
+					jump(target)
+				)
+			}
+
+			Opcode::Prompt => format!("call $prompt\n\t\tlocal.set $s{depth}"),
+			Opcode::Random => format!("call $random\n\t\tlocal.set $s{depth}"),
+			Opcode::Dup => {
+				format!("local.get {}\n\t\tcall $retain\n\t\tlocal.set $s{depth}", top(0))
+			}
+			Opcode::Dump => format!("local.get {}\n\t\tcall $dump", top(0)),
+
+			Opcode::Return => format!("local.get {}\n\t\treturn", top(0)),
+			Opcode::Call => unary("call"),
+			Opcode::Quit => format!("local.get {}\n\t\tcall $quit", top(0)),
+			Opcode::Output => unary("output"),
+			Opcode::Length => unary("length"),
+			Opcode::Not => unary("not"),
+			Opcode::Negate => unary("negate"),
+			Opcode::Ascii => unary("ascii"),
+			Opcode::Box => unary("box"),
+			Opcode::Head => unary("head"),
+			Opcode::Tail => unary("tail"),
+			Opcode::Pop => format!("local.get {}\n\t\tcall $release", top(0)),
+
+			Opcode::Add => binary("add"),
+			Opcode::Sub => binary("subtract"),
+			Opcode::Mul => binary("multiply"),
+			Opcode::Div => binary("divide"),
+			Opcode::Mod => binary("remainder"),
+			Opcode::Pow => binary("power"),
+			Opcode::Lth => binary("less_than"),
+			Opcode::Gth => binary("greater_than"),
+			Opcode::Eql => binary("equals"),
+
+			Opcode::Get => format!(
+				"local.get {}\n\t\tlocal.get {}\n\t\tlocal.get {}\n\t\tcall $get\n\t\tlocal.set {}",
+				top(2),
+				top(1),
+				top(0),
+				top(2)
+			),
+			Opcode::Set => format!(
+				"local.get {}\n\t\tlocal.get {}\n\t\tlocal.get {}\n\t\tlocal.get {}\n\t\t\
+				 call $set\n\t\tlocal.set {}",
+				top(3),
+				top(2),
+				top(1),
+				top(0),
+				top(3)
+			),
+
+			_ => bug!("{:?} isn't supported by the WebAssembly backend", opcode),
+		};
+
+		writeln!(f, "\t\t;; {offset}: {opcode:?}")?;
+		writeln!(f, "\t\t{instructions}")
+	}
+}
+
+impl Display for WatSource<'_, '_, '_, '_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let program = self.program;
+		let reachable =
+			|| program.instruction_offsets().filter(|&offset| self.depths[offset].is_some());
+
+		// The program itself starts at offset `0`, and is run just like any other block. Only the
+		// starts of blocks and jump targets need labels.
+		let mut labels = BTreeSet::from([0]);
+		labels.extend(program.block_constants().into_iter().map(|(_, block)| block.0));
+		for offset in reachable() {
+			// SAFETY: `offset` came from `instruction_offsets`.
+			let (opcode, operand) = unsafe { program.opcode_at(offset) };
+			labels.extend(jump_target(opcode, operand));
+		}
+		let labels = labels.into_iter().collect::<Vec<_>>();
+
+		// Imports have to come before everything else.
+		let imports = match self.imports {
+			WasmImports::Wasi => WASI_IMPORTS,
+			WasmImports::Host => HOST_IMPORTS,
+		};
+
+		let mut data = StaticData::new();
+		let runtime = expand_strings(&format!("{imports}\n{RUNTIME}"), &mut data);
+		let constants = program
+			.constants
+			.iter()
+			.map(|&constant| data.add_value(constant, &labels))
+			.collect::<Vec<_>>();
+
+		let names = program
+			.variables
+			.iter()
+			.map(|variable| {
+				let name = variable.to_string();
+				(data.add_bytes(name.as_bytes(), false), name.len() as u32)
+			})
+			.collect::<Vec<_>>();
+		data.align();
+		let variable_names = data.address();
+		for (address, length) in names {
+			data.bytes.extend_from_slice(&address.to_le_bytes());
+			data.bytes.extend_from_slice(&length.to_le_bytes());
+		}
+
+		// Variables start out as `$UNDEFINED`, which is zero, so they don't need to be in the data.
+		data.align();
+		let variables = data.address();
+		let heap = variables + (program.num_variables() as u32 + 1) * 8;
+
+		writeln!(f, ";; Translated from Knight by knightrs-bytecode.")?;
+		writeln!(f, "(module")?;
+		f.write_str(&runtime)?;
+		writeln!(f)?;
+
+		for name in CONFIG {
+			writeln!(f, "(global ${name} i32 (i32.const {}))", self.enabled.contains(&name) as u8)?;
+		}
+		writeln!(f, "(memory (export \"memory\") {})", heap.div_ceil(65536))?;
+		writeln!(f, "(global $heap (mut i32) (i32.const {heap}))")?;
+		writeln!(f, "(global $variables i32 (i32.const {variables}))")?;
+		writeln!(f, "(global $variable_names i32 (i32.const {variable_names}))")?;
+		data.write(f)?;
+		writeln!(f)?;
+
+		// Blocks are nested so that the end of each label's block is where that label starts; the
+		// innermost block is for labels which don't exist.
+		let stack_size = reachable().filter_map(|offset| self.depths[offset]).max().unwrap_or(0) + 1;
+		writeln!(f, "(func $run (param $label i32) (result i64)")?;
+		for idx in 0..stack_size {
+			writeln!(f, "\t(local $s{idx} i64)")?;
+		}
+		writeln!(f, "\tloop $dispatch")?;
+		for label in labels.iter().rev() {
+			writeln!(f, "\tblock $L{label}")?;
+		}
+		writeln!(f, "\tblock $invalid")?;
+		write!(f, "\t\tlocal.get $label\n\t\tbr_table")?;
+		for label in &labels {
+			write!(f, " $L{label}")?;
+		}
+		writeln!(f, " $invalid")?;
+		writeln!(f, "\tend\n\t\tunreachable")?;
+
+		for offset in reachable() {
+			if labels.binary_search(&offset).is_ok() {
+				writeln!(f, "\tend $L{offset}")?;
+			}
+
+			self.write_instruction(f, offset, self.depths[offset].unwrap(), &labels, &constants)?;
+		}
+
+		writeln!(f, "\tend\n\tunreachable)")?;
+		writeln!(f, ")")
+	}
+}
@@ -0,0 +1,36 @@
+;; The imports for modules which get their I/O from the host, instead of from WASI. They're all in
+;; the `knight` module:
+;;   `output` is given bytes (as an address in the exported memory and a length) to write to stdout.
+;;   `error` is given the bytes of an error message, in as many parts as it needs, the last of which
+;;     ends with a newline. `quit` is then called with a status of `1`.
+;;   `prompt` is given a buffer to read stdin into, and returns how many bytes it read, or `0` at the
+;;     end of input. `PROMPT` splits them into lines, so they don't need to be a whole line.
+;;   `random` returns 64 random bits.
+;;   `quit` is given the status code to exit with. It shouldn't return, and the module traps if it
+;;     does.
+;;
+;; Modules like this don't have any command-line arguments, so `argv` is always empty.
+
+(import "knight" "output" (func $host_output (param i32 i32)))
+(import "knight" "error" (func $host_error (param i32 i32)))
+(import "knight" "prompt" (func $host_prompt (param i32 i32) (result i32)))
+(import "knight" "random" (func $host_random (result i64)))
+(import "knight" "quit" (func $host_quit (param i32)))
+
+(func $write (param $fd i32) (param $bytes i32) (param $length i32)
+	(if (i32.eq (local.get $fd) (i32.const 1))
+		(then (call $host_output (local.get $bytes) (local.get $length)))
+		(else (call $host_error (local.get $bytes) (local.get $length)))))
+
+(func $read (param $buffer i32) (param $capacity i32) (result i32)
+	(call $host_prompt (local.get $buffer) (local.get $capacity)))
+
+(func $random_bits (result i64)
+	(call $host_random))
+
+(func $exit (param $status i32)
+	(call $host_quit (local.get $status))
+	(unreachable))
+
+(func $arguments (result i64)
+	(call $list_value (call $list_alloc (i32.const 0))))
@@ -0,0 +1,1737 @@
+;; The runtime for Knight programs translated to WebAssembly by `Program::to_wat`.
+;;
+;; Everything which talks to the outside world is in the module's imports, which come from either
+;; `wasi.wat` or `host.wat`. They each define the same functions for this file to use:
+;;   `$write` writes bytes to stdout (`1`) or stderr (`2`). `OUTPUT` and `DUMP` write to stdout, and
+;;     error messages are written to stderr, in as many parts as they need.
+;;   `$read` reads bytes from stdin into a buffer, returning how many it read, or `0` at the end of
+;;     input. `PROMPT` splits them into lines.
+;;   `$random_bits` returns 64 random bits, for `RANDOM`.
+;;   `$exit` exits with a status code, after an error or for `QUIT`. It never returns.
+;;   `$arguments` returns a list of the command-line arguments, for the `argv` extension.
+;;
+;; Values are `i64`s. Integers are shifted left once with the lowest bit set, and everything else
+;; has a three-bit tag: `0` for the constants below, `2` for strings, `4` for lists, and `6` for
+;; blocks. Strings and lists are stored in memory as an `i32` refcount and an `i32` length, followed
+;; by their bytes or `i64` elements, and their values are their address shifted left by three.
+;; Blocks are the index of their label within `$run`, shifted left by three.
+;;
+;; Every function which takes a value takes ownership of it, and every function which returns one
+;; returns an owned value, unless it's documented otherwise. Strings and lists with a refcount of -1
+;; (such as the program's constants) are never freed.
+;;
+;; This file is part of a module, not a complete one: the translated program defines `$run`
+;; (which runs a block), the configuration globals, and the memory. Additionally, `str` (which
+;; takes a string literal) isn't WAT: it's replaced with the string's address and length when
+;; translating.
+;;
+;; The first 256 bytes of memory are reserved for the runtime:
+;;   0..16    arguments for imported functions
+;;   16..48   a buffer for formatting integers and characters
+;;   48..56   a buffer for random numbers
+;;   64..192  the free lists for each size of allocation
+;;   192..217 the empty string, the empty list, and a string containing a newline
+
+;; `$UNDEFINED` is only ever used for variables which haven't been assigned yet.
+(global $UNDEFINED i64 (i64.const 0))
+(global $NULL i64 (i64.const 8))
+(global $FALSE i64 (i64.const 16))
+(global $TRUE i64 (i64.const 24))
+
+(global $EMPTY_STRING i32 (i32.const 192))
+(global $EMPTY_LIST i32 (i32.const 200))
+(global $NEWLINE i32 (i32.const 208))
+(data (i32.const 192)
+	"\ff\ff\ff\ff\00\00\00\00"
+	"\ff\ff\ff\ff\00\00\00\00"
+	"\ff\ff\ff\ff\01\00\00\00\n")
+
+;; The buffer for `PROMPT`, which is allocated the first time it's used.
+(global $input (mut i32) (i32.const 0))
+(global $input_start (mut i32) (i32.const 0))
+(global $input_end (mut i32) (i32.const 0))
+(global $input_eof (mut i32) (i32.const 0))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                              Errors                                              ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+(func $print (param $bytes i32) (param $length i32)
+	(call $write (i32.const 1) (local.get $bytes) (local.get $length)))
+
+;; Errors with more than one part are written with `$error_begin`, then `$error_part` for each part,
+;; and then `$error_end`, which exits.
+(func $error_begin
+	(call $write (i32.const 2) (str "error: runtime error: ")))
+
+(func $error_part (param $bytes i32) (param $length i32)
+	(call $write (i32.const 2) (local.get $bytes) (local.get $length)))
+
+(func $error_end
+	(call $write (i32.const 2) (str "\n"))
+	(call $exit (i32.const 1)))
+
+(func $error (param $message i32) (param $length i32)
+	(call $error_begin)
+	(call $error_part (local.get $message) (local.get $length))
+	(call $error_end))
+
+(func $type_name (param $value i64) (result i32 i32)
+	(if (call $is_integer (local.get $value))
+		(then (return (str "Integer"))))
+	(if (call $is_string (local.get $value))
+		(then (return (str "String"))))
+	(if (call $is_list (local.get $value))
+		(then (return (str "List"))))
+	(if (call $is_block (local.get $value))
+		(then (return (str "Block"))))
+	(if (i64.eq (local.get $value) (global.get $NULL))
+		(then (return (str "Null"))))
+	(if (i64.eq (local.get $value) (global.get $UNDEFINED))
+		(then (return (str "<undefined>"))))
+	(str "Boolean"))
+
+;; `$value` is borrowed.
+(func $type_error (param $value i64) (param $function i32) (param $length i32)
+	(call $error_begin)
+	(call $error_part (str "bad type "))
+	(call $error_part (call $type_name (local.get $value)))
+	(call $error_part (str " to function \""))
+	(call $error_part (local.get $function) (local.get $length))
+	(call $error_part (str "\""))
+	(call $error_end))
+
+(func $overflow (param $function i32) (param $length i32)
+	(call $error_begin)
+	(call $error_part (str "method '"))
+	(call $error_part (local.get $function) (local.get $length))
+	(call $error_part (str "' overflowed the bounds"))
+	(call $error_end))
+
+(func $out_of_memory
+	(call $write (i32.const 2) (str "error: out of memory\n"))
+	(call $exit (i32.const 1)))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                              Memory                                              ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+;; Gets the address of the free list for allocations of `2 ** $class` bytes.
+(func $free_list (param $class i32) (result i32)
+	(i32.add (i32.const 64) (i32.shl (local.get $class) (i32.const 2))))
+
+;; Allocates `$size` bytes, aligned to 8. Allocations are rounded up to a power of two (including an
+;; 8-byte header holding that power), and freed ones are reused by later ones of the same size.
+(func $allocate (param $size i32) (result i32)
+	(local $class i32)
+	(local $address i32)
+	(local $end i64)
+
+	(if (i32.gt_u (local.get $size) (i32.const 0x3FFFFFF8))
+		(then (call $out_of_memory)))
+
+	(local.set $class (i32.sub (i32.const 32) (i32.clz (i32.add (local.get $size) (i32.const 7)))))
+	(if (i32.lt_u (local.get $class) (i32.const 4))
+		(then (local.set $class (i32.const 4))))
+
+	(local.set $address (i32.load (call $free_list (local.get $class))))
+	(if (local.get $address)
+		(then
+			(i32.store (call $free_list (local.get $class)) (i32.load (local.get $address)))
+			(return (local.get $address))))
+
+	(local.set $address (global.get $heap))
+	(local.set $end
+		(i64.add
+			(i64.extend_i32_u (local.get $address))
+			(i64.shl (i64.const 1) (i64.extend_i32_u (local.get $class)))))
+
+	(if (i64.ge_u (local.get $end) (i64.const 0xFFFFFFFF))
+		(then (call $out_of_memory)))
+
+	(if (i64.gt_u (local.get $end) (i64.shl (i64.extend_i32_u (memory.size)) (i64.const 16)))
+		(then
+			(if (i32.eq
+					(memory.grow
+						(i32.sub
+							(i32.wrap_i64 (i64.shr_u (i64.add (local.get $end) (i64.const 0xFFFF)) (i64.const 16)))
+							(memory.size)))
+					(i32.const -1))
+				(then (call $out_of_memory)))))
+
+	(i32.store (local.get $address) (local.get $class))
+	(global.set $heap (i32.wrap_i64 (local.get $end)))
+	(i32.add (local.get $address) (i32.const 8)))
+
+(func $free (param $address i32)
+	(local $list i32)
+	(local.set $list (call $free_list (i32.load (i32.sub (local.get $address) (i32.const 8)))))
+	(i32.store (local.get $address) (i32.load (local.get $list)))
+	(i32.store (local.get $list) (local.get $address)))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                              Values                                              ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+(func $integer (param $integer i64) (result i64)
+	(i64.or (i64.shl (local.get $integer) (i64.const 1)) (i64.const 1)))
+
+(func $boolean (param $boolean i32) (result i64)
+	(select (global.get $TRUE) (global.get $FALSE) (local.get $boolean)))
+
+(func $string_value (param $string i32) (result i64)
+	(i64.or (i64.shl (i64.extend_i32_u (local.get $string)) (i64.const 3)) (i64.const 2)))
+
+(func $list_value (param $list i32) (result i64)
+	(i64.or (i64.shl (i64.extend_i32_u (local.get $list)) (i64.const 3)) (i64.const 4)))
+
+(func $is_integer (param $value i64) (result i32)
+	(i32.wrap_i64 (i64.and (local.get $value) (i64.const 1))))
+
+(func $is_boolean (param $value i64) (result i32)
+	(i32.or
+		(i64.eq (local.get $value) (global.get $FALSE))
+		(i64.eq (local.get $value) (global.get $TRUE))))
+
+(func $is_string (param $value i64) (result i32)
+	(i64.eq (i64.and (local.get $value) (i64.const 7)) (i64.const 2)))
+
+(func $is_list (param $value i64) (result i32)
+	(i64.eq (i64.and (local.get $value) (i64.const 7)) (i64.const 4)))
+
+(func $is_block (param $value i64) (result i32)
+	(i64.eq (i64.and (local.get $value) (i64.const 7)) (i64.const 6)))
+
+(func $as_integer (param $value i64) (result i64)
+	(i64.shr_s (local.get $value) (i64.const 1)))
+
+;; Gets the address of a string or list, or the label of a block.
+(func $payload (param $value i64) (result i32)
+	(i32.wrap_i64 (i64.shr_u (local.get $value) (i64.const 3))))
+
+;; Gets the length of the string or list at `$address`.
+(func $length_of (param $address i32) (result i32)
+	(i32.load offset=4 (local.get $address)))
+
+(func $bytes_of (param $string i32) (result i32)
+	(i32.add (local.get $string) (i32.const 8)))
+
+;; Gets the address of the `$index`th element of `$list`.
+(func $element (param $list i32) (param $index i32) (result i32)
+	(i32.add (i32.add (local.get $list) (i32.const 8)) (i32.shl (local.get $index) (i32.const 3))))
+
+;; Allocates a string of `$length` bytes, which the caller must fill in.
+(func $string_alloc (param $length i32) (result i32)
+	(local $string i32)
+
+	(if (i32.eqz (local.get $length))
+		(then (return (global.get $EMPTY_STRING))))
+	(if (i32.gt_u (local.get $length) (i32.const 0x3FFFFFF0))
+		(then (call $out_of_memory)))
+
+	(local.set $string (call $allocate (i32.add (local.get $length) (i32.const 8))))
+	(i32.store (local.get $string) (i32.const 1))
+	(i32.store offset=4 (local.get $string) (local.get $length))
+	(local.get $string))
+
+(func $string_new (param $bytes i32) (param $length i32) (result i32)
+	(local $string i32)
+	(local.set $string (call $string_alloc (local.get $length)))
+	(memory.copy (call $bytes_of (local.get $string)) (local.get $bytes) (local.get $length))
+	(local.get $string))
+
+;; Allocates a list of `$length` elements, which the caller must fill in.
+(func $list_alloc (param $length i32) (result i32)
+	(local $list i32)
+
+	(if (i32.eqz (local.get $length))
+		(then (return (global.get $EMPTY_LIST))))
+	(if (i32.gt_u (local.get $length) (i32.const 0x07FFFFFE))
+		(then (call $out_of_memory)))
+
+	(local.set $list (call $allocate (i32.add (i32.shl (local.get $length) (i32.const 3)) (i32.const 8))))
+	(i32.store (local.get $list) (i32.const 1))
+	(i32.store offset=4 (local.get $list) (local.get $length))
+	(local.get $list))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                         Reference Counts                                         ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+;; Borrows `$value`, returning a new reference to it.
+(func $retain (param $value i64) (result i64)
+	(local $address i32)
+
+	(if (i32.or (call $is_string (local.get $value)) (call $is_list (local.get $value)))
+		(then
+			(local.set $address (call $payload (local.get $value)))
+			(if (i32.ne (i32.load (local.get $address)) (i32.const -1))
+				(then (i32.store (local.get $address) (i32.add (i32.load (local.get $address)) (i32.const 1)))))))
+
+	(local.get $value))
+
+(func $release (param $value i64)
+	(local $address i32)
+	(local $index i32)
+
+	(if (i32.eqz (i32.or (call $is_string (local.get $value)) (call $is_list (local.get $value))))
+		(then (return)))
+
+	(local.set $address (call $payload (local.get $value)))
+	(if (i32.eq (i32.load (local.get $address)) (i32.const -1))
+		(then (return)))
+
+	(i32.store (local.get $address) (i32.sub (i32.load (local.get $address)) (i32.const 1)))
+	(if (i32.load (local.get $address))
+		(then (return)))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(block $done
+				(loop $elements
+					(br_if $done (i32.ge_u (local.get $index) (call $length_of (local.get $address))))
+					(call $release (i64.load (call $element (local.get $address) (local.get $index))))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $elements)))))
+
+	(call $free (local.get $address)))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                             Integers                                             ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+(func $integer_max (result i64)
+	(select (i64.const 0x7FFFFFFF) (i64.const 0x3FFFFFFFFFFFFFFF) (global.get $i32_integer)))
+
+;; Truncates `$integer` to 63 bits, which is all the interpreter has room for.
+(func $truncate (param $integer i64) (result i64)
+	(i64.shr_s (i64.shl (local.get $integer) (i64.const 1)) (i64.const 1)))
+
+;; Creates the result of the function `$function`, making sure it's in bounds.
+(func $integer_result (param $integer i64) (param $function i32) (param $length i32) (result i64)
+	(if (global.get $i32_integer)
+		(then
+			(if (i32.or
+					(i64.lt_s (local.get $integer) (i64.const -0x80000000))
+					(i64.gt_s (local.get $integer) (i64.const 0x7FFFFFFF)))
+				(then (call $overflow (local.get $function) (local.get $length))))))
+
+	(call $integer (local.get $integer)))
+
+(func $add_integers (param $lhs i64) (param $rhs i64) (result i64)
+	(local $sum i64)
+	(local.set $sum (i64.add (local.get $lhs) (local.get $rhs)))
+
+	;; It overflowed if the sum's sign is different from both of the operands' signs.
+	(if (global.get $check_overflow)
+		(then
+			(if (i64.lt_s
+					(i64.and
+						(i64.xor (local.get $lhs) (local.get $sum))
+						(i64.xor (local.get $rhs) (local.get $sum)))
+					(i64.const 0))
+				(then (call $overflow (str "+"))))))
+
+	(local.get $sum))
+
+(func $subtract_integers (param $lhs i64) (param $rhs i64) (result i64)
+	(local $difference i64)
+	(local.set $difference (i64.sub (local.get $lhs) (local.get $rhs)))
+
+	;; It overflowed if the operands' signs are different, and the difference's sign isn't `$lhs`'s.
+	(if (global.get $check_overflow)
+		(then
+			(if (i64.lt_s
+					(i64.and
+						(i64.xor (local.get $lhs) (local.get $rhs))
+						(i64.xor (local.get $lhs) (local.get $difference)))
+					(i64.const 0))
+				(then (call $overflow (str "-"))))))
+
+	(local.get $difference))
+
+(func $multiply_integers (param $lhs i64) (param $rhs i64) (param $function i32) (param $length i32)
+		(result i64)
+	(local $product i64)
+	(local.set $product (i64.mul (local.get $lhs) (local.get $rhs)))
+
+	(if (global.get $check_overflow)
+		(then
+			(if (i64.eq (local.get $lhs) (i64.const -1))
+				(then
+					(if (i64.eq (local.get $rhs) (i64.const 0x8000000000000000))
+						(then (call $overflow (local.get $function) (local.get $length)))))
+				(else
+					(if (i64.ne (local.get $lhs) (i64.const 0))
+						(then
+							(if (i64.ne (i64.div_s (local.get $product) (local.get $lhs)) (local.get $rhs))
+								(then (call $overflow (local.get $function) (local.get $length))))))))))
+
+	(local.get $product))
+
+(func $power_integers (param $base i64) (param $exponent i64) (result i64)
+	(local $result i64)
+
+	(if (i64.lt_s (local.get $exponent) (i64.const 0))
+		(then
+			(if (global.get $check_integer_function_bounds)
+				(then (call $error (str "domain error: negative exponent"))))
+			(if (i64.eq (local.get $base) (i64.const -1))
+				(then
+					(return
+						(select
+							(local.get $base)
+							(i64.const 1)
+							(i64.eqz (i64.rem_s (local.get $exponent) (i64.const 2)))))))
+			(if (i64.eqz (local.get $base))
+				(then (call $error (str "0 exponentiated by a negative power"))))
+			(return (i64.extend_i32_u (i64.eq (local.get $base) (i64.const 1))))))
+
+	(if (i64.gt_u (local.get $exponent) (i64.const 0xFFFFFFFF))
+		(then
+			(if (i64.le_u (local.get $base) (i64.const 1))
+				(then (return (local.get $base))))
+			(call $error (str "domain error: exponent too large"))))
+
+	(local.set $result (i64.const 1))
+	(block $done
+		(loop $square
+			(br_if $done (i64.eqz (local.get $exponent)))
+			(if (i32.wrap_i64 (i64.and (local.get $exponent) (i64.const 1)))
+				(then
+					(local.set $result
+						(call $multiply_integers (local.get $result) (local.get $base) (str "^")))))
+			(if (i64.ne (local.get $exponent) (i64.const 1))
+				(then
+					(local.set $base
+						(call $multiply_integers (local.get $base) (local.get $base) (str "^")))))
+			(local.set $exponent (i64.shr_u (local.get $exponent) (i64.const 1)))
+			(br $square)))
+
+	(local.get $result))
+
+;; Formats `$integer` in the formatting buffer, returning the address and length of the result.
+(func $format_integer (param $integer i64) (result i32 i32)
+	(local $position i32)
+	(local $magnitude i64)
+
+	(local.set $position (i32.const 48))
+	(local.set $magnitude
+		(select
+			(i64.sub (i64.const 0) (local.get $integer))
+			(local.get $integer)
+			(i64.lt_s (local.get $integer) (i64.const 0))))
+
+	(loop $digits
+		(local.set $position (i32.sub (local.get $position) (i32.const 1)))
+		(i32.store8
+			(local.get $position)
+			(i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $magnitude) (i64.const 10)))))
+		(local.set $magnitude (i64.div_u (local.get $magnitude) (i64.const 10)))
+		(br_if $digits (i64.ne (local.get $magnitude) (i64.const 0))))
+
+	(if (i64.lt_s (local.get $integer) (i64.const 0))
+		(then
+			(local.set $position (i32.sub (local.get $position) (i32.const 1)))
+			(i32.store8 (local.get $position) (i32.const 45))))
+
+	(local.get $position)
+	(i32.sub (i32.const 48) (local.get $position)))
+
+;; Parses an integer out of `$string` (borrowed), like the Knight spec says to.
+(func $parse_integer (param $string i32) (result i64)
+	(local $position i32)
+	(local $end i32)
+	(local $byte i32)
+	(local $negative i32)
+	(local $integer i64)
+
+	(local.set $position (call $bytes_of (local.get $string)))
+	(local.set $end (i32.add (local.get $position) (call $length_of (local.get $string))))
+
+	(block $done
+		(loop $whitespace
+			(br_if $done (i32.ge_u (local.get $position) (local.get $end)))
+			(local.set $byte (i32.load8_u (local.get $position)))
+			(br_if $done
+				(i32.eqz
+					(i32.or
+						(i32.eq (local.get $byte) (i32.const 32))
+						(i32.le_u (i32.sub (local.get $byte) (i32.const 9)) (i32.const 4)))))
+			(local.set $position (i32.add (local.get $position) (i32.const 1)))
+			(br $whitespace)))
+
+	(if (i32.lt_u (local.get $position) (local.get $end))
+		(then
+			(local.set $byte (i32.load8_u (local.get $position)))
+			(if (i32.or (i32.eq (local.get $byte) (i32.const 45)) (i32.eq (local.get $byte) (i32.const 43)))
+				(then
+					(local.set $negative (i32.eq (local.get $byte) (i32.const 45)))
+					(local.set $position (i32.add (local.get $position) (i32.const 1)))))))
+
+	(block $done
+		(loop $digits
+			(br_if $done (i32.ge_u (local.get $position) (local.get $end)))
+			(local.set $byte (i32.sub (i32.load8_u (local.get $position)) (i32.const 48)))
+			(br_if $done (i32.gt_u (local.get $byte) (i32.const 9)))
+
+			(if (i64.gt_u (local.get $integer) (i64.const 922337203685477580))
+				(then (call $error (str "integer overflow"))))
+			(local.set $integer
+				(i64.add
+					(i64.mul (local.get $integer) (i64.const 10))
+					(i64.extend_i32_u (local.get $byte))))
+			(if (i64.gt_u
+					(local.get $integer)
+					(i64.add (i64.const 0x7FFFFFFFFFFFFFFF) (i64.extend_i32_u (local.get $negative))))
+				(then (call $error (str "integer overflow"))))
+
+			(local.set $position (i32.add (local.get $position) (i32.const 1)))
+			(br $digits)))
+
+	(if (local.get $negative)
+		(then (local.set $integer (i64.sub (i64.const 0) (local.get $integer)))))
+
+	(if (global.get $i32_integer)
+		(then
+			(if (i32.or
+					(i64.lt_s (local.get $integer) (i64.const -0x80000000))
+					(i64.gt_s (local.get $integer) (i64.const 0x7FFFFFFF)))
+				(then
+					(call $error_begin)
+					(call $error_part (str "integer "))
+					(call $error_part (call $format_integer (local.get $integer)))
+					(call $error_part (str " is out of bounds"))
+					(call $error_end)))))
+
+	(call $truncate (local.get $integer)))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                           Conversions                                            ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+;; Converts `$value` (borrowed) to a boolean.
+(func $to_boolean (param $value i64) (result i32)
+	(if (call $is_integer (local.get $value))
+		(then (return (i64.ne (local.get $value) (call $integer (i64.const 0))))))
+	(if (i32.or (call $is_string (local.get $value)) (call $is_list (local.get $value)))
+		(then (return (i32.ne (call $length_of (call $payload (local.get $value))) (i32.const 0)))))
+
+	(i32.and
+		(i64.ne (local.get $value) (global.get $NULL))
+		(i64.ne (local.get $value) (global.get $FALSE))))
+
+;; Converts `$value` (borrowed) to an integer.
+(func $to_integer (param $value i64) (result i64)
+	(if (call $is_integer (local.get $value))
+		(then (return (call $as_integer (local.get $value)))))
+	(if (call $is_string (local.get $value))
+		(then (return (call $parse_integer (call $payload (local.get $value))))))
+	(if (call $is_list (local.get $value))
+		(then (return (i64.extend_i32_u (call $length_of (call $payload (local.get $value)))))))
+	(if (call $is_block (local.get $value))
+		(then (call $error (str "cannot convert Blocks to integers"))))
+
+	(i64.extend_i32_u (i64.eq (local.get $value) (global.get $TRUE))))
+
+;; Converts `$value` to a string.
+(func $to_string (param $value i64) (result i32)
+	(local $string i32)
+
+	(if (call $is_string (local.get $value))
+		(then (return (call $payload (local.get $value)))))
+	(if (call $is_integer (local.get $value))
+		(then (return (call $string_new (call $format_integer (call $as_integer (local.get $value)))))))
+	(if (call $is_list (local.get $value))
+		(then
+			(local.set $string (call $list_join (call $payload (local.get $value)) (global.get $NEWLINE)))
+			(call $release (local.get $value))
+			(return (local.get $string))))
+	(if (call $is_block (local.get $value))
+		(then (call $error (str "cannot convert Blocks to strings"))))
+	(if (i64.eq (local.get $value) (global.get $TRUE))
+		(then (return (call $string_new (str "true")))))
+	(if (i64.eq (local.get $value) (global.get $FALSE))
+		(then (return (call $string_new (str "false")))))
+
+	(global.get $EMPTY_STRING))
+
+;; The length of the UTF-8 character starting with `$byte`.
+(func $char_length (param $byte i32) (result i32)
+	(if (i32.lt_u (local.get $byte) (i32.const 0x80))
+		(then (return (i32.const 1))))
+	(if (i32.lt_u (local.get $byte) (i32.const 0xE0))
+		(then (return (i32.const 2))))
+	(if (i32.lt_u (local.get $byte) (i32.const 0xF0))
+		(then (return (i32.const 3))))
+	(i32.const 4))
+
+;; Converts `$value` to a list.
+(func $to_list (param $value i64) (result i32)
+	(local $list i32)
+	(local $length i32)
+	(local $index i32)
+	(local $integer i64)
+	(local $position i32)
+	(local $end i32)
+	(local $size i32)
+
+	(if (call $is_list (local.get $value))
+		(then (return (call $payload (local.get $value)))))
+
+	(if (call $is_integer (local.get $value))
+		(then
+			(local.set $integer (call $as_integer (local.get $value)))
+			(local.set $length (i32.const 1))
+			(block $done
+				(loop $count
+					(br_if $done (i64.eqz (i64.div_s (local.get $integer) (i64.const 10))))
+					(local.set $integer (i64.div_s (local.get $integer) (i64.const 10)))
+					(local.set $length (i32.add (local.get $length) (i32.const 1)))
+					(br $count)))
+
+			(local.set $list (call $list_alloc (local.get $length)))
+			(local.set $integer (call $as_integer (local.get $value)))
+			(local.set $index (local.get $length))
+			(loop $digits
+				(local.set $index (i32.sub (local.get $index) (i32.const 1)))
+				(i64.store
+					(call $element (local.get $list) (local.get $index))
+					(call $integer (i64.rem_s (local.get $integer) (i64.const 10))))
+				(local.set $integer (i64.div_s (local.get $integer) (i64.const 10)))
+				(br_if $digits (local.get $index)))
+			(return (local.get $list))))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(local.set $position (call $bytes_of (call $payload (local.get $value))))
+			(local.set $end
+				(i32.add (local.get $position) (call $length_of (call $payload (local.get $value)))))
+			(block $done
+				(loop $count
+					(br_if $done (i32.ge_u (local.get $position) (local.get $end)))
+					(local.set $length (i32.add (local.get $length) (i32.const 1)))
+					(local.set $position
+						(i32.add (local.get $position) (call $char_length (i32.load8_u (local.get $position)))))
+					(br $count)))
+
+			(local.set $list (call $list_alloc (local.get $length)))
+			(local.set $position (call $bytes_of (call $payload (local.get $value))))
+			(block $done
+				(loop $chars
+					(br_if $done (i32.ge_u (local.get $position) (local.get $end)))
+					(local.set $size (call $char_length (i32.load8_u (local.get $position))))
+					(i64.store
+						(call $element (local.get $list) (local.get $index))
+						(call $string_value (call $string_new (local.get $position) (local.get $size))))
+					(local.set $position (i32.add (local.get $position) (local.get $size)))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $chars)))
+
+			(call $release (local.get $value))
+			(return (local.get $list))))
+
+	(if (call $is_block (local.get $value))
+		(then (call $error (str "cannot convert Blocks to lists"))))
+
+	(if (i64.eq (local.get $value) (global.get $TRUE))
+		(then
+			(local.set $list (call $list_alloc (i32.const 1)))
+			(i64.store (call $element (local.get $list) (i32.const 0)) (local.get $value))
+			(return (local.get $list))))
+
+	(global.get $EMPTY_LIST))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                        Strings and Lists                                         ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+;; Joins `$list` (borrowed) with `$separator` (borrowed).
+(func $list_join (param $list i32) (param $separator i32) (result i32)
+	(local $length i32)
+	(local $strings i32)
+	(local $index i32)
+	(local $string i32)
+	(local $total i64)
+	(local $result i32)
+	(local $position i32)
+
+	(local.set $length (call $length_of (local.get $list)))
+	(if (i32.eqz (local.get $length))
+		(then (return (global.get $EMPTY_STRING))))
+
+	(local.set $strings (call $allocate (i32.shl (local.get $length) (i32.const 2))))
+	(block $done
+		(loop $convert
+			(br_if $done (i32.ge_u (local.get $index) (local.get $length)))
+			(local.set $string
+				(call $to_string
+					(call $retain (i64.load (call $element (local.get $list) (local.get $index))))))
+			(i32.store
+				(i32.add (local.get $strings) (i32.shl (local.get $index) (i32.const 2)))
+				(local.get $string))
+			(local.set $total
+				(i64.add (local.get $total) (i64.extend_i32_u (call $length_of (local.get $string)))))
+			(if (local.get $index)
+				(then
+					(local.set $total
+						(i64.add (local.get $total) (i64.extend_i32_u (call $length_of (local.get $separator)))))))
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $convert)))
+
+	(if (i64.gt_u (local.get $total) (i64.const 0x3FFFFFF0))
+		(then (call $out_of_memory)))
+
+	(local.set $result (call $string_alloc (i32.wrap_i64 (local.get $total))))
+	(local.set $position (call $bytes_of (local.get $result)))
+	(local.set $index (i32.const 0))
+	(block $done
+		(loop $join
+			(br_if $done (i32.ge_u (local.get $index) (local.get $length)))
+			(local.set $string
+				(i32.load (i32.add (local.get $strings) (i32.shl (local.get $index) (i32.const 2)))))
+
+			(if (local.get $index)
+				(then
+					(memory.copy
+						(local.get $position)
+						(call $bytes_of (local.get $separator))
+						(call $length_of (local.get $separator)))
+					(local.set $position
+						(i32.add (local.get $position) (call $length_of (local.get $separator))))))
+
+			(memory.copy
+				(local.get $position)
+				(call $bytes_of (local.get $string))
+				(call $length_of (local.get $string)))
+			(local.set $position (i32.add (local.get $position) (call $length_of (local.get $string))))
+			(call $release (call $string_value (local.get $string)))
+
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $join)))
+
+	(call $free (local.get $strings))
+	(local.get $result))
+
+;; Copies `$count` elements from `$from` to `$to`, retaining each of them.
+(func $copy_elements (param $to i32) (param $from i32) (param $count i32)
+	(local $index i32)
+	(block $done
+		(loop $copy
+			(br_if $done (i32.ge_u (local.get $index) (local.get $count)))
+			(i64.store
+				(i32.add (local.get $to) (i32.shl (local.get $index) (i32.const 3)))
+				(call $retain (i64.load (i32.add (local.get $from) (i32.shl (local.get $index) (i32.const 3))))))
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $copy))))
+
+;; Makes a new list out of `$length` elements of `$list` (borrowed) starting at `$start`, followed
+;; by `$count` elements from `$rest` (borrowed), followed by `$list`'s elements from `$resume` on.
+(func $list_splice
+		(param $list i32) (param $start i32) (param $length i32)
+		(param $rest i32) (param $count i32) (param $resume i32)
+		(result i32)
+	(local $tail i32)
+	(local $result i32)
+
+	(if (i32.lt_u (local.get $resume) (call $length_of (local.get $list)))
+		(then (local.set $tail (i32.sub (call $length_of (local.get $list)) (local.get $resume)))))
+
+	(local.set $result
+		(call $list_alloc (i32.add (i32.add (local.get $length) (local.get $count)) (local.get $tail))))
+	(call $copy_elements
+		(call $element (local.get $result) (i32.const 0))
+		(call $element (local.get $list) (local.get $start))
+		(local.get $length))
+	(call $copy_elements
+		(call $element (local.get $result) (local.get $length))
+		(call $element (local.get $rest) (i32.const 0))
+		(local.get $count))
+	(call $copy_elements
+		(call $element (local.get $result) (i32.add (local.get $length) (local.get $count)))
+		(call $element (local.get $list) (local.get $resume))
+		(local.get $tail))
+
+	(local.get $result))
+
+;; Converts `$start` and `$length` into the start and end of a range, for `GET` and `SET`.
+(func $range (param $start i64) (param $length i64) (result i32 i32)
+	(local $begin i64)
+	(local $amount i64)
+
+	(local.set $begin (call $to_integer (local.get $start)))
+	(call $release (local.get $start))
+	(if (i64.lt_s (local.get $begin) (i64.const 0))
+		(then (call $error (str "domain error: negative start position"))))
+
+	(local.set $amount (call $to_integer (local.get $length)))
+	(call $release (local.get $length))
+	(if (i64.lt_s (local.get $amount) (i64.const 0))
+		(then (call $error (str "domain error: negative length"))))
+
+	;; Nothing is longer than `i32::MAX`, so capping both of them there doesn't change anything.
+	(if (i64.gt_u (local.get $begin) (i64.const 0x7FFFFFFF))
+		(then (local.set $begin (i64.const 0x7FFFFFFF))))
+	(if (i64.gt_u (local.get $amount) (i64.const 0x7FFFFFFF))
+		(then (local.set $amount (i64.const 0x7FFFFFFF))))
+
+	(i32.wrap_i64 (local.get $begin))
+	(i32.wrap_i64 (i64.add (local.get $begin) (local.get $amount))))
+
+;; Compares `$lhs_length` bytes at `$lhs` with `$rhs_length` bytes at `$rhs`.
+(func $compare_bytes (param $lhs i32) (param $lhs_length i32) (param $rhs i32) (param $rhs_length i32)
+		(result i32)
+	(local $index i32)
+	(local $difference i32)
+
+	(block $done
+		(loop $bytes
+			(br_if $done (i32.ge_u (local.get $index) (local.get $lhs_length)))
+			(br_if $done (i32.ge_u (local.get $index) (local.get $rhs_length)))
+			(local.set $difference
+				(i32.sub
+					(i32.load8_u (i32.add (local.get $lhs) (local.get $index)))
+					(i32.load8_u (i32.add (local.get $rhs) (local.get $index)))))
+			(if (local.get $difference)
+				(then
+					(return
+						(i32.sub
+							(i32.gt_s (local.get $difference) (i32.const 0))
+							(i32.lt_s (local.get $difference) (i32.const 0))))))
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $bytes)))
+
+	(i32.sub
+		(i32.gt_u (local.get $lhs_length) (local.get $rhs_length))
+		(i32.lt_u (local.get $lhs_length) (local.get $rhs_length))))
+
+;; Compares `$lhs` and `$rhs` (both borrowed) for `<` and `>`, which is `$function`.
+(func $compare (param $lhs i64) (param $rhs i64) (param $function i32) (param $function_length i32)
+		(result i32)
+	(local $integer i64)
+	(local $string i32)
+	(local $list i32)
+	(local $length i32)
+	(local $index i32)
+	(local $result i32)
+
+	(if (call $is_integer (local.get $lhs))
+		(then
+			(local.set $integer (call $to_integer (local.get $rhs)))
+			(return
+				(i32.sub
+					(i64.gt_s (call $as_integer (local.get $lhs)) (local.get $integer))
+					(i64.lt_s (call $as_integer (local.get $lhs)) (local.get $integer))))))
+
+	(if (call $is_boolean (local.get $lhs))
+		(then
+			(return
+				(i32.sub
+					(i64.eq (local.get $lhs) (global.get $TRUE))
+					(call $to_boolean (local.get $rhs))))))
+
+	(if (call $is_string (local.get $lhs))
+		(then
+			(local.set $string (call $to_string (call $retain (local.get $rhs))))
+			(local.set $result
+				(call $compare_bytes
+					(call $bytes_of (call $payload (local.get $lhs)))
+					(call $length_of (call $payload (local.get $lhs)))
+					(call $bytes_of (local.get $string))
+					(call $length_of (local.get $string))))
+			(call $release (call $string_value (local.get $string)))
+			(return (local.get $result))))
+
+	(if (call $is_list (local.get $lhs))
+		(then
+			(local.set $list (call $to_list (call $retain (local.get $rhs))))
+			(local.set $length (call $length_of (call $payload (local.get $lhs))))
+			(if (i32.lt_u (call $length_of (local.get $list)) (local.get $length))
+				(then (local.set $length (call $length_of (local.get $list)))))
+
+			(block $done
+				(loop $elements
+					(br_if $done (i32.ge_u (local.get $index) (local.get $length)))
+					(local.set $result
+						(call $compare
+							(i64.load (call $element (call $payload (local.get $lhs)) (local.get $index)))
+							(i64.load (call $element (local.get $list) (local.get $index)))
+							(local.get $function)
+							(local.get $function_length)))
+					(br_if $done (local.get $result))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $elements)))
+
+			(if (i32.eqz (local.get $result))
+				(then
+					(local.set $result
+						(i32.sub
+							(i32.gt_u (call $length_of (call $payload (local.get $lhs))) (call $length_of (local.get $list)))
+							(i32.lt_u (call $length_of (call $payload (local.get $lhs))) (call $length_of (local.get $list)))))))
+			(call $release (call $list_value (local.get $list)))
+			(return (local.get $result))))
+
+	(call $type_error (local.get $lhs) (local.get $function) (local.get $function_length))
+	(unreachable))
+
+;; Checks whether `$lhs` and `$rhs` (both borrowed) are equal.
+(func $equal (param $lhs i64) (param $rhs i64) (result i32)
+	(local $index i32)
+
+	(if (i64.eq (local.get $lhs) (local.get $rhs))
+		(then (return (i32.const 1))))
+
+	(if (i32.and (call $is_string (local.get $lhs)) (call $is_string (local.get $rhs)))
+		(then
+			(return
+				(i32.eqz
+					(call $compare_bytes
+						(call $bytes_of (call $payload (local.get $lhs)))
+						(call $length_of (call $payload (local.get $lhs)))
+						(call $bytes_of (call $payload (local.get $rhs)))
+						(call $length_of (call $payload (local.get $rhs))))))))
+
+	(if (i32.and (call $is_list (local.get $lhs)) (call $is_list (local.get $rhs)))
+		(then
+			(if (i32.ne
+					(call $length_of (call $payload (local.get $lhs)))
+					(call $length_of (call $payload (local.get $rhs))))
+				(then (return (i32.const 0))))
+
+			(block $done
+				(loop $elements
+					(br_if $done
+						(i32.ge_u (local.get $index) (call $length_of (call $payload (local.get $lhs)))))
+					(if (i32.eqz
+							(call $equal
+								(i64.load (call $element (call $payload (local.get $lhs)) (local.get $index)))
+								(i64.load (call $element (call $payload (local.get $rhs)) (local.get $index)))))
+						(then (return (i32.const 0))))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $elements)))
+
+			(return (i32.const 1))))
+
+	(i32.const 0))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                            Functions                                             ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+(func $truthy (param $value i64) (result i32)
+	(local $truthy i32)
+	(local.set $truthy (call $to_boolean (local.get $value)))
+	(call $release (local.get $value))
+	(local.get $truthy))
+
+;; Reads a byte from stdin, returning -1 at the end of input.
+(func $read_byte (result i32)
+	(local $byte i32)
+
+	(if (i32.eq (global.get $input_start) (global.get $input_end))
+		(then
+			(if (global.get $input_eof)
+				(then (return (i32.const -1))))
+			(if (i32.eqz (global.get $input))
+				(then (global.set $input (call $allocate (i32.const 4096)))))
+
+			(global.set $input_start (global.get $input))
+			(global.set $input_end
+				(i32.add (global.get $input) (call $read (global.get $input) (i32.const 4096))))
+			(if (i32.eq (global.get $input_start) (global.get $input_end))
+				(then
+					(global.set $input_eof (i32.const 1))
+					(return (i32.const -1))))))
+
+	(local.set $byte (i32.load8_u (global.get $input_start)))
+	(global.set $input_start (i32.add (global.get $input_start) (i32.const 1)))
+	(local.get $byte))
+
+(func $prompt (result i64)
+	(local $line i32)
+	(local $capacity i32)
+	(local $length i32)
+	(local $byte i32)
+	(local $larger i32)
+	(local $string i32)
+
+	(local.set $capacity (i32.const 64))
+	(local.set $line (call $allocate (local.get $capacity)))
+
+	(block $done
+		(loop $read
+			(local.set $byte (call $read_byte))
+			(br_if $done (i32.eq (local.get $byte) (i32.const -1)))
+			(br_if $done (i32.eq (local.get $byte) (i32.const 10)))
+
+			(if (i32.eq (local.get $length) (local.get $capacity))
+				(then
+					(local.set $larger (call $allocate (i32.shl (local.get $capacity) (i32.const 1))))
+					(memory.copy (local.get $larger) (local.get $line) (local.get $length))
+					(call $free (local.get $line))
+					(local.set $line (local.get $larger))
+					(local.set $capacity (i32.shl (local.get $capacity) (i32.const 1)))))
+
+			(i32.store8 (i32.add (local.get $line) (local.get $length)) (local.get $byte))
+			(local.set $length (i32.add (local.get $length) (i32.const 1)))
+			(br $read)))
+
+	(if (i32.and (i32.eqz (local.get $length)) (i32.eq (local.get $byte) (i32.const -1)))
+		(then
+			(call $free (local.get $line))
+			(return (global.get $NULL))))
+
+	(if (local.get $length)
+		(then
+			(if (i32.eq
+					(i32.load8_u (i32.add (local.get $line) (i32.sub (local.get $length) (i32.const 1))))
+					(i32.const 13))
+				(then (local.set $length (i32.sub (local.get $length) (i32.const 1)))))))
+
+	(local.set $string (call $string_new (local.get $line) (local.get $length)))
+	(call $free (local.get $line))
+	(call $string_value (local.get $string)))
+
+(func $random (result i64)
+	(local $random i64)
+	(local.set $random (call $random_bits))
+
+	(if (global.get $limit_rand_range)
+		(then (return (call $integer (i64.rem_u (local.get $random) (i64.const 0x8000))))))
+
+	(call $integer (i64.rem_u (local.get $random) (i64.add (call $integer_max) (i64.const 1)))))
+
+(func $call (param $value i64) (result i64)
+	(if (i32.eqz (call $is_block (local.get $value)))
+		(then (call $type_error (local.get $value) (str "CALL"))))
+
+	(call $run (call $payload (local.get $value))))
+
+(func $quit (param $value i64)
+	(local $status i64)
+
+	(local.set $status (call $to_integer (local.get $value)))
+	(call $release (local.get $value))
+
+	(if (i32.or
+			(i64.lt_s (local.get $status) (i64.const -0x80000000))
+			(i64.gt_s (local.get $status) (i64.const 0x7FFFFFFF)))
+		(then (call $error (str "domain error: QUIT: not in bounds"))))
+
+	(if (global.get $check_quit_status_codes)
+		(then
+			(if (i64.gt_u (local.get $status) (i64.const 127))
+				(then (call $error (str "domain error: QUIT: not in bounds"))))))
+
+	(call $exit (i32.wrap_i64 (local.get $status))))
+
+(func $hex_digit (param $digit i32) (result i32)
+	(i32.add
+		(local.get $digit)
+		(select (i32.const 48) (i32.const 87) (i32.lt_u (local.get $digit) (i32.const 10)))))
+
+;; Gets how `$byte` is escaped when dumping strings, or an empty string if it isn't.
+(func $escape (param $byte i32) (result i32 i32)
+	(if (i32.eq (local.get $byte) (i32.const 9))
+		(then (return (str "\\t"))))
+	(if (i32.eq (local.get $byte) (i32.const 10))
+		(then (return (str "\\n"))))
+	(if (i32.eq (local.get $byte) (i32.const 13))
+		(then (return (str "\\r"))))
+	(if (i32.eqz (local.get $byte))
+		(then (return (str "\\0"))))
+	(if (i32.eq (local.get $byte) (i32.const 34))
+		(then (return (str "\\\""))))
+	(if (i32.eq (local.get $byte) (i32.const 92))
+		(then (return (str "\\\\"))))
+	(if (i32.and (i32.ge_u (local.get $byte) (i32.const 0x20)) (i32.ne (local.get $byte) (i32.const 0x7F)))
+		(then (return (i32.const 0) (i32.const 0))))
+
+	;; Other control characters are written as `\u{XX}`, without any leading zeroes.
+	(i32.store8 (i32.const 16) (i32.const 92))
+	(i32.store8 (i32.const 17) (i32.const 117))
+	(i32.store8 (i32.const 18) (i32.const 123))
+	(if (i32.ge_u (local.get $byte) (i32.const 16))
+		(then
+			(i32.store8 (i32.const 19) (call $hex_digit (i32.shr_u (local.get $byte) (i32.const 4))))
+			(i32.store8 (i32.const 20) (call $hex_digit (i32.and (local.get $byte) (i32.const 15))))
+			(i32.store8 (i32.const 21) (i32.const 125))
+			(return (i32.const 16) (i32.const 6))))
+
+	(i32.store8 (i32.const 19) (call $hex_digit (local.get $byte)))
+	(i32.store8 (i32.const 20) (i32.const 125))
+	(i32.const 16)
+	(i32.const 5))
+
+(func $dump_string (param $string i32)
+	(local $position i32)
+	(local $end i32)
+	(local $unescaped i32)
+	(local $escape i32)
+	(local $escape_length i32)
+
+	(local.set $position (call $bytes_of (local.get $string)))
+	(local.set $end (i32.add (local.get $position) (call $length_of (local.get $string))))
+	(local.set $unescaped (local.get $position))
+
+	(call $print (str "\""))
+	(block $done
+		(loop $bytes
+			(br_if $done (i32.ge_u (local.get $position) (local.get $end)))
+			(call $escape (i32.load8_u (local.get $position)))
+			(local.set $escape_length)
+			(local.set $escape)
+
+			(if (local.get $escape_length)
+				(then
+					(call $print (local.get $unescaped) (i32.sub (local.get $position) (local.get $unescaped)))
+					(call $print (local.get $escape) (local.get $escape_length))
+					(local.set $unescaped (i32.add (local.get $position) (i32.const 1)))))
+
+			(local.set $position (i32.add (local.get $position) (i32.const 1)))
+			(br $bytes)))
+
+	(call $print (local.get $unescaped) (i32.sub (local.get $end) (local.get $unescaped)))
+	(call $print (str "\"")))
+
+;; Dumps `$value` (borrowed).
+(func $dump (param $value i64)
+	(local $index i32)
+
+	(if (call $is_integer (local.get $value))
+		(then
+			(call $print (call $format_integer (call $as_integer (local.get $value))))
+			(return)))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(call $dump_string (call $payload (local.get $value)))
+			(return)))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(call $print (str "["))
+			(block $done
+				(loop $elements
+					(br_if $done
+						(i32.ge_u (local.get $index) (call $length_of (call $payload (local.get $value)))))
+					(if (local.get $index)
+						(then (call $print (str ", "))))
+					(call $dump (i64.load (call $element (call $payload (local.get $value)) (local.get $index))))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $elements)))
+			(call $print (str "]"))
+			(return)))
+
+	(if (i64.eq (local.get $value) (global.get $NULL))
+		(then
+			(call $print (str "null"))
+			(return)))
+	(if (i64.eq (local.get $value) (global.get $TRUE))
+		(then
+			(call $print (str "true"))
+			(return)))
+	(if (i64.eq (local.get $value) (global.get $FALSE))
+		(then
+			(call $print (str "false"))
+			(return)))
+
+	(call $type_error (local.get $value) (str "DUMP")))
+
+(func $output (param $value i64) (result i64)
+	(local $string i32)
+	(local $length i32)
+
+	(local.set $string (call $to_string (local.get $value)))
+	(local.set $length (call $length_of (local.get $string)))
+
+	(if (local.get $length)
+		(then
+			(if (i32.eq
+					(i32.load8_u
+						(i32.add (call $bytes_of (local.get $string)) (i32.sub (local.get $length) (i32.const 1))))
+					(i32.const 92))
+				(then
+					(call $print (call $bytes_of (local.get $string)) (i32.sub (local.get $length) (i32.const 1)))
+					(call $release (call $string_value (local.get $string)))
+					(return (global.get $NULL))))))
+
+	(call $print (call $bytes_of (local.get $string)) (local.get $length))
+	(call $print (str "\n"))
+	(call $release (call $string_value (local.get $string)))
+	(global.get $NULL))
+
+(func $length (param $value i64) (result i64)
+	(local $length i32)
+	(local $list i32)
+
+	(if (call $is_string (local.get $value))
+		(then
+			(local.set $length (call $length_of (call $payload (local.get $value))))
+			(call $release (local.get $value))
+			(return (call $integer (i64.extend_i32_u (local.get $length))))))
+
+	(local.set $list (call $to_list (local.get $value)))
+	(local.set $length (call $length_of (local.get $list)))
+	(call $release (call $list_value (local.get $list)))
+	(call $integer (i64.extend_i32_u (local.get $length))))
+
+(func $not (param $value i64) (result i64)
+	(call $boolean (i32.eqz (call $truthy (local.get $value)))))
+
+(func $negate (param $value i64) (result i64)
+	(local $integer i64)
+
+	(local.set $integer (call $to_integer (local.get $value)))
+	(call $release (local.get $value))
+	(call $integer_result (call $subtract_integers (i64.const 0) (local.get $integer)) (str "~")))
+
+;; Encodes `$codepoint` as UTF-8 in the formatting buffer, returning its address and length.
+(func $encode_utf8 (param $codepoint i32) (result i32 i32)
+	(if (i32.lt_u (local.get $codepoint) (i32.const 0x80))
+		(then
+			(i32.store8 (i32.const 16) (local.get $codepoint))
+			(return (i32.const 16) (i32.const 1))))
+
+	(if (i32.lt_u (local.get $codepoint) (i32.const 0x800))
+		(then
+			(i32.store8 (i32.const 16)
+				(i32.or (i32.const 0xC0) (i32.shr_u (local.get $codepoint) (i32.const 6))))
+			(i32.store8 (i32.const 17)
+				(i32.or (i32.const 0x80) (i32.and (local.get $codepoint) (i32.const 0x3F))))
+			(return (i32.const 16) (i32.const 2))))
+
+	(if (i32.lt_u (local.get $codepoint) (i32.const 0x10000))
+		(then
+			(i32.store8 (i32.const 16)
+				(i32.or (i32.const 0xE0) (i32.shr_u (local.get $codepoint) (i32.const 12))))
+			(i32.store8 (i32.const 17)
+				(i32.or (i32.const 0x80) (i32.and (i32.shr_u (local.get $codepoint) (i32.const 6)) (i32.const 0x3F))))
+			(i32.store8 (i32.const 18)
+				(i32.or (i32.const 0x80) (i32.and (local.get $codepoint) (i32.const 0x3F))))
+			(return (i32.const 16) (i32.const 3))))
+
+	(i32.store8 (i32.const 16)
+		(i32.or (i32.const 0xF0) (i32.shr_u (local.get $codepoint) (i32.const 18))))
+	(i32.store8 (i32.const 17)
+		(i32.or (i32.const 0x80) (i32.and (i32.shr_u (local.get $codepoint) (i32.const 12)) (i32.const 0x3F))))
+	(i32.store8 (i32.const 18)
+		(i32.or (i32.const 0x80) (i32.and (i32.shr_u (local.get $codepoint) (i32.const 6)) (i32.const 0x3F))))
+	(i32.store8 (i32.const 19)
+		(i32.or (i32.const 0x80) (i32.and (local.get $codepoint) (i32.const 0x3F))))
+	(i32.const 16)
+	(i32.const 4))
+
+;; Decodes the UTF-8 character at `$bytes`.
+(func $decode_utf8 (param $bytes i32) (result i32)
+	(local $byte i32)
+	(local.set $byte (i32.load8_u (local.get $bytes)))
+
+	(if (i32.lt_u (local.get $byte) (i32.const 0x80))
+		(then (return (local.get $byte))))
+
+	(if (i32.lt_u (local.get $byte) (i32.const 0xE0))
+		(then
+			(return
+				(i32.or
+					(i32.shl (i32.and (local.get $byte) (i32.const 0x1F)) (i32.const 6))
+					(i32.and (i32.load8_u offset=1 (local.get $bytes)) (i32.const 0x3F))))))
+
+	(if (i32.lt_u (local.get $byte) (i32.const 0xF0))
+		(then
+			(return
+				(i32.or
+					(i32.or
+						(i32.shl (i32.and (local.get $byte) (i32.const 0x0F)) (i32.const 12))
+						(i32.shl (i32.and (i32.load8_u offset=1 (local.get $bytes)) (i32.const 0x3F)) (i32.const 6)))
+					(i32.and (i32.load8_u offset=2 (local.get $bytes)) (i32.const 0x3F))))))
+
+	(i32.or
+		(i32.or
+			(i32.shl (i32.and (local.get $byte) (i32.const 0x07)) (i32.const 18))
+			(i32.shl (i32.and (i32.load8_u offset=1 (local.get $bytes)) (i32.const 0x3F)) (i32.const 12)))
+		(i32.or
+			(i32.shl (i32.and (i32.load8_u offset=2 (local.get $bytes)) (i32.const 0x3F)) (i32.const 6))
+			(i32.and (i32.load8_u offset=3 (local.get $bytes)) (i32.const 0x3F)))))
+
+(func $ascii (param $value i64) (result i64)
+	(local $codepoint i64)
+	(local $string i32)
+
+	(if (call $is_integer (local.get $value))
+		(then
+			(local.set $codepoint (call $as_integer (local.get $value)))
+			(if (i32.or
+					(i64.gt_u (local.get $codepoint) (i64.const 0x10FFFF))
+					(i32.and
+						(i64.ge_u (local.get $codepoint) (i64.const 0xD800))
+						(i64.le_u (local.get $codepoint) (i64.const 0xDFFF))))
+				(then
+					(call $error_begin)
+					(call $error_part (str "integer Integer("))
+					(call $error_part (call $format_integer (local.get $codepoint)))
+					(call $error_part (str ") isn't a valid char for Utf8"))
+					(call $error_end)))
+			(return
+				(call $string_value (call $string_new (call $encode_utf8 (i32.wrap_i64 (local.get $codepoint))))))))
+
+	(if (i32.eqz (call $is_string (local.get $value)))
+		(then (call $type_error (local.get $value) (str "ASCII"))))
+
+	(local.set $string (call $payload (local.get $value)))
+	(if (i32.eqz (call $length_of (local.get $string)))
+		(then (call $error (str "domain error: empty string for head"))))
+
+	(local.set $codepoint (i64.extend_i32_u (call $decode_utf8 (call $bytes_of (local.get $string)))))
+	(call $release (local.get $value))
+	(call $integer (local.get $codepoint)))
+
+(func $box (param $value i64) (result i64)
+	(local $list i32)
+	(local.set $list (call $list_alloc (i32.const 1)))
+	(i64.store (call $element (local.get $list) (i32.const 0)) (local.get $value))
+	(call $list_value (local.get $list)))
+
+(func $head (param $value i64) (result i64)
+	(local $head i64)
+	(local $container i32)
+
+	(local.set $container (call $payload (local.get $value)))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(if (i32.eqz (call $length_of (local.get $container)))
+				(then (call $error (str "domain error: empty string for head"))))
+			(local.set $head
+				(call $string_value
+					(call $string_new
+						(call $bytes_of (local.get $container))
+						(call $char_length (i32.load8_u (call $bytes_of (local.get $container)))))))
+			(call $release (local.get $value))
+			(return (local.get $head))))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(if (i32.eqz (call $length_of (local.get $container)))
+				(then (call $error (str "domain error: empty list for head"))))
+			(local.set $head (call $retain (i64.load (call $element (local.get $container) (i32.const 0)))))
+			(call $release (local.get $value))
+			(return (local.get $head))))
+
+	(call $type_error (local.get $value) (str "["))
+	(unreachable))
+
+(func $tail (param $value i64) (result i64)
+	(local $tail i64)
+	(local $container i32)
+	(local $first i32)
+
+	(local.set $container (call $payload (local.get $value)))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(if (i32.eqz (call $length_of (local.get $container)))
+				(then (call $error (str "domain error: empty string for tail"))))
+			(local.set $first (call $char_length (i32.load8_u (call $bytes_of (local.get $container)))))
+			(local.set $tail
+				(call $string_value
+					(call $string_new
+						(i32.add (call $bytes_of (local.get $container)) (local.get $first))
+						(i32.sub (call $length_of (local.get $container)) (local.get $first)))))
+			(call $release (local.get $value))
+			(return (local.get $tail))))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(if (i32.eqz (call $length_of (local.get $container)))
+				(then (call $error (str "domain error: empty list for head"))))
+			(local.set $tail
+				(call $list_value
+					(call $list_splice
+						(local.get $container)
+						(i32.const 1)
+						(i32.sub (call $length_of (local.get $container)) (i32.const 1))
+						(global.get $EMPTY_LIST)
+						(i32.const 0)
+						(i32.const -1))))
+			(call $release (local.get $value))
+			(return (local.get $tail))))
+
+	(call $type_error (local.get $value) (str "]"))
+	(unreachable))
+
+(func $add (param $lhs i64) (param $rhs i64) (result i64)
+	(local $integer i64)
+	(local $string i32)
+	(local $rest i32)
+	(local $result i32)
+
+	(if (call $is_integer (local.get $lhs))
+		(then
+			(local.set $integer (call $to_integer (local.get $rhs)))
+			(call $release (local.get $rhs))
+			(return
+				(call $integer_result
+					(call $add_integers (call $as_integer (local.get $lhs)) (local.get $integer))
+					(str "+")))))
+
+	(if (call $is_string (local.get $lhs))
+		(then
+			(local.set $string (call $payload (local.get $lhs)))
+			(local.set $rest (call $to_string (local.get $rhs)))
+			(if (i32.eqz (call $length_of (local.get $rest)))
+				(then
+					(call $release (call $string_value (local.get $rest)))
+					(return (local.get $lhs))))
+
+			(local.set $result
+				(call $string_alloc
+					(i32.add (call $length_of (local.get $string)) (call $length_of (local.get $rest)))))
+			(memory.copy
+				(call $bytes_of (local.get $result))
+				(call $bytes_of (local.get $string))
+				(call $length_of (local.get $string)))
+			(memory.copy
+				(i32.add (call $bytes_of (local.get $result)) (call $length_of (local.get $string)))
+				(call $bytes_of (local.get $rest))
+				(call $length_of (local.get $rest)))
+			(call $release (local.get $lhs))
+			(call $release (call $string_value (local.get $rest)))
+			(return (call $string_value (local.get $result)))))
+
+	(if (call $is_list (local.get $lhs))
+		(then
+			(local.set $rest (call $to_list (local.get $rhs)))
+			(local.set $result
+				(call $list_splice
+					(call $payload (local.get $lhs))
+					(i32.const 0)
+					(call $length_of (call $payload (local.get $lhs)))
+					(local.get $rest)
+					(call $length_of (local.get $rest))
+					(i32.const -1)))
+			(call $release (local.get $lhs))
+			(call $release (call $list_value (local.get $rest)))
+			(return (call $list_value (local.get $result)))))
+
+	(call $type_error (local.get $lhs) (str "+"))
+	(unreachable))
+
+(func $subtract (param $lhs i64) (param $rhs i64) (result i64)
+	(local $integer i64)
+
+	(if (i32.eqz (call $is_integer (local.get $lhs)))
+		(then (call $type_error (local.get $lhs) (str "-"))))
+
+	(local.set $integer (call $to_integer (local.get $rhs)))
+	(call $release (local.get $rhs))
+	(call $integer_result
+		(call $subtract_integers (call $as_integer (local.get $lhs)) (local.get $integer))
+		(str "-")))
+
+(func $multiply (param $lhs i64) (param $rhs i64) (result i64)
+	(local $amount i64)
+	(local $container i32)
+	(local $length i32)
+	(local $result i32)
+	(local $index i32)
+	(local $total i32)
+
+	(if (i32.eqz
+			(i32.or
+				(call $is_integer (local.get $lhs))
+				(i32.or (call $is_string (local.get $lhs)) (call $is_list (local.get $lhs)))))
+		(then (call $type_error (local.get $lhs) (str "*"))))
+
+	(local.set $amount (call $to_integer (local.get $rhs)))
+	(call $release (local.get $rhs))
+
+	(if (call $is_integer (local.get $lhs))
+		(then
+			(return
+				(call $integer_result
+					(call $multiply_integers (call $as_integer (local.get $lhs)) (local.get $amount) (str "*"))
+					(str "*")))))
+
+	(if (i64.lt_s (local.get $amount) (i64.const 0))
+		(then (call $error (str "domain error: repetition count is negative"))))
+
+	(local.set $container (call $payload (local.get $lhs)))
+	(local.set $length (call $length_of (local.get $container)))
+
+	(if (call $is_string (local.get $lhs))
+		(then
+			(if (local.get $length)
+				(then
+					(if (i64.gt_u
+							(local.get $amount)
+							(i64.div_u (i64.const 0x3FFFFFF0) (i64.extend_i32_u (local.get $length))))
+						(then (call $error (str "domain error: repetition is too large"))))))
+
+			(local.set $result
+				(call $string_alloc (i32.mul (local.get $length) (i32.wrap_i64 (local.get $amount)))))
+			(block $done
+				(loop $repeat
+					(br_if $done (i64.ge_u (i64.extend_i32_u (local.get $index)) (local.get $amount)))
+					(memory.copy
+						(i32.add
+							(call $bytes_of (local.get $result))
+							(i32.mul (local.get $index) (local.get $length)))
+						(call $bytes_of (local.get $container))
+						(local.get $length))
+					(local.set $index (i32.add (local.get $index) (i32.const 1)))
+					(br $repeat)))
+
+			(call $release (local.get $lhs))
+			(return (call $string_value (local.get $result)))))
+
+	(if (local.get $length)
+		(then
+			(if (i64.gt_u
+					(local.get $amount)
+					(i64.div_u (i64.const 0x07FFFFFE) (i64.extend_i32_u (local.get $length))))
+				(then (call $error (str "bounds too large!"))))))
+
+	(local.set $total (i32.mul (local.get $length) (i32.wrap_i64 (local.get $amount))))
+	(local.set $result (call $list_alloc (local.get $total)))
+	(block $done
+		(loop $repeat
+			(br_if $done (i32.ge_u (local.get $index) (local.get $total)))
+			(i64.store
+				(call $element (local.get $result) (local.get $index))
+				(call $retain
+					(i64.load
+						(call $element (local.get $container) (i32.rem_u (local.get $index) (local.get $length))))))
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $repeat)))
+
+	(call $release (local.get $lhs))
+	(call $list_value (local.get $result)))
+
+(func $divide (param $lhs i64) (param $rhs i64) (result i64)
+	(local $integer i64)
+
+	(if (i32.eqz (call $is_integer (local.get $lhs)))
+		(then (call $type_error (local.get $lhs) (str "/"))))
+
+	(local.set $integer (call $to_integer (local.get $rhs)))
+	(call $release (local.get $rhs))
+	(if (i64.eqz (local.get $integer))
+		(then (call $error (str "division by zero"))))
+
+	(call $integer_result (i64.div_s (call $as_integer (local.get $lhs)) (local.get $integer)) (str "/")))
+
+(func $remainder (param $lhs i64) (param $rhs i64) (result i64)
+	(local $integer i64)
+
+	(if (i32.eqz (call $is_integer (local.get $lhs)))
+		(then (call $type_error (local.get $lhs) (str "%"))))
+
+	(local.set $integer (call $to_integer (local.get $rhs)))
+	(call $release (local.get $rhs))
+	(if (i64.eqz (local.get $integer))
+		(then (call $error (str "remainder by zero"))))
+
+	(if (global.get $check_integer_function_bounds)
+		(then
+			(if (i64.lt_s (call $as_integer (local.get $lhs)) (i64.const 0))
+				(then (call $error (str "domain error: remainder with a negative number"))))
+			(if (i64.lt_s (local.get $integer) (i64.const 0))
+				(then (call $error (str "domain error: remainder by a negative base"))))))
+
+	(call $integer_result (i64.rem_s (call $as_integer (local.get $lhs)) (local.get $integer)) (str "%")))
+
+(func $power (param $lhs i64) (param $rhs i64) (result i64)
+	(local $separator i32)
+	(local $joined i32)
+	(local $exponent i64)
+
+	(if (call $is_list (local.get $lhs))
+		(then
+			(local.set $separator (call $to_string (local.get $rhs)))
+			(local.set $joined (call $list_join (call $payload (local.get $lhs)) (local.get $separator)))
+			(call $release (call $string_value (local.get $separator)))
+			(call $release (local.get $lhs))
+			(return (call $string_value (local.get $joined)))))
+
+	(if (i32.eqz (call $is_integer (local.get $lhs)))
+		(then (call $type_error (local.get $lhs) (str "^"))))
+
+	(local.set $exponent (call $to_integer (local.get $rhs)))
+	(call $release (local.get $rhs))
+	(call $integer_result
+		(call $power_integers (call $as_integer (local.get $lhs)) (local.get $exponent))
+		(str "^")))
+
+(func $less_than (param $lhs i64) (param $rhs i64) (result i64)
+	(local $less i32)
+	(local.set $less (i32.lt_s (call $compare (local.get $lhs) (local.get $rhs) (str "<")) (i32.const 0)))
+	(call $release (local.get $lhs))
+	(call $release (local.get $rhs))
+	(call $boolean (local.get $less)))
+
+(func $greater_than (param $lhs i64) (param $rhs i64) (result i64)
+	(local $greater i32)
+	(local.set $greater (i32.gt_s (call $compare (local.get $lhs) (local.get $rhs) (str ">")) (i32.const 0)))
+	(call $release (local.get $lhs))
+	(call $release (local.get $rhs))
+	(call $boolean (local.get $greater)))
+
+(func $equals (param $lhs i64) (param $rhs i64) (result i64)
+	(local $equal i32)
+	(local.set $equal (call $equal (local.get $lhs) (local.get $rhs)))
+	(call $release (local.get $lhs))
+	(call $release (local.get $rhs))
+	(call $boolean (local.get $equal)))
+
+(func $get (param $value i64) (param $start i64) (param $length i64) (result i64)
+	(local $begin i32)
+	(local $end i32)
+	(local $container i32)
+	(local $result i64)
+
+	(call $range (local.get $start) (local.get $length))
+	(local.set $end)
+	(local.set $begin)
+	(local.set $container (call $payload (local.get $value)))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(if (i32.lt_u (call $length_of (local.get $container)) (local.get $end))
+				(then (call $error (str "domain error: invalid args for get for list"))))
+			(local.set $result
+				(call $list_value
+					(call $list_splice
+						(local.get $container)
+						(local.get $begin)
+						(i32.sub (local.get $end) (local.get $begin))
+						(global.get $EMPTY_LIST)
+						(i32.const 0)
+						(i32.const -1))))
+			(call $release (local.get $value))
+			(return (local.get $result))))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(if (i32.lt_u (call $length_of (local.get $container)) (local.get $end))
+				(then (call $error (str "domain error: invalid args for get for str"))))
+			(local.set $result
+				(call $string_value
+					(call $string_new
+						(i32.add (call $bytes_of (local.get $container)) (local.get $begin))
+						(i32.sub (local.get $end) (local.get $begin)))))
+			(call $release (local.get $value))
+			(return (local.get $result))))
+
+	(call $type_error (local.get $value) (str "GET"))
+	(unreachable))
+
+(func $set (param $value i64) (param $start i64) (param $length i64) (param $replacement i64)
+		(result i64)
+	(local $begin i32)
+	(local $end i32)
+	(local $container i32)
+	(local $rest i32)
+	(local $prefix i32)
+	(local $suffix i32)
+	(local $result i32)
+
+	(call $range (local.get $start) (local.get $length))
+	(local.set $end)
+	(local.set $begin)
+	(local.set $container (call $payload (local.get $value)))
+
+	(local.set $prefix (local.get $begin))
+	(if (i32.lt_u (call $length_of (local.get $container)) (local.get $prefix))
+		(then (local.set $prefix (call $length_of (local.get $container)))))
+
+	(if (call $is_list (local.get $value))
+		(then
+			(local.set $rest (call $to_list (local.get $replacement)))
+			(local.set $result
+				(call $list_splice
+					(local.get $container)
+					(i32.const 0)
+					(local.get $prefix)
+					(local.get $rest)
+					(call $length_of (local.get $rest))
+					(local.get $end)))
+			(call $release (call $list_value (local.get $rest)))
+			(call $release (local.get $value))
+			(return (call $list_value (local.get $result)))))
+
+	(if (call $is_string (local.get $value))
+		(then
+			(local.set $rest (call $to_string (local.get $replacement)))
+			(if (i32.lt_u (local.get $end) (call $length_of (local.get $container)))
+				(then (local.set $suffix (i32.sub (call $length_of (local.get $container)) (local.get $end)))))
+
+			(local.set $result
+				(call $string_alloc
+					(i32.add
+						(i32.add (local.get $prefix) (call $length_of (local.get $rest)))
+						(local.get $suffix))))
+			(memory.copy
+				(call $bytes_of (local.get $result))
+				(call $bytes_of (local.get $container))
+				(local.get $prefix))
+			(memory.copy
+				(i32.add (call $bytes_of (local.get $result)) (local.get $prefix))
+				(call $bytes_of (local.get $rest))
+				(call $length_of (local.get $rest)))
+			(memory.copy
+				(i32.add
+					(call $bytes_of (local.get $result))
+					(i32.add (local.get $prefix) (call $length_of (local.get $rest))))
+				(i32.add (call $bytes_of (local.get $container)) (local.get $end))
+				(local.get $suffix))
+			(call $release (call $string_value (local.get $rest)))
+			(call $release (local.get $value))
+			(return (call $string_value (local.get $result)))))
+
+	(call $type_error (local.get $value) (str "SET"))
+	(unreachable))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                            Variables                                             ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+;; `$variables` is an array of values, and `$variable_names` is an array of each variable's name's
+;; address and length; both are defined by the translated program.
+(func $variable_name (param $index i32) (result i32 i32)
+	(i32.load (i32.add (global.get $variable_names) (i32.shl (local.get $index) (i32.const 3))))
+	(i32.load offset=4 (i32.add (global.get $variable_names) (i32.shl (local.get $index) (i32.const 3)))))
+
+(func $get_variable (param $index i32) (result i64)
+	(local $value i64)
+	(local.set $value
+		(i64.load (i32.add (global.get $variables) (i32.shl (local.get $index) (i32.const 3)))))
+
+	(if (i64.eq (local.get $value) (global.get $UNDEFINED))
+		(then
+			(if (global.get $check_variables)
+				(then
+					(call $error_begin)
+					(call $error_part (str "undefined variable "))
+					(call $error_part (call $variable_name (local.get $index)))
+					(call $error_part (str " accessed"))
+					(call $error_end)))
+			(return (global.get $NULL))))
+
+	(call $retain (local.get $value)))
+
+(func $set_variable (param $index i32) (param $value i64)
+	(local $address i32)
+	(local.set $address (i32.add (global.get $variables) (i32.shl (local.get $index) (i32.const 3))))
+	(call $release (i64.load (local.get $address)))
+	(i64.store (local.get $address) (local.get $value)))
+
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+;;                                               Main                                               ;;
+;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;;
+
+(func (export "_start")
+	(if (global.get $argv)
+		(then (call $set_variable (i32.const 0) (call $arguments))))
+
+	(call $release (call $run (i32.const 0))))
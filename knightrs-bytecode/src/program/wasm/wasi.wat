@@ -0,0 +1,91 @@
+;; The imports for modules which are WASI commands, so that they can be run with `wasmtime` (or
+;; anything else which supports WASI). See `runtime.wat` for what each function does.
+
+(import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "args_get" (func $args_get (param i32 i32) (result i32)))
+(import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+(func $write (param $fd i32) (param $bytes i32) (param $length i32)
+	(block $done
+		(loop $partial
+			(br_if $done (i32.eqz (local.get $length)))
+			(i32.store (i32.const 0) (local.get $bytes))
+			(i32.store (i32.const 4) (local.get $length))
+			(br_if $done (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8)))
+			(br_if $done (i32.eqz (i32.load (i32.const 8))))
+			(local.set $bytes (i32.add (local.get $bytes) (i32.load (i32.const 8))))
+			(local.set $length (i32.sub (local.get $length) (i32.load (i32.const 8))))
+			(br $partial))))
+
+(func $read (param $buffer i32) (param $capacity i32) (result i32)
+	(i32.store (i32.const 0) (local.get $buffer))
+	(i32.store (i32.const 4) (local.get $capacity))
+	(if (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))
+		(then (return (i32.const 0))))
+	(i32.load (i32.const 8)))
+
+(func $random_bits (result i64)
+	(drop (call $random_get (i32.const 48) (i32.const 8)))
+	(i64.load (i32.const 48)))
+
+(func $exit (param $status i32)
+	(call $proc_exit (local.get $status))
+	(unreachable))
+
+(func $strlen (param $bytes i32) (result i32)
+	(local $length i32)
+	(block $done
+		(loop $bytes
+			(br_if $done (i32.eqz (i32.load8_u (i32.add (local.get $bytes) (local.get $length)))))
+			(local.set $length (i32.add (local.get $length) (i32.const 1)))
+			(br $bytes)))
+	(local.get $length))
+
+;; Gets the command-line arguments, without the program's name or a leading `--`.
+(func $arguments (result i64)
+	(local $count i32)
+	(local $pointers i32)
+	(local $buffer i32)
+	(local $skip i32)
+	(local $list i32)
+	(local $index i32)
+	(local $argument i32)
+
+	(drop (call $args_sizes_get (i32.const 8) (i32.const 12)))
+	(local.set $count (i32.load (i32.const 8)))
+	(local.set $pointers (call $allocate (i32.shl (local.get $count) (i32.const 2))))
+	(local.set $buffer (call $allocate (i32.load (i32.const 12))))
+	(drop (call $args_get (local.get $pointers) (local.get $buffer)))
+
+	(if (local.get $count)
+		(then (local.set $skip (i32.const 1))))
+	(if (i32.gt_u (local.get $count) (i32.const 1))
+		(then
+			(local.set $argument (i32.load offset=4 (local.get $pointers)))
+			(if (i32.and
+					(i32.eq (call $strlen (local.get $argument)) (i32.const 2))
+					(i32.eq (i32.load16_u (local.get $argument)) (i32.const 0x2D2D)))
+				(then (local.set $skip (i32.const 2))))))
+
+	(local.set $list (call $list_alloc (i32.sub (local.get $count) (local.get $skip))))
+	(block $done
+		(loop $arguments
+			(br_if $done (i32.ge_u (local.get $index) (i32.sub (local.get $count) (local.get $skip))))
+			(local.set $argument
+				(i32.load
+					(i32.add
+						(local.get $pointers)
+						(i32.shl (i32.add (local.get $index) (local.get $skip)) (i32.const 2)))))
+			(i64.store
+				(call $element (local.get $list) (local.get $index))
+				(call $string_value
+					(call $string_new (local.get $argument) (call $strlen (local.get $argument)))))
+			(local.set $index (i32.add (local.get $index) (i32.const 1)))
+			(br $arguments)))
+
+	(call $free (local.get $pointers))
+	(call $free (local.get $buffer))
+	(call $list_value (local.get $list)))
@@ -2,7 +2,7 @@ use std::cmp::Ordering;
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 
-use crate::gc::{GarbageCollected, GcRoot, ValueInner};
+use crate::gc::{GarbageCollected, GcRoot, Tracer, ValueInner};
 use crate::strings::KnStr;
 use crate::{program::JumpIndex, vm::Vm, Environment, Error};
 
@@ -35,6 +35,7 @@ XXXX ... XXXX 000 -- "allocated", nonzero `X`
 XXXX ... XXXX XX1 -- Integer
 0000 ... 0000 010 -- False
 0000 ... 0001 010 -- True
+0000 ... 1111 010 -- Undefined (only used by the vm for unassigned variables)
 XXXX ... XXXX 100 -- Block
 XXXX ... XXXX 110 -- Float32
 */
@@ -57,16 +58,18 @@ sa::assert_eq_size!(ValueAlign, ());
 pub const ALLOC_VALUE_SIZE_IN_BYTES: usize = 32;
 type ValueRepr = u64;
 
-const REPR_NULL: ValueRepr = 0b0000_000;
-const REPR_FALSE: ValueRepr = 0b0000_010;
-const REPR_TRUE: ValueRepr = 0b0001_010;
+const REPR_NULL: ValueRepr = 0b000_0000;
+const REPR_FALSE: ValueRepr = 0b000_0010;
+const REPR_TRUE: ValueRepr = 0b000_1010;
+#[cfg(feature = "check-variables")]
+const REPR_UNDEFINED: ValueRepr = 0b111_1010;
 
-const TAG_BLOCK: ValueRepr = 0b100;
-const TAG_MASK: ValueRepr = 0b111;
+pub(crate) const TAG_BLOCK: ValueRepr = 0b100;
+pub(crate) const TAG_MASK: ValueRepr = 0b111;
 const TAG_SHIFT: ValueRepr = 3;
-const TAG_INT: ValueRepr = 1;
-const TAG_MASK_INT: ValueRepr = 1;
-const TAG_INT_SHIFT: ValueRepr = 1;
+pub(crate) const TAG_INT: ValueRepr = 1;
+pub(crate) const TAG_MASK_INT: ValueRepr = 1;
+pub(crate) const TAG_INT_SHIFT: ValueRepr = 1;
 
 impl Debug for Value<'_> {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -184,13 +187,18 @@ impl<'gc> Value<'gc> {
 	/// The `TRUE` value. It's a constant so it's usable in const contexts.
 	pub const TRUE: Self = unsafe { Self::from_val(REPR_TRUE) };
 
+	/// A value which isn't any of Knight's types, which the [`Vm`] uses for variables that haven't
+	/// been assigned yet. It must never be given to Knight programs.
+	#[cfg(feature = "check-variables")]
+	pub(crate) const UNDEFINED: Self = unsafe { Self::from_val(REPR_UNDEFINED) };
+
 	/// Creates a new value from the given representation.
 	///
 	/// # Safety
 	/// `repr` must be a valid representation of a [`Value`], and must be either `0`, or have at
 	/// least one of the [`TAG_MASK`] bits set.
 	#[inline]
-	const unsafe fn from_val(repr: ValueRepr) -> Self {
+	pub(crate) const unsafe fn from_val(repr: ValueRepr) -> Self {
 		debug_assert!(repr == REPR_NULL || repr & TAG_MASK != 0, "repr has tag bits set");
 		Self(Inner { repr }, PhantomData)
 	}
@@ -256,6 +264,16 @@ impl<'gc> Value<'gc> {
 		}
 	}
 
+	// Returns the underlying allocation, if `self` is allocated.
+	pub(crate) fn as_alloc(self) -> Option<*const ValueInner> {
+		if self.is_alloc() {
+			// SAFETY: allocated values always store their pointer.
+			Some(unsafe { self.0.ptr })
+		} else {
+			None
+		}
+	}
+
 	/// Returns the underlying [`List`], if `self` is actually a list.
 	#[inline]
 	pub fn as_list(self) -> Option<List<'gc>> {
@@ -279,9 +297,9 @@ impl<'gc> Value<'gc> {
 
 unsafe impl GarbageCollected for Value<'_> {
 	#[inline]
-	unsafe fn mark(&self) {
+	unsafe fn mark(&self, tracer: &mut Tracer) {
 		if self.is_alloc() {
-			unsafe { ValueInner::mark(self.0.ptr) }
+			unsafe { tracer.mark(self.0.ptr) }
 		}
 	}
 
@@ -777,7 +795,7 @@ impl<'gc> Value<'gc> {
 			let gcstring = KnString::from_knstr(
 				KnStr::new(chr.inner().encode_utf8(&mut buf), env.opts())?,
 				&env.gc(),
-			);
+			)?;
 
 			unsafe {
 				gcstring.with_inner(|inner| target.write(inner.into()));
@@ -862,7 +880,7 @@ impl<'gc> Value<'gc> {
 		Err(Error::TypeError { type_name: self.type_name(), function: "SET" })
 	}
 
-	const fn repr(&self) -> u64 {
+	pub(crate) const fn repr(&self) -> u64 {
 		// safety: all permutations are valid `u64`s
 		unsafe { self.0.repr }
 	}
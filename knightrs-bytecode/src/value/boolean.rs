@@ -47,7 +47,7 @@ impl<'gc> ToList<'gc> for Boolean {
 		}
 
 		if *self {
-			Ok(List::from_slice_unvalidated(&[(*self).into()], env.gc()))
+			List::from_slice_unvalidated(&[(*self).into()], env.gc())
 			// Ok(GcRoot::new_unchecked(crate::value::list::consts::JUST_TRUE))
 		} else {
 			Ok(GcRoot::new_unchecked(List::default()))
@@ -470,7 +470,7 @@ impl<'gc> ToKnString<'gc> for Integer {
 		// COMPLIANCE: `Integer#to_string` yields just an optional leading `-` followed by digits,
 		// which is valid in all encodings. Additionally, it's nowhere near the maximum length for a
 		// string.
-		Ok(KnString::new_unvalidated(self.to_string(), env.gc()))
+		KnString::new_unvalidated(self.to_string(), env.gc())
 	}
 }
 
@@ -482,7 +482,7 @@ impl<'gc> ToList<'gc> for Integer {
 		}
 
 		if *self == 0 {
-			return Ok(List::boxed((*self).into(), env.gc()));
+			return List::boxed((*self).into(), env.gc());
 		}
 
 		let mut integer = self.0;
@@ -495,6 +495,6 @@ impl<'gc> ToList<'gc> for Integer {
 
 		// COMPLIANCE: The maximum amount of digits in an integer is vastly smaller than the maximum
 		// size of `i32::MAX`.
-		Ok(List::new_unvalidated(digits, env.gc()))
+		List::new_unvalidated(digits, env.gc())
 	}
 }
@@ -1,8 +1,10 @@
-use crate::gc::{self, AsValueInner, GarbageCollected, Gc, GcRoot, ValueInner};
+use crate::gc::{
+	self, AsValueInner, GarbageCollected, Gc, GcRoot, Tracer, ValueInner, ALLOC_VALUE_SIZE,
+};
 use crate::parser::{ParseError, ParseErrorKind, Parseable, Parser};
 use crate::program::Compilable;
 use crate::program::Compiler;
-use crate::value::{Boolean, Integer, List, NamedType, ToBoolean, ToInteger, ToList};
+use crate::value::{Boolean, Integer, List, NamedType, ToBoolean, ToInteger, ToList, Value};
 use crate::{Environment, Options};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::isize;
@@ -174,9 +176,9 @@ impl PartialEq<KnStr> for KnString<'_> {
 
 impl<'gc> KnString<'gc> {
 	/// Creates a new [`KnString`] from the given `source`.
-	pub fn from_knstr(source: &KnStr, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	pub fn from_knstr(source: &KnStr, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		match source.len() {
-			0 => GcRoot::new_unchecked(Self::default()),
+			0 => Ok(GcRoot::new_unchecked(Self::default())),
 
 			// SAFETY: we know it's within the bounds because we checked in the `match`
 			1..=MAX_EMBEDDED_LENGTH => unsafe { Self::new_embedded(source.as_str(), gc) },
@@ -185,18 +187,14 @@ impl<'gc> KnString<'gc> {
 		}
 	}
 
-	pub fn new(
-		source: String,
-		opts: &Options,
-		gc: &'gc Gc,
-	) -> Result<GcRoot<'gc, Self>, StringError> {
+	pub fn new(source: String, opts: &Options, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		KnStr::new(&source, opts)?;
-		Ok(Self::new_unvalidated(source, gc))
+		Self::new_unvalidated(source, gc)
 	}
 
-	pub fn new_unvalidated(source: String, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	pub fn new_unvalidated(source: String, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		if source.is_empty() {
-			return GcRoot::new_unchecked(Self::default());
+			return Ok(GcRoot::new_unchecked(Self::default()));
 		}
 
 		// We already are given an allocated pointer, might as well use `new_alloc`
@@ -212,17 +210,17 @@ impl<'gc> KnString<'gc> {
 	}
 
 	// Allocate the underlying `ValueInner`.
-	fn allocate(flags: u8, gc: &'gc Gc) -> *mut Inner {
-		unsafe { gc.alloc_value_inner(gc::FLAG_IS_STRING as u8 | flags).cast::<Inner>() }
+	fn allocate(flags: u8, gc: &'gc Gc) -> crate::Result<*mut Inner> {
+		Ok(unsafe { gc.alloc_value_inner(gc::FLAG_IS_STRING as u8 | flags) }?.cast::<Inner>())
 	}
 
 	// SAFETY: `source.len()` needs to be `<= MAX_EMBEDDED_LENGTH`, otherwise we copy off the end.
-	unsafe fn new_embedded(source: &str, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	unsafe fn new_embedded(source: &str, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		let len = source.len();
 		debug_assert!(len <= MAX_EMBEDDED_LENGTH);
 
 		// Allocate the `Inner`.
-		let inner = Self::allocate((len as u8) << SIZE_MASK_SHIFT, gc);
+		let inner = Self::allocate((len as u8) << SIZE_MASK_SHIFT, gc)?;
 
 		// SAFETY:
 		// - `Self::allocate` guarantees `(*inner).kind.embedded` is non-null and properly aligned
@@ -237,15 +235,16 @@ impl<'gc> KnString<'gc> {
 			embedded_ptr.copy_from_nonoverlapping(source.as_ptr(), len);
 		}
 
-		GcRoot::new(&Self(inner, PhantomData), gc)
+		Ok(GcRoot::new(&Self(inner, PhantomData), gc))
 	}
 
 	// SAFETY: source.len() cannot be zero
-	unsafe fn new_alloc(mut source: String, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	unsafe fn new_alloc(mut source: String, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		let len = source.len();
 
-		// Allocate the `Inner`.
-		let inner = Self::allocate(ALLOCATED_FLAG, gc);
+		// Allocate the `Inner`, making sure there's room for the buffer too.
+		gc.reserve(ALLOC_VALUE_SIZE + len)?;
+		let inner = Self::allocate(ALLOCATED_FLAG, gc)?;
 
 		// SAFETY: `Self::allocate` guarantees it'll be aligned and non-null
 		unsafe {
@@ -253,19 +252,22 @@ impl<'gc> KnString<'gc> {
 		}
 
 		source.shrink_to_fit();
+		gc.add_buffer_bytes(len);
 
 		// SAFETY: `Self::allocate` guarantees it'll be aligned and non-null
 		unsafe {
 			(&raw mut (*inner).kind.alloc.ptr).write(ManuallyDrop::new(source).as_mut_ptr());
 		}
 
-		GcRoot::new(&Self(inner, PhantomData), gc)
+		Ok(GcRoot::new(&Self(inner, PhantomData), gc))
 	}
 
 	fn flags_and_inner(&self) -> (u8, *mut Inner) {
 		unsafe {
 			// TODO: orderings
-			((*&raw const (*self.0).flags).load(Ordering::SeqCst), self.0 as _)
+			let flags = (*&raw const (*self.0).flags).load(Ordering::SeqCst);
+			debug_assert_ne!(flags, 0, "used a string after it was freed");
+			(flags, self.0 as _)
 		}
 	}
 
@@ -295,6 +297,16 @@ impl<'gc> KnString<'gc> {
 		}
 	}
 
+	// How many bytes the buffer `self` owns is, which is zero if it's embedded.
+	pub(crate) fn buffer_size(&self) -> usize {
+		let (flags, _) = self.flags_and_inner();
+		if flags & ALLOCATED_FLAG != 0 {
+			self.len()
+		} else {
+			0
+		}
+	}
+
 	#[inline]
 	pub fn is_empty(&self) -> bool {
 		self.len() == 0
@@ -306,6 +318,8 @@ impl<'gc> KnString<'gc> {
 		opts: &Options,
 		gc: &'gc Gc,
 	) -> crate::Result<GcRoot<'gc, Self>> {
+		gc.reserve(ALLOC_VALUE_SIZE + self.len() + other.len())?;
+
 		let mut me = self.as_str().to_owned();
 		me += other.as_str();
 		Ok(Self::new(me, opts, gc)?)
@@ -325,6 +339,8 @@ impl<'gc> KnString<'gc> {
 			return Ok(GcRoot::new_unchecked(Self::default()));
 		}
 
+		gc.reserve(ALLOC_VALUE_SIZE + self.len() * amount)?;
+
 		// todo: optimized variant?
 		Ok(Self::new(self.as_str().repeat(amount), opts, gc)?)
 	}
@@ -345,7 +361,7 @@ impl<'gc> KnString<'gc> {
 			.ok_or(crate::Error::DomainError("empty string for head"))?
 			.encode_utf8(&mut buf);
 
-		Ok(Self::from_knstr(KnStr::new_unvalidated(head_string), gc))
+		Self::from_knstr(KnStr::new_unvalidated(head_string), gc)
 	}
 
 	pub fn tail(&self, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
@@ -354,7 +370,7 @@ impl<'gc> KnString<'gc> {
 			return Err(crate::Error::DomainError("empty string for tail"));
 		}
 
-		Ok(Self::from_knstr(KnStr::new_unvalidated(chars.as_str()), gc))
+		Self::from_knstr(KnStr::new_unvalidated(chars.as_str()), gc)
 	}
 
 	pub fn ord(&self) -> crate::Result<Integer> {
@@ -373,7 +389,7 @@ impl<'gc> KnString<'gc> {
 			.get(index)
 			.ok_or(crate::Error::DomainError("invalid args for get for str"))?;
 
-		Ok(Self::from_knstr(KnStr::new_unvalidated(rest), gc))
+		Self::from_knstr(KnStr::new_unvalidated(rest), gc)
 	}
 
 	pub fn try_set(
@@ -384,6 +400,8 @@ impl<'gc> KnString<'gc> {
 		opts: &Options,
 		gc: &'gc Gc,
 	) -> crate::Result<GcRoot<'gc, Self>> {
+		gc.reserve(ALLOC_VALUE_SIZE + self.len() + repl.len())?;
+
 		// TODO: optimize this
 		let mut s = String::new();
 		let mut chars = self.as_str().chars();
@@ -416,7 +434,7 @@ impl Display for KnString<'_> {
 }
 
 unsafe impl GarbageCollected for KnString<'_> {
-	unsafe fn mark(&self) {
+	unsafe fn mark(&self, _tracer: &mut Tracer) {
 		// Do nothing, `self` doesn't reference other `GarbageCollected `types.
 		// TODO: If we add in "cons" variants and whatnot, then this should be modified
 	}
@@ -485,21 +503,25 @@ impl<'gc> ToList<'gc> for KnString<'gc> {
 	/// Returns an empty list for `false`, and a list with just `self` if true.
 	#[inline]
 	fn to_list(&self, env: &mut Environment<'gc>) -> crate::Result<GcRoot<'gc, List<'gc>>> {
+		// Each character becomes its own string, plus the list's buffer and the list itself.
+		let chars_len = self.chars().count();
+		env.gc().reserve(ALLOC_VALUE_SIZE + chars_len * (ALLOC_VALUE_SIZE + size_of::<Value>()))?;
+
 		env.gc().pause();
 
 		let chars = self
 			.chars()
 			.map(|c| {
-				let chr_string = Self::new_unvalidated(c.to_string(), env.gc());
-				unsafe { chr_string.assume_used() }.into()
+				let chr_string = Self::new_unvalidated(c.to_string(), env.gc())?;
+				Ok(unsafe { chr_string.assume_used() }.into())
 			})
-			.collect::<Vec<_>>();
+			.collect::<crate::Result<Vec<_>>>();
 
 		// COMPLIANCE: If `self` is within the container bounds, so is the length of its chars.
-		let result = List::new_unvalidated(chars, env.gc());
+		let result = chars.and_then(|chars| List::new_unvalidated(chars, env.gc()));
 		env.gc().unpause();
 
-		Ok(result)
+		result
 	}
 }
 
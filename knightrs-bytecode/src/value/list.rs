@@ -1,4 +1,6 @@
-use crate::gc::{self, AsValueInner, GarbageCollected, Gc, GcRoot, ValueInner};
+use crate::gc::{
+	self, AsValueInner, GarbageCollected, Gc, GcRoot, Tracer, ValueInner, ALLOC_VALUE_SIZE,
+};
 use crate::parser::{ParseError, Parseable, Parser};
 use crate::program::{Compilable, Compiler};
 use crate::strings::KnStr;
@@ -139,7 +141,7 @@ impl<'gc> List<'gc> {
 		Self(ptr.cast())
 	}
 
-	pub fn boxed(value: Value<'gc>, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	pub fn boxed(value: Value<'gc>, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		Self::from_slice_unvalidated(&[value], gc)
 	}
 
@@ -153,12 +155,15 @@ impl<'gc> List<'gc> {
 			return Err(Error::ListIsTooLarge);
 		}
 
-		Ok(Self::from_slice_unvalidated(source, gc))
+		Self::from_slice_unvalidated(source, gc)
 	}
 
-	pub fn from_slice_unvalidated(source: &[Value<'gc>], gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	pub fn from_slice_unvalidated(
+		source: &[Value<'gc>],
+		gc: &'gc Gc,
+	) -> crate::Result<GcRoot<'gc, Self>> {
 		match source.len() {
-			0 => GcRoot::new_unchecked(Self::default()),
+			0 => Ok(GcRoot::new_unchecked(Self::default())),
 			1..=MAX_EMBEDDED_LENGTH => unsafe { Self::new_embedded(source, gc) },
 			_ => Self::new_alloc(source.to_vec(), gc),
 		}
@@ -185,10 +190,10 @@ impl<'gc> List<'gc> {
 			return Err(Error::ListIsTooLarge);
 		}
 
-		Ok(Self::new_unvalidated(source, gc))
+		Self::new_unvalidated(source, gc)
 	}
 
-	pub fn new_unvalidated<I>(source: I, gc: &'gc Gc) -> GcRoot<'gc, Self>
+	pub fn new_unvalidated<I>(source: I, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>>
 	where
 		I: IntoIterator<Item = Value<'gc>>,
 		I::IntoIter: ExactSizeIterator + TrustedLen,
@@ -212,21 +217,21 @@ impl<'gc> List<'gc> {
 		// GcRoot::new(&Self(inner), gc)
 
 		match source.len() {
-			0 => GcRoot::new_unchecked(Self::default()),
+			0 => Ok(GcRoot::new_unchecked(Self::default())),
 			//TODO
 			1..=MAX_EMBEDDED_LENGTH => unsafe { Self::new_embedded(&source.collect::<Vec<_>>(), gc) },
 			_ => Self::new_alloc(source.collect(), gc),
 		}
 	}
 
-	fn allocate(flags: u8, gc: &'gc Gc) -> *mut Inner<'gc> {
-		unsafe { gc.alloc_value_inner(flags | gc::FLAG_IS_LIST) }.cast::<Inner>()
+	fn allocate(flags: u8, gc: &'gc Gc) -> crate::Result<*mut Inner<'gc>> {
+		Ok(unsafe { gc.alloc_value_inner(flags | gc::FLAG_IS_LIST) }?.cast::<Inner>())
 	}
 
 	// SAFETY: caller has to ensure source is exactly the right length
-	unsafe fn new_embedded(source: &[Value<'gc>], gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	unsafe fn new_embedded(source: &[Value<'gc>], gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		debug_assert!(source.len() <= MAX_EMBEDDED_LENGTH);
-		let inner = Self::allocate((source.len() as u8) << SIZE_MASK_SHIFT, gc);
+		let inner = Self::allocate((source.len() as u8) << SIZE_MASK_SHIFT, gc)?;
 
 		unsafe {
 			(&raw mut (*inner).kind.embedded)
@@ -234,28 +239,32 @@ impl<'gc> List<'gc> {
 				.copy_from_nonoverlapping(source.as_ptr(), source.len());
 		}
 
-		GcRoot::new(&Self(inner), gc)
+		Ok(GcRoot::new(&Self(inner), gc))
 	}
 
-	fn new_alloc(mut source: Vec<Value<'gc>>, gc: &'gc Gc) -> GcRoot<'gc, Self> {
+	fn new_alloc(mut source: Vec<Value<'gc>>, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		// debug_assert!(source.len() > MAX_EMBEDDED_LENGTH); TODO: remove me when `add` is updated to use an alloc variant
 
-		let inner = Self::allocate(ALLOCATED_FLAG, gc);
+		gc.reserve(ALLOC_VALUE_SIZE + source.len() * size_of::<Value>())?;
+		let inner = Self::allocate(ALLOCATED_FLAG, gc)?;
 
 		source.shrink_to_fit();
+		gc.add_buffer_bytes(source.len() * size_of::<Value>());
 
 		unsafe {
 			(&raw mut (*inner).kind.alloc.len).write(source.len());
 			(&raw mut (*inner).kind.alloc.ptr).write(ManuallyDrop::new(source).as_mut_ptr());
 		}
 
-		GcRoot::new(&Self(inner), gc)
+		Ok(GcRoot::new(&Self(inner), gc))
 	}
 
 	fn flags_and_inner(&self) -> (u8, *mut Inner<'gc>) {
 		unsafe {
 			// TODO: orderings
-			((*&raw const (*self.0).flags).load(std::sync::atomic::Ordering::Relaxed), self.0 as _)
+			let flags = (*&raw const (*self.0).flags).load(std::sync::atomic::Ordering::Relaxed);
+			debug_assert_ne!(flags, 0, "used a list after it was freed");
+			(flags, self.0 as _)
 		}
 	}
 
@@ -292,6 +301,16 @@ impl<'gc> List<'gc> {
 		self.len() == 0
 	}
 
+	// How many bytes the buffer `self` owns is, which is zero if it's embedded.
+	pub(crate) fn buffer_size(&self) -> usize {
+		let (flags, _) = self.flags_and_inner();
+		if flags & ALLOCATED_FLAG != 0 {
+			self.len() * size_of::<Value>()
+		} else {
+			0
+		}
+	}
+
 	pub fn join(
 		&self,
 		sep: &KnStr,
@@ -312,6 +331,8 @@ impl<'gc> List<'gc> {
 			unsafe {
 				ele_str.with_inner(|inner| s.push_str(inner.as_str()));
 			}
+
+			env.gc().reserve(ALLOC_VALUE_SIZE + s.len())?;
 		}
 		Ok(KnString::new(s, env.opts(), env.gc())?)
 		// // Ok(GcRoot::new_unchecked(Self(self.0, PhantomData)))
@@ -333,6 +354,8 @@ impl<'gc> List<'gc> {
 	}
 
 	pub fn concat(&self, other: &Self, opts: &Options, gc: &'gc Gc) -> crate::Result<GcRoot<Self>> {
+		gc.reserve(ALLOC_VALUE_SIZE + (self.len() + other.len()) * size_of::<Value>())?;
+
 		// todo: use a "concat" variant
 		Self::new(self.into_iter().chain(other.into_iter()).collect::<Vec<_>>(), opts, gc)
 	}
@@ -350,6 +373,10 @@ impl<'gc> List<'gc> {
 			return Ok(GcRoot::new_unchecked(Self(self.0)));
 		}
 
+		gc.reserve(
+			ALLOC_VALUE_SIZE.saturating_add((self.len() * amount).saturating_mul(size_of::<Value>())),
+		)?;
+
 		// todo: optimized variant?
 		Ok(Self::new(self.__as_slice().repeat(amount), opts, gc)?)
 	}
@@ -365,7 +392,7 @@ impl<'gc> List<'gc> {
 	pub fn tail(&self, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>> {
 		let rest =
 			self.__as_slice().get(1..).ok_or(crate::Error::DomainError("empty list for head"))?;
-		Ok(Self::from_slice_unvalidated(rest, gc))
+		Self::from_slice_unvalidated(rest, gc)
 	}
 
 	pub fn try_get<I>(&self, index: I, gc: &'gc Gc) -> crate::Result<GcRoot<'gc, Self>>
@@ -376,7 +403,7 @@ impl<'gc> List<'gc> {
 			.__as_slice()
 			.get(index)
 			.ok_or(crate::Error::DomainError("invalid args for get for list"))?;
-		Ok(Self::from_slice_unvalidated(rest, gc))
+		Self::from_slice_unvalidated(rest, gc)
 	}
 
 	pub fn try_set(
@@ -387,6 +414,8 @@ impl<'gc> List<'gc> {
 		opts: &Options,
 		gc: &'gc Gc,
 	) -> crate::Result<GcRoot<'gc, Self>> {
+		gc.reserve(ALLOC_VALUE_SIZE + (self.len() + repl.len()) * size_of::<Value>())?;
+
 		// TODO: optimize this
 		let mut v = Vec::new();
 		v.extend(&mut self.into_iter().take(start));
@@ -453,10 +482,10 @@ impl Debug for List<'_> {
 }
 
 unsafe impl GarbageCollected for List<'_> {
-	unsafe fn mark(&self) {
+	unsafe fn mark(&self, tracer: &mut Tracer) {
 		for value in self {
 			unsafe {
-				value.mark();
+				value.mark(tracer);
 			}
 		}
 	}
@@ -0,0 +1,689 @@
+//! Compiling frequently-called blocks to native code.
+//!
+//! Blocks are counted every time they're called, and once a block is called [`HOT_CALL_COUNT`]
+//! times, it's compiled with Cranelift, as long as it only uses opcodes which are supported (which
+//! are mostly integer arithmetic, comparisons, variables, and jumps).
+//!
+//! Compiled code works directly on [`Value`]s' representations, and guards every operation so that
+//! it only runs when the operation's arguments are types it knows how to handle. When a guard fails
+//! (such as when adding a string, dividing by zero, or overflowing when compliance checking says
+//! to), the compiled code _bails out_: it hands the stack back to the interpreter, which resumes at
+//! the instruction that failed. Guards are always checked before an instruction does anything, so
+//! bailing out never changes how programs behave.
+//!
+//! Compiled code also bails out just before jumping backwards if the vm's been interrupted, so that
+//! the interpreter can stop; otherwise, compiled loops could never be interrupted.
+use std::collections::{BTreeMap, HashMap};
+use std::mem::ManuallyDrop;
+
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{self, types, AbiParam, InstBuilder, MemFlags};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use super::{FusedOperands, InterruptHandle, Opcode};
+use crate::program::{jump_target, JumpIndex, Program};
+use crate::value::{Value, TAG_BLOCK, TAG_INT, TAG_INT_SHIFT, TAG_MASK, TAG_MASK_INT};
+use crate::Options;
+
+/// How many times a block has to be called before it's compiled.
+pub const HOT_CALL_COUNT: u32 = 1000;
+
+// The signature of compiled blocks.
+//
+// `variables` is the vm's variables, and `stack` is where the stack is written to when bailing out,
+// which must have room for at least `max_depth` values. If the block returns, the value is returned
+// and `exit` isn't modified. Otherwise, the offset to resume at is written to `exit`, and the
+// amount of values written to `stack` is returned. `interrupt` is the state of the vm's
+// interrupt handle, which is checked before every backward jump.
+type NativeFn = unsafe extern "C" fn(
+	variables: *mut u64,
+	stack: *mut u64,
+	exit: *mut usize,
+	interrupt: *const u64,
+) -> u64;
+
+/// A block which has been compiled to native code.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Compiled {
+	function: NativeFn,
+	max_depth: usize,
+}
+
+/// What happened when a [`Compiled`] block was run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Exit {
+	/// The block returned the value with this representation.
+	Returned(u64),
+
+	/// The block bailed out, after writing `depth` values to the stack. The interpreter should
+	/// resume at `offset`.
+	BailedOut { offset: usize, depth: usize },
+}
+
+impl Compiled {
+	/// The most values [`call`](Self::call) will write to the stack.
+	pub(crate) fn max_depth(self) -> usize {
+		self.max_depth
+	}
+
+	/// Runs the compiled block.
+	///
+	/// # Safety
+	/// `variables` must be the variables of the program the block was compiled for, and `stack`
+	/// must be valid for writing [`max_depth`](Self::max_depth) values.
+	pub(crate) unsafe fn call(
+		self,
+		variables: *mut Value<'_>,
+		stack: *mut Value<'_>,
+		interrupt: &InterruptHandle,
+	) -> Exit {
+		let mut exit = usize::MAX;
+
+		// SAFETY: `Value`s are `repr(transparent)` over their representation, and the caller
+		// guarantees that the pointers are valid. The handle outlives the call.
+		let result =
+			unsafe { (self.function)(variables.cast(), stack.cast(), &mut exit, interrupt.as_ptr()) };
+
+		if exit == usize::MAX {
+			Exit::Returned(result)
+		} else {
+			Exit::BailedOut { offset: exit, depth: result as usize }
+		}
+	}
+}
+
+// The state of a single block.
+enum Entry {
+	Counting(u32),
+	Compiled(Compiled),
+	Unsupported,
+}
+
+// Compliance checks which compiled code needs to bail out for.
+#[derive(Default, Clone, Copy)]
+struct Checks {
+	overflow: bool,
+	i32_integer: bool,
+	integer_function_bounds: bool,
+}
+
+/// The JIT compiler for a single program.
+pub(crate) struct Jit {
+	// This is never taken except when dropping, after which nothing else can use it.
+	module: ManuallyDrop<JITModule>,
+	context: Context,
+	builder_context: FunctionBuilderContext,
+	depths: Vec<Option<usize>>,
+	blocks: HashMap<usize, Entry>,
+	checks: Checks,
+}
+
+impl Drop for Jit {
+	fn drop(&mut self) {
+		// SAFETY: `Compiled`s never outlive the `Jit` that made them, as they're only ever used
+		// by the `Vm` which owns it.
+		unsafe { ManuallyDrop::take(&mut self.module).free_memory() }
+	}
+}
+
+impl Jit {
+	/// Creates a new JIT for `program`.
+	///
+	/// Returns `None` if the program can't be [verified](Program::verify), or if Cranelift doesn't
+	/// support the current machine.
+	pub(crate) fn new(program: &Program<'_, '_, '_>, opts: &Options) -> Option<Self> {
+		let depths = program.stack_depths().ok()?;
+
+		let mut flags = settings::builder();
+		flags.set("opt_level", "speed").ok()?;
+		let isa = cranelift_native::builder().ok()?.finish(settings::Flags::new(flags)).ok()?;
+		let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+		#[cfg_attr(not(feature = "compliance"), allow(unused_mut))]
+		let mut checks = Checks::default();
+		#[cfg(feature = "compliance")]
+		{
+			checks.overflow = opts.compliance.check_overflow;
+			checks.i32_integer = opts.compliance.i32_integer;
+			checks.integer_function_bounds = opts.compliance.check_integer_function_bounds;
+		}
+		let _ = opts;
+
+		Some(Self {
+			context: module.make_context(),
+			module: ManuallyDrop::new(module),
+			builder_context: FunctionBuilderContext::new(),
+			depths,
+			blocks: HashMap::new(),
+			checks,
+		})
+	}
+
+	/// Records that `block` is being called, returning its compiled code if it has any.
+	///
+	/// Blocks are compiled the [`HOT_CALL_COUNT`]th time they're called.
+	pub(crate) fn called(
+		&mut self,
+		program: &Program<'_, '_, '_>,
+		block: JumpIndex,
+	) -> Option<Compiled> {
+		let entry = self.blocks.entry(block.0).or_insert(Entry::Counting(0));
+		match entry {
+			Entry::Compiled(compiled) => return Some(*compiled),
+			Entry::Unsupported => return None,
+			Entry::Counting(count) if *count + 1 < HOT_CALL_COUNT => {
+				*count += 1;
+				return None;
+			}
+			Entry::Counting(_) => {}
+		}
+
+		let compiled = self.compile(program, block.0);
+		self.blocks.insert(block.0, compiled.map_or(Entry::Unsupported, Entry::Compiled));
+		compiled
+	}
+
+	// Compiles the block starting at `start`, returning `None` if it uses anything unsupported.
+	fn compile(&mut self, program: &Program<'_, '_, '_>, start: usize) -> Option<Compiled> {
+		let instructions = self.reachable_instructions(program, start)?;
+		let max_depth = instructions.keys().filter_map(|&offset| self.depths[offset]).max()?;
+
+		self.module.clear_context(&mut self.context);
+		let pointer = self.module.target_config().pointer_type();
+		let signature = &mut self.context.func.signature;
+		signature.params.extend([AbiParam::new(pointer); 4]);
+		signature.returns.push(AbiParam::new(types::I64));
+
+		let builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+		Translator::new(builder, self.checks, max_depth + 1).translate(
+			program,
+			&instructions,
+			&self.depths,
+			start,
+		)?;
+
+		let id = self.module.declare_anonymous_function(&self.context.func.signature).ok()?;
+		self.module.define_function(id, &mut self.context).ok()?;
+		self.module.finalize_definitions().ok()?;
+
+		// SAFETY: the function was defined with `NativeFn`'s signature.
+		let function = unsafe {
+			std::mem::transmute::<*const u8, NativeFn>(self.module.get_finalized_function(id))
+		};
+
+		Some(Compiled { function, max_depth })
+	}
+
+	// Gets every instruction which can be reached from `start`, returning `None` if any of them
+	// aren't supported.
+	fn reachable_instructions(
+		&self,
+		program: &Program<'_, '_, '_>,
+		start: usize,
+	) -> Option<BTreeMap<usize, (Opcode, usize)>> {
+		let mut instructions = BTreeMap::new();
+		let mut worklist = vec![start];
+
+		while let Some(offset) = worklist.pop() {
+			if instructions.contains_key(&offset) {
+				continue;
+			}
+
+			// SAFETY: `start` is the start of a block, and every offset after it was either the
+			// target of a jump or the next instruction, which verified programs always have.
+			let (opcode, operand) = unsafe { program.opcode_at(offset) };
+			self.depths.get(offset).copied().flatten()?;
+			if !is_supported(program, opcode, operand) {
+				return None;
+			}
+
+			instructions.insert(offset, (opcode, operand));
+			worklist.extend(jump_target(opcode, operand));
+			if !matches!(opcode, Opcode::Jump | Opcode::Return) {
+				worklist.push(offset + opcode.encoded_len());
+			}
+		}
+
+		Some(instructions)
+	}
+}
+
+// Whether `opcode` can be compiled.
+fn is_supported(program: &Program<'_, '_, '_>, opcode: Opcode, operand: usize) -> bool {
+	// SAFETY: verified programs' constants are always valid.
+	let constant = |index: usize| unsafe { program.constant_at(index) };
+
+	match opcode {
+		// Strings and lists can't be pushed, as compiled code only handles values by their repr.
+		Opcode::PushConstant => is_scalar(constant(operand).repr()),
+
+		Opcode::GetVar
+		| Opcode::SetVar
+		| Opcode::SetVarPop
+		| Opcode::Pop
+		| Opcode::Dup
+		| Opcode::Jump
+		| Opcode::JumpIfTrue
+		| Opcode::JumpIfFalse
+		| Opcode::Return
+		| Opcode::Not
+		| Opcode::Add
+		| Opcode::Sub
+		| Opcode::Mul
+		| Opcode::Div
+		| Opcode::Mod
+		| Opcode::Lth
+		| Opcode::Gth
+		| Opcode::Eql => true,
+
+		// The constant is always an argument to an integer operation, except for `?`.
+		Opcode::AddVarConst | Opcode::SubVarConst | Opcode::BranchVarLth | Opcode::BranchVarGth => {
+			constant(FusedOperands::unpack(operand).constant).as_integer().is_some()
+		}
+		Opcode::BranchVarEql => is_scalar(constant(FusedOperands::unpack(operand).constant).repr()),
+
+		_ => false,
+	}
+}
+
+// Whether `repr` is an integer, boolean, or null, i.e. something whose representation alone
+// determines what it is.
+fn is_scalar(repr: u64) -> bool {
+	repr & TAG_MASK_INT == TAG_INT
+		|| [Value::NULL, Value::FALSE, Value::TRUE].iter().any(|value| value.repr() == repr)
+}
+
+// Translates a single block's bytecode to Cranelift IR.
+struct Translator<'a> {
+	builder: FunctionBuilder<'a>,
+	checks: Checks,
+
+	// The parameters of the function.
+	variables: ir::Value,
+	stack: ir::Value,
+	exit: ir::Value,
+	interrupt: ir::Value,
+
+	// The stack is kept in Cranelift variables, one for each depth.
+	stack_len: usize,
+}
+
+impl<'a> Translator<'a> {
+	fn new(mut builder: FunctionBuilder<'a>, checks: Checks, stack_len: usize) -> Self {
+		let entry = builder.create_block();
+		builder.append_block_params_for_function_params(entry);
+		builder.switch_to_block(entry);
+
+		let [variables, stack, exit, interrupt] = builder.block_params(entry).try_into().unwrap();
+		for depth in 0..stack_len {
+			builder.declare_var(Variable::new(depth), types::I64);
+		}
+
+		Self { builder, checks, variables, stack, exit, interrupt, stack_len }
+	}
+
+	fn translate(
+		mut self,
+		program: &Program<'_, '_, '_>,
+		instructions: &BTreeMap<usize, (Opcode, usize)>,
+		depths: &[Option<usize>],
+		start: usize,
+	) -> Option<()> {
+		// Every jump target, and every instruction after a conditional jump, starts a new block.
+		let mut blocks = HashMap::new();
+		blocks.insert(start, self.builder.create_block());
+		for (&offset, &(opcode, operand)) in instructions {
+			if let Some(target) = jump_target(opcode, operand) {
+				blocks.entry(target).or_insert_with(|| self.builder.create_block());
+
+				if opcode != Opcode::Jump {
+					let next = offset + opcode.encoded_len();
+					blocks.entry(next).or_insert_with(|| self.builder.create_block());
+				}
+			}
+		}
+
+		self.builder.ins().jump(blocks[&start], &[]);
+
+		let mut falls_through = false;
+		for (&offset, &(opcode, operand)) in instructions {
+			if let Some(&block) = blocks.get(&offset) {
+				if falls_through {
+					self.builder.ins().jump(block, &[]);
+				}
+				self.builder.switch_to_block(block);
+			}
+
+			let depth = depths[offset]?;
+			let next = blocks.get(&(offset + opcode.encoded_len())).copied();
+			falls_through = opcode != Opcode::Return && jump_target(opcode, operand).is_none();
+
+			self.instruction(program, offset, depth, opcode, operand, |target| blocks[&target], next);
+		}
+
+		self.builder.seal_all_blocks();
+		self.builder.finalize();
+		Some(())
+	}
+
+	// Gets the value at `depth` on the stack.
+	fn get(&mut self, depth: usize) -> ir::Value {
+		self.builder.use_var(Variable::new(depth))
+	}
+
+	// Sets the value at `depth` on the stack.
+	fn set(&mut self, depth: usize, value: ir::Value) {
+		debug_assert!(depth < self.stack_len);
+		self.builder.def_var(Variable::new(depth), value);
+	}
+
+	fn constant(&mut self, program: &Program<'_, '_, '_>, index: usize) -> ir::Value {
+		// SAFETY: verified programs' constants are always valid.
+		let repr = unsafe { program.constant_at(index) }.repr();
+		self.builder.ins().iconst(types::I64, repr as i64)
+	}
+
+	fn load_variable(&mut self, offset: usize, depth: usize, variable: usize) -> ir::Value {
+		let value = self.builder.ins().load(
+			types::I64,
+			MemFlags::trusted(),
+			self.variables,
+			(variable * size_of::<u64>()) as i32,
+		);
+
+		// Let the interpreter deal with unassigned variables.
+		#[cfg(feature = "check-variables")]
+		{
+			let unassigned =
+				self.builder.ins().icmp_imm(IntCC::Equal, value, Value::UNDEFINED.repr() as i64);
+			self.bail_if(unassigned, offset, depth);
+		}
+		let _ = (offset, depth);
+
+		value
+	}
+
+	fn store_variable(&mut self, offset: usize, depth: usize, variable: usize, value: ir::Value) {
+		// The interpreter keeps track of the names of blocks for stacktraces.
+		#[cfg(feature = "stacktrace")]
+		{
+			let tag = self.builder.ins().band_imm(value, TAG_MASK as i64);
+			let is_block = self.builder.ins().icmp_imm(IntCC::Equal, tag, TAG_BLOCK as i64);
+			self.bail_if(is_block, offset, depth);
+		}
+		let _ = (offset, depth);
+
+		self.builder.ins().store(
+			MemFlags::trusted(),
+			value,
+			self.variables,
+			(variable * size_of::<u64>()) as i32,
+		);
+	}
+
+	// Bails out to the interpreter at `offset`, where the stack is `depth` deep, if the vm's been
+	// interrupted. The interpreter then checks the interrupt itself and stops.
+	fn bail_if_interrupted(&mut self, offset: usize, depth: usize) {
+		// The state's lowest bit is whether it's interrupted.
+		let state = self.builder.ins().atomic_load(types::I64, MemFlags::trusted(), self.interrupt);
+		let interrupted = self.builder.ins().band_imm(state, 1);
+		self.bail_if(interrupted, offset, depth);
+	}
+
+	// Bails out to the interpreter at `offset`, where the stack is `depth` deep, if `condition` is
+	// nonzero.
+	fn bail_if(&mut self, condition: ir::Value, offset: usize, depth: usize) {
+		let bail = self.builder.create_block();
+		let next = self.builder.create_block();
+		self.builder.set_cold_block(bail);
+		self.builder.ins().brif(condition, bail, &[], next, &[]);
+
+		self.builder.switch_to_block(bail);
+		for index in 0..depth {
+			let value = self.get(index);
+			let position = (index * size_of::<u64>()) as i32;
+			self.builder.ins().store(MemFlags::trusted(), value, self.stack, position);
+		}
+		let offset = self.builder.ins().iconst(types::I64, offset as i64);
+		self.builder.ins().store(MemFlags::trusted(), offset, self.exit, 0);
+		let depth = self.builder.ins().iconst(types::I64, depth as i64);
+		self.builder.ins().return_(&[depth]);
+
+		self.builder.switch_to_block(next);
+	}
+
+	// Bails out unless both `lhs` and `rhs` are integers.
+	fn guard_integers(&mut self, lhs: ir::Value, rhs: ir::Value, offset: usize, depth: usize) {
+		let both = self.builder.ins().band(lhs, rhs);
+		let tag = self.builder.ins().band_imm(both, TAG_MASK_INT as i64);
+		let not_integers = self.builder.ins().icmp_imm(IntCC::NotEqual, tag, TAG_INT as i64);
+		self.bail_if(not_integers, offset, depth);
+	}
+
+	// Bails out unless `value` is an integer, boolean, or null. (See `is_scalar`.)
+	fn guard_scalar(&mut self, value: ir::Value, offset: usize, depth: usize) {
+		let tag = self.builder.ins().band_imm(value, TAG_MASK_INT as i64);
+		let mut is_scalar = self.builder.ins().icmp_imm(IntCC::Equal, tag, TAG_INT as i64);
+		for other in [Value::NULL, Value::FALSE, Value::TRUE] {
+			let is_other = self.builder.ins().icmp_imm(IntCC::Equal, value, other.repr() as i64);
+			is_scalar = self.builder.ins().bor(is_scalar, is_other);
+		}
+
+		let not_scalar = self.builder.ins().bxor_imm(is_scalar, 1);
+		self.bail_if(not_scalar, offset, depth);
+	}
+
+	// Gets whether `value` is truthy, bailing out if it isn't a scalar.
+	fn truthy(&mut self, value: ir::Value, offset: usize, depth: usize) -> ir::Value {
+		self.guard_scalar(value, offset, depth);
+
+		// `NULL`, `FALSE`, and `0` are the only falsey scalars, and they have the smallest
+		// representations.
+		self.builder.ins().icmp_imm(IntCC::UnsignedGreaterThan, value, Value::FALSE.repr() as i64)
+	}
+
+	// Converts a boolean from `icmp` into a Knight boolean.
+	fn boolean(&mut self, condition: ir::Value) -> ir::Value {
+		let true_ = self.builder.ins().iconst(types::I64, Value::TRUE.repr() as i64);
+		let false_ = self.builder.ins().iconst(types::I64, Value::FALSE.repr() as i64);
+		self.builder.ins().select(condition, true_, false_)
+	}
+
+	// Runs the integer operation `opcode` on `lhs` and `rhs`, bailing out when the interpreter would
+	// raise an error.
+	fn arithmetic(
+		&mut self,
+		opcode: Opcode,
+		lhs: ir::Value,
+		rhs: ir::Value,
+		offset: usize,
+		depth: usize,
+	) -> ir::Value {
+		self.guard_integers(lhs, rhs, offset, depth);
+
+		let lhs = self.builder.ins().sshr_imm(lhs, TAG_INT_SHIFT as i64);
+		let rhs = self.builder.ins().sshr_imm(rhs, TAG_INT_SHIFT as i64);
+
+		// Integers only have 63 bits, so only multiplication can overflow an `i64`. Likewise,
+		// `i64::MIN / -1` can't happen.
+		let result = match opcode {
+			Opcode::Add => self.builder.ins().iadd(lhs, rhs),
+			Opcode::Sub => self.builder.ins().isub(lhs, rhs),
+			Opcode::Mul if self.checks.overflow => {
+				let (result, overflowed) = self.builder.ins().smul_overflow(lhs, rhs);
+				self.bail_if(overflowed, offset, depth);
+				result
+			}
+			Opcode::Mul => self.builder.ins().imul(lhs, rhs),
+			Opcode::Div | Opcode::Mod => {
+				let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+				self.bail_if(is_zero, offset, depth);
+
+				if opcode == Opcode::Div {
+					self.builder.ins().sdiv(lhs, rhs)
+				} else {
+					if self.checks.integer_function_bounds {
+						let either = self.builder.ins().bor(lhs, rhs);
+						let is_negative = self.builder.ins().icmp_imm(IntCC::SignedLessThan, either, 0);
+						self.bail_if(is_negative, offset, depth);
+					}
+
+					self.builder.ins().srem(lhs, rhs)
+				}
+			}
+			_ => bug!("{:?} isn't an arithmetic opcode", opcode),
+		};
+
+		if self.checks.i32_integer {
+			let too_small =
+				self.builder.ins().icmp_imm(IntCC::SignedLessThan, result, i32::MIN as i64);
+			let too_large =
+				self.builder.ins().icmp_imm(IntCC::SignedGreaterThan, result, i32::MAX as i64);
+			let out_of_bounds = self.builder.ins().bor(too_small, too_large);
+			self.bail_if(out_of_bounds, offset, depth);
+		}
+
+		let shifted = self.builder.ins().ishl_imm(result, TAG_INT_SHIFT as i64);
+		self.builder.ins().bor_imm(shifted, TAG_INT as i64)
+	}
+
+	// Compares `lhs` and `rhs` like `opcode` does, bailing out if it's not a simple comparison.
+	fn compare(
+		&mut self,
+		opcode: Opcode,
+		lhs: ir::Value,
+		rhs: ir::Value,
+		offset: usize,
+		depth: usize,
+	) -> ir::Value {
+		match opcode {
+			// Tagging integers doesn't change their order, so they can be compared directly.
+			Opcode::Lth | Opcode::Gth => {
+				self.guard_integers(lhs, rhs, offset, depth);
+				let cond =
+					if opcode == Opcode::Lth { IntCC::SignedLessThan } else { IntCC::SignedGreaterThan };
+				self.builder.ins().icmp(cond, lhs, rhs)
+			}
+
+			// Scalars are only ever equal to values with the same representation.
+			Opcode::Eql => {
+				self.guard_scalar(lhs, offset, depth);
+				self.guard_scalar(rhs, offset, depth);
+				self.builder.ins().icmp(IntCC::Equal, lhs, rhs)
+			}
+
+			_ => bug!("{:?} isn't a comparison opcode", opcode),
+		}
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	fn instruction(
+		&mut self,
+		program: &Program<'_, '_, '_>,
+		offset: usize,
+		depth: usize,
+		opcode: Opcode,
+		operand: usize,
+		block_at: impl Fn(usize) -> ir::Block,
+		next: Option<ir::Block>,
+	) {
+		if jump_target(opcode, operand).is_some_and(|target| target < offset) {
+			self.bail_if_interrupted(offset, depth);
+		}
+
+		match opcode {
+			Opcode::PushConstant => {
+				let value = self.constant(program, operand);
+				self.set(depth, value);
+			}
+			Opcode::GetVar => {
+				let value = self.load_variable(offset, depth, operand);
+				self.set(depth, value);
+			}
+			Opcode::SetVar | Opcode::SetVarPop => {
+				let value = self.get(depth - 1);
+				self.store_variable(offset, depth, operand, value);
+			}
+			Opcode::Pop => {}
+			Opcode::Dup => {
+				let value = self.get(depth - 1);
+				self.set(depth, value);
+			}
+
+			Opcode::Jump => {
+				self.builder.ins().jump(block_at(operand), &[]);
+			}
+			Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+				let value = self.get(depth - 1);
+				let truthy = self.truthy(value, offset, depth);
+				let (then, otherwise) = (block_at(operand), next.unwrap());
+				if opcode == Opcode::JumpIfTrue {
+					self.builder.ins().brif(truthy, then, &[], otherwise, &[]);
+				} else {
+					self.builder.ins().brif(truthy, otherwise, &[], then, &[]);
+				}
+			}
+
+			Opcode::Return => {
+				let value = self.get(depth - 1);
+				self.builder.ins().return_(&[value]);
+			}
+
+			Opcode::Not => {
+				let value = self.get(depth - 1);
+				let truthy = self.truthy(value, offset, depth);
+				let falsey = self.builder.ins().bxor_imm(truthy, 1);
+				let result = self.boolean(falsey);
+				self.set(depth - 1, result);
+			}
+
+			Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => {
+				let (lhs, rhs) = (self.get(depth - 2), self.get(depth - 1));
+				let result = self.arithmetic(opcode, lhs, rhs, offset, depth);
+				self.set(depth - 2, result);
+			}
+
+			Opcode::Lth | Opcode::Gth | Opcode::Eql => {
+				let (lhs, rhs) = (self.get(depth - 2), self.get(depth - 1));
+				let condition = self.compare(opcode, lhs, rhs, offset, depth);
+				let result = self.boolean(condition);
+				self.set(depth - 2, result);
+			}
+
+			Opcode::AddVarConst | Opcode::SubVarConst => {
+				let FusedOperands { variable, constant, .. } = FusedOperands::unpack(operand);
+				let lhs = self.load_variable(offset, depth, variable);
+				let rhs = self.constant(program, constant);
+				let opcode = if opcode == Opcode::AddVarConst { Opcode::Add } else { Opcode::Sub };
+				let result = self.arithmetic(opcode, lhs, rhs, offset, depth);
+				self.store_variable(offset, depth, variable, result);
+			}
+
+			Opcode::BranchVarLth | Opcode::BranchVarGth | Opcode::BranchVarEql => {
+				let FusedOperands { variable, constant, target, jump_if } =
+					FusedOperands::unpack(operand);
+				let lhs = self.load_variable(offset, depth, variable);
+				let rhs = self.constant(program, constant);
+				let opcode = match opcode {
+					Opcode::BranchVarLth => Opcode::Lth,
+					Opcode::BranchVarGth => Opcode::Gth,
+					_ => Opcode::Eql,
+				};
+				let condition = self.compare(opcode, lhs, rhs, offset, depth);
+				let (then, otherwise) = (block_at(target), next.unwrap());
+				if jump_if {
+					self.builder.ins().brif(condition, then, &[], otherwise, &[]);
+				} else {
+					self.builder.ins().brif(condition, otherwise, &[], then, &[]);
+				}
+			}
+
+			_ => bug!("{:?} isn't supported by the jit", opcode),
+		}
+	}
+}
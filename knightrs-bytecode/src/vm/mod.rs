@@ -1,11 +1,14 @@
 mod error;
+#[cfg(feature = "jit")]
+mod jit;
 pub mod opcode;
 #[cfg(feature = "stacktrace")]
 mod stacktrace;
 mod vm;
 
 pub use error::RuntimeError;
-pub use opcode::Opcode;
+pub use knightrs_common::InterruptHandle;
+pub use opcode::{FusedOperands, Opcode};
 #[cfg(feature = "stacktrace")]
 pub use stacktrace::{Callsite, Stacktrace};
 pub use vm::*;
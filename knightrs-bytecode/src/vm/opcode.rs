@@ -2,10 +2,11 @@
 // Implementation note: They're intentionally constructed in a special way, so as to make accessing
 // information like their arity super easy. More precisely, they're structured like:
 //
-//   opcode := `AAIIIIIO`
+//   opcode := `AAAIIIIO`
 //
 // where `A` is the arity, `I` is index, and `O` is if it takes an offset. Note that functions which
-// take more than 3 arguments need to pop their arguments off manually.
+// take more than 3 arguments need to pop their arguments off manually. This means there's room for
+// 16 opcodes of each arity which take offsets, and another 16 which don't.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 #[non_exhaustive]
@@ -22,6 +23,14 @@ pub enum Opcode {
 	#[cfg(feature = "extensions")]
 	AssignDynamic = opcode(7, 0, true), // offset is the type to use
 
+	// Superinstructions, which are fused versions of common sequences. Their offsets are
+	// `FusedOperands`, and they don't touch the stack.
+	AddVarConst  = opcode(8, 0, true),  // `GetVar; PushConstant; Add; SetVarPop`
+	SubVarConst  = opcode(9, 0, true),  // `GetVar; PushConstant; Sub; SetVarPop`
+	BranchVarLth = opcode(10, 0, true), // `GetVar; PushConstant; Lth; JumpIf{True,False}`
+	BranchVarGth = opcode(11, 0, true), // `GetVar; PushConstant; Gth; JumpIf{True,False}`
+	BranchVarEql = opcode(12, 0, true), // `GetVar; PushConstant; Eql; JumpIf{True,False}`
+
 	// Arity 0
 	Prompt = opcode(1, 0, false),
 	Random = opcode(2, 0, false),
@@ -86,11 +95,29 @@ pub enum DynamicAssignment {
 // If it goes higher than this, we need to rework the structure of the opcode.
 const fn opcode(id: u8, arity: u8, takes_offset: bool) -> u8 {
 	assert!(arity as usize <= 0b111, "7 is max arity that can be taken");
-	assert!(id <= 0b11111, "too many IDs of a given arity will clobber stuff");
+	assert!(id <= 0b1111, "too many IDs of a given arity will clobber stuff");
 
 	(arity << 5) | (id << 1) | (takes_offset as u8)
 }
 
+// The `offset_len` of every possible opcode byte, computed from the bits alone. (Superinstructions
+// are exactly the zero-arity opcodes which take offsets and have an index of at least 8.)
+const OFFSET_LENS: [u8; 256] = {
+	let mut lens = [0; 256];
+	let mut byte = 0;
+	while byte < lens.len() {
+		lens[byte] = if byte & 1 == 0 {
+			0
+		} else if byte as u8 & 0b1111_0001 == opcode(8, 0, true) & 0b1111_0001 {
+			8
+		} else {
+			4
+		};
+		byte += 1;
+	}
+	lens
+};
+
 impl Opcode {
 	// TODO: check for things
 	pub const MAX_ARITY: usize = 4;
@@ -107,13 +134,45 @@ impl Opcode {
 		(self as u8) & 1 != 0
 	}
 
-	/// Returns the [`Opcode`] from the byte, without checking to see if it's a valid [`Opcode`].
+	/// The amount of bytes the opcode's offset takes up in a [`Program`](crate::Program)'s code.
 	///
-	/// # Safety
-	/// The caller must ensure that `byte` corresponds to a valid [`Opcode`] representation.
-	#[cfg_attr(not(debug_assertions), inline)]
-	pub unsafe fn from_byte_unchecked(byte: u8) -> Self {
-		debug_assert!(
+	/// Offsets are stored in little-endian directly after the opcode. Most are four bytes, except
+	/// for superinstructions, which use eight.
+	#[inline]
+	pub const fn offset_len(self) -> usize {
+		// This is in the hot path of the VM, so it's looked up instead of using `is_fused`.
+		OFFSET_LENS[self as usize] as usize
+	}
+
+	/// The total amount of bytes the opcode (including its offset) takes up.
+	#[inline]
+	pub const fn encoded_len(self) -> usize {
+		1 + self.offset_len()
+	}
+
+	/// Whether the opcode is a superinstruction whose offset is [`FusedOperands`].
+	#[inline]
+	pub const fn is_fused(self) -> bool {
+		matches!(
+			self,
+			Self::AddVarConst
+				| Self::SubVarConst
+				| Self::BranchVarLth
+				| Self::BranchVarGth
+				| Self::BranchVarEql
+		)
+	}
+
+	/// Whether the opcode is a superinstruction which conditionally jumps.
+	#[inline]
+	pub const fn is_fused_branch(self) -> bool {
+		matches!(self, Self::BranchVarLth | Self::BranchVarGth | Self::BranchVarEql)
+	}
+
+	/// Returns the [`Opcode`] corresponding to `byte`, or `None` if it's not a valid [`Opcode`].
+	#[rustfmt::skip]
+	pub fn from_byte(byte: u8) -> Option<Self> {
+		let is_valid =
 			// Builtins
 			byte == Self::PushConstant as u8
 				|| byte == Self::Jump as u8
@@ -122,17 +181,30 @@ impl Opcode {
 				|| byte == Self::GetVar as u8
 				|| byte == Self::SetVar as u8
 				|| byte == Self::SetVarPop as u8
+				|| {
+					#[cfg(feature = "extensions")]
+					{ byte == Self::AssignDynamic as u8 }
+					#[cfg(not(feature = "extensions"))]
+					{ false }
+				}
+
+			// Superinstructions
+				|| byte == Self::AddVarConst as u8
+				|| byte == Self::SubVarConst as u8
+				|| byte == Self::BranchVarLth as u8
+				|| byte == Self::BranchVarGth as u8
+				|| byte == Self::BranchVarEql as u8
 
 			// Arity 0
 				|| byte == Self::Prompt as u8
 				|| byte == Self::Random as u8
 				|| byte == Self::Dup as u8
-				|| byte == Self::Return as u8
+				|| byte == Self::Dump as u8
 
 			// Arity 1
+				|| byte == Self::Return as u8
 				|| byte == Self::Call as u8
 				|| byte == Self::Quit as u8
-				|| byte == Self::Dump as u8
 				|| byte == Self::Output as u8
 				|| byte == Self::Length as u8
 				|| byte == Self::Not as u8
@@ -147,8 +219,6 @@ impl Opcode {
 						   byte == Self::Eval as u8
 						|| byte == Self::Value as u8
 						|| byte == Self::System as u8
-						|| byte == Self::SetDynamicVar as u8
-						|| byte == Self::AssignDynamic as u8
 					}
 					#[cfg(not(feature = "extensions"))]
 					{ false }
@@ -164,16 +234,88 @@ impl Opcode {
 				|| byte == Self::Lth as u8
 				|| byte == Self::Gth as u8
 				|| byte == Self::Eql as u8
+				|| {
+					#[cfg(feature = "extensions")]
+					{ byte == Self::SetDynamicVar as u8 }
+					#[cfg(not(feature = "extensions"))]
+					{ false }
+				}
 
 			// Arity 3
 				|| byte == Self::Get as u8
 
 			// Arity 4
-				|| byte == Self::Set as u8
-		);
+				|| byte == Self::Set as u8;
+
+		// SAFETY: `Opcode` is `#[repr(u8)]`, and we just checked `byte` is a valid opcode.
+		is_valid.then(|| unsafe { std::mem::transmute::<u8, Opcode>(byte) })
+	}
+
+	/// Returns the [`Opcode`] from the byte, without checking to see if it's a valid [`Opcode`].
+	///
+	/// # Safety
+	/// The caller must ensure that `byte` corresponds to a valid [`Opcode`] representation.
+	#[cfg_attr(not(debug_assertions), inline)]
+	pub unsafe fn from_byte_unchecked(byte: u8) -> Self {
+		debug_assert!(Self::from_byte(byte).is_some(), "invalid opcode byte: {byte:#04x}");
 
 		// SAFETY: `Opcode` is `#[repr(u8)]`, and the caller ensures that `byte` is actually a valid
 		// opcode, so this transmutation is safe.
-		return unsafe { std::mem::transmute::<u8, Opcode>(byte) };
+		unsafe { std::mem::transmute::<u8, Opcode>(byte) }
+	}
+}
+
+/// The operands of superinstructions (see [`Opcode::is_fused`]), which are packed into a single
+/// offset.
+///
+/// `variable` is the variable which is operated on, and `constant` is the constant used as the
+/// other argument. Branches jump to `target` when the result of the comparison is `jump_if`; other
+/// superinstructions ignore both fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FusedOperands {
+	pub variable: usize,
+	pub constant: usize,
+	pub target: usize,
+	pub jump_if: bool,
+}
+
+impl FusedOperands {
+	const VARIABLE_BITS: u32 = 16;
+	const CONSTANT_BITS: u32 = 16;
+	const TARGET_BITS: u32 = 31;
+
+	/// Packs `self` into an offset, returning `None` if any field is too large to fit.
+	///
+	/// Packed operands can take up to 64 bits, so on 32-bit targets this fails for most operands,
+	/// and no superinstructions are used.
+	pub fn pack(self) -> Option<usize> {
+		let fits = |field: usize, bits: u32| field < (1 << bits);
+		if !fits(self.variable, Self::VARIABLE_BITS)
+			|| !fits(self.constant, Self::CONSTANT_BITS)
+			|| !fits(self.target, Self::TARGET_BITS)
+		{
+			return None;
+		}
+
+		let mut packed = self.target as u64;
+		packed = (packed << Self::CONSTANT_BITS) | self.constant as u64;
+		packed = (packed << Self::VARIABLE_BITS) | self.variable as u64;
+		usize::try_from((packed << 1) | self.jump_if as u64).ok()
+	}
+
+	/// Unpacks an offset created by [`FusedOperands::pack`].
+	#[inline]
+	pub fn unpack(offset: usize) -> Self {
+		let mask = |bits: u32| (1 << bits) - 1;
+		let offset = offset as u64;
+
+		let jump_if = offset & 1 != 0;
+		let offset = offset >> 1;
+		let variable = (offset & mask(Self::VARIABLE_BITS)) as usize;
+		let offset = offset >> Self::VARIABLE_BITS;
+		let constant = (offset & mask(Self::CONSTANT_BITS)) as usize;
+		let target = ((offset >> Self::CONSTANT_BITS) & mask(Self::TARGET_BITS)) as usize;
+
+		Self { variable, constant, target, jump_if }
 	}
 }
@@ -32,13 +32,21 @@ impl Display for Stacktrace<'_, '_> {
 pub struct Callsite<'src, 'path> {
 	location: SourceLocation<'path>,
 	fn_name: Option<VariableName<'src>>,
+	elided_tail_calls: usize,
 }
 
 impl<'src, 'path> Callsite<'src, 'path> {
 	/// Creates a new [`Callsite`]. The `fn_name` can be supplied to indicate the call happened within
 	/// a function.
 	pub fn new(fn_name: Option<VariableName<'src>>, location: SourceLocation<'path>) -> Self {
-		Self { location, fn_name }
+		Self { location, fn_name, elided_tail_calls: 0 }
+	}
+
+	/// Indicates that `amount` tail calls happened after this callsite. Tail calls reuse the frame
+	/// of the block which made them, so they don't get their own callsites.
+	pub fn with_elided_tail_calls(mut self, amount: usize) -> Self {
+		self.elided_tail_calls = amount;
+		self
 	}
 
 	/// The name of function, if present.
@@ -50,6 +58,11 @@ impl<'src, 'path> Callsite<'src, 'path> {
 	pub fn location(&self) -> SourceLocation<'path> {
 		self.location
 	}
+
+	/// The amount of tail calls which happened after this callsite.
+	pub fn elided_tail_calls(&self) -> usize {
+		self.elided_tail_calls
+	}
 }
 
 impl Display for Callsite<'_, '_> {
@@ -60,6 +73,12 @@ impl Display for Callsite<'_, '_> {
 			write!(f, " (function {})", fn_name)?;
 		}
 
+		match self.elided_tail_calls {
+			0 => {}
+			1 => write!(f, " (1 tail call elided)")?,
+			amount => write!(f, " ({amount} tail calls elided)")?,
+		}
+
 		Ok(())
 	}
 }
@@ -1,91 +1,278 @@
-use crate::gc::GarbageCollected;
+use crate::gc::{GarbageCollected, Tracer};
+use std::cell::{Ref, RefCell, RefMut};
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::mem::MaybeUninit;
+use std::rc::Rc;
 
-use super::{Opcode, RuntimeError};
+#[cfg(feature = "jit")]
+use super::jit::{Exit, Jit};
+use super::{FusedOperands, InterruptHandle, Opcode, RuntimeError};
 use crate::parser::VariableName;
-use crate::program::{JumpIndex, Program};
+use crate::program::{JumpIndex, Program, RegisterCode};
 use crate::value::{Block, KnString, List, ToBoolean, ToInteger, ToKnString, Value};
 use crate::{Environment, Error};
 
+mod checkpoint;
+mod heap;
+mod registers;
+
+pub use checkpoint::{CheckpointError, CHECKPOINT_MAGIC, CHECKPOINT_VERSION};
+pub use heap::{HeapEntry, HeapSnapshot, Retainer};
+
+// A call to a block which hasn't returned yet.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+	// Where to continue once the block returns: a bytecode offset for the stack-based vm, or an
+	// instruction index for the register-based one.
+	return_index: usize,
+
+	// The bytecode offset just after the `CALL` which created this frame.
+	#[cfg(feature = "stacktrace")]
+	callsite: usize,
+
+	// The amount of tail calls which have reused this frame.
+	#[cfg(feature = "stacktrace")]
+	tail_calls: usize,
+}
+
+// Where a call to `run` is up to. Calls which run out of fuel (or are interrupted) keep this around
+// to be resumed.
+#[derive(Debug, Clone, Copy)]
+struct Suspended {
+	// The amount of frames there were before `run` was called.
+	depth: usize,
+
+	// The length of the stack before `run` was called.
+	stack_len: usize,
+
+	// The instruction to continue at: a bytecode offset for the stack-based vm, or an instruction
+	// index for the register-based one.
+	index: usize,
+
+	// Where the current frame's temporaries start. Only used by the register-based vm.
+	base: usize,
+
+	// The `current_index` from before `run` was called.
+	caller_index: usize,
+}
+
+// Everything a vm has which the gc needs to mark. It's shared with the mark fn the vm registers,
+// which can't go through the vm itself, as it's mutably borrowed for as long as it's running.
+//
+// Collections happen whenever something's allocated, so it must never be borrowed across anything
+// which could allocate.
+struct Roots<'gc> {
+	stack: Vec<Value<'gc>>,
+
+	// The arguments the current instruction popped off the stack are moved here, so they're still
+	// marked in case the instruction allocates. It's always `Opcode::MAX_ARITY` long, and only the
+	// first `popped_args` are in use. (It's a `Vec` so pointers into it stay valid when `Roots` is
+	// borrowed again.)
+	args: Vec<Value<'gc>>,
+	popped_args: usize,
+
+	// When `check-variables` is enabled, unassigned variables are `Value::UNDEFINED`.
+	variables: Box<[Value<'gc>]>,
+
+	#[cfg(feature = "extensions")]
+	dynamic_variables: HashMap<VariableName<'static>, Value<'gc>>,
+}
+
+impl Roots<'_> {
+	unsafe fn mark(&self, tracer: &mut Tracer) {
+		for value in self.stack.iter().chain(&self.args[..self.popped_args]) {
+			unsafe {
+				value.mark(tracer);
+			}
+		}
+
+		// `Value::UNDEFINED` isn't allocated, so it's fine to mark.
+		for var in self.variables.iter() {
+			unsafe {
+				var.mark(tracer);
+			}
+		}
+
+		#[cfg(feature = "extensions")]
+		for value in self.dynamic_variables.values() {
+			unsafe {
+				value.mark(tracer);
+			}
+		}
+	}
+}
+
 pub struct Vm<'prog, 'src, 'path, 'env, 'gc> {
 	program: &'prog Program<'src, 'path, 'gc>,
 	env: &'env mut Environment<'gc>,
 	current_index: usize,
-	stack: Vec<Value<'gc>>,
+	roots: Rc<RefCell<Roots<'gc>>>,
 
-	#[cfg(feature = "check-variables")]
-	variables: Box<[Option<Value<'gc>>]>,
+	// Only set when running with the register-based vm.
+	registers: Option<Rc<RegisterCode>>,
 
-	#[cfg(not(feature = "check-variables"))]
-	variables: Box<[Value<'gc>]>,
+	// Only set when the jit is enabled, and the register-based vm isn't being used.
+	#[cfg(feature = "jit")]
+	jit: Option<Box<Jit>>,
 
-	#[cfg(feature = "stacktrace")]
-	callstack: Vec<usize>,
+	frames: Vec<Frame>,
+
+	// How many calls deep the `EVAL` which created this vm was, so that evaluated code can't get
+	// around the `max_call_depth`. It's zero for every other vm.
+	eval_depth: usize,
+
+	// The mark fn registered with the gc for as long as the vm exists, as its variables (and the
+	// stack of a suspended vm) have to stay alive between calls to `run`.
+	mark_fn: usize,
+
+	// Whether `execute` is running. Calls to `run` made while it is (by the instruction it's on)
+	// can't be resumed on their own.
+	running: bool,
+
+	// How many more backward jumps and calls can be run, or `None` if there's no limit.
+	fuel: Option<u64>,
+
+	// Checked alongside the fuel, to see whether the vm should stop.
+	interrupt: InterruptHandle,
+
+	// Set by the interpreters when they run out of fuel or are interrupted, to the instruction (and
+	// register base) that they stopped just before.
+	stopped_at: Option<(usize, usize)>,
+
+	// The call to `run` which ran out of fuel or was interrupted, if any.
+	suspended: Option<Suspended>,
 
 	#[cfg(feature = "stacktrace")]
 	known_blocks: HashMap<usize, VariableName<'src>>,
+}
 
-	#[cfg(feature = "extensions")]
-	dynamic_variables: HashMap<VariableName<'static>, Value<'gc>>,
+// Checks whether the vm's been interrupted and uses up a unit of `fuel`, returning the error to stop
+// with if it can't keep going. (It only borrows what it needs, so it can be used while the stack's
+// borrowed.)
+#[inline]
+fn burn_fuel(fuel: &mut Option<u64>, interrupt: &InterruptHandle) -> Option<Error> {
+	if interrupt.is_interrupted() {
+		return Some(Error::Interrupted);
+	}
+
+	match fuel {
+		None => None,
+		Some(0) => Some(Error::OutOfFuel),
+		Some(fuel) => {
+			*fuel -= 1;
+			None
+		}
+	}
+}
+
+// Registers `program` and `roots` with the gc, so that everything a vm uses is marked during
+// collections.
+fn register_mark_fn<'gc>(
+	program: &Program<'_, '_, 'gc>,
+	roots: &Rc<RefCell<Roots<'gc>>>,
+	env: &Environment<'gc>,
+) -> usize {
+	let program = (program as *const Program).cast::<()>();
+
+	// SAFETY: Only the lifetime changes. The mark fn is unregistered when the vm is dropped, so it's
+	// never called after the values in `roots` (or the program, which outlives the vm) are gone.
+	let roots = unsafe {
+		std::mem::transmute::<Rc<RefCell<Roots<'gc>>>, Rc<RefCell<Roots<'static>>>>(roots.clone())
+	};
+
+	env.gc().add_mark_fn(move |tracer| unsafe {
+		(*program.cast::<Program>()).mark(tracer);
+		roots.borrow().mark(tracer);
+	})
+}
+
+impl Drop for Vm<'_, '_, '_, '_, '_> {
+	fn drop(&mut self) {
+		self.env.gc().del_mark_fn(self.mark_fn);
+	}
 }
 
 impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 	pub fn new(program: &'prog Program<'src, 'path, 'gc>, env: &'env mut Environment<'gc>) -> Self {
-		Self {
-			program,
-			env,
-			current_index: 0,
+		// Programs which can't be translated just fall back to the stack-based vm.
+		let registers = env
+			.opts()
+			.register_vm
+			.then(|| RegisterCode::new(program, env.opts()).ok().map(Rc::new))
+			.flatten();
+
+		#[cfg(feature = "jit")]
+		let jit = (env.opts().jit && registers.is_none())
+			.then(|| Jit::new(program, env.opts()).map(Box::new))
+			.flatten();
+
+		let roots = Rc::new(RefCell::new(Roots {
 			stack: Vec::new(),
+			args: vec![Value::NULL; Opcode::MAX_ARITY],
+			popped_args: 0,
 
 			#[cfg(feature = "check-variables")]
-			variables: vec![None; program.num_variables()].into(),
+			variables: vec![Value::UNDEFINED; program.num_variables()].into(),
 
 			#[cfg(not(feature = "check-variables"))]
 			variables: vec![Value::NULL; program.num_variables()].into(),
 
-			#[cfg(feature = "stacktrace")]
-			callstack: Vec::new(),
+			#[cfg(feature = "extensions")]
+			dynamic_variables: HashMap::default(),
+		}));
+		let mark_fn = register_mark_fn(program, &roots, env);
+
+		Self {
+			program,
+			env,
+			current_index: 0,
+			roots,
+			registers,
+
+			#[cfg(feature = "jit")]
+			jit,
+
+			frames: Vec::new(),
+			eval_depth: 0,
+			mark_fn,
+			running: false,
+			fuel: None,
+			interrupt: InterruptHandle::new(),
+			stopped_at: None,
+			suspended: None,
 
 			#[cfg(feature = "stacktrace")]
 			known_blocks: HashMap::default(),
-
-			#[cfg(feature = "extensions")]
-			dynamic_variables: HashMap::default(),
 		}
 	}
 
-	pub unsafe fn mark(&self) {
+	/// Marks everything the vm uses: its program's constants, and every value it has.
+	///
+	/// # Safety
+	/// This must only be called by the gc the vm's environment uses, while it's collecting garbage.
+	pub unsafe fn mark(&self, tracer: &mut Tracer) {
 		unsafe {
-			self.program.mark();
-		}
-
-		for value in self.stack.iter() {
-			unsafe {
-				value.mark();
-			}
+			self.program.mark(tracer);
+			self.roots().mark(tracer);
 		}
+	}
 
-		for var in self.variables.iter() {
-			#[cfg(feature = "check-variables")]
-			if let Some(value) = var {
-				unsafe {
-					value.mark();
-				}
-			}
+	// Borrows the values the gc marks. This must not be held across anything that allocates.
+	#[inline]
+	fn roots(&self) -> Ref<'_, Roots<'gc>> {
+		self.roots.borrow()
+	}
 
-			#[cfg(not(feature = "check-variables"))]
-			unsafe {
-				var.mark();
-			}
-		}
+	// Mutably borrows the values the gc marks. This must not be held across anything that allocates.
+	#[inline]
+	fn roots_mut(&self) -> RefMut<'_, Roots<'gc>> {
+		self.roots.borrow_mut()
+	}
 
-		#[cfg(feature = "extensions")]
-		for value in self.dynamic_variables.values() {
-			unsafe {
-				value.mark();
-			}
-		}
+	#[inline]
+	fn push(&self, value: Value<'gc>) {
+		self.roots_mut().stack.push(value);
 	}
 
 	pub fn run_entire_program(
@@ -118,7 +305,7 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 			// SAFETY: if extensions are enabled, argv is always added, regardless of whether or not it
 			// was specified, so this is valid. Also, TODO: make sure `VALUE`, when implemented, fails
 			// for undefined variables on `argv` if argv isn't set
-			debug_assert_ne!(self.variables.len(), 0);
+			debug_assert_ne!(self.roots().variables.len(), 0);
 			unsafe {
 				self.set_variable(crate::program::Compiler::ARGV_VARIABLE_INDEX, argv);
 			}
@@ -133,41 +320,157 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 	}
 
 	pub fn run(&mut self, block: Block) -> crate::Result<Value<'gc>> {
+		assert!(self.suspended.is_none(), "the vm must be resumed before running anything else");
+
 		// Save previous index
-		let index = self.current_index;
+		let caller_index = self.current_index;
+
+		// Blocks are called without recursing, so returning from the frame pushed here is what
+		// finishes `block`.
+		let depth = self.frames.len();
+		self.push_frame(caller_index)?;
+
+		let stack_len = self.roots().stack.len();
+		let index = match &self.registers {
+			// The register-based vm keeps the block's temporaries on top of the stack.
+			Some(code) => {
+				self.roots_mut().stack.resize(stack_len + code.frame_size(), Value::NULL);
+				code.entry(block.inner())
+			}
+			None => block.inner().0,
+		};
 
-		#[cfg(feature = "stacktrace")]
-		self.callstack.push(self.current_index);
+		self.execute(Suspended { depth, stack_len, index, base: stack_len, caller_index })
+	}
+
+	/// Sets how much fuel the vm has, or removes the limit if `fuel` is `None`.
+	///
+	/// Every backward jump (i.e. each iteration of a loop) and every call uses up one unit of fuel.
+	/// Once there's none left, the vm stops with [`Error::OutOfFuel`], and can be refueled and then
+	/// continued via [`Vm::resume`]. Since compiled code can't be stopped partway through, the jit
+	/// isn't used while fuel is limited.
+	pub fn set_fuel(&mut self, fuel: Option<u64>) {
+		self.fuel = fuel;
+	}
+
+	/// Gets how much fuel the vm has left, or `None` if it's unlimited.
+	pub fn fuel(&self) -> Option<u64> {
+		self.fuel
+	}
+
+	/// Gives the vm `amount` more fuel. This does nothing if its fuel is unlimited.
+	pub fn refuel(&mut self, amount: u64) {
+		if let Some(fuel) = &mut self.fuel {
+			*fuel = fuel.saturating_add(amount);
+		}
+	}
+
+	/// Gets the handle which can be used to interrupt the vm, even from other threads.
+	///
+	/// Like running out of fuel, an interrupted vm stops (with [`Error::Interrupted`]) just before its
+	/// next backward jump or call, and can be continued via [`Vm::resume`] once the handle's been
+	/// [reset](InterruptHandle::reset). To stop programs which run for too long, use
+	/// [`InterruptHandle::interrupt_after`].
+	pub fn interrupt_handle(&self) -> InterruptHandle {
+		self.interrupt.clone()
+	}
+
+	/// Makes the vm use `handle` for interrupts instead of its own, so that more than one vm can be
+	/// interrupted at once.
+	pub fn set_interrupt_handle(&mut self, handle: InterruptHandle) {
+		self.interrupt = handle;
+	}
+
+	/// Whether the vm ran out of fuel (or was interrupted) partway through running something, and can
+	/// be resumed.
+	///
+	/// Stopping within `EVAL` can't be resumed, as the code being evaluated is discarded.
+	pub fn is_suspended(&self) -> bool {
+		self.suspended.is_some()
+	}
+
+	/// Continues running whatever ran out of fuel or was interrupted, returning its result.
+	///
+	/// # Panics
+	/// Panics if the vm isn't [suspended](Vm::is_suspended).
+	pub fn resume(&mut self) -> crate::Result<Value<'gc>> {
+		let suspended = self.suspended.take().expect("the vm isn't suspended");
+		self.execute(suspended)
+	}
+
+	// Runs from `at` until the frame that `run` pushed returns.
+	fn execute(&mut self, at: Suspended) -> crate::Result<Value<'gc>> {
+		let outermost = !std::mem::replace(&mut self.running, true);
 
-		// Used for debugging later
-		#[cfg(debug_assertions)]
-		let stack_len = self.stack.len();
+		// The instructions run here reuse `Roots::args`, so the instruction which called `run` can't
+		// use its arguments afterwards.
+		self.roots_mut().popped_args = 0;
 
 		// Actually call the function
-		self.current_index = block.inner().0;
-		let result = self.run_inner();
+		let result = match self.registers.clone() {
+			Some(code) => self.run_registers(&code, at.index, at.base, at.depth + 1),
+			None => {
+				self.current_index = at.index;
+				self.run_inner(at.depth + 1)
+			}
+		};
+
+		// Running out of fuel, or being interrupted, leaves everything where it was so that it can be
+		// resumed later. Only the outermost `run` can be resumed, as the others were called by the
+		// instruction it's on.
+		let result = match (result, self.stopped_at.take()) {
+			(Err(err @ (Error::OutOfFuel | Error::Interrupted)), Some((index, base))) if outermost => {
+				self.suspended = Some(Suspended { index, base, ..at });
+				Err(err)
+			}
+			(result, _) => self.finish(result, at),
+		};
 
+		self.current_index = at.caller_index;
+		self.roots_mut().popped_args = 0;
+		if outermost {
+			self.running = false;
+		}
+
+		result
+	}
+
+	// Cleans up after the call to `run` described by `at` finishes with `result`.
+	fn finish(
+		&mut self,
+		result: crate::Result<Value<'gc>>,
+		at: Suspended,
+	) -> crate::Result<Value<'gc>> {
 		// Add the stacktrace to the lsit
 		#[cfg(feature = "stacktrace")]
 		let result = match result {
 			Ok(ok) => Ok(ok),
 			Err(todo @ crate::Error::Stacktrace(_)) => Err(todo),
+			#[cfg(feature = "embedded")]
+			Err(exit @ crate::Error::Exit(_)) => Err(exit),
+			// Errors which embedders need to tell apart aren't turned into stacktraces.
+			Err(
+				err @ (Error::OutOfFuel
+				| Error::Interrupted
+				| Error::OutOfMemory(_)
+				| Error::StackDepthExceeded(_)
+				| Error::ReplayDiverged { .. }),
+			) => Err(err),
 			Err(err) => Err(crate::Error::Stacktrace(self.error(err).to_string())),
 		};
 
-		#[cfg(feature = "stacktrace")]
-		{
-			let result = self.callstack.pop();
-			debug_assert_eq!(result, Some(index));
-		}
+		// Errors can leave frames behind, so remove them along with ours.
+		debug_assert!(result.is_err() || self.frames.len() == at.depth + 1);
+		self.frames.truncate(at.depth);
 
-		#[cfg(debug_assertions)]
-		if result.is_ok() {
-			debug_assert_eq!(stack_len, self.stack.len(), "{:?}", result);
+		// The register-based vm's temporaries are on the stack, whereas the stack-based one should've
+		// popped everything it pushed.
+		if self.registers.is_some() {
+			self.roots_mut().stack.truncate(at.stack_len);
+		} else if result.is_ok() {
+			debug_assert_eq!(at.stack_len, self.roots().stack.len(), "{:?}", result);
 		}
 
-		self.current_index = index;
-
 		result
 	}
 
@@ -184,9 +487,10 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 	pub fn stacktrace(&self) -> crate::vm::stacktrace::Stacktrace<'_, 'path> {
 		use crate::vm::stacktrace::{Callsite, Stacktrace};
 
-		Stacktrace::new(self.callstack.iter().map(|&idx| {
-			let loc = self.program.source_location_at(idx);
-			Callsite::new(self.block_name_at(idx), loc)
+		Stacktrace::new(self.frames.iter().map(|frame| {
+			let loc = self.program.source_location_at(frame.callsite);
+			Callsite::new(self.block_name_at(frame.callsite), loc)
+				.with_elided_tail_calls(frame.tail_calls)
 		}))
 	}
 
@@ -203,68 +507,89 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 		None
 	}
 
+	// Runs instructions until the frame at `depth` returns.
 	#[no_mangle]
-	fn run_inner(&mut self) -> crate::Result<Value<'gc>> {
-		#[cfg(not(feature = "stacktrace"))]
-		let mut jumpstack = Vec::new();
-
+	fn run_inner(&mut self, depth: usize) -> crate::Result<Value<'gc>> {
 		loop {
 			// SAFETY: all programs are well-formed, so we know the current index is in bounds.
-			let (opcode, offset) = unsafe { self.program.opcode_at(self.current_index) };
+			let start = self.current_index;
+			let (opcode, offset) = unsafe { self.program.opcode_at(start) };
 			// println!("[{:3?}:{opcode:08?}] {:?} ({:?})", self.current_index, offset, self.stack);
 			// println!("{opcode:?}");
-			self.current_index += 1;
-
-			// Pop the arguments off the stack. The remaining arguments are in `spare_capacity_mut`.
-			// This does mean that we cannot modify `self.stack` until we've interacted with all the
-			// individual arguments.
-			debug_assert!(opcode.arity() <= self.stack.len());
-			unsafe { self.stack.set_len(self.stack.len() - opcode.arity()) };
-			let args = self.stack.spare_capacity_mut();
+			self.current_index += opcode.encoded_len();
+
+			// Pop the arguments off the stack and into `Roots::args` (where they're marked until the
+			// next instruction), and read them from there via `args`.
+			let arity = opcode.arity();
+			let args = {
+				let mut roots = self.roots_mut();
+				let Roots { stack, args, popped_args, .. } = &mut *roots;
+				let len = stack.len();
+				debug_assert!(arity <= len && arity <= Opcode::MAX_ARITY);
+
+				// SAFETY: programs are well-formed, so there's always at least `arity` values on the
+				// stack, and no opcode takes more than `MAX_ARITY` arguments.
+				unsafe {
+					std::ptr::copy_nonoverlapping(
+						stack.as_ptr().add(len - arity),
+						args.as_mut_ptr(),
+						arity,
+					);
+					stack.set_len(len - arity);
+				}
+				*popped_args = arity;
+				args.as_ptr()
+			};
 
 			// Get the last argument on the stack. Requires an `unsafe` block in case the stack is
 			// empty for some reason.
 			macro_rules! last {
 				() => {{
-					debug_assert_ne!(self.stack.len(), 0);
-					*self.stack.last().unwrap_unchecked()
+					let roots = self.roots();
+					debug_assert_ne!(roots.stack.len(), 0);
+					*roots.stack.last().unwrap_unchecked()
 				}};
 			}
 
-			// Gets an argument from the argument stack
+			// Gets an argument that was popped off the stack. It must be read before any blocks are
+			// run, as they reuse `args`.
 			macro_rules! arg {
-				(&$idx:expr) => {{
-					let idx = $idx;
-
-					debug_assert!(idx < opcode.arity());
-					// realistically shouldnt ever happen as args is also the values past the end too
-					debug_assert!(idx <= args.len());
-
-					args.get_unchecked(idx).assume_init_ref()
-				}};
-
 				($idx:expr) => {{
 					let idx = $idx;
+					debug_assert!(idx < arity);
+					args.add(idx).read()
+				}};
+			}
 
-					debug_assert!(idx < opcode.arity());
-					// realistically shouldnt ever happen as args is also the values past the end too
-					debug_assert!(idx <= args.len());
-
-					args.get_unchecked(idx).assume_init_read()
+			// Pushes the result of an instruction which popped at least one argument, so there's
+			// always room for it.
+			macro_rules! push_no_resize {
+				($value:expr) => {{
+					let value = $value;
+					let mut roots = self.roots_mut();
+					let len = roots.stack.len();
+					debug_assert_ne!(len, roots.stack.capacity());
+					roots.stack.as_mut_ptr().add(len).write(value);
+					roots.stack.set_len(len + 1);
 				}};
 			}
 
-			macro_rules! end {
-				() => {
-					args.get_unchecked_mut(0)
-				};
+			// Runs `$op` with `$target` as the place to write its result, then pushes the result.
+			macro_rules! push_result {
+				(|$target:ident| $op:expr) => {{
+					let mut $target = MaybeUninit::uninit();
+					$op;
+					push_no_resize!($target.assume_init());
+				}};
 			}
 
-			macro_rules! push_no_resize {
-				($value:expr) => {
-					args.get_unchecked_mut(0).write($value);
-					debug_assert_ne!(self.stack.len(), self.stack.capacity());
-					self.stack.set_len(self.stack.len() + 1);
+			// Uses up a unit of fuel. If there's none left (or the vm's been interrupted), then the vm
+			// stops just before the current instruction, which must not have done anything yet.
+			macro_rules! burn_fuel {
+				() => {
+					if let Some(err) = burn_fuel(&mut self.fuel, &self.interrupt) {
+						return Err(self.stop_before(start, arity, err));
+					}
 				};
 			}
 
@@ -272,17 +597,30 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 				// Builtins
 
 				// No need for a "target", as `self.program` is always GC'd.
-				Opcode::PushConstant => self.stack.push(unsafe { self.program.constant_at(offset) }),
+				Opcode::PushConstant => self.push(unsafe { self.program.constant_at(offset) }),
 
 				// SAFETY: program is well-defined, so jumps are always correct
-				Opcode::Jump => unsafe { self.jump_to(offset) },
+				Opcode::Jump => unsafe {
+					if offset < start {
+						burn_fuel!();
+					}
+					self.jump_to(offset)
+				},
 				Opcode::JumpIfTrue => {
+					if offset < start {
+						unsafe { burn_fuel!() };
+					}
+
 					if unsafe { arg![0] }.to_boolean(self.env)? {
 						// SAFETY: program is well-defined, so jumps are always correct
 						unsafe { self.jump_to(offset) };
 					}
 				}
 				Opcode::JumpIfFalse => {
+					if offset < start {
+						unsafe { burn_fuel!() };
+					}
+
 					if !unsafe { arg![0] }.to_boolean(self.env)? {
 						// SAFETY: program is well-defined, so jumps are always correct
 						unsafe { self.jump_to(offset) }
@@ -291,8 +629,7 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 
 				Opcode::GetVar => {
 					let value = unsafe { self.get_variable(offset) }?;
-					// gotta push, in case the stack isn't large enough
-					self.stack.push(value);
+					self.push(value);
 				}
 
 				Opcode::SetVar => {
@@ -309,85 +646,145 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 
 				#[cfg(feature = "extensions")]
 				Opcode::SetDynamicVar => {
-					let value = unsafe { arg![1] }; // read in case `.to_kstring` in the next line modifies args
-					let name = unsafe { arg![0] }.to_knstring(self.env)?;
-					let varname = VariableName::new(&name, self.env.opts())
-						.map_err(|err| crate::Error::Todo(err.to_string()))?;
-
-					// If it already exists, then just use that
-					if let Some(index) = self.program.variable_index(&varname) {
-						unsafe {
-							self.set_variable(index, value.clone());
-						}
-					} else {
-						// check for compliance, even with the extension
-						#[cfg(feature = "compliance")]
-						if self.env.opts().compliance.variable_count
-							&& self.dynamic_variables.len() + self.program.num_variables()
-								> super::MAX_VARIABLE_COUNT
-						{
-							return Err(crate::Error::Todo(format!(
-								"too many variables encountered (only {} allowed)",
-								super::MAX_VARIABLE_COUNT
-							)));
-						}
+					let value = unsafe { arg![1] };
+					let name = unsafe { arg![0] };
+					self.set_dynamic_variable(name, value)?;
+					self.push(value);
+				}
 
-						self.dynamic_variables.insert(varname.become_owned(), value.clone());
+				Opcode::SetVarPop => {
+					// SAFETY: `SetVarPop` has an arity of one, so its value was just popped off.
+					let value = unsafe { arg![0] };
+
+					// SAFETY: construction of `Program`s guarantees that `SetVarPop` will have an
+					// offset, and that it's a a valid variable index.
+					unsafe {
+						self.set_variable(offset, value);
 					}
+					continue;
+				}
+
+				// Superinstructions
+				Opcode::AddVarConst | Opcode::SubVarConst => {
+					let FusedOperands { variable, constant, .. } = FusedOperands::unpack(offset);
 
-					// TODO: Can this be replaced with an `&mut MaybeUninit`?
-					self.stack.push(value);
+					// SAFETY: construction of `Program`s guarantees that `variable` and `constant` are
+					// valid.
+					unsafe {
+						let value = self.get_variable(variable)?;
+						let rhs = self.program.constant_at(constant);
+						let mut result = MaybeUninit::uninit();
+						if opcode == Opcode::AddVarConst {
+							value.kn_plus(&rhs, &mut result, self.env)?;
+						} else {
+							value.kn_minus(&rhs, &mut result, self.env)?;
+						}
+						self.set_variable(variable, result.assume_init());
+					}
+					continue;
 				}
+				Opcode::BranchVarLth | Opcode::BranchVarGth | Opcode::BranchVarEql => {
+					let FusedOperands { variable, constant, target, jump_if } =
+						FusedOperands::unpack(offset);
+					if target < start {
+						unsafe { burn_fuel!() };
+					}
+
+					// SAFETY: construction of `Program`s guarantees that `variable` and `constant` are
+					// valid.
+					let (value, rhs) =
+						unsafe { (self.get_variable(variable)?, self.program.constant_at(constant)) };
+					let result = match opcode {
+						Opcode::BranchVarLth => value.kn_compare(&rhs, "<", self.env)? == Ordering::Less,
+						Opcode::BranchVarGth => {
+							value.kn_compare(&rhs, ">", self.env)? == Ordering::Greater
+						}
+						_ => value.kn_equals(&rhs, self.env)?,
+					};
 
-				Opcode::SetVarPop => todo!(), //self.variables[offset] = unsafe{arg![0]}.clone(),
+					if result == jump_if {
+						// SAFETY: program is well-defined, so jumps are always correct
+						unsafe { self.jump_to(target) }
+					}
+					continue;
+				}
 
 				// Arity 0
 				Opcode::Prompt => {
 					if let Some(prompted) = self.env.prompt()? {
-						unsafe { prompted.with_inner(|inner| self.stack.push(inner.into())) }
+						unsafe { prompted.with_inner(|inner| self.push(inner.into())) }
 					} else {
-						self.stack.push(Value::NULL);
+						self.push(Value::NULL);
 					}
 				}
-				Opcode::Random => self.stack.push(self.env.random()?.into()),
+				Opcode::Random => {
+					let random = self.env.random()?;
+					self.push(random.into());
+				}
 
-				Opcode::Dup => self.stack.push(unsafe { last!() }),
+				Opcode::Dup => self.push(unsafe { last!() }),
 
 				// SAFETY: `function.rs` special-cases `DUMP` to ensure it has something, even tho
 				// its arity is 0
 				Opcode::Dump => unsafe { last!() }.kn_dump(self.env)?,
 
 				// Arity 1
-				#[cfg(feature = "stacktrace")]
-				Opcode::Return => return Ok(unsafe { arg![0] }),
-
-				#[cfg(not(feature = "stacktrace"))]
 				Opcode::Return => {
-					// There's somewhere to jump to, go there.
-					if let Some(ip) = jumpstack.pop() {
-						likely_stable::likely(true);
-						unsafe { self.jump_to(ip) };
-					} else {
-						// There's nowhere to jump to, return the block of code.
-						debug_assert_eq!(self.stack.len(), 1, "should only have one value at the end");
+					#[cfg(feature = "stacktrace")]
+					let value = unsafe { arg![0] };
+
+					#[cfg(not(feature = "stacktrace"))]
+					let value = self.roots_mut().stack.pop().unwrap_or_else(|| bug!("pop when nothing left"));
 
-						return Ok(self.stack.pop().unwrap_or_else(|| bug!("pop when nothing left")));
+					// There's nowhere to return to, so return the value of the block `run` was given.
+					if self.frames.len() == depth {
+						return Ok(value);
 					}
+
+					// Leave the value on the stack for the caller.
+					self.push(value);
+					let frame = self.frames.pop().unwrap_or_else(|| bug!("returned without a frame"));
+					unsafe { self.jump_to(frame.return_index) };
 				}
 
 				Opcode::Call => {
+					unsafe { burn_fuel!() };
 					let arg = unsafe { arg![0] };
 
-					#[cfg(not(feature = "stacktrace"))]
 					if let Some(block) = arg.as_block() {
-						likely_stable::likely(true);
-						jumpstack.push(self.current_index);
+						// If the `CALL` is in tail position, then the block can reuse the current frame,
+						// as it'll return directly to whatever called us.
+						let is_tail_call = self.is_tail_call();
+						if is_tail_call {
+							self.elide_frame();
+						} else {
+							self.push_frame(self.current_index)?;
+						}
+
+						// Compiled blocks return directly to us, so there's no need for their frame. If
+						// they bail out, then the interpreter picks up where they left off.
+						#[cfg(feature = "jit")]
+						match self.run_jit(block.inner()) {
+							Some(Ok(value)) => {
+								if !is_tail_call {
+									self.frames.pop();
+								}
+								self.push(value);
+								continue;
+							}
+							Some(Err(offset)) => {
+								unsafe { self.jump_to(offset) };
+								continue;
+							}
+							None => {}
+						}
+
 						unsafe { self.jump_to(block.inner().0) };
 						continue;
 					}
 
 					let value = arg.kn_call(self)?;
-					self.stack.push(value); // TODO: can we use `push_no_resize`?
+					self.push(value);
 				}
 
 				Opcode::Quit => {
@@ -396,211 +793,338 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 				}
 
 				Opcode::Output => {
-					use std::io::Write;
-					let kstring = unsafe { arg![0] }.to_knstring(self.env)?;
-					let strref = kstring.as_str();
-
-					let mut output = self.env.output();
-
-					if let Some(stripped) = strref.strip_suffix('\\') {
-						write!(output, "{stripped}")
-					} else {
-						writeln!(output, "{strref}")
-					}
-					.map_err(|err| Error::IoError { func: "OUTPUT", err })?;
-					let _ = output.flush(); // explicitly ignore errors with flushing
-
-					// SAFETY: `Output` is guaranteed to be given an argument. We've also already
-					// read from it.
-					unsafe {
-						push_no_resize!(Value::NULL);
-					}
+					let value = unsafe { arg![0] };
+					self.output(value)?;
+					unsafe { push_no_resize!(Value::NULL) };
 				}
 				Opcode::Length => {
 					let value = unsafe { arg![0] }.kn_length(self.env)?.into();
-					unsafe {
-						push_no_resize!(value);
-					}
+					unsafe { push_no_resize!(value) };
 				}
-				Opcode::Not => unsafe {
-					// TODO: should `kn_not` even exist?
-					arg![0].kn_not(end!(), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
-				},
+				// TODO: should `kn_not` even exist?
+				Opcode::Not => unsafe { push_result!(|result| arg![0].kn_not(&mut result, self.env)?) },
 				Opcode::Negate => unsafe {
-					arg![0].kn_negate(end!(), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_negate(&mut result, self.env)?)
 				},
 				Opcode::Ascii => unsafe {
-					arg![0].kn_ascii(end!(), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_ascii(&mut result, self.env)?)
 				},
 				Opcode::Box => {
-					let boxed = List::boxed(unsafe { arg![0] }, self.env.gc());
-
-					unsafe {
-						boxed.with_inner(|inner| end!().write(inner.into()));
-						self.stack.set_len(self.stack.len() + 1);
-					}
+					let boxed = List::boxed(unsafe { arg![0] }, self.env.gc())?;
+					unsafe { boxed.with_inner(|inner| push_no_resize!(inner.into())) };
 				}
 				Opcode::Head => unsafe {
-					arg![0].kn_head(end!(), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_head(&mut result, self.env)?)
 				},
 				Opcode::Tail => unsafe {
-					arg![0].kn_tail(end!(), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_tail(&mut result, self.env)?)
 				},
 				Opcode::Pop => continue, /* do nothing, the arity already popped */
 
 				Opcode::Add => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_plus(&rhs, &mut start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_plus(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Sub => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_minus(&rhs, start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_minus(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Mul => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_asterisk(&rhs, start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_asterisk(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Div => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_slash(&rhs, start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_slash(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Mod => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_percent(&rhs, start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_percent(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Pow => unsafe {
-					let (start, rest) = args.split_at_mut_unchecked(1);
-					let value = start.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let rhs = rest.get_unchecked(0).assume_init_read();
-					value.kn_caret(&rhs, start.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_caret(&arg![1], &mut result, self.env)?)
 				},
 				Opcode::Lth => {
 					let value = (unsafe { arg![0] }.kn_compare(&unsafe { arg![1] }, "<", self.env)?
 						== Ordering::Less)
 						.into();
-					unsafe {
-						push_no_resize!(value);
-					}
+					unsafe { push_no_resize!(value) };
 				}
 				Opcode::Gth => {
 					let value = (unsafe { arg![0] }.kn_compare(&unsafe { arg![1] }, ">", self.env)?
 						== Ordering::Greater)
 						.into();
-					unsafe {
-						push_no_resize!(value);
-					}
+					unsafe { push_no_resize!(value) };
 				}
 
 				Opcode::Eql => {
 					let value = (unsafe { arg![0] }.kn_equals(&unsafe { arg![1] }, self.env)?).into();
-					unsafe {
-						push_no_resize!(value);
-					}
+					unsafe { push_no_resize!(value) };
 				}
 
 				Opcode::Get => unsafe {
-					let (first, rest) = args.split_at_mut_unchecked(1);
-					let value = first.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let start = rest.get_unchecked(0).assume_init_read();
-					let length = rest.get_unchecked(1).assume_init_read();
-					value.kn_get(&start, &length, first.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| arg![0].kn_get(&arg![1], &arg![2], &mut result, self.env)?)
 				},
 
 				Opcode::Set => unsafe {
-					let (first, rest) = args.split_at_mut_unchecked(1);
-					let value = first.get_unchecked(0).assume_init_read(); // read it so we can target it with `kn_plus`
-					let start = rest.get_unchecked(0).assume_init_read();
-					let length = rest.get_unchecked(1).assume_init_read();
-					let repl = rest.get_unchecked(2).assume_init_read();
-					value.kn_set(&start, &length, &repl, first.get_unchecked_mut(0), self.env)?;
-					self.stack.set_len(self.stack.len() + 1);
+					push_result!(|result| {
+						arg![0].kn_set(&arg![1], &arg![2], &arg![3], &mut result, self.env)?
+					})
 				},
 
 				// EXTENSIONS
 				#[cfg(feature = "extensions")]
-				Opcode::AssignDynamic => match offset {
-					_ if offset == super::opcode::DynamicAssignment::Random as _ => {
-						let seed = unsafe { last!() }.to_integer(self.env)?;
-						self.env.seed_random(seed);
-					}
-					_ => todo!("{:?}", offset),
-				},
+				Opcode::AssignDynamic => {
+					let value = unsafe { last!() };
+					self.assign_dynamic(offset, value)?;
+				}
 
-				// TODO: the `vm` evals in its entirely own vm, which isnt what we wnat
 				#[cfg(feature = "extensions")]
 				Opcode::Eval => {
-					let program = unsafe { arg![0] }.to_knstring(self.env)?;
-					let parser = crate::parser::Parser::new(
-						&mut self.env,
-						crate::parser::source_location::ProgramSource::Eval,
-						program.as_str(),
-					)?;
-					let program = parser.parse_program()?;
-					let value = Vm::new(&program, self.env).run_entire_program_without_argv()?;
-					unsafe {
-						push_no_resize!(value);
-					}
+					let source = unsafe { arg![0] };
+					let value = self.eval(source)?;
+					self.push(value);
 				}
 
 				#[cfg(feature = "extensions")]
 				Opcode::System => {
-					let cmd = unsafe { arg![0] }.to_knstring(self.env)?;
-					let stdout = self.env.system(&cmd)?;
-
-					// TODO: is this actually safe?
-					unsafe {
-						let output = Value::from(stdout.assume_used());
-						push_no_resize!(output);
-					}
+					let command = unsafe { arg![0] };
+					let output = self.system(command)?;
+					self.push(output);
 				}
 
 				#[cfg(feature = "extensions")]
 				Opcode::Value => {
-					let variable_name = unsafe { arg![0] }.to_knstring(self.env)?;
+					let name = unsafe { arg![0] };
+					let value = self.value_of(name)?;
+					self.push(value);
+				}
+			}
+		}
+	}
 
-					let varname = VariableName::new(&variable_name, self.env.opts())
-						.map_err(|err| crate::Error::Todo(err.to_string()))?;
+	// Writes `value` to the output, as `OUTPUT` does.
+	fn output(&mut self, value: Value<'gc>) -> crate::Result<()> {
+		use std::io::Write;
+		let kstring = value.to_knstring(self.env)?;
+		let strref = kstring.as_str();
 
-					let value = if let Some(compiletime_variable_offset) =
-						self.program.variable_index(&varname)
-					{
-						// SAFETY: `variable_index` ensures it always returns a valid index., i think
-						unsafe { self.get_variable(compiletime_variable_offset)? }
-					} else {
-						self
-							.dynamic_variables
-							.get(&varname)
-							.ok_or_else(|| crate::Error::UndefinedVariable(varname.become_owned()))?
-							.clone()
-					};
-					self.stack.push(value);
-				}
+		let mut output = self.env.output();
+
+		if let Some(stripped) = strref.strip_suffix('\\') {
+			write!(output, "{stripped}")
+		} else {
+			writeln!(output, "{strref}")
+		}
+		.map_err(|err| Error::IoError { func: "OUTPUT", err })?;
+		let _ = output.flush(); // explicitly ignore errors with flushing
+		Ok(())
+	}
+
+	// Assigns `value` to the variable named `name`, which isn't necessarily known at compile-time.
+	#[cfg(feature = "extensions")]
+	fn set_dynamic_variable(&mut self, name: Value<'gc>, value: Value<'gc>) -> crate::Result<()> {
+		let name = name.to_knstring(self.env)?;
+		let varname = VariableName::new(&name, self.env.opts())
+			.map_err(|err| crate::Error::Todo(err.to_string()))?;
+
+		// If it already exists, then just use that
+		if let Some(index) = self.program.variable_index(&varname) {
+			unsafe {
+				self.set_variable(index, value);
+			}
+		} else {
+			// check for compliance, even with the extension
+			#[cfg(feature = "compliance")]
+			if self.env.opts().compliance.variable_count
+				&& self.roots().dynamic_variables.len() + self.program.num_variables()
+					> super::MAX_VARIABLE_COUNT
+			{
+				return Err(crate::Error::Todo(format!(
+					"too many variables encountered (only {} allowed)",
+					super::MAX_VARIABLE_COUNT
+				)));
+			}
+
+			self.roots_mut().dynamic_variables.insert(varname.become_owned(), value);
+		}
+
+		Ok(())
+	}
+
+	// Runs `AssignDynamic` with the offset `kind`.
+	#[cfg(feature = "extensions")]
+	fn assign_dynamic(&mut self, kind: usize, value: Value<'gc>) -> crate::Result<()> {
+		match kind {
+			_ if kind == super::opcode::DynamicAssignment::Random as _ => {
+				let seed = value.to_integer(self.env)?;
+				self.env.seed_random(seed);
+			}
+			// The verifier rejects every other kind.
+			_ => bug!("unsupported dynamic assignment {}", kind),
+		}
+
+		Ok(())
+	}
+
+	// Evaluates `source` in a new vm, which shares the call depth, fuel, and interrupt handle with
+	// this one. (`EVAL` counts as a call itself.)
+	#[cfg(feature = "extensions")]
+	fn eval(&mut self, source: Value<'gc>) -> crate::Result<Value<'gc>> {
+		// The parsed program borrows from the source, so it's rooted until the evaluated code is done
+		// running. (Strings that are already on the heap aren't rooted by `to_knstring`.)
+		let source = source.to_knstring(self.env)?;
+		let source = crate::gc::GcRoot::new(&*source, self.env.gc());
+
+		// Nothing's rooted while parsing, so the gc has to be paused.
+		let gc = self.env.gc();
+		gc.pause();
+		let parsed = crate::parser::Parser::new(
+			self.env,
+			crate::parser::source_location::ProgramSource::Eval,
+			source.as_str(),
+		)
+		.map_err(crate::Error::from)
+		.and_then(|parser| Ok(parser.parse_program()?));
+		gc.unpause();
+
+		let program = parsed?;
+
+		// The evaluated code uses up the same fuel, and is interrupted along with, everything else.
+		let mut vm = Vm::new(&program, self.env);
+		vm.eval_depth = self.eval_depth + self.frames.len();
+		vm.set_fuel(self.fuel);
+		vm.set_interrupt_handle(self.interrupt.clone());
+		let result = vm.run_entire_program_without_argv();
+		self.fuel = vm.fuel();
+		result
+	}
+
+	#[cfg(feature = "extensions")]
+	fn system(&mut self, command: Value<'gc>) -> crate::Result<Value<'gc>> {
+		let cmd = command.to_knstring(self.env)?;
+		let stdout = self.env.system(&cmd)?;
+
+		// TODO: is this actually safe?
+		Ok(unsafe { Value::from(stdout.assume_used()) })
+	}
+
+	// Gets the value of the variable named `name`, as `VALUE` does.
+	#[cfg(feature = "extensions")]
+	fn value_of(&mut self, name: Value<'gc>) -> crate::Result<Value<'gc>> {
+		let variable_name = name.to_knstring(self.env)?;
+
+		let varname = VariableName::new(&variable_name, self.env.opts())
+			.map_err(|err| crate::Error::Todo(err.to_string()))?;
+
+		if let Some(compiletime_variable_offset) = self.program.variable_index(&varname) {
+			// SAFETY: `variable_index` ensures it always returns a valid index., i think
+			unsafe { self.get_variable(compiletime_variable_offset) }
+		} else {
+			Ok(self
+				.roots()
+				.dynamic_variables
+				.get(&varname)
+				.copied()
+				.ok_or_else(|| crate::Error::UndefinedVariable(varname.become_owned()))?)
+		}
+	}
+
+	// Runs `block` natively if it's been compiled, returning either its result or the offset to
+	// continue at if it bailed out. `None` is returned if `block` isn't compiled.
+	#[cfg(feature = "jit")]
+	fn run_jit(&mut self, block: JumpIndex) -> Option<Result<Value<'gc>, usize>> {
+		// Compiled code can't be stopped partway through, so it can't be run with limited fuel.
+		if self.fuel.is_some() {
+			return None;
+		}
+
+		let compiled = self.jit.as_mut()?.called(self.program, block)?;
+
+		// Compiled code never allocates, so the roots can stay borrowed while it runs.
+		let mut roots = self.roots.borrow_mut();
+		let roots = &mut *roots;
+		roots.stack.reserve(compiled.max_depth());
+		let len = roots.stack.len();
+
+		// SAFETY: `compiled` was compiled for `self.program`, and there's room for `max_depth` more
+		// values on the stack.
+		let exit = unsafe {
+			compiled.call(
+				roots.variables.as_mut_ptr(),
+				roots.stack.as_mut_ptr().add(len),
+				&self.interrupt,
+			)
+		};
+
+		match exit {
+			// SAFETY: compiled code only ever returns values it got from the program or its
+			// variables, or integers and booleans it created.
+			Exit::Returned(repr) => Some(Ok(unsafe { Value::from_val(repr) })),
+			Exit::BailedOut { offset, depth } => {
+				// SAFETY: compiled code writes `depth` values to the stack before bailing out.
+				unsafe { roots.stack.set_len(len + depth) };
+				Some(Err(offset))
 			}
 		}
 	}
 
+	// Stops the stack-based vm just before the instruction at `start`, as it's out of fuel or was
+	// interrupted, returning `err`.
+	//
+	// SAFETY: The instruction must've popped `arity` arguments, and not done anything else.
+	#[cold]
+	unsafe fn stop_before(&mut self, start: usize, arity: usize, err: Error) -> Error {
+		let mut roots = self.roots_mut();
+		let Roots { stack, args, popped_args, .. } = &mut *roots;
+		stack.extend_from_slice(&args[..arity]);
+		*popped_args = 0;
+		drop(roots);
+		self.current_index = start;
+		self.stopped_at = Some((start, 0));
+		err
+	}
+
+	// Whether the instruction after the current one returns (possibly after jumping, like the end of
+	// an `IF`'s branch does), i.e. a `CALL` that was just run is in tail position.
+	#[inline]
+	fn is_tail_call(&self) -> bool {
+		// SAFETY: `Call` is never the last instruction in a program, as blocks always end in `Return`,
+		// and programs are well-formed so jumps are always to valid instructions.
+		unsafe {
+			match self.program.opcode_at(self.current_index) {
+				(Opcode::Return, _) => true,
+				(Opcode::Jump, target) => self.program.opcode_at(target).0 == Opcode::Return,
+				_ => false,
+			}
+		}
+	}
+
+	// Records that a tail call reused the current frame, for stacktraces.
+	#[inline]
+	fn elide_frame(&mut self) {
+		#[cfg(feature = "stacktrace")]
+		if let Some(frame) = self.frames.last_mut() {
+			frame.tail_calls += 1;
+		}
+	}
+
+	// Starts a new call, which continues at `return_index` when it returns.
+	#[inline]
+	fn push_frame(&mut self, return_index: usize) -> crate::Result<()> {
+		// The frame `run` pushes isn't a call, so it doesn't count towards the depth.
+		if let Some(max) = self.env.opts().max_call_depth {
+			if self.eval_depth + self.frames.len() > max {
+				return Err(Error::StackDepthExceeded(max));
+			}
+		}
+
+		self.frames.push(Frame {
+			return_index,
+			#[cfg(feature = "stacktrace")]
+			callsite: self.current_index,
+			#[cfg(feature = "stacktrace")]
+			tail_calls: 0,
+		});
+
+		Ok(())
+	}
+
 	// SAFETY: offset must be a valid place to jump to
 	unsafe fn jump_to(&mut self, offset: usize) {
 		self.current_index = offset
@@ -608,28 +1132,28 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 
 	// SAFETY: the `offset` must be a valid variable offset
 	unsafe fn get_variable(&mut self, offset: usize) -> crate::Result<Value<'gc>> {
-		debug_assert!(offset <= self.variables.len());
-
-		let value = *unsafe { self.variables.get_unchecked(offset) };
+		let value = {
+			let roots = self.roots();
+			debug_assert!(offset <= roots.variables.len());
+			*unsafe { roots.variables.get_unchecked(offset) }
+		};
 
 		#[cfg(feature = "check-variables")]
-		let value = if !self.env.opts().check_variables {
-			value.unwrap_or_default()
-		} else {
-			value.ok_or_else(|| {
-				crate::Error::UndefinedVariable(
-					self.program.variable_name(offset).clone().become_owned(),
-				)
-			})?
-		};
+		if value.repr() == Value::UNDEFINED.repr() {
+			if !self.env.opts().check_variables {
+				return Ok(Value::NULL);
+			}
+
+			return Err(crate::Error::UndefinedVariable(
+				self.program.variable_name(offset).clone().become_owned(),
+			));
+		}
 
 		Ok(value)
 	}
 
 	// SAFETY: the `offset` must be a valid variable offset
 	unsafe fn set_variable(&mut self, offset: usize, value: Value<'gc>) {
-		debug_assert!(offset <= self.variables.len());
-
 		// TODO: rework how stacktraces work
 		#[cfg(feature = "stacktrace")]
 		if let Some(ref block) = value.as_block() {
@@ -637,9 +1161,8 @@ impl<'prog, 'src, 'path, 'env, 'gc> Vm<'prog, 'src, 'path, 'env, 'gc> {
 			self.known_blocks.insert(block.inner().0, varname.clone());
 		}
 
-		#[cfg(feature = "check-variables")]
-		let value = Some(value);
-
-		*unsafe { self.variables.get_unchecked_mut(offset) } = value
+		let mut roots = self.roots_mut();
+		debug_assert!(offset <= roots.variables.len());
+		*unsafe { roots.variables.get_unchecked_mut(offset) } = value
 	}
 }
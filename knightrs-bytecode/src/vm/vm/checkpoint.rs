@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::io::{self, Write};
+
+use super::{Frame, Suspended, Vm};
+use crate::env::{RngPosition, MAX_GENERATED};
+use crate::gc::ValueInner;
+use crate::program::{DeserializeError, Fnv1a, JumpIndex, Reader, Writer};
+use crate::value::{Block, Integer, KnString, List, Value};
+
+/// The bytes every [checkpoint](Vm::checkpoint) starts with.
+pub const CHECKPOINT_MAGIC: [u8; 4] = *b"KNV\0";
+
+/// The current version of the checkpoint format.
+///
+/// This is bumped whenever the layout of checkpoints changes, and checkpoints made with a different
+/// version will be rejected by [`Vm::restore`].
+pub const CHECKPOINT_VERSION: u16 = 1;
+
+// Features which change what's in a vm, and so must match between the vm that made the checkpoint
+// and the vm that's restoring it.
+const FEATURE_STACKTRACE: u8 = 1 << 0;
+const FEATURE_EXTENSIONS: u8 = 1 << 1;
+const FEATURE_CHECK_VARIABLES: u8 = 1 << 2;
+
+const VM_FEATURES: u8 = {
+	let mut features = 0;
+	if cfg!(feature = "stacktrace") {
+		features |= FEATURE_STACKTRACE;
+	}
+	if cfg!(feature = "extensions") {
+		features |= FEATURE_EXTENSIONS;
+	}
+	if cfg!(feature = "check-variables") {
+		features |= FEATURE_CHECK_VARIABLES;
+	}
+	features
+};
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_BLOCK: u8 = 4;
+const TAG_UNDEFINED: u8 = 5;
+const TAG_HEAP: u8 = 6;
+
+const HEAP_STRING: u8 = 0;
+const HEAP_LIST: u8 = 1;
+
+/// Problems that can occur when [restoring](Vm::restore) a checkpoint.
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+	/// The input didn't start with [`CHECKPOINT_MAGIC`], so it's not a checkpoint.
+	#[error("not a knight vm checkpoint")]
+	BadMagic,
+
+	/// The checkpoint was made with a different [`CHECKPOINT_VERSION`].
+	#[error("unsupported checkpoint version {0} (expected {CHECKPOINT_VERSION})")]
+	UnsupportedVersion(u16),
+
+	/// The checkpoint was made with a different set of vm-relevant features enabled.
+	#[error(
+		"checkpoint was made with incompatible features (stacktrace/extensions/check-variables)"
+	)]
+	FeatureMismatch,
+
+	/// The checkpoint was made while running a different program.
+	#[error("checkpoint was made with a different program")]
+	ProgramMismatch,
+
+	/// The checkpoint was made by the register-based vm and is being restored by the stack-based one,
+	/// or vice versa.
+	#[error("checkpoint was made with a different `register_vm` option")]
+	EngineMismatch,
+
+	/// The checkpoint's checksum didn't match its contents.
+	#[error("checkpoint is corrupt")]
+	Corrupt,
+
+	/// A part of the checkpoint couldn't be loaded.
+	#[error("{0}")]
+	Load(#[from] DeserializeError),
+}
+
+impl<'src, 'gc> Vm<'_, 'src, '_, '_, 'gc> {
+	/// Writes everything needed to continue the vm later to `out`, so that it can be [restored](
+	/// Vm::restore), possibly in another process.
+	///
+	/// This is the stack, the variables (including dynamic ones), the calls which haven't returned,
+	/// every value on the heap that they use, and where the environment's rng is up to. If the vm is
+	/// [suspended](Vm::is_suspended), then it's also where it stopped, so the restored vm can be
+	/// [resumed](Vm::resume). Things the host sets up aren't included, such as the vm's fuel and
+	/// interrupt handle, and the environment's stdin and stdout.
+	///
+	/// Rngs can't be inspected, so it's actually the seed the rng was last given, and how many numbers
+	/// it's generated since. If it was [set](crate::Environment::set_rng) and hasn't been reseeded
+	/// since, then the environment restoring the checkpoint needs to be given an identical one. (So
+	/// that there's never too many numbers to generate again, rngs reseed themselves every million or
+	/// so numbers.)
+	pub fn checkpoint(&self, mut out: impl Write) -> io::Result<()> {
+		let roots = self.roots();
+		debug_assert_eq!(roots.popped_args, 0, "checkpoint made while an instruction was running");
+
+		let mut bytes = Vec::new();
+		let mut writer = Writer(&mut bytes);
+
+		writer.0.write_all(&CHECKPOINT_MAGIC)?;
+		writer.0.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+		writer.u8(VM_FEATURES)?;
+		writer.u64(self.program.fingerprint())?;
+		writer.u8(self.registers.is_some() as u8)?;
+
+		// Values on the heap are written first, so the rest of the checkpoint can refer to them. This
+		// also means values used in more than one place are only written once.
+		let heap = self.write_heap(&mut writer)?;
+
+		writer.usize(self.current_index)?;
+
+		writer.usize(roots.stack.len())?;
+		for &element in &roots.stack {
+			write_value(&mut writer, element, &heap)?;
+		}
+
+		writer.usize(roots.variables.len())?;
+		for &variable in roots.variables.iter() {
+			write_value(&mut writer, variable, &heap)?;
+		}
+
+		writer.usize(self.frames.len())?;
+		for frame in &self.frames {
+			writer.usize(frame.return_index)?;
+
+			#[cfg(feature = "stacktrace")]
+			{
+				writer.usize(frame.callsite)?;
+				writer.usize(frame.tail_calls)?;
+			}
+		}
+
+		match self.suspended {
+			Some(Suspended { depth, stack_len, index, base, caller_index }) => {
+				writer.u8(1)?;
+				for field in [depth, stack_len, index, base, caller_index] {
+					writer.usize(field)?;
+				}
+			}
+			None => writer.u8(0)?,
+		}
+
+		#[cfg(feature = "stacktrace")]
+		{
+			// Sort them so the output is deterministic.
+			let mut known_blocks = self.known_blocks.iter().collect::<Vec<_>>();
+			known_blocks.sort_by_key(|(&index, _)| index);
+			writer.usize(known_blocks.len())?;
+			for (&index, name) in known_blocks {
+				writer.usize(index)?;
+				writer.str(&name.to_string())?;
+			}
+		}
+
+		#[cfg(feature = "extensions")]
+		{
+			let mut dynamic_variables = roots.dynamic_variables.iter().collect::<Vec<_>>();
+			dynamic_variables.sort_by_key(|(name, _)| name.to_string());
+			writer.usize(dynamic_variables.len())?;
+			for (name, &variable) in dynamic_variables {
+				writer.str(&name.to_string())?;
+				write_value(&mut writer, variable, &heap)?;
+			}
+		}
+
+		let rng = self.env.rng_position();
+		writer.u8(rng.seed.is_some() as u8)?;
+		writer.u64(rng.seed.unwrap_or_default())?;
+		writer.u64(rng.generated)?;
+
+		let mut checksum = Fnv1a::new();
+		checksum.write_all(&bytes)?;
+		bytes.extend(checksum.finish().to_le_bytes());
+
+		out.write_all(&bytes)
+	}
+
+	// Writes every value on the heap the vm uses, returning the index each one was written at.
+	// Elements of lists are written before the lists themselves.
+	fn write_heap(
+		&self,
+		writer: &mut Writer<&mut Vec<u8>>,
+	) -> io::Result<HashMap<*const ValueInner, usize>> {
+		let mut roots = {
+			let roots = self.roots();
+			let mut values =
+				roots.stack.iter().chain(roots.variables.iter()).copied().collect::<Vec<_>>();
+			#[cfg(feature = "extensions")]
+			values.extend(roots.dynamic_variables.values().copied());
+			values
+		};
+
+		let mut indices = HashMap::new();
+		let mut order = Vec::new();
+
+		// Each allocation is visited twice: first to queue its elements, and then (once they've all
+		// been given indices) to give it one.
+		let mut queue = roots
+			.iter()
+			.filter_map(|root| root.as_alloc())
+			.map(|inner| (inner, false))
+			.collect::<Vec<_>>();
+		while let Some((inner, elements_done)) = queue.pop() {
+			if indices.contains_key(&inner) {
+				continue;
+			}
+
+			if elements_done {
+				indices.insert(inner, order.len());
+				order.push(inner);
+				continue;
+			}
+
+			queue.push((inner, true));
+
+			// SAFETY: Everything reachable from the vm is allocated.
+			if let Some(list) = unsafe { ValueInner::as_list(inner) } {
+				queue.extend(list.iter().filter_map(Value::as_alloc).map(|element| (element, false)));
+			}
+		}
+
+		writer.usize(order.len())?;
+		for &inner in &order {
+			// SAFETY: Everything reachable from the vm is allocated.
+			if let Some(string) = unsafe { ValueInner::as_knstring(inner) } {
+				writer.u8(HEAP_STRING)?;
+				writer.str(string.as_str())?;
+			} else if let Some(list) = unsafe { ValueInner::as_list(inner) } {
+				writer.u8(HEAP_LIST)?;
+				writer.usize(list.len())?;
+				for element in list.iter() {
+					write_value(writer, element, &indices)?;
+				}
+			} else {
+				bug!("unknown allocated value: {}", unsafe { ValueInner::type_name(inner) })
+			}
+		}
+
+		Ok(indices)
+	}
+
+	/// Replaces the vm's state with a [checkpoint](Vm::checkpoint) of a vm running the same program.
+	///
+	/// If the vm which made the checkpoint was suspended, then this one will be too, and calling
+	/// [`Vm::resume`] continues exactly where it left off.
+	///
+	/// # Safety
+	/// The checkpoint must have been made by [`Vm::checkpoint`]. It's checked that it was made with
+	/// the same program and is intact, but unlike [programs](crate::program::Program::deserialize),
+	/// the state within it isn't verified, so checkpoints from untrusted sources mustn't be restored.
+	pub unsafe fn restore(&mut self, checkpoint: &[u8]) -> Result<(), CheckpointError> {
+		if !checkpoint.starts_with(&CHECKPOINT_MAGIC) {
+			return Err(CheckpointError::BadMagic);
+		}
+
+		let Some((contents, checksum)) = checkpoint.split_last_chunk::<8>() else {
+			return Err(CheckpointError::Corrupt);
+		};
+
+		let mut hasher = Fnv1a::new();
+		let _ = hasher.write_all(contents);
+		if hasher.finish() != u64::from_le_bytes(*checksum) {
+			return Err(CheckpointError::Corrupt);
+		}
+
+		let mut input = Reader(&contents[CHECKPOINT_MAGIC.len()..]);
+		match input.u16()? {
+			CHECKPOINT_VERSION => {}
+			version => return Err(CheckpointError::UnsupportedVersion(version)),
+		}
+
+		if input.u8()? != VM_FEATURES {
+			return Err(CheckpointError::FeatureMismatch);
+		}
+
+		if input.u64()? != self.program.fingerprint() {
+			return Err(CheckpointError::ProgramMismatch);
+		}
+
+		if (input.u8()? != 0) != self.registers.is_some() {
+			return Err(CheckpointError::EngineMismatch);
+		}
+
+		// Nothing's rooted until it's in the vm, so the gc can't run until everything's been read.
+		self.env.gc().pause();
+		let result = self.read_state(&mut input);
+		self.env.gc().unpause();
+		result
+	}
+
+	fn read_state(&mut self, input: &mut Reader<'_>) -> Result<(), CheckpointError> {
+		let heap_len = input.len(1)?;
+		let mut heap = Vec::with_capacity(heap_len);
+		for _ in 0..heap_len {
+			let value = match input.u8()? {
+				HEAP_STRING => {
+					let string = KnString::new(input.str()?.to_string(), self.env.opts(), self.env.gc())
+						.map_err(DeserializeError::from)?;
+					unsafe { string.with_inner(Value::from) }
+				}
+				HEAP_LIST => {
+					let len = input.len(1)?;
+					let mut elements = Vec::with_capacity(len);
+					for _ in 0..len {
+						elements.push(self.read_value(input, &heap)?);
+					}
+
+					let list = List::from_slice(&elements, self.env.opts(), self.env.gc())
+						.map_err(DeserializeError::from)?;
+					unsafe { list.with_inner(Value::from) }
+				}
+				tag => return Err(DeserializeError::UnknownConstantTag(tag).into()),
+			};
+
+			heap.push(value);
+		}
+
+		let current_index = input.len(0)?;
+
+		let stack_len = input.len(1)?;
+		let mut stack = Vec::with_capacity(stack_len);
+		for _ in 0..stack_len {
+			stack.push(self.read_value(input, &heap)?);
+		}
+
+		let num_variables = self.roots().variables.len();
+		if input.len(1)? != num_variables {
+			return Err(CheckpointError::ProgramMismatch);
+		}
+		let mut variables = Vec::with_capacity(num_variables);
+		for _ in 0..num_variables {
+			variables.push(self.read_value(input, &heap)?);
+		}
+
+		let frames_len = input.len(size_of::<u64>())?;
+		let mut frames = Vec::with_capacity(frames_len);
+		for _ in 0..frames_len {
+			frames.push(Frame {
+				return_index: input.len(0)?,
+				#[cfg(feature = "stacktrace")]
+				callsite: input.len(0)?,
+				#[cfg(feature = "stacktrace")]
+				tail_calls: input.len(0)?,
+			});
+		}
+
+		let suspended = match input.u8()? {
+			0 => None,
+			_ => Some(Suspended {
+				depth: input.len(0)?,
+				stack_len: input.len(0)?,
+				index: input.len(0)?,
+				base: input.len(0)?,
+				caller_index: input.len(0)?,
+			}),
+		};
+
+		#[cfg(feature = "stacktrace")]
+		let known_blocks = {
+			let len = input.len(2 * size_of::<u64>())?;
+			let mut known_blocks = HashMap::with_capacity(len);
+			for _ in 0..len {
+				let index = input.len(0)?;
+				known_blocks.insert(index, input.variable_name()?);
+			}
+			known_blocks
+		};
+
+		#[cfg(feature = "extensions")]
+		let dynamic_variables = {
+			let len = input.len(2 * size_of::<u64>())?;
+			let mut dynamic_variables = HashMap::with_capacity(len);
+			for _ in 0..len {
+				let name = input.variable_name()?;
+				dynamic_variables.insert(name, self.read_value(input, &heap)?);
+			}
+			dynamic_variables
+		};
+
+		let has_seed = input.u8()? != 0;
+		let seed = input.u64()?;
+		let rng = RngPosition { seed: has_seed.then_some(seed), generated: input.u64()? };
+		if rng.generated > MAX_GENERATED {
+			return Err(CheckpointError::Corrupt);
+		}
+
+		if !input.0.is_empty() {
+			return Err(DeserializeError::TrailingData.into());
+		}
+
+		self.current_index = current_index;
+		self.frames = frames;
+		self.suspended = suspended;
+
+		#[cfg(feature = "stacktrace")]
+		{
+			self.known_blocks = known_blocks;
+		}
+
+		{
+			let mut roots = self.roots_mut();
+			roots.stack = stack;
+			roots.variables = variables.into_boxed_slice();
+
+			#[cfg(feature = "extensions")]
+			{
+				roots.dynamic_variables = dynamic_variables;
+			}
+		}
+
+		self.env.seek_rng(rng);
+		Ok(())
+	}
+
+	fn read_value(
+		&self,
+		input: &mut Reader<'_>,
+		heap: &[Value<'gc>],
+	) -> Result<Value<'gc>, CheckpointError> {
+		Ok(match input.u8()? {
+			TAG_NULL => Value::NULL,
+			TAG_FALSE => Value::FALSE,
+			TAG_TRUE => Value::TRUE,
+			TAG_INTEGER => Integer::new_error(input.u64()? as i64, self.env.opts())
+				.map_err(crate::Error::from)
+				.map_err(DeserializeError::from)?
+				.into(),
+			TAG_BLOCK => match input.len(0)? {
+				index if index < self.program.code().len() => Block::new(JumpIndex(index)).into(),
+				_ => return Err(CheckpointError::Corrupt),
+			},
+			#[cfg(feature = "check-variables")]
+			TAG_UNDEFINED => Value::UNDEFINED,
+			TAG_HEAP => *heap.get(input.len(0)?).ok_or(CheckpointError::Corrupt)?,
+			tag => return Err(DeserializeError::UnknownConstantTag(tag).into()),
+		})
+	}
+}
+
+fn write_value(
+	writer: &mut Writer<&mut Vec<u8>>,
+	value: Value<'_>,
+	heap: &HashMap<*const ValueInner, usize>,
+) -> io::Result<()> {
+	#[cfg(feature = "check-variables")]
+	if value.repr() == Value::UNDEFINED.repr() {
+		return writer.u8(TAG_UNDEFINED);
+	}
+
+	if let Some(inner) = value.as_alloc() {
+		writer.u8(TAG_HEAP)?;
+		writer.usize(heap[&inner])
+	} else if value.is_null() {
+		writer.u8(TAG_NULL)
+	} else if let Some(boolean) = value.as_boolean() {
+		writer.u8(if boolean { TAG_TRUE } else { TAG_FALSE })
+	} else if let Some(integer) = value.as_integer() {
+		writer.u8(TAG_INTEGER)?;
+		writer.u64(integer.inner() as u64)
+	} else if let Some(block) = value.as_block() {
+		writer.u8(TAG_BLOCK)?;
+		writer.usize(block.inner().0)
+	} else {
+		bug!("unknown value type: {:?}", value)
+	}
+}
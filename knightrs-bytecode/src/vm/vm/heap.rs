@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use super::Vm;
+use crate::gc::ValueInner;
+use crate::parser::VariableName;
+use crate::value::Value;
+
+/// Something which keeps a value on the heap alive.
+#[derive(Debug, Clone)]
+pub enum Retainer<'src> {
+	/// A variable in the program.
+	Variable(VariableName<'src>),
+
+	/// A variable which was assigned dynamically, and isn't in the program.
+	#[cfg(feature = "extensions")]
+	DynamicVariable(VariableName<'static>),
+
+	/// A slot on the vm's stack, counting up from the bottom.
+	Stack(usize),
+
+	/// A constant in the program.
+	Constant(usize),
+
+	/// A [`GcRoot`](crate::gc::GcRoot) that's held by the host.
+	Root,
+}
+
+impl Display for Retainer<'_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			Self::Variable(name) => write!(f, "variable {name}"),
+			#[cfg(feature = "extensions")]
+			Self::DynamicVariable(name) => write!(f, "dynamic variable {name}"),
+			Self::Stack(slot) => write!(f, "stack slot {slot}"),
+			Self::Constant(index) => write!(f, "constant {index}"),
+			Self::Root => write!(f, "root"),
+		}
+	}
+}
+
+/// A value which was on the heap when a [`HeapSnapshot`] was taken.
+#[derive(Debug, Clone)]
+pub struct HeapEntry<'src> {
+	/// Where the value is in memory, which is how values reference each other.
+	pub address: usize,
+
+	/// The name of the value's type.
+	pub type_name: &'static str,
+
+	/// How many bytes the value uses, including the buffer it owns (if any).
+	pub size: usize,
+
+	/// What's keeping the value alive. Values reachable from more than one retainer just have the
+	/// first one which was found, and values without any are waiting to be collected.
+	pub retainer: Option<Retainer<'src>>,
+}
+
+/// Every value on the heap at some point while a [`Vm`] was running, from [`Vm::heap_snapshot`].
+///
+/// Its [`Display`] lists each value on a separate line, along with what's retaining it.
+#[derive(Debug, Clone)]
+pub struct HeapSnapshot<'src> {
+	pub entries: Vec<HeapEntry<'src>>,
+}
+
+impl Display for HeapSnapshot<'_> {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		let bytes = self.entries.iter().map(|entry| entry.size).sum::<usize>();
+		writeln!(f, "{} values, {bytes} bytes", self.entries.len())?;
+
+		for entry in &self.entries {
+			write!(f, "{:#x} {:<6} {:>8} bytes, ", entry.address, entry.type_name, entry.size)?;
+			match &entry.retainer {
+				Some(retainer) => writeln!(f, "retained by {retainer}")?,
+				None => writeln!(f, "unreachable")?,
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl<'src, 'gc> Vm<'_, 'src, '_, '_, 'gc> {
+	/// Takes a snapshot of every value on the heap, and what's keeping them alive.
+	pub fn heap_snapshot(&self) -> HeapSnapshot<'src> {
+		let mut retainers = HashMap::new();
+		let roots = self.roots();
+
+		for (index, &value) in roots.variables.iter().enumerate() {
+			let name = self.program.variable_name(index);
+			retain(value, Retainer::Variable(name.clone()), &mut retainers);
+		}
+
+		#[cfg(feature = "extensions")]
+		for (name, &value) in &roots.dynamic_variables {
+			retain(value, Retainer::DynamicVariable(name.clone()), &mut retainers);
+		}
+
+		for (slot, &value) in roots.stack.iter().enumerate() {
+			retain(value, Retainer::Stack(slot), &mut retainers);
+		}
+
+		for (index, &value) in self.program.constants().iter().enumerate() {
+			retain(value, Retainer::Constant(index), &mut retainers);
+		}
+
+		for root in self.env.gc().rooted() {
+			retain_inner(root, Retainer::Root, &mut retainers);
+		}
+
+		let entries = self
+			.env
+			.gc()
+			.allocated()
+			.into_iter()
+			.map(|inner| HeapEntry {
+				address: inner as usize,
+				// SAFETY: `inner` was just gotten from the gc, so it's allocated.
+				type_name: unsafe { ValueInner::type_name(inner) },
+				size: unsafe { ValueInner::size(inner) },
+				retainer: retainers.remove(&inner),
+			})
+			.collect();
+
+		HeapSnapshot { entries }
+	}
+}
+
+// Records that `value`, and everything reachable from it, is kept alive by `retainer`.
+fn retain<'src>(
+	value: Value<'_>,
+	retainer: Retainer<'src>,
+	retainers: &mut HashMap<*const ValueInner, Retainer<'src>>,
+) {
+	if let Some(inner) = value.as_alloc() {
+		retain_inner(inner, retainer, retainers);
+	}
+}
+
+fn retain_inner<'src>(
+	inner: *const ValueInner,
+	retainer: Retainer<'src>,
+	retainers: &mut HashMap<*const ValueInner, Retainer<'src>>,
+) {
+	let mut queue = vec![inner];
+
+	while let Some(inner) = queue.pop() {
+		// If it's already retained, then so is everything reachable from it.
+		if retainers.contains_key(&inner) {
+			continue;
+		}
+		retainers.insert(inner, retainer.clone());
+
+		// SAFETY: Everything reachable from the vm is allocated.
+		if let Some(list) = unsafe { ValueInner::as_list(inner) } {
+			queue.extend(list.iter().filter_map(Value::as_alloc));
+		}
+	}
+}
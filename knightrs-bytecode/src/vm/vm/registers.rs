@@ -0,0 +1,276 @@
+use super::Vm;
+use crate::program::{Instruction, JumpIndex, Operand, Register, RegisterCode};
+use crate::value::{List, ToBoolean, ToInteger, Value};
+use crate::vm::Opcode;
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+
+impl<'gc> Vm<'_, '_, '_, '_, 'gc> {
+	/// Runs the instruction at `index` onwards using the register-based interpreter, until the frame
+	/// at `depth` returns.
+	///
+	/// Each call gets its own frame of temporaries, which lives on top of the stack so that it's
+	/// marked along with everything else. Frames are all the same size, so the caller's frame always
+	/// starts [`frame_size`](RegisterCode::frame_size) before the callee's. The current frame starts
+	/// at `base`.
+	pub(super) fn run_registers(
+		&mut self,
+		code: &RegisterCode,
+		mut index: usize,
+		mut base: usize,
+		depth: usize,
+	) -> crate::Result<Value<'gc>> {
+		loop {
+			// SAFETY: register code is translated from verified programs, so `index` is in bounds.
+			let start = index;
+			let instruction = unsafe { code.instruction_at(index) };
+
+			// Uses up a unit of fuel. If there's none left (or the vm's been interrupted), then the vm
+			// stops just before the current instruction, which must not have done anything yet.
+			macro_rules! burn_fuel {
+				() => {
+					if let Some(err) = super::burn_fuel(&mut self.fuel, &self.interrupt) {
+						self.stopped_at = Some((start, base));
+						return Err(err);
+					}
+				};
+			}
+
+			#[cfg(feature = "stacktrace")]
+			{
+				self.current_index = code.origin(index);
+			}
+
+			index += 1;
+
+			match instruction {
+				Instruction::Move { dst, src } => {
+					let value = self.read(base, src)?;
+					self.write(base, dst, value);
+				}
+
+				Instruction::Jump { target } => {
+					if (target as usize) < start {
+						burn_fuel!();
+					}
+					index = target as usize;
+				}
+				Instruction::JumpIf { cond, jump_if, target } => {
+					if (target as usize) < start {
+						burn_fuel!();
+					}
+
+					if self.read(base, cond)?.to_boolean(self.env)? == jump_if {
+						index = target as usize;
+					}
+				}
+
+				Instruction::Nullary { opcode, dst } => {
+					let value = match opcode {
+						Opcode::Prompt => match self.env.prompt()? {
+							Some(prompted) => unsafe { prompted.with_inner(Value::from) },
+							None => Value::NULL,
+						},
+						Opcode::Random => self.env.random()?.into(),
+						_ => bug!("{:?} isn't a nullary opcode", opcode),
+					};
+					self.write(base, dst, value);
+				}
+
+				Instruction::Unary { opcode: Opcode::Call, dst, arg } => {
+					burn_fuel!();
+					let arg = self.read(base, arg)?;
+
+					if let Some(block) = arg.as_block() {
+						// Just like the stack-based vm, `CALL`s in tail position reuse the current frame.
+						if code.is_tail_call(index, dst) {
+							self.elide_frame();
+						} else {
+							self.push_frame(index)?;
+							base += code.frame_size();
+							self.roots_mut().stack.resize(base + code.frame_size(), Value::NULL);
+						}
+
+						index = code.entry(block.inner());
+					} else {
+						let value = arg.kn_call(self)?;
+						self.write(base, dst, value);
+					}
+				}
+
+				Instruction::Unary { opcode, dst, arg } => {
+					let arg = self.read(base, arg)?;
+					let value = self.run_unary(opcode, arg)?;
+					self.write(base, dst, value);
+				}
+
+				Instruction::Binary { opcode, dst, lhs, rhs } => {
+					let lhs = self.read(base, lhs)?;
+					let rhs = self.read(base, rhs)?;
+					let value = self.run_binary(opcode, lhs, rhs)?;
+					self.write(base, dst, value);
+				}
+
+				Instruction::Get { dst, args: [value, start, length] } => {
+					let value = self.read(base, value)?;
+					let start = self.read(base, start)?;
+					let length = self.read(base, length)?;
+
+					let mut result = MaybeUninit::uninit();
+					// SAFETY: `result` is written to a register before anything else can run the gc.
+					unsafe {
+						value.kn_get(&start, &length, &mut result, self.env)?;
+						self.write(base, dst, result.assume_init());
+					}
+				}
+
+				Instruction::Set { dst, args: [value, start, length, replacement] } => {
+					let value = self.read(base, value)?;
+					let start = self.read(base, start)?;
+					let length = self.read(base, length)?;
+					let replacement = self.read(base, replacement)?;
+
+					let mut result = MaybeUninit::uninit();
+					// SAFETY: `result` is written to a register before anything else can run the gc.
+					unsafe {
+						value.kn_set(&start, &length, &replacement, &mut result, self.env)?;
+						self.write(base, dst, result.assume_init());
+					}
+				}
+
+				Instruction::Dump { value } => self.read(base, value)?.kn_dump(self.env)?,
+
+				#[cfg(feature = "extensions")]
+				Instruction::AssignDynamic { kind, value } => {
+					let value = self.read(base, value)?;
+					self.assign_dynamic(kind as usize, value)?;
+				}
+
+				Instruction::Quit { status } => {
+					let status = self.read(base, status)?.to_integer(self.env)?;
+					match self.env.quit(status)? {}
+				}
+
+				Instruction::Return { value } => {
+					let value = self.read(base, value)?;
+
+					// There's nowhere to return to, so return the value of the block `run` was given.
+					if self.frames.len() == depth {
+						return Ok(value);
+					}
+
+					let frame = self.frames.pop().unwrap_or_else(|| bug!("returned without a frame"));
+					self.roots_mut().stack.truncate(base);
+					base -= code.frame_size();
+					index = frame.return_index;
+
+					// SAFETY: frames are only pushed by calls, so the instruction before where we return
+					// to is always the call which pushed it.
+					let Instruction::Unary { opcode: Opcode::Call, dst, .. } =
+						(unsafe { code.instruction_at(index - 1) })
+					else {
+						bug!("frames are only pushed by calls");
+					};
+					self.write(base, dst, value);
+				}
+			}
+		}
+	}
+
+	#[inline]
+	fn read(&mut self, base: usize, operand: Operand) -> crate::Result<Value<'gc>> {
+		// SAFETY: register code is translated from verified programs, so all the operands are valid.
+		unsafe {
+			match operand {
+				Operand::Variable(variable) => self.get_variable(variable as usize),
+				Operand::Temporary(temporary) => {
+					let roots = self.roots();
+					debug_assert!(base + (temporary as usize) < roots.stack.len());
+					Ok(*roots.stack.get_unchecked(base + temporary as usize))
+				}
+				Operand::Constant(constant) => Ok(self.program.constant_at(constant as usize)),
+			}
+		}
+	}
+
+	#[inline]
+	fn write(&mut self, base: usize, register: Register, value: Value<'gc>) {
+		// SAFETY: register code is translated from verified programs, so all the registers are valid.
+		unsafe {
+			match register {
+				Register::Variable(variable) => self.set_variable(variable as usize, value),
+				Register::Temporary(temporary) => {
+					let mut roots = self.roots_mut();
+					debug_assert!(base + (temporary as usize) < roots.stack.len());
+					*roots.stack.get_unchecked_mut(base + temporary as usize) = value;
+				}
+			}
+		}
+	}
+
+	// Runs a one-argument opcode, returning its result.
+	fn run_unary(&mut self, opcode: Opcode, arg: Value<'gc>) -> crate::Result<Value<'gc>> {
+		let mut result = MaybeUninit::uninit();
+
+		// SAFETY: the result is written to a register before anything else can run the gc.
+		unsafe {
+			match opcode {
+				Opcode::Output => return self.output(arg).map(|()| Value::NULL),
+				Opcode::Length => return Ok(arg.kn_length(self.env)?.into()),
+				Opcode::Not => arg.kn_not(&mut result, self.env)?,
+				Opcode::Negate => arg.kn_negate(&mut result, self.env)?,
+				Opcode::Ascii => arg.kn_ascii(&mut result, self.env)?,
+				Opcode::Box => return Ok(List::boxed(arg, self.env.gc())?.with_inner(Value::from)),
+				Opcode::Head => arg.kn_head(&mut result, self.env)?,
+				Opcode::Tail => arg.kn_tail(&mut result, self.env)?,
+
+				#[cfg(feature = "extensions")]
+				Opcode::Eval => return self.eval(arg),
+				#[cfg(feature = "extensions")]
+				Opcode::System => return self.system(arg),
+				#[cfg(feature = "extensions")]
+				Opcode::Value => return self.value_of(arg),
+
+				_ => bug!("{:?} isn't a unary opcode", opcode),
+			}
+
+			Ok(result.assume_init())
+		}
+	}
+
+	// Runs a two-argument opcode, returning its result.
+	fn run_binary(
+		&mut self,
+		opcode: Opcode,
+		lhs: Value<'gc>,
+		rhs: Value<'gc>,
+	) -> crate::Result<Value<'gc>> {
+		let mut result = MaybeUninit::uninit();
+
+		// SAFETY: the result is written to a register before anything else can run the gc.
+		unsafe {
+			match opcode {
+				Opcode::Add => lhs.kn_plus(&rhs, &mut result, self.env)?,
+				Opcode::Sub => lhs.kn_minus(&rhs, &mut result, self.env)?,
+				Opcode::Mul => lhs.kn_asterisk(&rhs, &mut result, self.env)?,
+				Opcode::Div => lhs.kn_slash(&rhs, &mut result, self.env)?,
+				Opcode::Mod => lhs.kn_percent(&rhs, &mut result, self.env)?,
+				Opcode::Pow => lhs.kn_caret(&rhs, &mut result, self.env)?,
+				Opcode::Lth => {
+					return Ok((lhs.kn_compare(&rhs, "<", self.env)? == Ordering::Less).into())
+				}
+				Opcode::Gth => {
+					return Ok((lhs.kn_compare(&rhs, ">", self.env)? == Ordering::Greater).into())
+				}
+				Opcode::Eql => return Ok(lhs.kn_equals(&rhs, self.env)?.into()),
+
+				#[cfg(feature = "extensions")]
+				Opcode::SetDynamicVar => return self.set_dynamic_variable(lhs, rhs).map(|()| rhs),
+
+				_ => bug!("{:?} isn't a binary opcode", opcode),
+			}
+
+			Ok(result.assume_init())
+		}
+	}
+}
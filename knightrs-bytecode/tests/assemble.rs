@@ -0,0 +1,119 @@
+//! Assembling programs from their textual representation.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, run_program, with_env, PROGRAMS};
+use knightrs_bytecode::env::Environment;
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::parser::source_location::ProgramSource;
+use knightrs_bytecode::program::{AssembleError, Program};
+use knightrs_bytecode::Options;
+
+fn assemble<'src, 'gc>(
+	env: &Environment<'gc>,
+	source: &'src str,
+) -> Result<Program<'src, 'static, 'gc>, AssembleError> {
+	let gc = env.gc();
+	gc.pause();
+	// SAFETY: The gc is paused.
+	let program = unsafe { Program::assemble(source, ProgramSource::Other("<asm>"), env) };
+	gc.unpause();
+	program
+}
+
+// Gets the instructions of a disassembly, without any comments.
+fn instructions(disassembly: &str) -> Vec<&str> {
+	disassembly
+		.lines()
+		.map(|line| line.split(';').next().unwrap().trim())
+		.filter(|line| !line.is_empty())
+		.collect()
+}
+
+fn without_stacktrace(output: &str) -> &str {
+	output.split("\n\tin ").next().unwrap()
+}
+
+#[test]
+fn disassembled_programs_can_be_assembled() {
+	for superinstructions in [false, true] {
+		let mut opts = Options::default();
+		opts.optimizations.peephole = superinstructions;
+		opts.optimizations.superinstructions = superinstructions;
+
+		for (name, source) in PROGRAMS {
+			// Programs' constants are only kept alive while they're running, so each program gets its
+			// own environment.
+			let (disassembly, output) = with_env(opts.clone(), GcOptions::default(), |env| {
+				let program = compile(env, source).unwrap();
+				(program.disassemble().to_string(), run_program(env, &program))
+			});
+
+			with_env(opts.clone(), GcOptions::default(), |env| {
+				let assembled = assemble(env, &disassembly).unwrap();
+				let reassembled = assembled.disassemble().to_string();
+
+				// The only difference is the `Return` that's always added to the end.
+				let mut instructions_after = instructions(&reassembled);
+				let last = instructions_after.pop().unwrap();
+				assert_eq!(instructions_after, instructions(&disassembly), "{name}");
+				assert!(last.ends_with("Return"), "{name}: {last}");
+
+				// Source locations are the lines of the assembly, so stacktraces aren't the same.
+				let assembled_output = run_program(env, &assembled);
+				assert_eq!(
+					without_stacktrace(&assembled_output),
+					without_stacktrace(&output),
+					"{name}"
+				);
+			});
+		}
+	}
+}
+
+// Assembles `source`, returning the error it fails with.
+fn assemble_err(source: &str) -> AssembleError {
+	with_env(Options::default(), GcOptions::default(), |env| assemble(env, source).err().unwrap())
+}
+
+#[test]
+fn invalid_assembly_is_rejected() {
+	assert!(matches!(assemble_err("Nope"), AssembleError::UnknownOpcode { lineno: 1, .. }));
+	assert!(matches!(
+		assemble_err("PushConstant 1\nGetVar"),
+		AssembleError::MissingOperand { lineno: 2, .. }
+	));
+	assert!(matches!(assemble_err("Add 1"), AssembleError::UnexpectedInput { lineno: 1, .. }));
+	assert!(matches!(
+		assemble_err("PushConstant \"abc"),
+		AssembleError::InvalidLiteral { lineno: 1 }
+	));
+	assert!(matches!(
+		assemble_err("a: Dup\n\na: Dup"),
+		AssembleError::DuplicateLabel { lineno: 3, .. }
+	));
+	assert!(matches!(assemble_err("Jump nowhere"), AssembleError::UnknownLabel { lineno: 1, .. }));
+	assert!(matches!(
+		assemble_err("GetVar Abc"),
+		AssembleError::InvalidVariableName { lineno: 1, .. }
+	));
+	assert!(matches!(assemble_err("Add"), AssembleError::Verify(_)));
+
+	// Superinstructions can only refer to the first 65536 variables.
+	let mut source = String::new();
+	for variable in 0..=65536 {
+		source += &format!("PushConstant 0\nSetVarPop v{variable}\n");
+	}
+	source += "AddVarConst v65536, 1";
+	assert!(matches!(assemble_err(&source), AssembleError::OperandTooLarge { .. }));
+}
+
+#[test]
+#[cfg(feature = "extensions")]
+fn unknown_dynamic_assignments_are_rejected() {
+	assert!(matches!(
+		assemble_err("AssignDynamic nothing"),
+		AssembleError::UnknownDynamicAssignment { lineno: 1, .. }
+	));
+}
@@ -0,0 +1,63 @@
+//! Translates programs to C, and makes sure they do the same thing when they're compiled.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, with_env, PROGRAMS};
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::Options;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Whether there's a C compiler to compile the translated programs with.
+fn has_cc() -> bool {
+	Command::new("cc").arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+// Translates `source` to C, returning the C along with what the vm outputs when running it.
+fn translate(source: &str, opts: Options) -> (String, String) {
+	with_env(opts, GcOptions::default(), |env| {
+		let program = compile(env, source).unwrap();
+		let c = program.to_c(env.opts()).unwrap().to_string();
+
+		let capture = env.capture_output();
+		let _ = Vm::new(&program, env).run_entire_program_without_argv();
+		(c, capture.take())
+	})
+}
+
+fn temp_dir() -> PathBuf {
+	std::env::temp_dir().join(format!("knightrs-bytecode-c-{}", std::process::id()))
+}
+
+// Compiles `c` and runs it, returning what it wrote to stdout.
+fn compile_and_run(name: &str, c: &str) -> String {
+	let dir = temp_dir();
+	std::fs::create_dir_all(&dir).unwrap();
+	let file = name.replace(' ', "-");
+	let source = dir.join(format!("{file}.c"));
+	let binary = dir.join(file);
+	std::fs::write(&source, c).unwrap();
+
+	let built = Command::new("cc").arg("-o").arg(&binary).arg(&source).output().unwrap();
+	assert!(built.status.success(), "{name}: {}", String::from_utf8_lossy(&built.stderr));
+
+	let output = Command::new(&binary).output().unwrap();
+	String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn compiled_programs_output_the_same_as_the_vm() {
+	if !has_cc() {
+		eprintln!("skipping, as there's no `cc`");
+		return;
+	}
+
+	for (name, source) in PROGRAMS {
+		let (c, expected) = translate(source, Options::default());
+		assert_eq!(compile_and_run(name, &c), expected, "{name}");
+	}
+
+	let _ = std::fs::remove_dir_all(temp_dir());
+}
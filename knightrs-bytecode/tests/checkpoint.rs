@@ -0,0 +1,99 @@
+//! Checkpointing vms, and restoring them elsewhere.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, with_env};
+use knightrs_bytecode::env::Pcg32;
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::{CheckpointError, Vm};
+use knightrs_bytecode::{Error, Options};
+
+// Each call to `f` is inside the one before it, with the list of the random number it generated on
+// the stack, so stopping partway through leaves both frames and values behind.
+const RECURSIVE: &str = r#"
+	; = f BLOCK : + ,RANDOM (; = n - n 1 : IF < n 1 @ CALL f)
+	; = n 30
+	; = s "start"
+	: + s ^ (CALL f) ","
+"#;
+
+// Runs `RECURSIVE` until it's stopped by running out of fuel, returning the checkpoint it made then,
+// and what it finished with after being resumed.
+fn checkpoint(opts: Options) -> (Vec<u8>, String) {
+	with_env(opts, GcOptions::default(), |env| {
+		env.set_rng(Pcg32::new(7));
+		let program = compile(env, RECURSIVE).unwrap();
+		let mut vm = Vm::new(&program, env);
+
+		vm.set_fuel(Some(10));
+		assert!(matches!(vm.run_entire_program_without_argv(), Err(Error::OutOfFuel)));
+		assert!(vm.is_suspended());
+
+		let mut checkpoint = Vec::new();
+		vm.checkpoint(&mut checkpoint).unwrap();
+
+		vm.set_fuel(None);
+		let result = vm.resume().unwrap();
+		(checkpoint, format!("{result:?}"))
+	})
+}
+
+#[test]
+fn restored_vms_continue_where_they_stopped() {
+	for register_vm in [false, true] {
+		let opts = Options { register_vm, ..Options::default() };
+		let (checkpoint, expected) = checkpoint(opts.clone());
+
+		let restored = with_env(opts.clone(), GcOptions::default(), |env| {
+			env.set_rng(Pcg32::new(7));
+			let program = compile(env, RECURSIVE).unwrap();
+			let mut vm = Vm::new(&program, env);
+
+			// SAFETY: It was made by `Vm::checkpoint`.
+			unsafe { vm.restore(&checkpoint) }.unwrap();
+			assert!(vm.is_suspended());
+			format!("{:?}", vm.resume().unwrap())
+		});
+		assert_eq!(restored, expected, "register_vm={register_vm}");
+
+		// The random numbers generated before the checkpoint aren't generated again.
+		let uninterrupted = with_env(opts, GcOptions::default(), |env| {
+			env.set_rng(Pcg32::new(7));
+			let program = compile(env, RECURSIVE).unwrap();
+			let result = Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+			format!("{result:?}")
+		});
+		assert_eq!(restored, uninterrupted, "register_vm={register_vm}");
+	}
+}
+
+#[test]
+fn checkpoints_are_checked() {
+	let (checkpoint, _) = checkpoint(Options::default());
+
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, RECURSIVE).unwrap();
+		let other = compile(env, "OUTPUT 1").unwrap();
+
+		// SAFETY: These are all either checkpoints made by `Vm::checkpoint`, or are rejected before
+		// their contents are used.
+		unsafe {
+			let mut corrupt = checkpoint.clone();
+			*corrupt.last_mut().unwrap() ^= 1;
+			let err = Vm::new(&program, env).restore(&corrupt);
+			assert!(matches!(err, Err(CheckpointError::Corrupt)), "{err:?}");
+
+			let err = Vm::new(&other, env).restore(&checkpoint);
+			assert!(matches!(err, Err(CheckpointError::ProgramMismatch)), "{err:?}");
+
+			let registers = Options { register_vm: true, ..Options::default() };
+			let err = with_env(registers, GcOptions::default(), |env| {
+				let program = compile(env, RECURSIVE).unwrap();
+				let err = Vm::new(&program, env).restore(&checkpoint).map_err(|err| err.to_string());
+				err
+			});
+			assert_eq!(err, Err(CheckpointError::EngineMismatch.to_string()));
+		}
+	});
+}
@@ -0,0 +1,129 @@
+//! Runs the command-line program.
+#![cfg(feature = "clap")]
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// Creates an empty directory for the test `name` to put its files in.
+fn temp_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("knightrs-bytecode-{name}-{}", std::process::id()));
+	let _ = std::fs::remove_dir_all(&dir);
+	std::fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+// Runs `program` with `args`, returning what it wrote to stdout.
+fn run(program: &Path, args: &[&str]) -> String {
+	let output = Command::new(program).args(args).output().unwrap();
+	assert!(output.status.success(), "{args:?}: {}", String::from_utf8_lossy(&output.stderr));
+	String::from_utf8(output.stdout).unwrap()
+}
+
+// Runs `program` with `args`, which should fail, returning what it wrote to stderr.
+fn run_err(program: &Path, args: &[&str]) -> String {
+	let Output { status, stdout, stderr } = Command::new(program).args(args).output().unwrap();
+	assert!(!status.success(), "{args:?}: {}", String::from_utf8_lossy(&stdout));
+	String::from_utf8(stderr).unwrap()
+}
+
+// Runs the program with `args`, returning what it wrote to stdout.
+fn knightrs(args: &[&str]) -> String {
+	run(Path::new(env!("CARGO_BIN_EXE_knightrs-bytecode")), args)
+}
+
+fn entries(dir: &Path) -> HashSet<PathBuf> {
+	std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect()
+}
+
+#[test]
+fn cache_entries_are_checked_when_loaded() {
+	let dir = temp_dir("cache");
+	let cache = dir.join("cache");
+	let cache_dir = cache.to_str().unwrap();
+
+	let a = dir.join("a.kn");
+	let b = dir.join("b.kn");
+	std::fs::write(&a, r#"OUTPUT "a""#).unwrap();
+	std::fs::write(&b, r#"OUTPUT "b""#).unwrap();
+	let (a, b) = (a.to_str().unwrap(), b.to_str().unwrap());
+
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "-f", a]), "a\n");
+	let a_entry = entries(&cache);
+	assert_eq!(a_entry.len(), 1);
+	let a_entry = a_entry.into_iter().next().unwrap();
+
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "-f", b]), "b\n");
+	let b_entry = entries(&cache).into_iter().find(|entry| *entry != a_entry).unwrap();
+
+	// Pretend the two programs' keys collided, by putting `a` where `b` is.
+	std::fs::copy(&a_entry, &b_entry).unwrap();
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "-f", b]), "b\n");
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "-f", a]), "a\n");
+	assert_eq!(entries(&cache).len(), 2);
+
+	// Options which are only used while running don't need a separate entry, unlike ones which
+	// change how programs are compiled.
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "--registers", "-f", a]), "a\n");
+	assert_eq!(entries(&cache).len(), 2);
+	assert_eq!(knightrs(&["--cache-dir", cache_dir, "-O", "-f", a]), "a\n");
+	assert_eq!(entries(&cache).len(), 3);
+
+	std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn bundles_run_their_program() {
+	let dir = temp_dir("bundle");
+	let bundle = dir.join("bundle");
+	knightrs(&["-e", r#"OUTPUT + "hi " + 1 2"#, "bundle", bundle.to_str().unwrap()]);
+	assert_eq!(run(&bundle, &[]), "hi 3\n");
+
+	// Arguments are only passed to programs which were bundled with `--ext-argv`.
+	let err = run_err(&bundle, &["a"]);
+	assert!(err.contains("unless the program was bundled with --ext-argv"), "{err}");
+
+	#[cfg(feature = "extensions")]
+	{
+		let with_argv = dir.join("with-argv");
+		knightrs(&[
+			"--ext-argv",
+			"-e",
+			r#"OUTPUT ^ _argv ",""#,
+			"bundle",
+			with_argv.to_str().unwrap(),
+		]);
+		assert_eq!(run(&with_argv, &["a", "b"]), "a,b\n");
+		assert_eq!(run(&with_argv, &[]), "\n");
+	}
+
+	std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn corrupt_bundles_are_rejected() {
+	let dir = temp_dir("corrupt-bundle");
+	let bundle = dir.join("bundle");
+	knightrs(&["-e", r#"OUTPUT "hi""#, "bundle", bundle.to_str().unwrap()]);
+	let contents = std::fs::read(&bundle).unwrap();
+
+	// Bundles end with the length of the options and the program, followed by an 8-byte marker.
+	let trailer = contents.len() - 24;
+	let corrupt = |name: &str, edit: &dyn Fn(&mut Vec<u8>)| {
+		let path = dir.join(name);
+		let mut contents = contents.clone();
+		edit(&mut contents);
+		std::fs::write(&path, contents).unwrap();
+		std::fs::set_permissions(&path, std::fs::metadata(&bundle).unwrap().permissions()).unwrap();
+		run_err(&path, &[])
+	};
+
+	let err = corrupt("too-long", &|contents| contents[trailer..trailer + 8].fill(0xff));
+	assert!(err.contains("corrupt bundle"), "{err}");
+
+	// Removing part of the program leaves the lengths pointing at the wrong bytes.
+	let err = corrupt("truncated", &|contents| drop(contents.drain(trailer - 4..trailer)));
+	assert!(err.contains("not a compiled knight program"), "{err}");
+
+	std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,213 @@
+//! Helpers shared by the integration tests. Most of them need the `embedded` feature, to keep
+//! programs from using the process's stdin and stdout, or from exiting it.
+#![allow(dead_code)]
+
+use knightrs_bytecode::env::Environment;
+use knightrs_bytecode::parser::{source_location::ProgramSource, Parser};
+use knightrs_bytecode::program::Program;
+
+#[cfg(feature = "embedded")]
+use knightrs_bytecode::{
+	gc::{Gc, GcOptions},
+	vm::Vm,
+	Options,
+};
+
+/// Programs which, between them, use every function and a good mix of values.
+///
+/// None of them read from stdin or use `RANDOM`, so their output is always the same.
+pub const PROGRAMS: &[(&str, &str)] = &[
+	(
+		"fizzbuzz",
+		r#"
+		; = n 0
+		: WHILE < n 30
+			; = n + n 1
+			: OUTPUT IF ! (% n 15) "FizzBuzz" IF ! (% n 3) "Fizz" IF ! (% n 5) "Buzz" n
+		"#,
+	),
+	(
+		"factorial",
+		r#"
+		; = fact BLOCK : IF < n 2 1 * n ; = n - n 1 : CALL fact
+		; = n 10
+		: OUTPUT CALL fact
+		"#,
+	),
+	(
+		"tail calls",
+		r#"
+		; = count BLOCK : IF < i 5000 (; = i + i 1 : CALL count) i
+		; = i 0
+		: OUTPUT CALL count
+		"#,
+	),
+	(
+		"strings and lists",
+		r#"
+		; = s "hello, world"
+		; OUTPUT + s "!"
+		; OUTPUT * "ab" 3
+		; OUTPUT GET s 0 5
+		; OUTPUT SET s 0 5 "HELLO"
+		; OUTPUT ASCII 65
+		; DUMP ASCII "a"
+		; = l + @ "abc"
+		; DUMP l
+		; DUMP * ,1 3
+		; DUMP + ,1 ,2
+		; DUMP ^ +@123 ", "
+		; DUMP [ l
+		; DUMP ] l
+		; DUMP [ "xyz"
+		; DUMP ] "xyz"
+		; DUMP GET l 1 2
+		; DUMP SET l 0 1 @
+		; DUMP LENGTH "abc"
+		; DUMP LENGTH 12345
+		; DUMP ~ 5
+		; DUMP ^ 2 10
+		; DUMP ? "a" "a"
+		; DUMP ? +@12 +@12
+		; DUMP < "a" "b"
+		; DUMP > ,2 ,1
+		; DUMP & 1 0
+		; DUMP | 0 "x"
+		; DUMP + "" TRUE
+		; DUMP + 0 "12abc"
+		; DUMP +@ NULL
+		: DUMP ! TRUE
+		"#,
+	),
+	(
+		"constants",
+		r#"
+		; OUTPUT + + 1 2 * 3 4
+		; OUTPUT IF TRUE "yes" "no"
+		; OUTPUT IF ! "" "empty" "full"
+		; OUTPUT + @ "abc"
+		; WHILE FALSE OUTPUT "never"
+		; OUTPUT - 10 ~ 3
+		: OUTPUT / 7 2
+		"#,
+	),
+	(
+		"loops",
+		r#"
+		; = i 0
+		; = t 0
+		; WHILE < i 100 ; = t + t i : = i + i 1
+		; WHILE > i 0 : = i - i 1
+		; WHILE ! ? i 5 : = i + i 1
+		; = j 0
+		; WHILE < j 10 ; = j + j 1 : IF ! % j 2 NULL (: OUTPUT j)
+		: OUTPUT + + t " " i
+		"#,
+	),
+	(
+		"allocations",
+		r#"
+		; = i 0
+		; = acc @
+		; = str ""
+		; WHILE < i 300
+			; = acc + acc ,+ "item" i
+			; = str + str ,i
+			: = i + i 1
+		; OUTPUT LENGTH acc
+		; OUTPUT LENGTH str
+		; DUMP GET acc 297 3
+		: OUTPUT ^ acc "/"
+		"#,
+	),
+	(
+		"nested blocks",
+		r#"
+		; = make BLOCK : BLOCK + "made " x
+		; = x 1
+		; = b CALL make
+		; OUTPUT CALL b
+		; = x 2
+		: OUTPUT CALL CALL make
+		"#,
+	),
+	(
+		"runtime error",
+		r#"
+		; OUTPUT "before"
+		; OUTPUT / 1 0
+		: OUTPUT "after"
+		"#,
+	),
+	(
+		"quit",
+		r#"
+		; OUTPUT "bye"
+		; QUIT 0
+		: OUTPUT "not reached"
+		"#,
+	),
+];
+
+/// Creates an environment with `opts`, within a [`Gc`] created with `gc_opts`, and gives it to
+/// `func`. `QUIT` never exits the test process.
+#[cfg(feature = "embedded")]
+pub fn with_env<T>(
+	mut opts: Options,
+	gc_opts: GcOptions,
+	func: impl FnOnce(&mut Environment<'_>) -> T,
+) -> T {
+	opts.embedded.dont_exit_when_quitting = true;
+
+	// SAFETY: Nothing allocated by the gc escapes `func`.
+	unsafe {
+		Gc::new(gc_opts).run(|gc| {
+			let mut env = Environment::new(opts, gc);
+			env.set_stdin(std::io::empty());
+			func(&mut env)
+		})
+	}
+}
+
+/// Parses `source` into a program.
+pub fn compile<'src, 'gc>(
+	env: &mut Environment<'gc>,
+	source: &'src str,
+) -> Result<Program<'src, 'static, 'gc>, String> {
+	let gc = env.gc();
+	let parser =
+		Parser::new(env, ProgramSource::Other("<test>"), source).map_err(|err| err.to_string())?;
+
+	gc.pause();
+	let program = parser.parse_program().map_err(|err| err.to_string());
+	gc.unpause();
+	program
+}
+
+/// Runs `program`, returning everything it output followed by how it finished.
+#[cfg(feature = "embedded")]
+pub fn run_program<'gc>(env: &mut Environment<'gc>, program: &Program<'_, '_, 'gc>) -> String {
+	let capture = env.capture_output();
+	let result = Vm::new(program, env).run_entire_program_without_argv();
+
+	let finished = match result {
+		Ok(_) => "ok".to_string(),
+		Err(err) => format!("error: {err}"),
+	};
+	format!("{}=> {finished}", capture.take())
+}
+
+/// Parses and runs `source` with the given options, returning what [`run_program`] does.
+#[cfg(feature = "embedded")]
+pub fn run_with(source: &str, opts: Options, gc_opts: GcOptions) -> String {
+	with_env(opts, gc_opts, |env| match compile(env, source) {
+		Ok(program) => run_program(env, &program),
+		Err(err) => format!("=> parse error: {err}"),
+	})
+}
+
+/// Like [`run_with`], but using the default gc options.
+#[cfg(feature = "embedded")]
+pub fn run(source: &str, opts: Options) -> String {
+	run_with(source, opts, GcOptions::default())
+}
@@ -0,0 +1,147 @@
+//! Runs the same programs with each vm, optimization, and gc configuration, making sure that none
+//! of them change what programs do.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, run, run_with, with_env, PROGRAMS};
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::Options;
+
+// Every combination of vm and optimizations, along with the name of the combination.
+fn engines() -> Vec<(String, Options)> {
+	let mut engines = Vec::new();
+
+	for register_vm in [false, true] {
+		for optimizations in 0..8 {
+			let mut opts = Options { register_vm, ..Options::default() };
+			opts.optimizations.constant_folding = optimizations & 1 != 0;
+			opts.optimizations.peephole = optimizations & 2 != 0;
+			opts.optimizations.superinstructions = optimizations & 4 != 0;
+
+			let name = format!(
+				"{} vm, folding={} peephole={} superinstructions={}",
+				if register_vm { "register" } else { "stack" },
+				opts.optimizations.constant_folding,
+				opts.optimizations.peephole,
+				opts.optimizations.superinstructions,
+			);
+			engines.push((name, opts));
+		}
+	}
+
+	engines
+}
+
+// The name of a gc configuration, and how to create its options.
+type GcConfig = (&'static str, fn() -> GcOptions);
+
+// Gc options which collect as often as possible, in different ways.
+fn gc_configs() -> Vec<GcConfig> {
+	vec![
+		("stress", || {
+			let mut gc_opts = GcOptions::default();
+			gc_opts.stress = true;
+			gc_opts
+		}),
+		("tiny nursery", || {
+			let mut gc_opts = GcOptions::default();
+			gc_opts.nursery_size = 4;
+			gc_opts.pause_budget = 1;
+			gc_opts
+		}),
+	]
+}
+
+#[test]
+fn every_engine_agrees() {
+	for (program, source) in PROGRAMS {
+		let expected = run(source, Options::default());
+
+		for (engine, opts) in engines() {
+			assert_eq!(run(source, opts), expected, "{program} with the {engine}");
+		}
+	}
+}
+
+#[test]
+fn every_gc_agrees() {
+	for (program, source) in PROGRAMS {
+		let expected = run(source, Options::default());
+
+		for (gc, gc_opts) in gc_configs() {
+			for (engine, opts) in engines() {
+				let output = run_with(source, opts, gc_opts());
+				assert_eq!(output, expected, "{program} with the {engine}, and a {gc} gc");
+			}
+		}
+	}
+}
+
+#[test]
+fn tail_calls_dont_count_towards_the_call_depth() {
+	let (_, source) = PROGRAMS.iter().find(|(name, _)| *name == "tail calls").unwrap();
+	let expected = run(source, Options::default());
+
+	for (engine, mut opts) in engines() {
+		opts.max_call_depth = Some(10);
+		assert_eq!(run(source, opts), expected, "the {engine}");
+	}
+}
+
+#[test]
+fn gc_stats_and_snapshots_are_consistent() {
+	let (_, source) = PROGRAMS.iter().find(|(name, _)| *name == "allocations").unwrap();
+
+	for (gc, gc_opts) in gc_configs() {
+		with_env(Options::default(), gc_opts(), |env| {
+			let program = compile(env, source).unwrap();
+			let _capture = env.capture_output();
+
+			let mut vm = Vm::new(&program, env);
+			vm.run_entire_program_without_argv().unwrap();
+			let snapshot = vm.heap_snapshot();
+			drop(vm);
+
+			let stats = env.gc().stats();
+			assert!(stats.strings_allocated >= 300, "{gc}: {stats:?}");
+			assert!(stats.lists_allocated >= 300, "{gc}: {stats:?}");
+			assert!(stats.live_values <= stats.strings_allocated + stats.lists_allocated, "{gc}");
+			assert!(stats.live_values >= snapshot.entries.len(), "{gc}");
+
+			let snapshot_bytes = snapshot.entries.iter().map(|entry| entry.size).sum::<usize>();
+			assert!(snapshot_bytes <= stats.live_bytes, "{gc}: {snapshot_bytes} > {stats:?}");
+
+			// `acc` and `str` are both still alive, so at least they (and `acc`'s elements) are
+			// retained.
+			let retained = snapshot.entries.iter().filter(|entry| entry.retainer.is_some()).count();
+			assert!(retained > 300, "{gc}: only {retained} values are retained");
+		});
+	}
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn the_jit_agrees() {
+	for (program, source) in PROGRAMS {
+		let expected = run(source, Options::default());
+
+		for (engine, mut opts) in engines() {
+			opts.jit = true;
+			assert_eq!(run(source, opts), expected, "{program} with the {engine} and the jit");
+		}
+	}
+}
+
+// Folding shouldn't evaluate anything which would fail, even if it's never run.
+#[test]
+fn folding_keeps_errors() {
+	let mut opts = Options::default();
+	opts.optimizations.constant_folding = true;
+
+	for source in ["OUTPUT / 1 0", "; OUTPUT 1 : OUTPUT % 1 0", "OUTPUT * \"a\" ~1", "DUMP [ @"] {
+		assert_eq!(run(source, opts.clone()), run(source, Options::default()), "{source}");
+		assert!(run(source, opts.clone()).contains("=> error"), "{source}");
+	}
+}
@@ -0,0 +1,59 @@
+#![cfg(all(feature = "extensions", feature = "embedded"))]
+
+mod common;
+
+use common::run_with;
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::Options;
+
+fn with_eval(register_vm: bool) -> Options {
+	let mut opts = Options { register_vm, ..Options::default() };
+	opts.extensions.functions.eval = true;
+	opts
+}
+
+fn stress() -> GcOptions {
+	let mut gc_opts = GcOptions::default();
+	gc_opts.stress = true;
+	gc_opts
+}
+
+#[test]
+fn evaluated_source_outlives_its_value() {
+	// Nothing else refers to the source once it's been popped, but the evaluated program's variable
+	// names still borrow from it.
+	let source = r#"OUTPUT EVAL + "; = abc 12 ; = abc + abc " "+ abc 3 : * abc 2""#;
+
+	for register_vm in [false, true] {
+		assert_eq!(run_with(source, with_eval(register_vm), stress()), "54\n=> ok");
+	}
+}
+
+#[test]
+fn evaluated_calls_count_towards_the_call_depth() {
+	// `depth` nested calls to `f`, then `EVAL`, and then a call to `g` within it.
+	let program = |depth: usize| {
+		format!(
+			r#"; = n 1 ; = f BLOCK IF < n {depth} (; = n + n 1 : + 0 CALL f) EVAL "; = g BLOCK 1 : + 0 CALL g" : OUTPUT CALL f"#
+		)
+	};
+
+	for register_vm in [false, true] {
+		let opts = Options { max_call_depth: Some(10), ..with_eval(register_vm) };
+
+		assert_eq!(run_with(&program(8), opts.clone(), GcOptions::default()), "1\n=> ok");
+		assert_eq!(
+			run_with(&program(9), opts.clone(), GcOptions::default()),
+			"=> error: stack depth exceeded (at most 10 nested calls are allowed)"
+		);
+
+		// Each `EVAL` counts as a call too.
+		let nested = r#"OUTPUT EVAL "EVAL 'EVAL 1'""#;
+		assert_eq!(run_with(nested, opts.clone(), GcOptions::default()), "1\n=> ok");
+		let opts = Options { max_call_depth: Some(2), ..opts };
+		assert_eq!(
+			run_with(nested, opts, GcOptions::default()),
+			"=> error: stack depth exceeded (at most 2 nested calls are allowed)"
+		);
+	}
+}
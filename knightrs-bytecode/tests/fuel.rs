@@ -0,0 +1,144 @@
+//! Stopping vms partway through, by running out of fuel or interrupting them, and resuming them.
+
+mod common;
+
+use common::compile;
+use knightrs_bytecode::env::Environment;
+use knightrs_bytecode::gc::{Gc, GcOptions};
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::{Error, Options};
+use std::time::Duration;
+
+// Programs which allocate on every iteration, so collections happen while they're stopped.
+const ALLOCATING: [&str; 2] = [
+	r#"; = i 0 ; = l @ ; WHILE < i 40 ; = l + l ,+ "a" i : = i + i 1 : ^ l ",""#,
+	r#"; = f BLOCK : IF < n 1 "" + + "b" n ; = n - n 1 : CALL f ; = n 40 : CALL f"#,
+];
+
+fn stress() -> GcOptions {
+	let mut gc_opts = GcOptions::default();
+	gc_opts.stress = true;
+	gc_opts
+}
+
+// Runs `source` without stopping, returning the debug output of its result.
+fn run_uninterrupted(source: &str, opts: Options) -> String {
+	// SAFETY: Nothing allocated by the gc escapes.
+	unsafe {
+		Gc::new(GcOptions::default()).run(|gc| {
+			let mut env = Environment::new(opts, gc);
+			let program = compile(&mut env, source).unwrap();
+			let result = Vm::new(&program, &mut env).run_entire_program_without_argv();
+			format!("{:?}", result.unwrap())
+		})
+	}
+}
+
+#[test]
+fn suspended_vms_keep_their_values_alive() {
+	for register_vm in [false, true] {
+		let opts = Options { register_vm, ..Options::default() };
+		let expected = ALLOCATING.map(|source| run_uninterrupted(source, opts.clone()));
+
+		// SAFETY: Nothing allocated by the gc escapes.
+		let results = unsafe {
+			Gc::new(stress()).run(|gc| {
+				let mut first = Environment::new(opts.clone(), gc);
+				let mut second = Environment::new(opts.clone(), gc);
+				let first_program = compile(&mut first, ALLOCATING[0]).unwrap();
+				let second_program = compile(&mut second, ALLOCATING[1]).unwrap();
+
+				let mut vms =
+					[Vm::new(&first_program, &mut first), Vm::new(&second_program, &mut second)];
+				let mut results = [None, None];
+				let mut stops = 0;
+
+				for vm in &mut vms {
+					vm.set_fuel(Some(3));
+				}
+
+				// Take turns running each vm for a little bit, so that each one allocates (and collects)
+				// while the other's stopped.
+				for _ in 0..10_000 {
+					for (vm, result) in vms.iter_mut().zip(&mut results) {
+						if result.is_some() {
+							continue;
+						}
+
+						let step = if vm.is_suspended() {
+							vm.refuel(3);
+							vm.resume()
+						} else {
+							vm.run_entire_program_without_argv()
+						};
+
+						match step {
+							Err(Error::OutOfFuel) => stops += 1,
+							step => *result = Some(format!("{:?}", step.unwrap())),
+						}
+					}
+
+					if results.iter().all(Option::is_some) {
+						break;
+					}
+				}
+
+				assert!(stops > 20, "the vms only stopped {stops} times");
+				results.map(Option::unwrap)
+			})
+		};
+
+		assert_eq!(results, expected, "register_vm={register_vm}");
+	}
+}
+
+#[test]
+fn interrupted_vms_can_be_resumed() {
+	for register_vm in [false, true] {
+		let opts = Options { register_vm, ..Options::default() };
+		let expected = run_uninterrupted(ALLOCATING[1], opts.clone());
+
+		// SAFETY: Nothing allocated by the gc escapes.
+		let result = unsafe {
+			Gc::new(GcOptions::default()).run(|gc| {
+				let mut env = Environment::new(opts, gc);
+				let program = compile(&mut env, ALLOCATING[1]).unwrap();
+				let mut vm = Vm::new(&program, &mut env);
+				let handle = vm.interrupt_handle();
+
+				handle.interrupt();
+				let err = vm.run_entire_program_without_argv().unwrap_err();
+				assert!(matches!(err, Error::Interrupted), "{err:?}");
+				assert!(vm.is_suspended());
+
+				// It stays interrupted until it's reset.
+				assert!(matches!(vm.resume(), Err(Error::Interrupted)));
+				handle.reset();
+				format!("{:?}", vm.resume().unwrap())
+			})
+		};
+
+		assert_eq!(result, expected, "register_vm={register_vm}");
+	}
+}
+
+#[test]
+fn vms_can_be_timed_out() {
+	for register_vm in [false, true] {
+		let opts = Options { register_vm, ..Options::default() };
+
+		// SAFETY: Nothing allocated by the gc escapes.
+		unsafe {
+			Gc::new(GcOptions::default()).run(|gc| {
+				let mut env = Environment::new(opts, gc);
+				let program = compile(&mut env, "; = i 0 : WHILE TRUE : = i + i 1").unwrap();
+				let mut vm = Vm::new(&program, &mut env);
+
+				vm.interrupt_handle().interrupt_after(Duration::from_millis(10));
+				let err = vm.run_entire_program_without_argv().unwrap_err();
+				assert!(matches!(err, Error::Interrupted), "register_vm={register_vm}: {err:?}");
+				assert!(vm.is_suspended());
+			});
+		}
+	}
+}
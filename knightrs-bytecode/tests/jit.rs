@@ -0,0 +1,87 @@
+#![cfg(all(feature = "jit", feature = "embedded"))]
+
+mod common;
+
+use common::{run, PROGRAMS};
+use knightrs_bytecode::Options;
+
+/// Programs whose blocks are called often enough to be compiled, and which use values and
+/// operations that compiled code either handles itself or has to bail out on.
+const HOT_PROGRAMS: &[(&str, &str)] = &[
+	(
+		"string constants",
+		r#"
+		; = x 3001
+		; = cmp BLOCK IF < x 10 "lt" "ge"
+		; = i 0
+		; WHILE < i 2000 ; = r CALL cmp : = i + i 1
+		: OUTPUT r
+		"#,
+	),
+	(
+		"list constants",
+		r#"
+		; = wrap BLOCK IF x ,1 @
+		; = i 0
+		; = n 0
+		; WHILE < i 2000 ; = x % i 2 ; = n + n LENGTH CALL wrap : = i + i 1
+		: OUTPUT n
+		"#,
+	),
+	(
+		"integer arithmetic",
+		r#"
+		; = step BLOCK ; = a + * a 3 1 : = a % a 1000003
+		; = a 1
+		; = i 0
+		; WHILE < i 5000 ; CALL step : = i + i 1
+		: OUTPUT a
+		"#,
+	),
+	(
+		"changing types",
+		r#"
+		; = add BLOCK + x y
+		; = i 0
+		; = out @
+		; WHILE < i 3000
+			; = x IF < i 2500 i + "n" i
+			; = y IF ? (% i 3) 0 TRUE 2
+			; = r CALL add
+			; = out IF ? i 2999 ,r out
+			: = i + i 1
+		: DUMP out
+		"#,
+	),
+	(
+		"bailing out",
+		r#"
+		; = div BLOCK / 100 - 1500 i
+		; = i 0
+		; WHILE < i 2000 ; = r CALL div : = i + i 1
+		: OUTPUT r
+		"#,
+	),
+	(
+		"equality",
+		r#"
+		; = eq BLOCK IF ? x NULL 1 IF ? x TRUE 2 IF ? x "s" 3 4
+		; = i 0
+		; = t 0
+		; WHILE < i 2000
+			; = x IF ? 0 % i 4 NULL IF ? 1 % i 4 TRUE IF ? 2 % i 4 "s" i
+			; = t + t CALL eq
+			: = i + i 1
+		: OUTPUT t
+		"#,
+	),
+];
+
+#[test]
+fn jit_matches_the_interpreter() {
+	let jit = Options { jit: true, ..Options::default() };
+
+	for (name, source) in PROGRAMS.iter().chain(HOT_PROGRAMS) {
+		assert_eq!(run(source, jit.clone()), run(source, Options::default()), "{name}");
+	}
+}
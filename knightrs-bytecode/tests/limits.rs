@@ -0,0 +1,52 @@
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, with_env};
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::{Error, Options};
+
+// Runs `source`, returning the error it finished with.
+fn run_err(source: &str, opts: Options, gc_opts: GcOptions) -> Error {
+	with_env(opts, gc_opts, |env| {
+		let program = compile(env, source).unwrap();
+		let err = Vm::new(&program, env).run_entire_program_without_argv().unwrap_err();
+		err
+	})
+}
+
+fn heap_limit(max_heap_bytes: usize) -> GcOptions {
+	let mut gc_opts = GcOptions::default();
+	gc_opts.max_heap_bytes = Some(max_heap_bytes);
+	gc_opts
+}
+
+#[test]
+fn small_values_respect_the_heap_limit() {
+	// Neither boxed values nor short strings have buffers, so only their allocations are checked.
+	let sources = [
+		"; = l @ : WHILE TRUE = l ,l",
+		"; = l @ ; = i 0 : WHILE TRUE ; = i + i 1 : = l + l ,+ \"\" i",
+	];
+
+	for source in sources {
+		let err = run_err(source, Options::default(), heap_limit(1 << 16));
+		assert!(matches!(err, Error::OutOfMemory(max) if max == 1 << 16), "{source}: {err}");
+	}
+}
+
+#[test]
+fn garbage_is_collected_before_running_out() {
+	with_env(Options::default(), heap_limit(1 << 16), |env| {
+		let program = compile(env, "; = i 0 : WHILE < i 10000 ; = l ,,i : = i + i 1").unwrap();
+		Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+	});
+}
+
+#[test]
+fn limits_are_not_stacktraces() {
+	let opts = Options { max_call_depth: Some(100), ..Options::default() };
+	let err = run_err("; = f BLOCK + 1 CALL f : CALL f", opts, GcOptions::default());
+	assert!(matches!(err, Error::StackDepthExceeded(100)), "{err}");
+}
@@ -0,0 +1,41 @@
+//! Redirecting what programs output.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, with_env};
+use knightrs_bytecode::env::Capture;
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::Options;
+
+#[test]
+fn capture_output_captures_output_and_dump() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let capture = env.capture_output();
+		// A trailing backslash stops `OUTPUT` from adding a newline.
+		let program = compile(env, r#"; OUTPUT "a" ; DUMP 1 ; OUTPUT "b\" : DUMP ,"c""#).unwrap();
+		Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+
+		assert_eq!(capture.contents(), "a\n1b[\"c\"]");
+		assert_eq!(capture.take(), "a\n1b[\"c\"]");
+		assert_eq!(capture.take(), "");
+	});
+}
+
+#[test]
+fn set_stdout_replaces_the_previous_stdout() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, r#"OUTPUT "hi""#).unwrap();
+		let first = env.capture_output();
+		let second = Capture::new();
+
+		Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+		env.set_stdout(second.clone());
+		Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+		Vm::new(&program, env).run_entire_program_without_argv().unwrap();
+
+		assert_eq!(first.take(), "hi\n");
+		assert_eq!(second.take(), "hi\nhi\n");
+	});
+}
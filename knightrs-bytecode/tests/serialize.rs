@@ -0,0 +1,165 @@
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, run_program, with_env, PROGRAMS};
+use knightrs_bytecode::env::Environment;
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::parser::source_location::ProgramSource;
+use knightrs_bytecode::program::{DeserializeError, Program, FORMAT_VERSION, MAGIC};
+use knightrs_bytecode::Options;
+
+fn serialize(program: &Program<'_, '_, '_>) -> Vec<u8> {
+	let mut bytes = Vec::new();
+	program.serialize(&mut bytes).unwrap();
+	bytes
+}
+
+fn deserialize<'gc>(
+	env: &Environment<'gc>,
+	bytes: &[u8],
+) -> Result<Program<'static, 'static, 'gc>, DeserializeError> {
+	env.gc().pause();
+	// SAFETY: The gc is paused.
+	let program = unsafe { Program::deserialize(bytes, ProgramSource::Other("<test>"), env) };
+	env.gc().unpause();
+	program
+}
+
+#[test]
+fn round_trip() {
+	for (name, source) in PROGRAMS {
+		// Programs' constants are only kept alive while they're running, so each program gets its own
+		// environment.
+		let (bytes, output) = with_env(Options::default(), GcOptions::default(), |env| {
+			let program = compile(env, source).unwrap();
+			(serialize(&program), run_program(env, &program))
+		});
+
+		with_env(Options::default(), GcOptions::default(), |env| {
+			let loaded = deserialize(env, &bytes).unwrap();
+			assert_eq!(serialize(&loaded), bytes, "{name}: serializing again changed it");
+			assert_eq!(run_program(env, &loaded), output, "{name}");
+		});
+	}
+}
+
+#[test]
+fn rejects_other_formats() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, "OUTPUT 1").unwrap();
+		let bytes = serialize(&program);
+		assert!(bytes.starts_with(&MAGIC));
+
+		let mut wrong_magic = bytes.clone();
+		wrong_magic[0] ^= 1;
+		assert!(matches!(deserialize(env, &wrong_magic), Err(DeserializeError::BadMagic)));
+
+		let mut wrong_version = bytes.clone();
+		wrong_version[MAGIC.len()..][..2].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+		assert!(matches!(
+			deserialize(env, &wrong_version),
+			Err(DeserializeError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+		));
+
+		let mut trailing = bytes.clone();
+		trailing.push(0);
+		assert!(matches!(deserialize(env, &trailing), Err(DeserializeError::TrailingData)));
+	});
+}
+
+#[test]
+fn rejects_out_of_bounds_blocks() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, "CALL BLOCK x").unwrap();
+		let mut bytes = serialize(&program);
+
+		// The header is the magic, version, and features byte, and is followed by the code and then
+		// the constants, the only one of which is the block.
+		let code_len_at = MAGIC.len() + 2 + 1;
+		let code_len = u64::from_le_bytes(bytes[code_len_at..][..8].try_into().unwrap()) as usize;
+		let block_at = code_len_at + 8 + code_len + 8;
+		assert_eq!(bytes[block_at], 6, "the first constant should be the block");
+
+		for index in [code_len as u64, u32::MAX as u64, u64::MAX >> 1] {
+			bytes[block_at + 1..][..8].copy_from_slice(&index.to_le_bytes());
+			assert!(
+				matches!(deserialize(env, &bytes), Err(DeserializeError::BlockOutOfBounds(_))),
+				"block index {index} was accepted"
+			);
+		}
+	});
+}
+
+#[test]
+fn rejects_deeply_nested_constants() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, "OUTPUT 1").unwrap();
+		let bytes = serialize(&program);
+
+		// Replaces the `1` with a list nested `depth` times, followed by the rest of the program.
+		let code_len_at = MAGIC.len() + 2 + 1;
+		let code_len = u64::from_le_bytes(bytes[code_len_at..][..8].try_into().unwrap()) as usize;
+		let constants_at = code_len_at + 8 + code_len;
+		let nested = |depth: usize| {
+			let mut nested = bytes[..constants_at].to_vec();
+			nested.extend_from_slice(&1u64.to_le_bytes());
+			for _ in 0..depth {
+				nested.push(5);
+				nested.extend_from_slice(&1u64.to_le_bytes());
+			}
+			nested.extend_from_slice(&bytes[constants_at + 8..]);
+			nested
+		};
+
+		assert!(deserialize(env, &nested(100)).is_ok());
+
+		// Far more deeply than the stack allows.
+		let err = deserialize(env, &nested(1_000_000));
+		assert!(matches!(err, Err(DeserializeError::TooDeeplyNested)), "{err:?}");
+	});
+}
+
+#[test]
+fn rejects_duplicate_variables() {
+	with_env(Options::default(), GcOptions::default(), |env| {
+		let program = compile(env, "; = a 1 : = b 2").unwrap();
+		let mut bytes = serialize(&program);
+
+		// Variable names are their length followed by their bytes.
+		let b = [&1u64.to_le_bytes()[..], b"b"].concat();
+		let at = bytes.windows(b.len()).rposition(|window| window == b).unwrap();
+		bytes[at + 8] = b'a';
+
+		assert!(matches!(
+			deserialize(env, &bytes),
+			Err(DeserializeError::DuplicateVariable(name)) if name == "a"
+		));
+	});
+}
+
+// Corrupting any part of a program should only ever be an error, and never panic.
+#[test]
+fn corrupt_input_is_an_error() {
+	let sources = ["; = f BLOCK + 1 x ; = x \"abc\" : DUMP CALL f", PROGRAMS[3].1];
+
+	for source in sources {
+		with_env(Options::default(), GcOptions::default(), |env| {
+			let program = compile(env, source).unwrap();
+			let bytes = serialize(&program);
+
+			for len in 0..bytes.len() {
+				assert!(deserialize(env, &bytes[..len]).is_err(), "truncated to {len} bytes");
+			}
+
+			for at in 0..bytes.len() {
+				let original = bytes[at];
+				for byte in [0x00, 0x01, 0x7f, 0x80, 0xff, original ^ 0x01, original ^ 0x40] {
+					let mut corrupt = bytes.clone();
+					corrupt[at] = byte;
+					let _ = deserialize(env, &corrupt);
+				}
+			}
+		});
+	}
+}
@@ -0,0 +1,85 @@
+//! Recording the inputs programs are given, and replaying them later.
+#![cfg(feature = "embedded")]
+
+mod common;
+
+use common::{compile, run_program, with_env};
+use knightrs_bytecode::env::{Capture, Pcg32};
+use knightrs_bytecode::gc::GcOptions;
+use knightrs_bytecode::vm::Vm;
+use knightrs_bytecode::Options;
+
+const USES_INPUTS: &str = r#"
+	; OUTPUT PROMPT
+	; OUTPUT RANDOM
+	; OUTPUT + "" RANDOM
+	; OUTPUT PROMPT
+	: OUTPUT PROMPT
+"#;
+
+// Replays `transcript` for a program that's just `RANDOM`, returning the error it stops with.
+fn replay_random(transcript: &'static str, opts: Options) -> Option<String> {
+	with_env(opts, GcOptions::default(), |env| {
+		env.replay(transcript.as_bytes());
+		let program = compile(env, "RANDOM").unwrap();
+		let result = Vm::new(&program, env).run_entire_program_without_argv();
+		result.err().map(|err| err.to_string())
+	})
+}
+
+fn is_out_of_bounds(err: Option<String>) -> bool {
+	err.is_some_and(|err| err.contains("recorded RANDOM is out of bounds"))
+}
+
+#[test]
+fn replays_give_the_recorded_inputs() {
+	for register_vm in [false, true] {
+		let opts = Options { register_vm, ..Options::default() };
+		let transcript = Capture::new();
+
+		let recorded = with_env(opts.clone(), GcOptions::default(), |env| {
+			env.set_stdin("first\nsecond\n".as_bytes());
+			env.set_rng(Pcg32::new(1));
+			env.record(transcript.clone());
+			let program = compile(env, USES_INPUTS).unwrap();
+			run_program(env, &program)
+		});
+
+		// Neither stdin nor the rng are used when replaying.
+		let replayed = with_env(opts, GcOptions::default(), |env| {
+			let transcript = transcript.contents();
+			env.set_rng(Pcg32::new(2));
+			env.replay(std::io::Cursor::new(transcript));
+			let program = compile(env, USES_INPUTS).unwrap();
+			run_program(env, &program)
+		});
+
+		assert!(recorded.starts_with("first\n"), "{recorded}");
+		assert!(recorded.ends_with("second\n\n=> ok"), "{recorded}");
+		assert_eq!(replayed, recorded, "register_vm={register_vm}");
+	}
+}
+
+#[test]
+fn replayed_random_numbers_are_checked() {
+	assert_eq!(replay_random("RANDOM 12\n", Options::default()), None);
+	assert!(is_out_of_bounds(replay_random("RANDOM -1\n", Options::default())));
+
+	let diverged = replay_random("PROMPT EOF\n", Options::default()).unwrap();
+	assert!(diverged.contains("replay diverged"), "{diverged}");
+
+	#[cfg(feature = "extensions")]
+	{
+		let mut opts = Options::default();
+		opts.extensions.breaking.random_can_be_negative = true;
+		assert_eq!(replay_random("RANDOM -1\n", opts), None);
+	}
+
+	#[cfg(feature = "compliance")]
+	{
+		let mut opts = Options::default();
+		opts.compliance.limit_rand_range = true;
+		assert_eq!(replay_random("RANDOM 32767\n", opts.clone()), None);
+		assert!(is_out_of_bounds(replay_random("RANDOM 32768\n", opts)));
+	}
+}
@@ -0,0 +1,134 @@
+//! Runs translated WebAssembly modules which import their I/O from the host, with wasmtime.
+#![cfg(all(feature = "wasm", feature = "embedded"))]
+
+mod common;
+
+use common::{compile, run, with_env, PROGRAMS};
+use knightrs_bytecode::program::WasmImports;
+use knightrs_bytecode::Options;
+use std::io::{Cursor, Read};
+use wasmtime::{Caller, Engine, Linker, Module, Store};
+
+// What a module wrote, and the status it quit with.
+#[derive(Debug, Default)]
+struct Finished {
+	stdout: String,
+	stderr: String,
+	status: Option<i32>,
+}
+
+// The state given to the host functions.
+struct Host {
+	stdin: Cursor<Vec<u8>>,
+	random: i64,
+	finished: Finished,
+}
+
+// Gets the `length` bytes at `bytes` from the module's memory.
+fn memory<'a>(caller: &'a mut Caller<'_, Host>, bytes: i32, length: i32) -> &'a mut [u8] {
+	let memory = caller.get_export("memory").and_then(|export| export.into_memory()).unwrap();
+	let (start, length) = (bytes as usize, length as usize);
+	&mut memory.data_mut(caller)[start..start + length]
+}
+
+// Translates `source` into a module using the host's imports, and runs it with `stdin`.
+fn run_wasm(source: &str, stdin: &str) -> Finished {
+	let module = with_env(Options::default(), Default::default(), |env| {
+		let program = compile(env, source).unwrap();
+		program.to_wat(env.opts()).unwrap().with_imports(WasmImports::Host).to_wasm()
+	});
+
+	let engine = Engine::default();
+	let module = Module::new(&engine, module).unwrap();
+	let mut linker = Linker::<Host>::new(&engine);
+
+	linker
+		.func_wrap("knight", "output", |mut caller: Caller<'_, Host>, bytes: i32, length: i32| {
+			let output = String::from_utf8_lossy(memory(&mut caller, bytes, length)).into_owned();
+			caller.data_mut().finished.stdout += &output;
+		})
+		.unwrap();
+	linker
+		.func_wrap("knight", "error", |mut caller: Caller<'_, Host>, bytes: i32, length: i32| {
+			let output = String::from_utf8_lossy(memory(&mut caller, bytes, length)).into_owned();
+			caller.data_mut().finished.stderr += &output;
+		})
+		.unwrap();
+	linker
+		.func_wrap("knight", "prompt", |mut caller: Caller<'_, Host>, buffer: i32, capacity: i32| {
+			let mut stdin = std::mem::take(&mut caller.data_mut().stdin);
+			let read = stdin.read(memory(&mut caller, buffer, capacity)).unwrap();
+			caller.data_mut().stdin = stdin;
+			read as i32
+		})
+		.unwrap();
+	linker
+		.func_wrap("knight", "random", |mut caller: Caller<'_, Host>| {
+			caller.data_mut().random += 1;
+			caller.data().random
+		})
+		.unwrap();
+	linker
+		.func_wrap("knight", "quit", |mut caller: Caller<'_, Host>, status: i32| {
+			caller.data_mut().finished.status = Some(status);
+			Err::<(), _>(wasmtime::Error::msg("quit"))
+		})
+		.unwrap();
+
+	let host = Host {
+		stdin: Cursor::new(stdin.as_bytes().to_vec()),
+		random: 0,
+		finished: Finished::default(),
+	};
+	let mut store = Store::new(&engine, host);
+	let instance = linker.instantiate(&mut store, &module).unwrap();
+	let start = instance.get_typed_func::<(), ()>(&mut store, "_start").unwrap();
+
+	let result = start.call(&mut store, ());
+	let finished = store.into_data().finished;
+	assert_eq!(result.is_err(), finished.status.is_some(), "{result:?}");
+	finished
+}
+
+#[test]
+fn output_matches_the_vm() {
+	for (program, source) in PROGRAMS {
+		let expected = run(source, Options::default());
+		let (expected, _) = expected.rsplit_once("=> ").unwrap();
+
+		let finished = run_wasm(source, "");
+		assert_eq!(finished.stdout, expected, "{program}");
+	}
+}
+
+#[test]
+fn prompt_reads_lines_from_the_host() {
+	let finished = run_wasm(
+		"; OUTPUT PROMPT ; OUTPUT PROMPT ; OUTPUT PROMPT : DUMP PROMPT",
+		"first\nsecond\r\nlast",
+	);
+	assert_eq!(finished.stdout, "first\nsecond\nlast\nnull");
+	assert_eq!(finished.status, None);
+
+	let long = "x".repeat(10_000);
+	let finished = run_wasm(": OUTPUT LENGTH PROMPT", &long);
+	assert_eq!(finished.stdout, "10000\n");
+}
+
+#[test]
+fn random_comes_from_the_host() {
+	let finished = run_wasm("; OUTPUT RANDOM : OUTPUT RANDOM", "");
+	assert_eq!(finished.stdout, "1\n2\n");
+}
+
+#[test]
+fn quit_and_errors_call_the_host() {
+	let finished = run_wasm("; OUTPUT 1 ; QUIT 3 : OUTPUT 2", "");
+	assert_eq!(finished.stdout, "1\n");
+	assert_eq!(finished.status, Some(3));
+
+	let finished = run_wasm("; OUTPUT 1 : OUTPUT / 1 0", "");
+	assert_eq!(finished.stdout, "1\n");
+	assert!(finished.stderr.ends_with('\n'), "{:?}", finished.stderr);
+	assert_eq!(finished.status, Some(1));
+}
@@ -0,0 +1,172 @@
+//! Stopping running programs from other threads.
+
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Once, PoisonError, Weak};
+use std::time::{Duration, Instant};
+
+/// A handle which can be used to stop running Knight programs, even from another thread.
+///
+/// Programs check their handle every iteration of a loop and every time they call a block, and stop
+/// with an `Interrupted` error once it's been [interrupted](Self::interrupt). Handles can be cloned,
+/// and every clone refers to the same interrupt, so the same one can be used to stop more than one
+/// program. Handles are always `Send + Sync`, even when the programs using them aren't.
+///
+/// Interrupts stick around until the handle's [reset](Self::reset), so everything using the handle
+/// will keep being interrupted until then.
+///
+/// # Examples
+/// ```rust
+/// use knightrs_common::InterruptHandle;
+/// use std::time::Duration;
+///
+/// let handle = InterruptHandle::new();
+/// handle.interrupt_after(Duration::from_millis(10));
+///
+/// let other_thread = handle.clone();
+/// while !other_thread.is_interrupted() {
+///     std::thread::yield_now();
+/// }
+///
+/// handle.reset();
+/// assert!(!other_thread.is_interrupted());
+/// ```
+#[derive(Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicU64>);
+
+// The lowest bit of the state is whether the handle is interrupted; the rest of it is how many
+// times the handle's been reset, so that deadlines from before a reset can tell they're stale.
+const INTERRUPTED: u64 = 1;
+const GENERATION: u64 = 2;
+
+// Every handle's pending deadlines. They're all kept by the same thread, which is woken up by
+// `DEADLINES_CHANGED` whenever one's added.
+static DEADLINES: Mutex<Vec<Deadline>> = Mutex::new(Vec::new());
+static DEADLINES_CHANGED: Condvar = Condvar::new();
+static START_DEADLINE_THREAD: Once = Once::new();
+
+struct Deadline {
+	at: Instant,
+	state: Weak<AtomicU64>,
+	generation: u64,
+}
+
+// Nothing panics while the lock's held, but there's no need to panic if something ever does.
+fn deadlines() -> MutexGuard<'static, Vec<Deadline>> {
+	DEADLINES.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Interrupts handles as their deadlines pass, forever.
+fn keep_deadlines() {
+	let mut deadlines = deadlines();
+
+	loop {
+		let now = Instant::now();
+		deadlines.retain(|deadline| {
+			// Nothing needs to be kept around for handles which have all been dropped.
+			if deadline.at > now {
+				return deadline.state.strong_count() != 0;
+			}
+
+			// If it's been reset since, the deadline's stale and there's nothing to interrupt.
+			if let Some(state) = deadline.state.upgrade() {
+				let interrupted = deadline.generation | INTERRUPTED;
+				let _ = state.compare_exchange(
+					deadline.generation,
+					interrupted,
+					Ordering::Relaxed,
+					Ordering::Relaxed,
+				);
+			}
+			false
+		});
+
+		deadlines = match deadlines.iter().map(|deadline| deadline.at).min() {
+			Some(next) => {
+				let timeout = next.saturating_duration_since(now);
+				DEADLINES_CHANGED
+					.wait_timeout(deadlines, timeout)
+					.unwrap_or_else(PoisonError::into_inner)
+					.0
+			}
+			None => DEADLINES_CHANGED.wait(deadlines).unwrap_or_else(PoisonError::into_inner),
+		};
+	}
+}
+
+impl Debug for InterruptHandle {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		f.debug_struct("InterruptHandle").field("interrupted", &self.is_interrupted()).finish()
+	}
+}
+
+impl InterruptHandle {
+	/// Creates a new handle, which isn't interrupted.
+	#[must_use]
+	#[inline]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Interrupts everything using the handle.
+	#[inline]
+	pub fn interrupt(&self) {
+		self.0.fetch_or(INTERRUPTED, Ordering::Relaxed);
+	}
+
+	/// Whether the handle has been interrupted.
+	#[must_use]
+	#[inline]
+	pub fn is_interrupted(&self) -> bool {
+		self.0.load(Ordering::Relaxed) & INTERRUPTED != 0
+	}
+
+	/// Clears the interrupt, and cancels any pending deadlines.
+	pub fn reset(&self) {
+		let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+			Some(state.wrapping_add(GENERATION) & !INTERRUPTED)
+		});
+
+		// The deadlines would be ignored anyways, but there's no reason to keep them around.
+		if START_DEADLINE_THREAD.is_completed() {
+			deadlines()
+				.retain(|deadline| !std::ptr::eq(deadline.state.as_ptr(), Arc::as_ptr(&self.0)));
+		}
+	}
+
+	/// Interrupts the handle once `timeout` has passed, unless it's [reset](Self::reset) first.
+	///
+	/// This is a shorthand for [`interrupt_at`](Self::interrupt_at) `timeout` from now.
+	#[inline]
+	pub fn interrupt_after(&self, timeout: Duration) {
+		self.interrupt_at(Instant::now() + timeout)
+	}
+
+	/// Interrupts the handle at `deadline`, unless it's [reset](Self::reset) first.
+	///
+	/// Deadlines are kept by a separate thread (which every handle shares), so programs don't have to
+	/// keep checking the time while they're running.
+	pub fn interrupt_at(&self, deadline: Instant) {
+		START_DEADLINE_THREAD.call_once(|| {
+			std::thread::Builder::new()
+				.name("knightrs-deadlines".to_string())
+				.spawn(keep_deadlines)
+				.expect("couldn't start the thread which keeps deadlines");
+		});
+
+		let generation = self.0.load(Ordering::Relaxed) & !INTERRUPTED;
+		deadlines().push(Deadline { at: deadline, state: Arc::downgrade(&self.0), generation });
+		DEADLINES_CHANGED.notify_one();
+	}
+
+	/// Gets a pointer to the handle's state, for code which can't call [`is_interrupted`](
+	/// Self::is_interrupted), such as code compiled at runtime.
+	///
+	/// The state's lowest bit is set while the handle's interrupted, and it must only be read
+	/// atomically. The pointer is valid for as long as the handle (or any of its clones) is.
+	#[must_use]
+	#[inline]
+	pub fn as_ptr(&self) -> *const u64 {
+		self.0.as_ptr()
+	}
+}
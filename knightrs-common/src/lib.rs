@@ -0,0 +1,14 @@
+//! Code shared by `knightrs` and `knightrs-bytecode`.
+//!
+//! These are the parts of running Knight programs which don't depend on how they're run: stopping
+//! programs from other threads, generating random numbers, and recording and replaying the inputs
+//! programs are given. Both interpreters re-export everything here, so it doesn't need to be used
+//! directly.
+
+pub mod interrupt;
+pub mod random;
+pub mod transcript;
+
+pub use interrupt::InterruptHandle;
+pub use random::{Pcg32, Random};
+pub use transcript::Input;
@@ -0,0 +1,139 @@
+//! Generating random numbers for `RANDOM`.
+
+use rand::{RngCore, SeedableRng};
+
+/// A source of random numbers for `RANDOM`, which programs can reseed.
+///
+/// This is implemented for every [`rand`] RNG which can be seeded, so any of them can be given to
+/// an environment. However, most of them (including the default, [`StdRng`](rand::rngs::StdRng))
+/// don't guarantee they'll produce the same numbers in future versions, so [`Pcg32`] should be used
+/// when that matters.
+///
+/// This exists instead of simply using [`RngCore`] as that can't be reseeded without knowing its type.
+pub trait Random {
+	/// Gets the next random number.
+	fn next_u64(&mut self) -> u64;
+
+	/// Restarts the sequence of random numbers from `seed`.
+	fn reseed(&mut self, seed: u64);
+}
+
+impl<T: RngCore + SeedableRng> Random for T {
+	#[inline]
+	fn next_u64(&mut self) -> u64 {
+		RngCore::next_u64(self)
+	}
+
+	#[inline]
+	fn reseed(&mut self, seed: u64) {
+		*self = T::seed_from_u64(seed);
+	}
+}
+
+/// Gets a random integer in `min..=max`.
+///
+/// Only [`Random::next_u64`] is used (unlike [`rand::Rng::gen_range`], which may change between
+/// versions), so the same sequence of numbers always yields the same integers.
+pub fn in_range<R: Random + ?Sized>(rng: &mut R, min: i64, max: i64) -> i64 {
+	debug_assert!(min <= max);
+
+	// The amount of possible integers, which wraps around to zero for the entire range of `i64`.
+	let span = (max.wrapping_sub(min) as u64).wrapping_add(1);
+	if span == 0 {
+		return rng.next_u64() as i64;
+	}
+
+	// Lemire's method: the high half of `random * span` is in `0..span`, and it's unbiased as long
+	// as the low half isn't below `threshold`.
+	let threshold = span.wrapping_neg() % span;
+	loop {
+		let product = rng.next_u64() as u128 * span as u128;
+		if product as u64 >= threshold {
+			return min.wrapping_add((product >> 64) as i64);
+		}
+	}
+}
+
+/// A small random number generator whose output is fully specified, and will never change.
+///
+/// This is the `PCG-XSH-RR` generator from the [PCG paper](https://www.pcg-random.org/paper.html),
+/// with 64 bits of state and 32-bit outputs, using the multiplier `6364136223846793005` and
+/// increment `1442695040888963407`. Seeding with `seed` is done like the reference implementation's
+/// `pcg32_srandom_r`: the state starts as zero, is advanced, has `seed` added, and is advanced again.
+/// Each [`next_u64`](RngCore::next_u64) is two outputs, with the first being the upper half.
+///
+/// Given the same seed, it'll always produce the same numbers, regardless of platform or version.
+///
+/// # Examples
+/// ```rust
+/// use knightrs_common::random::{in_range, Pcg32};
+///
+/// let mut rng = Pcg32::new(1234);
+/// let first = in_range(&mut rng, 0, 100);
+/// assert!((0..=100).contains(&first));
+///
+/// assert_eq!(in_range(&mut Pcg32::new(1234), 0, 100), first);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcg32 {
+	state: u64,
+}
+
+impl Pcg32 {
+	const MULTIPLIER: u64 = 6364136223846793005;
+	const INCREMENT: u64 = 1442695040888963407;
+
+	/// Creates a new generator, seeded with `seed`.
+	#[must_use]
+	pub fn new(seed: u64) -> Self {
+		let mut pcg = Self { state: 0 };
+		pcg.step();
+		pcg.state = pcg.state.wrapping_add(seed);
+		pcg.step();
+		pcg
+	}
+
+	fn step(&mut self) {
+		self.state = self.state.wrapping_mul(Self::MULTIPLIER).wrapping_add(Self::INCREMENT);
+	}
+}
+
+impl RngCore for Pcg32 {
+	fn next_u32(&mut self) -> u32 {
+		let old = self.state;
+		self.step();
+
+		let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+		xorshifted.rotate_right((old >> 59) as u32)
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		let high = self.next_u32() as u64;
+		let low = self.next_u32() as u64;
+		(high << 32) | low
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8]) {
+		for chunk in dest.chunks_mut(4) {
+			chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+		}
+	}
+
+	fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+		self.fill_bytes(dest);
+		Ok(())
+	}
+}
+
+impl SeedableRng for Pcg32 {
+	type Seed = [u8; 8];
+
+	/// Seeds the generator with `seed`, as a little-endian `u64`.
+	fn from_seed(seed: Self::Seed) -> Self {
+		Self::new(u64::from_le_bytes(seed))
+	}
+
+	fn seed_from_u64(seed: u64) -> Self {
+		Self::new(seed)
+	}
+}
@@ -0,0 +1,206 @@
+//! Recording and replaying the inputs given to Knight programs.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Read, Write};
+
+/// An input given to a program which might not be the same between runs, such as a line read by
+/// `PROMPT`.
+///
+/// Transcripts are a list of inputs, each of which is a line starting with the input's kind:
+/// - `PROMPT <len>`, followed by the line that was read (which is `<len>` bytes) and a newline, or
+///   `PROMPT EOF` if the end of stdin was reached.
+/// - `RANDOM <int>`.
+/// - `SYSTEM <command len> <output len>`, followed by the command and a newline, and then the
+///   output and a newline.
+/// - `USE <filename len> <contents len>`, followed by the filename and a newline, and then the
+///   contents and a newline.
+///
+/// # Examples
+/// ```rust
+/// use knightrs_common::Input;
+///
+/// let hello = Input::Prompt(Some("hello".to_string()));
+/// let mut transcript = Vec::new();
+/// hello.write(&mut transcript).unwrap();
+/// Input::Random(12).write(&mut transcript).unwrap();
+/// assert_eq!(transcript, b"PROMPT 5\nhello\nRANDOM 12\n");
+///
+/// let mut transcript = &transcript[..];
+/// assert_eq!(Input::read(&mut transcript).unwrap(), Some(hello));
+/// assert_eq!(Input::read(&mut transcript).unwrap(), Some(Input::Random(12)));
+/// assert_eq!(Input::read(&mut transcript).unwrap(), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+	/// A line read by `PROMPT`, or `None` if the end of stdin was reached.
+	Prompt(Option<String>),
+
+	/// An integer returned by `RANDOM`.
+	Random(i64),
+
+	/// The output of a command run by `` ` `` (or `XSYSTEM`).
+	System { command: String, output: String },
+
+	/// The contents of a file read by `USE`.
+	UseFile { filename: String, contents: String },
+}
+
+impl Display for Input {
+	/// Writes a short description of the input, for use in error messages.
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			Self::Prompt(_) => f.write_str("PROMPT"),
+			Self::Random(_) => f.write_str("RANDOM"),
+			Self::System { command, .. } => write!(f, "the command {command:?}"),
+			Self::UseFile { filename, .. } => write!(f, "the file {filename:?}"),
+		}
+	}
+}
+
+fn invalid(message: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("invalid transcript: {message}"))
+}
+
+impl Input {
+	/// Writes the input to `out`, in the format described above.
+	pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+		match self {
+			Self::Prompt(None) => writeln!(out, "PROMPT EOF"),
+			Self::Prompt(Some(line)) => writeln!(out, "PROMPT {}\n{line}", line.len()),
+			Self::Random(int) => writeln!(out, "RANDOM {int}"),
+			Self::System { command, output } => {
+				writeln!(out, "SYSTEM {} {}\n{command}\n{output}", command.len(), output.len())
+			}
+			Self::UseFile { filename, contents } => {
+				writeln!(out, "USE {} {}\n{filename}\n{contents}", filename.len(), contents.len())
+			}
+		}
+	}
+
+	/// Reads the next input from `input`, or returns `None` at the end of the transcript.
+	pub fn read(input: &mut dyn BufRead) -> io::Result<Option<Self>> {
+		let mut header = String::new();
+		if input.read_line(&mut header)? == 0 {
+			return Ok(None);
+		}
+
+		let mut fields = header.trim_end_matches('\n').split(' ');
+		let kind = fields.next().unwrap_or_default();
+		let mut number = || -> io::Result<usize> {
+			fields.next().and_then(|field| field.parse().ok()).ok_or_else(|| invalid(&header))
+		};
+
+		let input = match kind {
+			"PROMPT" if header == "PROMPT EOF\n" => Self::Prompt(None),
+			"PROMPT" => Self::Prompt(Some(read_string(input, number()?)?)),
+			"RANDOM" => Self::Random(
+				header["RANDOM ".len()..].trim_end().parse().map_err(|_| invalid(&header))?,
+			),
+			"SYSTEM" => {
+				let (command_len, output_len) = (number()?, number()?);
+				let command = read_string(input, command_len)?;
+				Self::System { command, output: read_string(input, output_len)? }
+			}
+			"USE" => {
+				let (filename_len, contents_len) = (number()?, number()?);
+				let filename = read_string(input, filename_len)?;
+				Self::UseFile { filename, contents: read_string(input, contents_len)? }
+			}
+			_ => return Err(invalid(&header)),
+		};
+
+		Ok(Some(input))
+	}
+}
+
+// Reads a string that's `len` bytes long, followed by a newline. The length comes from the
+// transcript, so only what's actually there is allocated, rather than trusting it up front.
+fn read_string(input: &mut dyn BufRead, len: usize) -> io::Result<String> {
+	let mut bytes = Vec::new();
+	(&mut *input).take(len as u64).read_to_end(&mut bytes)?;
+	if bytes.len() != len {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+
+	let mut newline = [0];
+	input.read_exact(&mut newline)?;
+	if newline != *b"\n" {
+		return Err(invalid("missing newline after a string"));
+	}
+
+	String::from_utf8(bytes).map_err(|_| invalid("strings must be utf-8"))
+}
+
+/// The problems which can happen when [replaying](Transcript::replay) a transcript.
+#[derive(Debug)]
+pub enum ReplayError {
+	/// The transcript couldn't be read, or isn't valid.
+	Io(io::Error),
+
+	/// The program asked for a different input than what was recorded next.
+	Diverged { requested: String, recorded: String },
+}
+
+impl Display for ReplayError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			Self::Io(err) => Display::fmt(err, f),
+			Self::Diverged { requested, recorded } => {
+				write!(f, "replay diverged: asked for {requested}, but the transcript has {recorded}")
+			}
+		}
+	}
+}
+
+impl std::error::Error for ReplayError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Self::Io(err) => Some(err),
+			Self::Diverged { .. } => None,
+		}
+	}
+}
+
+/// Whether an environment's inputs are being recorded to `W`, or replayed from `R`.
+#[derive(Debug, Default)]
+pub enum Transcript<W, R> {
+	/// Inputs are neither recorded nor replayed.
+	#[default]
+	None,
+
+	/// Inputs are written to the transcript as they're given to programs.
+	Record(W),
+
+	/// Inputs are read from the transcript, instead of from wherever they normally come from.
+	Replay(R),
+}
+
+impl<W: Write, R: BufRead> Transcript<W, R> {
+	/// Records `input` if recording. It's flushed immediately, so the transcript is still complete if
+	/// the program crashes.
+	pub fn record(&mut self, input: Input) -> io::Result<()> {
+		if let Self::Record(out) = self {
+			input.write(out)?;
+			out.flush()?;
+		}
+
+		Ok(())
+	}
+
+	/// Gets the next input if replaying, or `None` otherwise.
+	///
+	/// Only the description of `requested` (see the [`Display`] impl of [`Input`]) matters, and it's
+	/// an error if the next input's description is different.
+	pub fn replay(&mut self, requested: &Input) -> Result<Option<Input>, ReplayError> {
+		let Self::Replay(transcript) = self else {
+			return Ok(None);
+		};
+
+		let requested = requested.to_string();
+		match Input::read(transcript).map_err(ReplayError::Io)? {
+			Some(input) if input.to_string() == requested => Ok(Some(input)),
+			Some(input) => Err(ReplayError::Diverged { requested, recorded: input.to_string() }),
+			None => Err(ReplayError::Diverged { requested, recorded: "nothing left".to_string() }),
+		}
+	}
+}
@@ -0,0 +1,53 @@
+use knightrs_common::InterruptHandle;
+use std::time::{Duration, Instant};
+
+// Waits for up to a few seconds for `handle` to be interrupted, returning whether it was.
+fn wait_for_interrupt(handle: &InterruptHandle) -> bool {
+	let give_up = Instant::now() + Duration::from_secs(5);
+	while Instant::now() < give_up {
+		if handle.is_interrupted() {
+			return true;
+		}
+		std::thread::sleep(Duration::from_millis(1));
+	}
+	false
+}
+
+#[test]
+fn deadlines_interrupt_only_their_handles() {
+	let later = InterruptHandle::new();
+	let sooner = InterruptHandle::new();
+
+	// The sooner deadline is added last, so it has to wake up the thread that's waiting on the later one.
+	later.interrupt_after(Duration::from_secs(60));
+	sooner.interrupt_after(Duration::from_millis(10));
+
+	assert!(wait_for_interrupt(&sooner));
+	assert!(!later.is_interrupted());
+	later.reset();
+}
+
+#[test]
+fn resetting_cancels_deadlines() {
+	let handle = InterruptHandle::new();
+	handle.interrupt_after(Duration::from_millis(20));
+	handle.reset();
+
+	std::thread::sleep(Duration::from_millis(60));
+	assert!(!handle.is_interrupted());
+
+	// New deadlines still work after a reset.
+	handle.interrupt_after(Duration::from_millis(10));
+	assert!(wait_for_interrupt(&handle));
+}
+
+#[test]
+fn dropped_handles_are_forgotten() {
+	let handle = InterruptHandle::new();
+	handle.interrupt_after(Duration::from_millis(10));
+	drop(handle);
+
+	let other = InterruptHandle::new();
+	other.interrupt_after(Duration::from_millis(30));
+	assert!(wait_for_interrupt(&other));
+}
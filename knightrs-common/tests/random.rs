@@ -0,0 +1,54 @@
+use knightrs_common::random::{in_range, Pcg32, Random};
+use rand::RngCore;
+
+// `Pcg32` must produce these numbers forever, as programs rely on a seed always producing the same
+// ones. They were computed independently, by a generator which also reproduces the PCG reference
+// implementation's demo output.
+
+#[test]
+fn pcg32_output_never_changes() {
+	let mut rng = Pcg32::new(42);
+	let outputs = [
+		0xc2f5_7bd6_6b07_c4a9,
+		0x72b7_b29b_4421_5383,
+		0xf5af_5ead_68be_b632,
+		0xcbc7_312c_d5ef_c7d7,
+		0x7aec_0808_ff13_3ab5,
+		0xb58a_1dd9_672e_2081,
+	];
+	for expected in outputs {
+		assert_eq!(RngCore::next_u64(&mut rng), expected);
+	}
+
+	let mut rng = Pcg32::new(0);
+	assert_eq!(rng.next_u32(), 0xe823_a24e);
+	assert_eq!(rng.next_u32(), 0x7a7e_cbd9);
+}
+
+#[test]
+fn reseeding_pcg32_restarts_it() {
+	let mut rng = Pcg32::new(1);
+	Random::next_u64(&mut rng);
+	rng.reseed(42);
+	assert_eq!(rng, Pcg32::new(42));
+	assert_eq!(Random::next_u64(&mut rng), 0xc2f5_7bd6_6b07_c4a9);
+}
+
+#[test]
+fn in_range_never_changes() {
+	let mut rng = Pcg32::new(42);
+	let small = [76, 45, 96, 80, 48, 71, 2, 90];
+	assert_eq!(small.map(|_| in_range(&mut rng, 0, 100)), small);
+
+	let mut rng = Pcg32::new(42);
+	let negative = [3, -1, 5, 3, 0, 2, -5, 4];
+	assert_eq!(negative.map(|_| in_range(&mut rng, -5, 5)), negative);
+
+	// The entire range of `i64` just reinterprets each number.
+	let mut rng = Pcg32::new(42);
+	let everything = [-4398473300208532311, 8266272020994544515, -743271314613160398];
+	assert_eq!(everything.map(|_| in_range(&mut rng, i64::MIN, i64::MAX)), everything);
+
+	let mut rng = Pcg32::new(42);
+	assert_eq!(in_range(&mut rng, 7, 7), 7);
+}
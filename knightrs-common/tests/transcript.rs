@@ -0,0 +1,46 @@
+use knightrs_common::Input;
+use std::io::ErrorKind;
+
+fn read_all(mut transcript: &[u8]) -> std::io::Result<Vec<Input>> {
+	let mut inputs = Vec::new();
+	while let Some(input) = Input::read(&mut transcript)? {
+		inputs.push(input);
+	}
+	Ok(inputs)
+}
+
+#[test]
+fn inputs_round_trip() {
+	let inputs = vec![
+		Input::Prompt(Some("a line".to_string())),
+		Input::Prompt(Some(String::new())),
+		Input::Prompt(None),
+		Input::Random(-12),
+		Input::System { command: "echo hi".to_string(), output: "hi\n".to_string() },
+		Input::UseFile { filename: "a.kn".to_string(), contents: "OUTPUT 1\n".to_string() },
+	];
+
+	let mut transcript = Vec::new();
+	for input in &inputs {
+		input.write(&mut transcript).unwrap();
+	}
+
+	assert_eq!(read_all(&transcript).unwrap(), inputs);
+}
+
+#[test]
+fn lengths_past_the_end_are_errors() {
+	let err = read_all(b"PROMPT 18446744073709551615\nshort\n").unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+	let err = read_all(b"SYSTEM 2 100\nls\nnot that long\n").unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn invalid_transcripts_are_errors() {
+	for transcript in [&b"PROMPT 2\nabc\n"[..], b"PROMPT x\n", b"RANDOM\n", b"WHAT 1\n"] {
+		let err = read_all(transcript).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", String::from_utf8_lossy(transcript));
+	}
+}
@@ -2,14 +2,13 @@
 
 use crate::function::Function;
 use crate::parse::{ParseFn, Parser};
-use crate::value::{Integer, Runnable, TextSlice, Value};
+use crate::value::{Integer, Runnable, Text, TextSlice, Value};
 use crate::Result;
-use rand::{rngs::StdRng, SeedableRng};
 use std::collections::HashSet;
 
 cfg_if! {
 if #[cfg(feature = "extensions")] {
-	use crate::value::{Text, List};
+	use crate::value::List;
 	use crate::function::ExtensionFunction;
 	use std::collections::VecDeque;
 
@@ -24,14 +23,24 @@ mod builder;
 pub mod flags;
 pub mod output;
 pub mod prompt;
+pub mod random;
 pub mod variable;
+pub use knightrs_common::{interrupt, transcript};
 
 pub use builder::Builder;
 pub use flags::Flags;
+pub use interrupt::InterruptHandle;
 use output::Output;
 use prompt::Prompt;
+use random::EnvRandom;
+pub use random::{Pcg32, Random};
+pub use transcript::Input;
 pub use variable::Variable;
 
+// Where inputs are recorded to or replayed from.
+type Transcript<'e> =
+	transcript::Transcript<Box<dyn output::Stdout + 'e>, Box<dyn prompt::Stdin + 'e>>;
+
 /// The environment hosts all relevant information for Knight programs.
 ///
 /// <todo: details>
@@ -41,7 +50,9 @@ pub struct Environment<'e> {
 	prompt: Prompt<'e>,
 	output: Output<'e>,
 	functions: HashSet<Function>,
-	rng: StdRng,
+	rng: Box<dyn EnvRandom + 'e>,
+	interrupt: InterruptHandle,
+	transcript: Transcript<'e>,
 
 	// Parsers are only modifiable when the `extensions` feature is enabled. Otherwise, the normal
 	// set of parsers is loaded up.
@@ -143,6 +154,39 @@ impl<'e> Environment<'e> {
 		&mut self.output
 	}
 
+	/// Gets the [`InterruptHandle`] which can be used to stop programs, even from other threads.
+	///
+	/// Programs check the handle every iteration of a `WHILE` loop and every `CALL`, and stop with
+	/// [`Error::Interrupted`](crate::Error::Interrupted) once it's been interrupted.
+	///
+	/// # Examples
+	/// ```rust
+	/// use knightrs::{env::Environment, value::Text, Error};
+	/// use std::time::Duration;
+	///
+	/// let mut env = Environment::default();
+	/// env.interrupt_handle().interrupt_after(Duration::from_millis(10));
+	///
+	/// let program = Text::new("WHILE TRUE 1".to_string(), env.flags()).unwrap();
+	/// assert!(matches!(env.play(&program), Err(Error::Interrupted)));
+	/// ```
+	#[must_use]
+	#[inline]
+	pub fn interrupt_handle(&self) -> &InterruptHandle {
+		&self.interrupt
+	}
+
+	/// Returns [`Error::Interrupted`](crate::Error::Interrupted) if the [`InterruptHandle`] has been
+	/// interrupted.
+	#[inline]
+	pub fn check_interrupt(&self) -> Result<()> {
+		if self.interrupt.is_interrupted() {
+			Err(crate::Error::Interrupted)
+		} else {
+			Ok(())
+		}
+	}
+
 	/// Fetches the variable corresponding to `name`, creating one if it's the first time that name
 	/// has been requested.
 	pub fn lookup(
@@ -160,10 +204,30 @@ impl<'e> Environment<'e> {
 	}
 
 	/// Gets a random [`Integer`].
-	#[must_use]
-	#[inline]
-	pub fn random(&mut self) -> Integer {
-		Integer::random(&mut self.rng, self.flags)
+	///
+	/// # Errors
+	/// If a transcript is being replayed, this returns an error if `RANDOM` wasn't recorded next.
+	pub fn random(&mut self) -> Result<Integer> {
+		if let Some(Input::Random(int)) = self.transcript.replay(&Input::Random(0))? {
+			// The transcript might've been recorded with different flags.
+			return Integer::new(int, self.flags)
+				.ok_or(crate::Error::DomainError("recorded RANDOM is out of bounds"));
+		}
+
+		let int = Integer::random(&mut *self.rng, self.flags);
+		self.transcript.record(Input::Random(int.into()))?;
+		Ok(int)
+	}
+
+	// Reads a line from the default stdin for `PROMPT`, recording or replaying it if needed.
+	fn read_stdin_line(&mut self) -> Result<Option<Text>> {
+		let line = match self.transcript.replay(&Input::Prompt(None))? {
+			Some(Input::Prompt(line)) => line,
+			_ => self.prompt.read_default_line()?,
+		};
+
+		self.transcript.record(Input::Prompt(line.clone()))?;
+		Ok(line.map(|line| Text::new(line, self.flags)).transpose()?)
 	}
 }
 
@@ -180,13 +244,21 @@ impl Environment<'_> {
 	/// Seeds the random number generator.
 	#[inline]
 	pub fn srand(&mut self, seed: Integer) {
-		self.rng = StdRng::seed_from_u64(i64::from(seed) as u64)
+		self.rng.reseed(i64::from(seed) as u64)
 	}
 
 	/// Executes `command` as a shell command, returning its result.
-	#[inline]
 	pub fn run_command(&mut self, command: &TextSlice, stdin: Option<&TextSlice>) -> Result<Text> {
-		(self.system)(command, stdin, self.flags)
+		let requested = Input::System { command: command.to_string(), output: String::new() };
+		if let Some(Input::System { output, .. }) = self.transcript.replay(&requested)? {
+			return Ok(Text::new(output, self.flags)?);
+		}
+
+		let output = (self.system)(command, stdin, self.flags)?;
+		self
+			.transcript
+			.record(Input::System { command: command.to_string(), output: output.to_string() })?;
+		Ok(output)
 	}
 
 	/// Adds `output` as the next value to return from the system command.
@@ -203,9 +275,18 @@ impl Environment<'_> {
 	}
 
 	/// Reads the file located at `filename`, returning its contents.
-	#[inline]
 	pub fn read_file(&mut self, filename: &TextSlice) -> Result<Text> {
-		(self.read_file)(filename, self.flags)
+		let requested = Input::UseFile { filename: filename.to_string(), contents: String::new() };
+		if let Some(Input::UseFile { contents, .. }) = self.transcript.replay(&requested)? {
+			return Ok(Text::new(contents, self.flags)?);
+		}
+
+		let contents = (self.read_file)(filename, self.flags)?;
+		self.transcript.record(Input::UseFile {
+			filename: filename.to_string(),
+			contents: contents.to_string(),
+		})?;
+		Ok(contents)
 	}
 
 	#[inline]
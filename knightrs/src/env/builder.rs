@@ -1,4 +1,5 @@
 use super::*;
+use rand::SeedableRng;
 
 /// A builder for an [`Environment`], allowing different options to be configured.
 #[must_use]
@@ -7,6 +8,9 @@ pub struct Builder<'e> {
 	prompt: Prompt<'e>,
 	output: Output<'e>,
 	functions: HashSet<Function>,
+	interrupt: InterruptHandle,
+	rng: Option<Box<dyn EnvRandom + 'e>>,
+	transcript: Transcript<'e>,
 
 	// While not feature gated to extensions, it's only modifiable with extensions.
 	parsers: Vec<ParseFn>,
@@ -33,9 +37,12 @@ impl<'e> Builder<'e> {
 	pub fn new(flags: &'e Flags) -> Self {
 		Self {
 			flags,
-			prompt: Prompt::new(flags),
+			prompt: Prompt::new(),
 			output: Output::new(flags),
 			functions: Function::default_set(&flags),
+			interrupt: InterruptHandle::default(),
+			rng: None,
+			transcript: Transcript::None,
 			parsers: crate::parse::default(&flags),
 
 			#[cfg(feature = "extensions")]
@@ -61,6 +68,78 @@ impl<'e> Builder<'e> {
 		self.output.set_stdout(stdout);
 	}
 
+	/// Sets the [`InterruptHandle`] programs check to see if they should stop.
+	///
+	/// By default, every [`Environment`] gets its own handle, but one can be shared to interrupt more
+	/// than one at once.
+	pub fn interrupt_handle(&mut self, handle: InterruptHandle) {
+		self.interrupt = handle;
+	}
+
+	/// Sets the random number generator used by `RANDOM`, which `XSRAND` reseeds.
+	///
+	/// By default, a [`StdRng`](rand::rngs::StdRng) seeded from the operating system is used. Use
+	/// [`Pcg32`] if the same seed needs to produce the same numbers on every platform and version.
+	///
+	/// # Examples
+	/// ```rust
+	/// use knightrs::env::{Environment, Flags, Pcg32};
+	///
+	/// let flags = Flags::default();
+	/// let random = || {
+	///     let mut builder = Environment::builder(&flags);
+	///     builder.rng(Pcg32::new(1234));
+	///     builder.build().random().unwrap()
+	/// };
+	///
+	/// assert_eq!(random(), random());
+	/// ```
+	pub fn rng<R: Random + crate::containers::MaybeSendSync + 'e>(&mut self, rng: R) {
+		self.rng = Some(Box::new(rng) as Box<_>);
+	}
+
+	/// Records every nondeterministic [`Input`] given to programs to `transcript`, so they can be
+	/// [replayed](Self::replay) later.
+	///
+	/// Lines read by `PROMPT` are only recorded when they come from stdin, not from a replacement
+	/// (see [`Prompt`]), as replacements are set by the program itself.
+	///
+	/// # Examples
+	/// ```rust
+	/// use knightrs::env::{Environment, Flags};
+	/// use knightrs::value::Text;
+	///
+	/// let flags = Flags::default();
+	/// let program = Text::new("+ PROMPT RANDOM".to_string(), &flags).unwrap();
+	/// let mut transcript = Vec::new();
+	///
+	/// let mut builder = Environment::builder(&flags);
+	/// builder.stdin(&b"the number is "[..]);
+	/// builder.record(&mut transcript);
+	/// let recorded = builder.build().play(&program).unwrap();
+	///
+	/// // Nothing's read from stdin when replaying, so it doesn't matter what it is.
+	/// let mut builder = Environment::builder(&flags);
+	/// builder.stdin(std::io::empty());
+	/// builder.replay(&transcript[..]);
+	/// let replayed = builder.build().play(&program).unwrap();
+	///
+	/// assert_eq!(format!("{recorded:?}"), format!("{replayed:?}"));
+	/// ```
+	pub fn record<W: super::output::Stdout + 'e>(&mut self, transcript: W) {
+		self.transcript = Transcript::Record(Box::new(transcript));
+	}
+
+	/// Gives programs the [`Input`]s from a transcript made by [`record`](Self::record), in order,
+	/// instead of reading stdin, generating random numbers, running commands, or reading files.
+	///
+	/// If a program asks for a different input than what was recorded next (such as `RANDOM` when
+	/// `PROMPT` was recorded, or a different command for `XSYSTEM`), it stops with
+	/// [`Error::ReplayDiverged`](crate::Error::ReplayDiverged).
+	pub fn replay<R: super::prompt::Stdin + 'e>(&mut self, transcript: R) {
+		self.transcript = Transcript::Replay(Box::new(transcript));
+	}
+
 	/// Gets a mutable set of normal (i.e. non-`X`) functions.
 	///
 	/// See [`Builder::extensions`] for extension functions.
@@ -115,9 +194,11 @@ impl<'e> Builder<'e> {
 			prompt: self.prompt,
 			output: self.output,
 			functions: self.functions,
+			interrupt: self.interrupt,
+			transcript: self.transcript,
 			parsers: self.parsers,
 
-			rng: StdRng::from_entropy(),
+			rng: self.rng.unwrap_or_else(|| Box::new(rand::rngs::StdRng::from_entropy())),
 
 			#[cfg(feature = "extensions")]
 			extensions: self.extensions,
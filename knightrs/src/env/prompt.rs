@@ -1,6 +1,6 @@
 //! How Knight reads from stdin.
 
-use super::{Environment, Flags};
+use super::Environment;
 use crate::containers::MaybeSendSync;
 use crate::value::text::Text;
 use crate::Result;
@@ -73,7 +73,6 @@ impl<T: BufRead + MaybeSendSync> Stdin for T {}
 /// ```
 pub struct Prompt<'e> {
 	default: Box<dyn Stdin + 'e>,
-	flags: &'e Flags,
 
 	#[cfg(feature = "extensions")]
 	replacement: Option<PromptReplacement>,
@@ -115,6 +114,10 @@ pub struct Line(Option<ReadLineResultInner>);
 enum ReadLineResultInner {
 	Text(Text),
 
+	// The line needs to be read from the default stdin, which is done by the environment so it can
+	// be recorded or replayed.
+	Stdin,
+
 	#[cfg(feature = "extensions")]
 	Ast(Ast),
 }
@@ -125,6 +128,7 @@ impl Line {
 		match self.0 {
 			None => Ok(None),
 			Some(ReadLineResultInner::Text(text)) => Ok(Some(text)),
+			Some(ReadLineResultInner::Stdin) => env.read_stdin_line(),
 
 			#[cfg(feature = "extensions")]
 			Some(ReadLineResultInner::Ast(ast)) => match ast.run(env)? {
@@ -136,10 +140,9 @@ impl Line {
 }
 
 impl<'e> Prompt<'e> {
-	pub(super) fn new(flags: &'e Flags) -> Self {
+	pub(super) fn new() -> Self {
 		Self {
 			default: Box::new(io::BufReader::new(io::stdin())),
-			flags,
 
 			#[cfg(feature = "extensions")]
 			replacement: None,
@@ -158,10 +161,12 @@ impl<'e> Prompt<'e> {
 	/// must then call [`.get(env)`](Line::get) on it to get the actual [`Text`]. This is because one
 	/// of the replacements allows you to assign to [`Ast`](crate::Ast)s: Running an `Ast` requires
 	/// a mutable reference to an [`Environment`], but `&mut self` already has a mutable reference,
-	/// so `env.prompt().read_line(env)` doesn't actually work.
+	/// so `env.prompt().read_line(env)` doesn't actually work. Likewise, lines from stdin are only
+	/// read by `.get(env)`, as the environment might be [recording or replaying](
+	/// super::Builder::record) them.
 	///
 	/// # Errors
-	/// Any errors that occur when reading from stdin are bubbled upwards.
+	/// Any errors that occur when reading from stdin are bubbled upwards by [`Line::get`].
 	pub fn read_line(&mut self) -> Result<Line> {
 		#[cfg(feature = "extensions")]
 		match self.replacement.as_mut() {
@@ -175,15 +180,20 @@ impl<'e> Prompt<'e> {
 			None => {}
 		}
 
+		Ok(Line(Some(ReadLineResultInner::Stdin)))
+	}
+
+	// Reads a line from the default stdin, without its line ending.
+	pub(super) fn read_default_line(&mut self) -> Result<Option<String>> {
 		let mut line = String::new();
 
 		// If we read an empty line, return null.
 		if self.default.read_line(&mut line)? == 0 {
-			return Ok(Line(None));
+			return Ok(None);
 		}
 
 		strip_ending(&mut line);
-		Ok(Line(Some(ReadLineResultInner::Text(Text::new(line, self.flags)?))))
+		Ok(Some(line))
 	}
 }
 
@@ -0,0 +1,9 @@
+//! How Knight generates random numbers.
+
+use crate::containers::MaybeSendSync;
+pub use knightrs_common::random::{in_range, Pcg32, Random};
+
+// The rngs environments can use. This exists as `Random` itself doesn't require `Send + Sync`, but
+// we need it when the `multithreaded` feature is enabled.
+pub(crate) trait EnvRandom: Random + MaybeSendSync {}
+impl<T: Random + MaybeSendSync + ?Sized> EnvRandom for T {}
@@ -1,3 +1,4 @@
+use crate::env::transcript::ReplayError;
 use crate::env::variable::IllegalVariableName;
 use crate::parse::Error as ParseError;
 use crate::value::text::NewTextError;
@@ -42,6 +43,13 @@ pub enum Error {
 	/// Indicates that either `GET` or `SET` were given an index that was out of bounds.
 	IndexOutOfBounds { len: usize, index: usize },
 
+	/// The environment's [`InterruptHandle`](crate::env::InterruptHandle) was interrupted.
+	Interrupted,
+
+	/// A program asked for a different input than what was recorded next in the transcript it's
+	/// replaying. See [`Builder::replay`](crate::env::Builder::replay).
+	ReplayDiverged { requested: String, recorded: String },
+
 	/// An integer operation overflowed. Only used when the `checked-overflow` feature is enabled.
 	IntegerOverflow,
 
@@ -71,6 +79,18 @@ impl From<io::Error> for Error {
 	}
 }
 
+impl From<ReplayError> for Error {
+	#[inline]
+	fn from(err: ReplayError) -> Self {
+		match err {
+			ReplayError::Io(err) => Self::IoError(err),
+			ReplayError::Diverged { requested, recorded } => {
+				Self::ReplayDiverged { requested, recorded }
+			}
+		}
+	}
+}
+
 impl From<NewTextError> for Error {
 	#[inline]
 	fn from(err: NewTextError) -> Self {
@@ -129,6 +149,10 @@ impl Display for Error {
 			Self::DivisionByZero => write!(f, "division/modulo by zero"),
 			Self::ParseError(err) => Display::fmt(&err, f),
 			Self::Quit(status) => write!(f, "quitting with status code {status}"),
+			Self::Interrupted => write!(f, "interrupted"),
+			Self::ReplayDiverged { requested, recorded } => {
+				write!(f, "replay diverged: asked for {requested}, but the transcript has {recorded}")
+			}
 			Self::IntegerOverflow => write!(f, "integer under/overflow"),
 			Self::IndexOutOfBounds { len, index } => {
 				write!(f, "end index {index} is out of bounds for length {len}")
@@ -286,7 +286,7 @@ pub fn PROMPT() -> Function {
 pub fn RANDOM() -> Function {
 	function!("RANDOM", env, |/* comment for rustfmt */| {
 		// note that `env.random()` is seedable with `XSRAND`
-		env.random().into()
+		env.random()?.into()
 	})
 }
 
@@ -342,6 +342,7 @@ pub fn BLOCK() -> Function {
 /// The `CALL` function.
 pub fn CALL() -> Function {
 	function!("CALL", env, |arg| {
+		env.check_interrupt()?;
 		let callable = arg.run(env)?;
 
 		#[cfg(feature = "compliance")]
@@ -605,6 +606,7 @@ pub fn WHILE() -> Function {
 	function!("WHILE", env, |condition, body| {
 		while condition.run(env)?.to_boolean(env)? {
 			body.run(env)?;
+			env.check_interrupt()?;
 		}
 
 		Value::Null
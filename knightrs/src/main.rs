@@ -1,6 +1,9 @@
 #![allow(unused)]
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 mod flags {
 	//! Flags to change how the Knight interpreter works at runtime.
@@ -406,38 +409,71 @@ mod flags {
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(group(clap::ArgGroup::new("program").required(true).args(["expr", "file"])))]
 struct Cli {
+	/// Run EXPR as a Knight program.
+	#[arg(short, long, value_name = "EXPR")]
+	expr: Option<String>,
+
+	/// Run the Knight program in FILE.
+	#[arg(short, long, value_name = "FILE")]
+	file: Option<PathBuf>,
+
+	/// Stop the program with an error once it's run for MS milliseconds.
+	#[arg(long, value_name = "MS")]
+	timeout: Option<u64>,
+
+	/// Write every line read by PROMPT, integer returned by RANDOM, output of `$`, and file read by
+	/// USE to FILE.
+	#[arg(long, value_name = "FILE", conflicts_with = "replay")]
+	record: Option<PathBuf>,
+
+	/// Use the inputs written by --record to FILE, instead of reading stdin, generating random
+	/// numbers, running commands, or reading files.
+	#[arg(long, value_name = "FILE")]
+	replay: Option<PathBuf>,
+
 	#[command(flatten)]
 	#[command(next_help_heading = "Flags")]
 	flags: knightrs::env::Flags,
 }
 
-// fn main() {
-// let cli = Cli::parse();
-// }
+// Creates the environment described by `cli`, and runs its program in it.
+fn run(cli: &Cli) -> Result<(), String> {
+	let source = match (&cli.expr, &cli.file) {
+		(Some(expr), _) => expr.clone(),
+		(None, Some(file)) => std::fs::read_to_string(file).map_err(with_path(file))?,
+		(None, None) => unreachable!("clap requires one of them"),
+	};
+	let source = knightrs::value::Text::new(source, &cli.flags).map_err(|err| err.to_string())?;
 
-fn main() {
-	let arg = std::env::args().nth(2).expect("no arg");
+	let mut builder = knightrs::env::Environment::builder(&cli.flags);
+	if let Some(path) = &cli.record {
+		builder.record(BufWriter::new(File::create(path).map_err(with_path(path))?));
+	}
+	if let Some(path) = &cli.replay {
+		builder.replay(BufReader::new(File::open(path).map_err(with_path(path))?));
+	}
 
-	let arg = if std::env::args().nth(1).unwrap() == "-e" {
-		arg
-	} else {
-		std::fs::read_to_string(&*arg).unwrap()
-	};
+	let mut env = builder.build();
+	if let Some(timeout) = cli.timeout {
+		env.interrupt_handle().interrupt_after(Duration::from_millis(timeout));
+	}
 
-	let flags = knightrs::env::Flags::default();
-	// match knightrs::play("utf8", "i64", "wrapping", &arg, &Default::default()) {
-	match knightrs::env::Environment::new(&flags)
-		.play(&knightrs::value::Text::new(arg, &flags).unwrap())
-	{
+	match env.play(&source) {
 		Err(knightrs::Error::Quit(code)) => std::process::exit(code),
-		Err(err) => {
-			eprintln!("error: {err}");
-			std::process::exit(1);
-		}
-		_ => {}
+		result => result.map(drop).map_err(|err| err.to_string()),
 	}
+}
 
-	#[allow(dropping_copy_types)]
-	drop(flags);
+// Adds `path` to I/O errors about it.
+fn with_path(path: &Path) -> impl FnOnce(std::io::Error) -> String + '_ {
+	move |err| format!("{}: {err}", path.display())
+}
+
+fn main() {
+	if let Err(err) = run(&Cli::parse()) {
+		eprintln!("error: {err}");
+		std::process::exit(1);
+	}
 }
@@ -1,4 +1,4 @@
-use crate::env::{Environment, Flags};
+use crate::env::{Environment, Flags, Random};
 use crate::parse::{self, Parsable, Parser};
 use crate::value::{Boolean, List, NamedType, Text, ToBoolean, ToList, ToText};
 use crate::{Error, Result};
@@ -325,7 +325,7 @@ impl Integer {
 	///
 	/// If neither of these flags are enabled, the returned integer will be in the range
 	/// `0..Self::MAX`.
-	pub fn random<R: rand::Rng + ?Sized>(rng: &mut R, flags: &Flags) -> Self {
+	pub fn random<R: Random + ?Sized>(rng: &mut R, flags: &Flags) -> Self {
 		let min = match () {
 			#[cfg(feature = "iffy-extensions")]
 			_ if flags.extensions.iffy.negative_random_integers => {
@@ -350,7 +350,7 @@ impl Integer {
 		};
 
 		let _ = flags;
-		Self(rng.gen_range(min..=max))
+		Self(crate::env::random::in_range(rng, min, max))
 	}
 }
 